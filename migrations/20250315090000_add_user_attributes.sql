@@ -0,0 +1,14 @@
+-- Admin-defined custom attributes stored alongside each user
+ALTER TABLE users ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}'::jsonb;
+
+CREATE INDEX users_attributes_idx ON users USING GIN (attributes);
+
+CREATE TABLE attribute_definitions (
+    name VARCHAR(63) PRIMARY KEY,
+    attribute_type TEXT NOT NULL CHECK (attribute_type IN ('string', 'number', 'boolean')),
+    required BOOLEAN NOT NULL DEFAULT FALSE,
+    is_unique BOOLEAN NOT NULL DEFAULT FALSE,
+    pattern TEXT,
+    visibility TEXT NOT NULL DEFAULT 'admin' CHECK (visibility IN ('self', 'admin')),
+    description TEXT
+);
@@ -0,0 +1,27 @@
+-- Organizations own users, every query on users is scoped to one of them
+CREATE TABLE organizations (
+    org_id UUID PRIMARY KEY,
+    slug VARCHAR(63) NOT NULL UNIQUE,
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Existing users are moved to the default organization
+INSERT INTO organizations (org_id, slug, name)
+VALUES ('00000000-0000-0000-0000-000000000000', 'default', 'Default Organization');
+
+ALTER TABLE users
+    ADD COLUMN org_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000'
+    REFERENCES organizations (org_id) ON DELETE RESTRICT;
+
+CREATE INDEX users_org_id_idx ON users (org_id);
+
+-- Row-level security backstop, only enforced for the table owner when TENANT_RLS forces it
+-- Sessions without app.tenant_id are maintenance sessions and see every row
+ALTER TABLE users ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY users_tenant_isolation ON users
+    USING (
+        coalesce(current_setting('app.tenant_id', true), '') = ''
+        OR org_id = current_setting('app.tenant_id', true)::uuid
+    );
@@ -0,0 +1,36 @@
+-- Groups form a tree per organization, members hold a role in each group they belong to
+CREATE TABLE groups (
+    group_id UUID PRIMARY KEY,
+    org_id UUID NOT NULL REFERENCES organizations (org_id) ON DELETE RESTRICT,
+    parent_id UUID REFERENCES groups (group_id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    -- Id of the mirrored Keycloak group when group sync is enabled
+    keycloak_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Sibling groups must have distinct names, top-level groups are siblings within their organization
+CREATE UNIQUE INDEX groups_sibling_name_idx
+    ON groups (org_id, COALESCE(parent_id, '00000000-0000-0000-0000-000000000000'), lower(name));
+
+CREATE INDEX groups_parent_id_idx ON groups (parent_id);
+
+CREATE TABLE group_memberships (
+    group_id UUID NOT NULL REFERENCES groups (group_id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    role TEXT NOT NULL DEFAULT 'member' CHECK (role IN ('owner', 'member')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (group_id, user_id)
+);
+
+CREATE INDEX group_memberships_user_id_idx ON group_memberships (user_id);
+
+-- Same row-level security backstop as users, toggled together with it by TENANT_RLS
+ALTER TABLE groups ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY groups_tenant_isolation ON groups
+    USING (
+        coalesce(current_setting('app.tenant_id', true), '') = ''
+        OR org_id = current_setting('app.tenant_id', true)::uuid
+    );
@@ -0,0 +1,19 @@
+-- Pending invitations, accepting one creates the user in Keycloak and in users
+CREATE TABLE invitations (
+    invitation_id UUID PRIMARY KEY,
+    org_id UUID NOT NULL REFERENCES organizations (org_id) ON DELETE CASCADE,
+    email VARCHAR(255) NOT NULL,
+    username VARCHAR(255),
+    attributes JSONB NOT NULL DEFAULT '{}'::jsonb,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'revoked')),
+    -- Bumped on every resend, tokens carrying an older version are rejected
+    token_version INTEGER NOT NULL DEFAULT 1,
+    invited_by TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    accepted_at TIMESTAMPTZ,
+    user_id UUID REFERENCES users (user_id) ON DELETE SET NULL
+);
+
+-- Only one open invitation per address and organization
+CREATE UNIQUE INDEX invitations_pending_email_idx ON invitations (org_id, lower(email)) WHERE status = 'pending';
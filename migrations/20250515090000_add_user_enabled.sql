@@ -0,0 +1,2 @@
+-- Disabled users are kept but cannot sign in, mirrored to the Keycloak enabled flag
+ALTER TABLE users ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT TRUE;
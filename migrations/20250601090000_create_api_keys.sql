@@ -0,0 +1,19 @@
+-- Machine credentials accepted by the protect layer next to Keycloak bearer tokens
+CREATE TABLE api_keys (
+    key_id UUID PRIMARY KEY,
+    org_id UUID NOT NULL REFERENCES organizations (org_id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    roles TEXT[] NOT NULL,
+    -- HMAC of the secret part of the key, the key itself is only shown once
+    secret_hash BYTEA NOT NULL,
+    -- Secret replaced by the last rotation, accepted until previous_expires_at
+    previous_hash BYTEA,
+    previous_expires_at TIMESTAMPTZ,
+    created_by TEXT NOT NULL,
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    rotated_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX api_keys_org_idx ON api_keys (org_id, created_at);
@@ -0,0 +1,13 @@
+-- Credentials of the local authentication provider, unused when Keycloak is the provider
+CREATE TABLE local_accounts (
+    -- Token subject, matches users.user_id for accounts created through invitations
+    subject UUID PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    email VARCHAR(255),
+    -- Argon2id hash in PHC string format
+    password_hash TEXT NOT NULL,
+    roles TEXT[] NOT NULL DEFAULT ARRAY['user'],
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX local_accounts_username_idx ON local_accounts (lower(username));
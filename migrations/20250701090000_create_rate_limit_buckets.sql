@@ -0,0 +1,11 @@
+-- Token buckets of the shared rate limit backend, losing them on a crash only resets the limits
+CREATE UNLOGGED TABLE rate_limit_buckets (
+    -- Route group and caller, e.g. private:sub:<uuid> or public:ip:<address>
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    -- Whether the last request took a token
+    allowed BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX rate_limit_buckets_updated_at_idx ON rate_limit_buckets (updated_at);
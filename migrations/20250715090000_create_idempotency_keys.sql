@@ -0,0 +1,19 @@
+-- Responses of unsafe requests sent with an Idempotency-Key, replayed for identical retries
+CREATE TABLE idempotency_keys (
+    -- Token subject or API key id of the caller, keys of different callers never collide
+    scope TEXT NOT NULL,
+    idempotency_key VARCHAR(255) NOT NULL,
+    -- SHA-256 of the method, path and body of the first request
+    fingerprint BYTEA NOT NULL,
+    -- NULL while the first request is still running
+    status_code SMALLINT,
+    response_headers JSONB,
+    response_body BYTEA,
+    -- A running request gives up its claim after this, e.g. when the replica died
+    locked_until TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (scope, idempotency_key)
+);
+
+CREATE INDEX idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
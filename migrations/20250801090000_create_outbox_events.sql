@@ -0,0 +1,22 @@
+-- Domain events written in the same statement as the user change they describe, delivered by the outbox relay
+CREATE TABLE outbox_events (
+    event_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- Delivery order, events of a user are delivered one after another
+    sequence BIGSERIAL NOT NULL UNIQUE,
+    -- UserCreated, UserUpdated or UserDeleted
+    event_type VARCHAR(64) NOT NULL,
+    aggregate_id UUID NOT NULL,
+    org_id UUID NOT NULL,
+    payload JSONB NOT NULL,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    delivered_at TIMESTAMPTZ,
+    -- Set once OUTBOX_MAX_ATTEMPTS deliveries failed, the event is kept for inspection and manual replay
+    dead_lettered_at TIMESTAMPTZ
+);
+
+CREATE INDEX outbox_events_pending_idx ON outbox_events (next_attempt_at) WHERE delivered_at IS NULL AND dead_lettered_at IS NULL;
+CREATE INDEX outbox_events_aggregate_idx ON outbox_events (aggregate_id, sequence) WHERE delivered_at IS NULL AND dead_lettered_at IS NULL;
+CREATE INDEX outbox_events_delivered_at_idx ON outbox_events (delivered_at) WHERE delivered_at IS NOT NULL;
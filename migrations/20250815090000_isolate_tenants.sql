@@ -0,0 +1,33 @@
+-- User ids are only unique within an organization, so a taken id no longer hints at another tenant
+ALTER TABLE group_memberships ADD COLUMN org_id UUID;
+UPDATE group_memberships SET org_id = groups.org_id FROM groups WHERE groups.group_id = group_memberships.group_id;
+ALTER TABLE group_memberships ALTER COLUMN org_id SET NOT NULL;
+
+ALTER TABLE group_memberships DROP CONSTRAINT group_memberships_user_id_fkey;
+ALTER TABLE group_memberships DROP CONSTRAINT group_memberships_group_id_fkey;
+ALTER TABLE invitations DROP CONSTRAINT invitations_user_id_fkey;
+
+ALTER TABLE users DROP CONSTRAINT users_pkey;
+ALTER TABLE users ADD PRIMARY KEY (org_id, user_id);
+DROP INDEX users_org_id_idx;
+
+-- Members and the groups they join belong to the same organization
+ALTER TABLE groups ADD CONSTRAINT groups_group_id_org_id_key UNIQUE (group_id, org_id);
+ALTER TABLE group_memberships
+    ADD CONSTRAINT group_memberships_group_fkey FOREIGN KEY (group_id, org_id) REFERENCES groups (group_id, org_id) ON DELETE CASCADE,
+    ADD CONSTRAINT group_memberships_user_fkey FOREIGN KEY (org_id, user_id) REFERENCES users (org_id, user_id) ON DELETE CASCADE;
+ALTER TABLE invitations
+    ADD CONSTRAINT invitations_user_fkey FOREIGN KEY (org_id, user_id) REFERENCES users (org_id, user_id) ON DELETE SET NULL (user_id);
+
+-- Sessions without app.tenant_id see no rows, maintenance sessions opt in to every organization with app.all_tenants
+ALTER POLICY users_tenant_isolation ON users
+    USING (
+        org_id = nullif(current_setting('app.tenant_id', true), '')::uuid
+        OR current_setting('app.all_tenants', true) = 'on'
+    );
+
+ALTER POLICY groups_tenant_isolation ON groups
+    USING (
+        org_id = nullif(current_setting('app.tenant_id', true), '')::uuid
+        OR current_setting('app.all_tenants', true) = 'on'
+    );
@@ -0,0 +1,2 @@
+-- Keycloak group holding the mirrored groups of an organization, the realm is shared by every tenant
+ALTER TABLE organizations ADD COLUMN keycloak_id TEXT;
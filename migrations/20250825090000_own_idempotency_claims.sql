@@ -0,0 +1,3 @@
+-- Request holding the claim, a claim taken over after its lock ran out can no longer be completed or released by
+-- the request that lost it. Scopes now include the organization of the caller
+ALTER TABLE idempotency_keys ADD COLUMN claim_id UUID;
@@ -0,0 +1,4 @@
+-- A dead letter holds back the later events of its user, so pending events are looked up per organization
+-- and user including dead-lettered ones
+DROP INDEX outbox_events_aggregate_idx;
+CREATE INDEX outbox_events_aggregate_idx ON outbox_events (org_id, aggregate_id, sequence) WHERE delivered_at IS NULL;
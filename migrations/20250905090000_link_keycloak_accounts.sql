@@ -0,0 +1,6 @@
+-- Keycloak account of a user. Local user ids are only unique per organization and accounts created
+-- directly in Keycloak have their own ids, so the account id is stored instead of derived
+ALTER TABLE users ADD COLUMN keycloak_id UUID;
+
+-- One account is never shared by users of different organizations
+CREATE UNIQUE INDEX users_keycloak_id_key ON users (keycloak_id);
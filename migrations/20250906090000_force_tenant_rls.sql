@@ -0,0 +1,4 @@
+-- Enforce the tenant policies for the table owner as well. Forcing takes an ACCESS EXCLUSIVE lock and
+-- requires ownership, so it lives here instead of in the server startup, which only checks the result
+ALTER TABLE users FORCE ROW LEVEL SECURITY;
+ALTER TABLE groups FORCE ROW LEVEL SECURITY;
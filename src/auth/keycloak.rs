@@ -0,0 +1,165 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use aide::axum::ApiRouter;
+use axum_keycloak_auth::{
+    instance::{KeycloakAuthInstance, KeycloakConfig},
+    layer::KeycloakAuthLayer,
+    PassthroughMode, Url,
+};
+use futures::{future::BoxFuture, FutureExt};
+use reqwest::{Client, StatusCode};
+use uuid::Uuid;
+
+use super::{AuthProvider, LoginError};
+use crate::{
+    config::EnvironmentVariables,
+    custom::telemetry::record_keycloak_call,
+    definitions::auth::{ClientTokenResponse, LoginResponse, TokenResponse},
+    keycloak::KeycloakAdmin,
+};
+
+// Keycloak realm as the identity provider, tokens are validated against its published keys
+pub struct KeycloakProvider {
+    instance: Arc<KeycloakAuthInstance>,
+    client: Client,
+    kc_admin: Arc<KeycloakAdmin>,
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+}
+
+impl KeycloakProvider {
+    pub fn new(instance: Arc<KeycloakAuthInstance>, env: &EnvironmentVariables, client: Client, kc_admin: Arc<KeycloakAdmin>) -> Self {
+        Self {
+            instance,
+            client,
+            kc_admin,
+            token_url: format!("{}{}", env.kc_server_addr, env.kc_login_path),
+            client_id: env.kc_client_id.to_string(),
+            client_secret: env.kc_client_secret.to_string(),
+        }
+    }
+
+    // Create the auth integration instance, this starts OIDC discovery in the background
+    pub fn from_env(env: &EnvironmentVariables, client: Client, kc_admin: Arc<KeycloakAdmin>) -> anyhow::Result<Self> {
+        if env.kc_server_addr.is_empty() || env.kc_login_path.is_empty() {
+            anyhow::bail!("KC_SERVER_ADDR and KC_LOGIN_PATH are required when AUTH_PROVIDER is keycloak");
+        }
+        let instance = Arc::new(KeycloakAuthInstance::new(
+            KeycloakConfig::builder()
+                .server(Url::parse(&env.kc_server_addr)?)
+                .realm(env.kc_realm.to_string())
+                .build(),
+        ));
+        Ok(Self::new(instance, env, client, kc_admin))
+    }
+
+    // Password grant against the token endpoint
+    async fn request_login(&self, username: &str, password: &str) -> Result<LoginResponse, LoginError> {
+        let mut params: HashMap<&str, &str> = HashMap::new();
+        params.insert("grant_type", "password");
+        params.insert("scope", "email openid");
+        params.insert("client_id", &self.client_id);
+        params.insert("client_secret", &self.client_secret);
+        params.insert("username", username);
+        params.insert("password", password);
+
+        let body = self.client.post(&self.token_url)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|err| LoginError::Unavailable(format!("Failed to reach Keycloak: {err}")))?;
+
+        // Wrong passwords and unknown or disabled users come back as 400 invalid_grant or 401
+        match body.status() {
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED => return Err(LoginError::InvalidCredentials),
+            status if !status.is_success() => {
+                return Err(LoginError::Unavailable(format!("Keycloak token endpoint returned {status}")));
+            },
+            _ => {},
+        }
+
+        let token = body.json::<TokenResponse>().await.map_err(|err| {
+            LoginError::Internal(format!("Failed to parse TokenResponse: {err}"))
+        })?;
+
+        Ok(LoginResponse {
+            access_token: token.access_token,
+            token_type: token.token_type,
+            expires_in: token.expires_in,
+            refresh_token: token.refresh_token,
+            refresh_expires_in: token.refresh_expires_in,
+        })
+    }
+
+    // Client credentials grant against the token endpoint
+    async fn request_client_token(&self, client_id: &str, client_secret: &str, scope: Option<&str>) -> Result<ClientTokenResponse, LoginError> {
+        let mut params: HashMap<&str, &str> = HashMap::new();
+        params.insert("grant_type", "client_credentials");
+        params.insert("client_id", client_id);
+        params.insert("client_secret", client_secret);
+        if let Some(scope) = scope {
+            params.insert("scope", scope);
+        }
+
+        let body = self.client.post(&self.token_url)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|err| LoginError::Unavailable(format!("Failed to request client token: {err}")))?;
+
+        // Keycloak answers unknown clients and wrong secrets with 400 or 401
+        if body.status().is_client_error() {
+            return Err(LoginError::InvalidCredentials);
+        }
+        body.json::<ClientTokenResponse>().await.map_err(|err| {
+            LoginError::Internal(format!("Failed to parse ClientTokenResponse: {err}"))
+        })
+    }
+}
+
+impl AuthProvider for KeycloakProvider {
+    fn name(&self) -> &'static str {
+        "keycloak"
+    }
+
+    fn authenticate(&self, router: ApiRouter) -> ApiRouter {
+        router.layer(
+            KeycloakAuthLayer::<String>::builder()
+                .instance(self.instance.clone())
+                .passthrough_mode(PassthroughMode::Pass)
+                .persist_raw_claims(true)
+                .expected_audiences(vec![String::from("account")])
+                .required_roles(vec![String::from("user")])
+                .build(),
+        )
+    }
+
+    fn login<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, Result<LoginResponse, LoginError>> {
+        async move {
+            let started = Instant::now();
+            let result = self.request_login(username, password).await;
+            record_keycloak_call("login", started, &result);
+            result
+        }
+        .boxed()
+    }
+
+    fn client_token<'a>(&'a self, client_id: &'a str, client_secret: &'a str, scope: Option<&'a str>) -> BoxFuture<'a, Result<ClientTokenResponse, LoginError>> {
+        async move {
+            let started = Instant::now();
+            let result = self.request_client_token(client_id, client_secret, scope).await;
+            record_keycloak_call("client_token", started, &result);
+            result
+        }
+        .boxed()
+    }
+
+    fn create_account<'a>(&'a self, username: &'a str, email: Option<&'a str>, password: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Uuid>>> {
+        self.kc_admin.create_user(username, email, password).boxed()
+    }
+
+    fn delete_account(&self, subject: Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.kc_admin.delete_user(subject).boxed()
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::{Arc, OnceLock};
+
+use aide::axum::ApiRouter;
+use anyhow::{anyhow, Context};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_keycloak_auth::{decode::{ProfileAndEmail, RawClaims}, KeycloakAuthStatus};
+use chrono::Utc;
+use futures::{future::BoxFuture, FutureExt};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use super::{AuthProvider, LoginError};
+use crate::{
+    config::EnvironmentVariables,
+    custom::signing::{derive_key, LOCAL_JWT},
+    database::local_accounts::{create_local_account, find_local_account, remove_local_account},
+    definitions::{
+        api_key::ApiKeyIdentity,
+        auth::{ClientTokenResponse, LocalAccount, LocalClaims, LoginResponse, RealmAccess},
+    },
+    middleware::authentication::issued_token,
+};
+
+// Issuer of tokens signed by the local provider
+const LOCAL_ISSUER: &str = "local";
+
+// Keys derived from SECRET, shared with the token validation middleware
+struct LocalKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    token_ttl: u16,
+}
+
+// Accounts stored in local_accounts with Argon2id hashes, tokens are HS256 JWTs signed with a key derived from SECRET
+pub struct LocalProvider {
+    pgpool: Pool<Postgres>,
+    keys: Arc<LocalKeys>,
+}
+
+impl LocalProvider {
+    pub fn new(env: &EnvironmentVariables, pgpool: Pool<Postgres>) -> anyhow::Result<Self> {
+        let token_ttl = env.local_token_ttl_secs.parse().context("LOCAL_TOKEN_TTL_SECS must be a number of seconds")?;
+        let key = derive_key(&env.secret, LOCAL_JWT);
+        Ok(Self {
+            pgpool,
+            keys: Arc::new(LocalKeys {
+                encoding: EncodingKey::from_secret(key.as_bytes()),
+                decoding: DecodingKey::from_secret(key.as_bytes()),
+                token_ttl,
+            }),
+        })
+    }
+
+    // Create the administrator named by LOCAL_ADMIN_USERNAME unless it already exists
+    pub async fn bootstrap_admin(&self, env: &EnvironmentVariables) -> anyhow::Result<()> {
+        if env.local_admin_username.is_empty() {
+            return Ok(());
+        }
+        if env.local_admin_password.is_empty() {
+            anyhow::bail!("LOCAL_ADMIN_PASSWORD must be set together with LOCAL_ADMIN_USERNAME");
+        }
+
+        let roles = [String::from("user"), String::from("administrator")];
+        let password_hash = hash_password(env.local_admin_password.to_string()).await?;
+        if create_local_account(Uuid::new_v4(), &env.local_admin_username, None, &password_hash, &roles, &self.pgpool).await?.is_some() {
+            println!("Created local administrator {}", env.local_admin_username);
+        }
+        Ok(())
+    }
+
+    fn sign(&self, account: &LocalAccount) -> Result<String, LoginError> {
+        let now = Utc::now().timestamp();
+        let claims = LocalClaims {
+            iss: LOCAL_ISSUER.to_string(),
+            sub: account.subject,
+            aud: String::from("account"),
+            exp: now + i64::from(self.keys.token_ttl),
+            iat: now,
+            jti: Uuid::new_v4(),
+            preferred_username: account.username.clone(),
+            email: account.email.clone(),
+            realm_access: RealmAccess { roles: account.roles.clone() },
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.keys.encoding)
+            .map_err(|err| LoginError::Internal(format!("Failed to sign local token: {err}")))
+    }
+}
+
+impl AuthProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn authenticate(&self, router: ApiRouter) -> ApiRouter {
+        router.layer(axum::middleware::from_fn_with_state(self.keys.clone(), validate_local_token))
+    }
+
+    fn login<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, Result<LoginResponse, LoginError>> {
+        async move {
+            let account = find_local_account(username, &self.pgpool)
+                .await
+                .map_err(|err| LoginError::Internal(format!("Failed to load local account: {err}")))?;
+
+            // Unknown usernames are checked against a throwaway hash so both cases take as long
+            let password_hash = match &account {
+                Some(account) => account.password_hash.clone(),
+                None => dummy_hash().to_string(),
+            };
+            let verified = verify_password(password.to_string(), password_hash)
+                .await
+                .map_err(|err| LoginError::Internal(format!("{err:#}")))?;
+
+            let account = match account {
+                Some(account) if verified => account,
+                _ => return Err(LoginError::InvalidCredentials),
+            };
+
+            // There is no refresh grant, clients log in again once the token expires
+            Ok(LoginResponse {
+                access_token: self.sign(&account)?,
+                token_type: String::from("Bearer"),
+                expires_in: self.keys.token_ttl,
+                refresh_token: String::new(),
+                refresh_expires_in: 0,
+            })
+        }
+        .boxed()
+    }
+
+    fn client_token<'a>(&'a self, _client_id: &'a str, _client_secret: &'a str, _scope: Option<&'a str>) -> BoxFuture<'a, Result<ClientTokenResponse, LoginError>> {
+        // Machine clients use API keys with the local provider
+        async { Err(LoginError::Unsupported) }.boxed()
+    }
+
+    fn create_account<'a>(&'a self, username: &'a str, email: Option<&'a str>, password: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Uuid>>> {
+        async move {
+            let password_hash = hash_password(password.to_string()).await?;
+            let account = create_local_account(Uuid::new_v4(), username, email, &password_hash, &[String::from("user")], &self.pgpool).await?;
+            Ok(account.map(|account| account.subject))
+        }
+        .boxed()
+    }
+
+    fn delete_account(&self, subject: Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move { Ok(remove_local_account(subject, &self.pgpool).await?) }.boxed()
+    }
+}
+
+// Validate bearer tokens signed by this provider, unauthenticated requests are rejected by require_authentication
+async fn validate_local_token(State(keys): State<Arc<LocalKeys>>, mut req: Request<Body>, next: Next) -> Response {
+    if req.extensions().get::<ApiKeyIdentity>().is_some() {
+        return next.run(req).await;
+    }
+
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let Some(bearer) = bearer else {
+        return next.run(req).await;
+    };
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&["account"]);
+    validation.set_issuer(&[LOCAL_ISSUER]);
+    let raw_claims = match jsonwebtoken::decode::<RawClaims>(&bearer, &keys.decoding, &validation) {
+        Ok(data) => data.claims,
+        Err(err) => {
+            eprintln!("Rejected local token: {err}");
+            return next.run(req).await;
+        }
+    };
+    let Ok(claims) = serde_json::from_value::<LocalClaims>(json!(raw_claims)) else {
+        return next.run(req).await;
+    };
+
+    // Same requirement as the Keycloak layer
+    if !claims.realm_access.roles.iter().any(|role| role == "user") {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "insufficient privileges" })),
+        ).into_response();
+    }
+
+    let token = issued_token(
+        &claims.sub.to_string(),
+        &claims.iss,
+        &claims.preferred_username,
+        claims.email.as_deref(),
+        &claims.realm_access.roles,
+        Some(claims.exp),
+    );
+    req.extensions_mut().insert(raw_claims);
+    req.extensions_mut().insert(KeycloakAuthStatus::<String, ProfileAndEmail>::Success(token));
+    next.run(req).await
+}
+
+// Argon2id hash in PHC string format, computed off the async runtime
+async fn hash_password(password: String) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|err| anyhow!("Failed to hash password: {err}"))
+    })
+    .await?
+}
+
+async fn verify_password(password: String, password_hash: String) -> anyhow::Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&password_hash).map_err(|err| anyhow!("Invalid password hash: {err}"))?;
+        Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+    })
+    .await?
+}
+
+// Hash of a random password, verified when the username does not exist
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(Uuid::new_v4().as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .unwrap_or_default()
+    })
+}
@@ -0,0 +1,66 @@
+pub mod keycloak;
+pub mod local;
+
+use std::sync::Arc;
+
+use aide::axum::ApiRouter;
+use anyhow::bail;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::{
+    config::EnvironmentVariables,
+    definitions::auth::{ClientTokenResponse, LoginResponse},
+    keycloak::KeycloakAdmin,
+};
+
+// Why a credential exchange failed, mapped to a response by the route
+#[derive(Debug)]
+pub enum LoginError {
+    InvalidCredentials,
+    // The provider has no equivalent of the requested grant
+    Unsupported,
+    Unavailable(String),
+    Internal(String),
+}
+
+// Source of user credentials and bearer tokens, selected with AUTH_PROVIDER
+pub trait AuthProvider: Send + Sync {
+    // Name accepted by AUTH_PROVIDER
+    fn name(&self) -> &'static str;
+
+    // Attach bearer token validation, results are stored as a KeycloakAuthStatus extension
+    fn authenticate(&self, router: ApiRouter) -> ApiRouter;
+
+    // Exchange a username and password for tokens
+    fn login<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, Result<LoginResponse, LoginError>>;
+
+    // Exchange client credentials for a service token
+    fn client_token<'a>(&'a self, client_id: &'a str, client_secret: &'a str, scope: Option<&'a str>) -> BoxFuture<'a, Result<ClientTokenResponse, LoginError>>;
+
+    // Create an account holding the user role, None when the username or email is taken
+    fn create_account<'a>(&'a self, username: &'a str, email: Option<&'a str>, password: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Uuid>>>;
+
+    // Remove an account created through create_account
+    fn delete_account(&self, subject: Uuid) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+// Build the provider named by AUTH_PROVIDER
+pub async fn from_env(
+    env: &EnvironmentVariables,
+    pgpool: &Pool<Postgres>,
+    client: &Client,
+    kc_admin: &Arc<KeycloakAdmin>,
+) -> anyhow::Result<Arc<dyn AuthProvider>> {
+    match env.auth_provider.as_ref() {
+        "keycloak" => Ok(Arc::new(keycloak::KeycloakProvider::from_env(env, client.clone(), kc_admin.clone())?)),
+        "local" => {
+            let provider = local::LocalProvider::new(env, pgpool.clone())?;
+            provider.bootstrap_admin(env).await?;
+            Ok(Arc::new(provider))
+        },
+        other => bail!("Unknown AUTH_PROVIDER {other}, expected keycloak or local"),
+    }
+}
@@ -15,6 +15,81 @@ pub struct EnvironmentVariables {
     pub kc_client_secret: Cow<'static, str>,
     pub kc_server_addr: Cow<'static, str>,
     pub kc_login_path: Cow<'static, str>,
+    pub kc_realm: Cow<'static, str>,
+    pub tenant_sources: Cow<'static, str>,
+    pub tenant_claim: Cow<'static, str>,
+    pub tenant_header: Cow<'static, str>,
+    pub tenant_base_domain: Cow<'static, str>,
+    pub tenant_rls: Cow<'static, str>,
+    pub kc_group_sync: Cow<'static, str>,
+    pub kc_user_sync: Cow<'static, str>,
+    pub kc_reconcile_interval_secs: Cow<'static, str>,
+    pub kc_reconcile_fix: Cow<'static, str>,
+    pub auth_provider: Cow<'static, str>,
+    pub local_token_ttl_secs: Cow<'static, str>,
+    pub local_admin_username: Cow<'static, str>,
+    pub local_admin_password: Cow<'static, str>,
+    pub trust_proxy_headers: Cow<'static, str>,
+    pub trusted_proxy_hops: Cow<'static, str>,
+    pub login_ip_max_failures: Cow<'static, str>,
+    pub login_user_max_failures: Cow<'static, str>,
+    pub login_window_secs: Cow<'static, str>,
+    pub login_lockout_secs: Cow<'static, str>,
+    pub login_max_lockout_secs: Cow<'static, str>,
+    pub rate_limit_backend: Cow<'static, str>,
+    pub rate_limit_public: Cow<'static, str>,
+    pub rate_limit_private: Cow<'static, str>,
+    pub rate_limit_identity: Cow<'static, str>,
+    pub idempotency_ttl_secs: Cow<'static, str>,
+    pub idempotency_lock_secs: Cow<'static, str>,
+    pub idempotency_max_body_bytes: Cow<'static, str>,
+    pub tls_cert_path: Cow<'static, str>,
+    pub tls_key_path: Cow<'static, str>,
+    pub tls_client_ca_path: Cow<'static, str>,
+    pub tls_client_auth: Cow<'static, str>,
+    pub tls_reload_interval_secs: Cow<'static, str>,
+    pub http_request_timeout_secs: Cow<'static, str>,
+    pub http_body_limit_bytes: Cow<'static, str>,
+    pub http_import_timeout_secs: Cow<'static, str>,
+    pub http_import_body_limit_bytes: Cow<'static, str>,
+    pub http_cors_origins: Cow<'static, str>,
+    pub http_compression: Cow<'static, str>,
+    pub http_security_headers: Cow<'static, str>,
+    pub http_hsts_max_age_secs: Cow<'static, str>,
+    pub http_catch_panic: Cow<'static, str>,
+    pub admin_bind_addr: Cow<'static, str>,
+    pub listen_addrs: Cow<'static, str>,
+    pub unix_socket_mode: Cow<'static, str>,
+    pub metrics_sets: Cow<'static, str>,
+    pub metrics_basic_auth: Cow<'static, str>,
+    pub metrics_bearer_token: Cow<'static, str>,
+    pub metrics_allowed_ips: Cow<'static, str>,
+    pub metrics_push_url: Cow<'static, str>,
+    pub metrics_push_interval_secs: Cow<'static, str>,
+    pub database_replica_hosts: Cow<'static, str>,
+    pub database_replica_sticky_secs: Cow<'static, str>,
+    pub database_replica_max_lag_secs: Cow<'static, str>,
+    pub database_replica_check_interval_secs: Cow<'static, str>,
+    pub database_connect_deadline_secs: Cow<'static, str>,
+    pub database_min_connections: Cow<'static, str>,
+    pub database_acquire_timeout_secs: Cow<'static, str>,
+    pub database_idle_timeout_secs: Cow<'static, str>,
+    pub database_max_lifetime_secs: Cow<'static, str>,
+    pub database_statement_timeout_ms: Cow<'static, str>,
+    pub database_application_name: Cow<'static, str>,
+    pub outbox_sinks: Cow<'static, str>,
+    pub outbox_webhook_url: Cow<'static, str>,
+    pub outbox_webhook_secret: Cow<'static, str>,
+    pub outbox_nats_url: Cow<'static, str>,
+    pub outbox_nats_subject: Cow<'static, str>,
+    pub outbox_kafka_rest_url: Cow<'static, str>,
+    pub outbox_kafka_topic: Cow<'static, str>,
+    pub outbox_poll_interval_ms: Cow<'static, str>,
+    pub outbox_batch_size: Cow<'static, str>,
+    pub outbox_lease_secs: Cow<'static, str>,
+    pub outbox_max_attempts: Cow<'static, str>,
+    pub outbox_retry_delay_secs: Cow<'static, str>,
+    pub outbox_retention_hours: Cow<'static, str>,
 
 }
 
@@ -27,8 +102,83 @@ make_config!(EnvironmentVariables {
     secret: Cow<'static, str>,
     hostname: Cow<'static, str>,
     max_pool_connections: Cow<'static, str>,
-    kc_client_id: Cow<'static, str>,
-    kc_client_secret: Cow<'static, str>,
-    kc_server_addr: Cow<'static, str>,
-    kc_login_path: Cow<'static, str>,
+    kc_client_id: Cow<'static, str> = "",
+    kc_client_secret: Cow<'static, str> = "",
+    kc_server_addr: Cow<'static, str> = "",
+    kc_login_path: Cow<'static, str> = "",
+    kc_realm: Cow<'static, str> = "api-template",
+    tenant_sources: Cow<'static, str> = "header,subdomain",
+    tenant_claim: Cow<'static, str> = "organization",
+    tenant_header: Cow<'static, str> = "x-tenant-id",
+    tenant_base_domain: Cow<'static, str> = "",
+    tenant_rls: Cow<'static, str> = "true",
+    kc_group_sync: Cow<'static, str> = "false",
+    kc_user_sync: Cow<'static, str> = "false",
+    kc_reconcile_interval_secs: Cow<'static, str> = "0",
+    kc_reconcile_fix: Cow<'static, str> = "false",
+    auth_provider: Cow<'static, str> = "keycloak",
+    local_token_ttl_secs: Cow<'static, str> = "300",
+    local_admin_username: Cow<'static, str> = "",
+    local_admin_password: Cow<'static, str> = "",
+    trust_proxy_headers: Cow<'static, str> = "false",
+    trusted_proxy_hops: Cow<'static, str> = "1",
+    login_ip_max_failures: Cow<'static, str> = "20",
+    login_user_max_failures: Cow<'static, str> = "5",
+    login_window_secs: Cow<'static, str> = "900",
+    login_lockout_secs: Cow<'static, str> = "30",
+    login_max_lockout_secs: Cow<'static, str> = "3600",
+    rate_limit_backend: Cow<'static, str> = "memory",
+    rate_limit_public: Cow<'static, str> = "60/60",
+    rate_limit_private: Cow<'static, str> = "300/60",
+    rate_limit_identity: Cow<'static, str> = "off",
+    idempotency_ttl_secs: Cow<'static, str> = "86400",
+    idempotency_lock_secs: Cow<'static, str> = "60",
+    idempotency_max_body_bytes: Cow<'static, str> = "1048576",
+    tls_cert_path: Cow<'static, str> = "",
+    tls_key_path: Cow<'static, str> = "",
+    tls_client_ca_path: Cow<'static, str> = "",
+    tls_client_auth: Cow<'static, str> = "required",
+    tls_reload_interval_secs: Cow<'static, str> = "30",
+    http_request_timeout_secs: Cow<'static, str> = "30",
+    http_body_limit_bytes: Cow<'static, str> = "2097152",
+    http_import_timeout_secs: Cow<'static, str> = "900",
+    http_import_body_limit_bytes: Cow<'static, str> = "1073741824",
+    http_cors_origins: Cow<'static, str> = "",
+    http_compression: Cow<'static, str> = "true",
+    http_security_headers: Cow<'static, str> = "true",
+    http_hsts_max_age_secs: Cow<'static, str> = "31536000",
+    http_catch_panic: Cow<'static, str> = "true",
+    admin_bind_addr: Cow<'static, str> = "",
+    listen_addrs: Cow<'static, str> = "",
+    unix_socket_mode: Cow<'static, str> = "660",
+    metrics_sets: Cow<'static, str> = "http,pool,database,keycloak,domain",
+    metrics_basic_auth: Cow<'static, str> = "",
+    metrics_bearer_token: Cow<'static, str> = "",
+    metrics_allowed_ips: Cow<'static, str> = "",
+    metrics_push_url: Cow<'static, str> = "",
+    metrics_push_interval_secs: Cow<'static, str> = "15",
+    database_replica_hosts: Cow<'static, str> = "",
+    database_replica_sticky_secs: Cow<'static, str> = "5",
+    database_replica_max_lag_secs: Cow<'static, str> = "10",
+    database_replica_check_interval_secs: Cow<'static, str> = "5",
+    database_connect_deadline_secs: Cow<'static, str> = "60",
+    database_min_connections: Cow<'static, str> = "0",
+    database_acquire_timeout_secs: Cow<'static, str> = "30",
+    database_idle_timeout_secs: Cow<'static, str> = "600",
+    database_max_lifetime_secs: Cow<'static, str> = "1800",
+    database_statement_timeout_ms: Cow<'static, str> = "0",
+    database_application_name: Cow<'static, str> = "api-server-template",
+    outbox_sinks: Cow<'static, str> = "log",
+    outbox_webhook_url: Cow<'static, str> = "",
+    outbox_webhook_secret: Cow<'static, str> = "",
+    outbox_nats_url: Cow<'static, str> = "",
+    outbox_nats_subject: Cow<'static, str> = "events.users",
+    outbox_kafka_rest_url: Cow<'static, str> = "",
+    outbox_kafka_topic: Cow<'static, str> = "user-events",
+    outbox_poll_interval_ms: Cow<'static, str> = "1000",
+    outbox_batch_size: Cow<'static, str> = "100",
+    outbox_lease_secs: Cow<'static, str> = "300",
+    outbox_max_attempts: Cow<'static, str> = "10",
+    outbox_retry_delay_secs: Cow<'static, str> = "5",
+    outbox_retention_hours: Cow<'static, str> = "168",
 });
\ No newline at end of file
@@ -1,11 +1,12 @@
 mod environment;
 
-use crate::cli_divider;
-use anyhow::bail;
-use environment::EnvironmentVariables;
+use crate::{auth::{self, AuthProvider}, cli_divider, database::{connection::DatabaseOptions, replicas::ReplicaSet}, definitions::{attribute::UserSchemaCache, idempotency::IdempotencyOptions}, custom::{login_guard::LoginGuard, rate_limiter::RateLimiter, telemetry::MetricsOptions}, database, keycloak::KeycloakAdmin, middleware::hardening::HttpOptions, outbox::Outbox};
+use std::sync::Arc;
+
+pub use environment::EnvironmentVariables;
 use reqwest::Client;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
-use tracing::{info, span, Level};
+use sqlx::{Pool, Postgres};
+use tracing::{span, Level};
 
 #[derive(Clone)]
 pub struct ConfigState {
@@ -13,7 +14,16 @@ pub struct ConfigState {
     pub appname: String,
     pub version: String,
     pub pgpool: Pool<Postgres>,
-    pub client: Client,
+    pub replicas: Arc<ReplicaSet>,
+    pub auth: Arc<dyn AuthProvider>,
+    pub login_guard: Arc<LoginGuard>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub idempotency: IdempotencyOptions,
+    pub http: HttpOptions,
+    pub metrics: MetricsOptions,
+    pub outbox: Arc<Outbox>,
+    pub user_schema: Arc<UserSchemaCache>,
+    pub kc_admin: Arc<KeycloakAdmin>,
 }
 
 impl ConfigState {
@@ -26,36 +36,52 @@ impl ConfigState {
         let appname: String = "API Server Template".to_string();
         let version: String = "0.1".to_string();
         
-        // Database Connections
-        let database_fqdn: String = format!("{}:{}", &env.database_host, &env.database_port);
-        let connection_url: String = format!("postgresql://{}@{}/{}", &env.database_creds, database_fqdn, &env.database_name);
-
-        println!("Attempting to connect to PgPool @ {database_fqdn}");
-        info!("Attempting to connect to PgPool @ {database_fqdn}");
-        let pgpool: Pool<Postgres> = match PgPoolOptions::new().max_connections(env.max_pool_connections.parse()?).connect(&connection_url).await {
-            Ok(pool) => {
-                println!("Connected to DB: {database_fqdn}");
-                info!("Connected to DB: {database_fqdn}");
-                pool
-            },
-            Err(err) => {
-                info!("Failed To Connect To DB: {err}");
-                bail!("Failed To Connect To DB: {err}");
-            },
-        };
+        // Database Connections, retried with backoff until DATABASE_CONNECT_DEADLINE_SECS
+        let database = DatabaseOptions::from_env(&env)?;
+        let pgpool: Pool<Postgres> = database.connect().await?;
+
+        // Reads are spread over the replicas once they pass their first health check
+        let replicas = Arc::new(ReplicaSet::from_env(&env, &database, &pgpool).await?);
+        if replicas.replica_count() > 0 {
+            println!("Read replicas: {} of {} healthy", replicas.healthy_count(), replicas.replica_count());
+        }
+
+        // Refuse to start when the schema and TENANT_RLS disagree on enforcing tenant row-level security
+        database::verify_tenant_rls(&pgpool, env.tenant_rls == "true").await?;
 
         let client: Client = Client::new();
+        let kc_admin = Arc::new(KeycloakAdmin::new(client.clone(), &env));
+
+        // Keycloak or the local provider, selected with AUTH_PROVIDER
+        let auth = auth::from_env(&env, &pgpool, &client, &kc_admin).await?;
+        println!("Authentication provider: {}", auth.name());
+        let login_guard = Arc::new(LoginGuard::from_env(&env)?);
+        let rate_limiter = Arc::new(RateLimiter::from_env(&env, &pgpool)?);
+        println!("Rate limit backend: {}", rate_limiter.backend_name());
+        let idempotency = IdempotencyOptions::from_env(&env)?;
+        let http = HttpOptions::from_env(&env)?;
+        let metrics = MetricsOptions::from_env(&env)?;
+        let outbox = Arc::new(Outbox::from_env(&env, &client)?);
+        println!("Outbox sinks: {}", outbox.sink_names().join(", "));
 
         cli_divider!();
-        println!("Started {}", format!("{}:{} on port {}", appname.as_str(), version.as_str(), env.port));
-        println!("Secret: {}", env.secret);
+        println!("Started {}:{}", appname.as_str(), version.as_str());
 
         Ok(Self {
             env,
             appname,
             version,
             pgpool,
-            client,
+            replicas,
+            auth,
+            login_guard,
+            rate_limiter,
+            idempotency,
+            http,
+            metrics,
+            outbox,
+            user_schema: Arc::default(),
+            kc_admin,
         })
     }
 }
\ No newline at end of file
@@ -0,0 +1,153 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, Extensions, HeaderMap, StatusCode},
+    Json,
+};
+use axum_keycloak_auth::decode::RawClaims;
+use serde_json::{json, Value};
+
+use crate::{
+    config::ConfigState,
+    database::organizations::find_organization,
+    definitions::{api_key::ApiKeyIdentity, organization::{TenantSource, DEFAULT_ORGANIZATION_ID}},
+};
+use uuid::Uuid;
+
+// Organization the current request operates on. Credentials decide it: API keys belong to the organization
+// they were created in, tokens to the one named by TENANT_CLAIM and tokens without the claim to the default
+// organization. The TENANT_SOURCES inputs of the request are only checked against it
+#[derive(Debug, Clone, Copy)]
+pub struct Tenant(pub Uuid);
+
+impl Tenant {
+    pub fn org_id(&self) -> Uuid {
+        self.0
+    }
+
+    // Whether the caller belongs to the default organization, the only one allowed to manage shared state
+    pub fn is_default(&self) -> bool {
+        self.0 == DEFAULT_ORGANIZATION_ID
+    }
+}
+
+impl aide::OperationInput for Tenant {}
+
+impl FromRequestParts<Arc<ConfigState>> for Tenant {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, config: &Arc<ConfigState>) -> Result<Self, Self::Rejection> {
+        let requested: Vec<String> = TenantSource::parse_list(&config.env.tenant_sources)
+            .iter()
+            .filter_map(|source| match source {
+                TenantSource::Header => parts
+                    .headers
+                    .get(config.env.tenant_header.as_ref())
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string),
+                TenantSource::Subdomain => subdomain(parts, &config.env.tenant_base_domain),
+            })
+            .collect();
+
+        let bound = match parts.extensions.get::<ApiKeyIdentity>() {
+            Some(identity) => identity.org_id.to_string(),
+            None => {
+                let claim = parts
+                    .extensions
+                    .get::<RawClaims>()
+                    .and_then(|claims| claims.get(config.env.tenant_claim.as_ref()))
+                    .and_then(Value::as_str);
+                match claim {
+                    Some(claim) => claim.to_string(),
+                    // Nothing to check, the default organization needs no lookup
+                    None if requested.is_empty() => return Ok(Tenant(DEFAULT_ORGANIZATION_ID)),
+                    None => DEFAULT_ORGANIZATION_ID.to_string(),
+                }
+            },
+        };
+
+        let organization = match find_organization(&bound, &config.pgpool).await {
+            Ok(Some(organization)) => organization,
+            Ok(None) => {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Json(json!({ "error": "Token does not belong to a known organization" })),
+                ));
+            }
+            Err(err) => {
+                eprintln!("Internal Server Error: {err}");
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({ "error": "Internal server error" })),
+                ));
+            }
+        };
+
+        // A header or subdomain naming another organization is refused rather than followed
+        if requested.iter().any(|key| *key != organization.slug && *key != organization.org_id.to_string()) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(json!({ "error": "Token does not belong to this organization" })),
+            ));
+        }
+
+        Ok(Tenant(organization.org_id))
+    }
+}
+
+// Leftmost label of the Host header when it is a direct subdomain of the base domain
+fn subdomain(parts: &Parts, base_domain: &str) -> Option<String> {
+    if base_domain.is_empty() {
+        return None;
+    }
+
+    let host = parts.headers.get(header::HOST)?.to_str().ok()?;
+    let host = host.split(':').next()?;
+    let label = host.strip_suffix(base_domain)?.strip_suffix('.')?;
+
+    match label.is_empty() || label.contains('.') {
+        true => None,
+        false => Some(label.to_string()),
+    }
+}
+
+// Address of the caller, taken from X-Forwarded-For only when TRUST_PROXY_HEADERS is enabled
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+impl aide::OperationInput for ClientIp {}
+
+impl FromRequestParts<Arc<ConfigState>> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, config: &Arc<ConfigState>) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(client_ip(&parts.headers, &parts.extensions, config.http.proxy_hops)))
+    }
+}
+
+// Forwarded address appended by the outermost trusted proxy, otherwise the peer address of the connection.
+// Unknown callers, e.g. of Unix sockets without trusted proxy headers, share the unspecified address
+pub(crate) fn client_ip(headers: &HeaderMap, extensions: &Extensions, proxy_hops: Option<usize>) -> IpAddr {
+    client_addr(headers, extensions, proxy_hops).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+// Same as `client_ip`, None when the listener gives no peer address and no trusted proxy named the client.
+// Each proxy appends the address it received the request from, so entries left of that one are client supplied
+pub(crate) fn client_addr(headers: &HeaderMap, extensions: &Extensions, proxy_hops: Option<usize>) -> Option<IpAddr> {
+    let forwarded = proxy_hops.and_then(|hops| {
+        let entries: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        entries.len().checked_sub(hops).and_then(|index| entries[index].trim().parse().ok())
+    });
+
+    forwarded.or_else(|| extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip()))
+}
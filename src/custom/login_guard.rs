@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::config::EnvironmentVariables;
+
+// Tracked keys are pruned once the table grows beyond this size
+const PRUNE_THRESHOLD: usize = 10_000;
+
+// Suggested wait when the limit is taken up by attempts still in flight
+const IN_FLIGHT_RETRY: Duration = Duration::from_secs(1);
+
+// Which limit a login attempt ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoginScope {
+    Ip,
+    Username,
+}
+
+impl LoginScope {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LoginScope::Ip => "ip",
+            LoginScope::Username => "username",
+        }
+    }
+}
+
+// Failed attempts of one IP or username inside the sliding window
+#[derive(Default)]
+struct Attempts {
+    failures: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+    // Consecutive lockouts, each one doubles the next lockout
+    lockouts: u32,
+    // Reserved attempts still waiting for the identity provider
+    in_flight: usize,
+}
+
+// Sliding-window failure counting with exponentially growing lockouts for /login
+pub struct LoginGuard {
+    entries: Mutex<HashMap<(LoginScope, String), Attempts>>,
+    ip_max_failures: usize,
+    user_max_failures: usize,
+    window: Duration,
+    lockout: Duration,
+    max_lockout: Duration,
+}
+
+impl LoginGuard {
+    pub fn from_env(env: &EnvironmentVariables) -> anyhow::Result<Self> {
+        let seconds = |value: &str, name: &str| -> anyhow::Result<Duration> {
+            Ok(Duration::from_secs(value.parse().with_context(|| format!("{name} must be a number of seconds"))?))
+        };
+        Ok(Self {
+            entries: Mutex::new(HashMap::new()),
+            ip_max_failures: env.login_ip_max_failures.parse().context("LOGIN_IP_MAX_FAILURES must be a number")?,
+            user_max_failures: env.login_user_max_failures.parse().context("LOGIN_USER_MAX_FAILURES must be a number")?,
+            window: seconds(&env.login_window_secs, "LOGIN_WINDOW_SECS")?,
+            lockout: seconds(&env.login_lockout_secs, "LOGIN_LOCKOUT_SECS")?,
+            max_lockout: seconds(&env.login_max_lockout_secs, "LOGIN_MAX_LOCKOUT_SECS")?,
+        })
+    }
+
+    // Reserve a login attempt before asking the provider, refused while the IP or the username is locked out or
+    // while the attempts already in flight could exhaust the remaining failures
+    pub fn reserve(&self, ip: &str, username: &str) -> Result<LoginAttempt<'_>, (LoginScope, Duration)> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() > PRUNE_THRESHOLD {
+            entries.retain(|_, attempts| !self.expired(attempts, now));
+        }
+
+        let keys = [(LoginScope::Ip, normalize(ip), self.ip_max_failures), (LoginScope::Username, normalize(username), self.user_max_failures)];
+        let refusal = keys
+            .iter()
+            .filter_map(|(scope, key, limit)| {
+                let attempts = entries.get_mut(&(*scope, key.clone()))?;
+                self.prune(attempts, now);
+                match attempts.locked_until.filter(|locked_until| *locked_until > now) {
+                    Some(locked_until) => Some((*scope, locked_until - now)),
+                    None => (attempts.failures.len() + attempts.in_flight >= *limit).then_some((*scope, IN_FLIGHT_RETRY)),
+                }
+            })
+            .max_by_key(|(_, remaining)| *remaining);
+        if let Some(refusal) = refusal {
+            return Err(refusal);
+        }
+
+        for (scope, key, _) in &keys {
+            entries.entry((*scope, key.clone())).or_default().in_flight += 1;
+        }
+        Ok(LoginAttempt { guard: self, ip: keys[0].1.clone(), username: keys[1].1.clone() })
+    }
+
+    // Drop expired state and failures that left the window
+    fn prune(&self, attempts: &mut Attempts, now: Instant) {
+        if self.expired(attempts, now) {
+            *attempts = Attempts { in_flight: attempts.in_flight, ..Attempts::default() };
+        }
+        while attempts.failures.front().is_some_and(|failure| now.duration_since(*failure) > self.window) {
+            attempts.failures.pop_front();
+        }
+    }
+
+    // No lockout pending and no failure inside the window, so the backoff starts over
+    fn expired(&self, attempts: &Attempts, now: Instant) -> bool {
+        let locked = attempts.locked_until.is_some_and(|locked_until| locked_until > now);
+        let recent = attempts.failures.back().is_some_and(|failure| now.duration_since(*failure) <= self.window);
+        let recently_locked = attempts.locked_until.is_some_and(|locked_until| now.duration_since(locked_until) <= self.window);
+        attempts.in_flight == 0 && !locked && !recent && !recently_locked
+    }
+}
+
+// A reserved login attempt, counted against the limits until it is resolved or dropped
+pub struct LoginAttempt<'a> {
+    guard: &'a LoginGuard,
+    ip: String,
+    username: String,
+}
+
+impl LoginAttempt<'_> {
+    // Count the attempt as failed, returns the lockouts it started
+    pub fn failed(self) -> Vec<(LoginScope, Duration)> {
+        let guard = self.guard;
+        let now = Instant::now();
+        let mut entries = guard.entries.lock().unwrap();
+
+        let mut started = Vec::new();
+        for (scope, key, limit) in [(LoginScope::Ip, &self.ip, guard.ip_max_failures), (LoginScope::Username, &self.username, guard.user_max_failures)] {
+            let attempts = entries.entry((scope, key.clone())).or_default();
+            guard.prune(attempts, now);
+            attempts.failures.push_back(now);
+
+            if attempts.failures.len() >= limit {
+                let lockout = guard.lockout
+                    .saturating_mul(2u32.saturating_pow(attempts.lockouts))
+                    .min(guard.max_lockout);
+                attempts.locked_until = Some(now + lockout);
+                attempts.lockouts = attempts.lockouts.saturating_add(1);
+                attempts.failures.clear();
+                started.push((scope, lockout));
+            }
+        }
+        started
+    }
+
+    // A successful login clears the history of the username, the IP keeps its count
+    pub fn succeeded(self) {
+        if let Some(attempts) = self.guard.entries.lock().unwrap().get_mut(&(LoginScope::Username, self.username.clone())) {
+            *attempts = Attempts { in_flight: attempts.in_flight, ..Attempts::default() };
+        }
+    }
+}
+
+// Resolved or not, the attempt no longer counts as in flight
+impl Drop for LoginAttempt<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.guard.entries.lock().unwrap();
+        for key in [(LoginScope::Ip, std::mem::take(&mut self.ip)), (LoginScope::Username, std::mem::take(&mut self.username))] {
+            if let Some(attempts) = entries.get_mut(&key) {
+                attempts.in_flight = attempts.in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+// Usernames are matched case-insensitively like the identity providers do
+fn normalize(key: &str) -> String {
+    key.to_lowercase()
+}
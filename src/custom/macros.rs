@@ -1,21 +1,32 @@
 #[macro_export]
 macro_rules! make_config {
-    ($struct_name:ident { $( $field_name:ident : $field_type:ty ),* $(,)? }) => {
+    ($struct_name:ident { $( $field_name:ident : $field_type:ty $(= $default:expr)? ),* $(,)? }) => {
         impl $struct_name {
             pub fn from_env() -> anyhow::Result<Self> {
                 dotenv::from_filename("AXUM.env").ok();
 
+                Self::from_lookup(|key| dotenv::var(key).ok())
+            }
+
+            // Build the config from an arbitrary key lookup, fields with a default are optional
+            pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
                 Ok(Self {
                     $(
-                        $field_name: match dotenv::var(stringify!($field_name).to_uppercase()) {
-                            Ok(value) => value.into(),
-                            Err(err) => anyhow::bail!("Missing {}: {}", stringify!($field_name), err),
+                        $field_name: match lookup(&stringify!($field_name).to_uppercase()) {
+                            Some(value) => value.into(),
+                            None => $crate::make_config!(@default $field_name $(, $default)?),
                         }
                     ),*
                 })
             }
         }
     };
+    (@default $field_name:ident) => {
+        anyhow::bail!("Missing {}", stringify!($field_name).to_uppercase())
+    };
+    (@default $field_name:ident, $default:expr) => {
+        $default.into()
+    };
 }
 
 #[macro_export]
@@ -38,4 +49,29 @@ macro_rules! expect_admin {
             )
         }
     };
-}
\ No newline at end of file
+    // Variant for handlers that respond with a plain `Response`
+    ($token: expr, into_response) => {
+        if let Err(_) = axum_keycloak_auth::role::ExpectRoles::expect_roles($token, &[String::from("administrator")]) {
+            return axum::response::IntoResponse::into_response((
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "error": "insufficient privileges",
+                })),
+            ))
+        }
+    };
+}
+// Shared state such as organizations and attribute definitions is only managed from the default organization
+#[macro_export]
+macro_rules! expect_default_tenant {
+    ($tenant: expr) => {
+        if !$tenant.is_default() {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "error": "insufficient privileges",
+                })),
+            )
+        }
+    };
+}
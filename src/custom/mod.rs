@@ -1,3 +1,7 @@
 pub mod extractors;
+pub mod login_guard;
+pub mod rate_limiter;
+pub mod signing;
+pub mod telemetry;
 pub mod validators;
-pub mod macros;
\ No newline at end of file
+pub mod macros;
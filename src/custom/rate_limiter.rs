@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use futures::future::BoxFuture;
+use sqlx::{Pool, Postgres};
+
+use crate::{config::EnvironmentVariables, database::rate_limits::{prune_buckets, take_token}};
+
+// Tracked buckets are pruned once the table grows beyond this size
+const PRUNE_THRESHOLD: usize = 10_000;
+
+// The shared backend deletes idle buckets once every this many requests
+const PRUNE_INTERVAL: u64 = 1_000;
+
+// Route groups with their own limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Public,
+    Private,
+    // Inner limit of the private routes per API key or token subject
+    Identity,
+}
+
+impl RouteGroup {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RouteGroup::Public => "public",
+            RouteGroup::Private => "private",
+            RouteGroup::Identity => "identity",
+        }
+    }
+}
+
+// Bucket of `capacity` requests refilled evenly over `period`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitPolicy {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimitPolicy {
+    // "<requests>/<seconds>", empty or "off" disables the limit
+    pub fn parse(value: &str, name: &str) -> anyhow::Result<Option<Self>> {
+        if value.is_empty() || value == "off" {
+            return Ok(None);
+        }
+        let (capacity, period) = value.split_once('/').with_context(|| format!("{name} must look like <requests>/<seconds>"))?;
+        let capacity: u32 = capacity.trim().parse().with_context(|| format!("{name} requests must be a number"))?;
+        let period: u64 = period.trim().parse().with_context(|| format!("{name} seconds must be a number"))?;
+        if capacity == 0 || period == 0 {
+            bail!("{name} requests and seconds must be greater than zero");
+        }
+        Ok(Some(Self { capacity, period: Duration::from_secs(period) }))
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        f64::from(self.capacity) / self.period.as_secs_f64()
+    }
+}
+
+// Outcome of taking a token, used for the RateLimit-* response headers
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub policy: RateLimitPolicy,
+    pub remaining: u32,
+    // Until the bucket is full again, or until the next token when the request was denied
+    pub reset: Duration,
+}
+
+impl RateLimitDecision {
+    fn new(policy: RateLimitPolicy, allowed: bool, tokens: f64) -> Self {
+        let missing = if allowed { f64::from(policy.capacity) - tokens } else { 1.0 - tokens };
+        Self {
+            allowed,
+            policy,
+            remaining: tokens.max(0.0).floor() as u32,
+            reset: Duration::from_secs_f64(missing.max(0.0) / policy.refill_per_sec()),
+        }
+    }
+}
+
+// Storage of token buckets, returns whether a token was taken and how many are left
+pub trait RateLimitBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn take<'a>(&'a self, key: &'a str, policy: RateLimitPolicy) -> BoxFuture<'a, anyhow::Result<(bool, f64)>>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // A full bucket behaves like a missing one and can be pruned
+    full_at: Instant,
+}
+
+// Buckets kept in process memory, limits apply per replica
+#[derive(Default)]
+pub struct MemoryBackend {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimitBackend for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn take<'a>(&'a self, key: &'a str, policy: RateLimitPolicy) -> BoxFuture<'a, anyhow::Result<(bool, f64)>> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let capacity = f64::from(policy.capacity);
+            let rate = policy.refill_per_sec();
+            let mut buckets = self.buckets.lock().unwrap();
+
+            if buckets.len() > PRUNE_THRESHOLD {
+                buckets.retain(|_, bucket| bucket.full_at > now);
+            }
+
+            let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated: now, full_at: now });
+            let tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(capacity);
+            let allowed = tokens >= 1.0;
+            bucket.tokens = if allowed { tokens - 1.0 } else { tokens };
+            bucket.updated = now;
+            bucket.full_at = now + Duration::from_secs_f64((capacity - bucket.tokens) / rate);
+            Ok((allowed, bucket.tokens))
+        })
+    }
+}
+
+// Buckets shared by every replica through postgres
+pub struct PostgresBackend {
+    pgpool: Pool<Postgres>,
+    // Buckets idle for longer than this are full and get deleted
+    retention: Duration,
+    calls: AtomicU64,
+}
+
+impl RateLimitBackend for PostgresBackend {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn take<'a>(&'a self, key: &'a str, policy: RateLimitPolicy) -> BoxFuture<'a, anyhow::Result<(bool, f64)>> {
+        Box::pin(async move {
+            if self.calls.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL == PRUNE_INTERVAL - 1 {
+                if let Err(err) = prune_buckets(self.retention, &self.pgpool).await {
+                    eprintln!("Failed to prune rate limit buckets: {err}");
+                }
+            }
+            Ok(take_token(key, f64::from(policy.capacity), policy.refill_per_sec(), &self.pgpool).await?)
+        })
+    }
+}
+
+// Token-bucket limits per route group, keyed by the caller
+pub struct RateLimiter {
+    backend: Box<dyn RateLimitBackend>,
+    public: Option<RateLimitPolicy>,
+    private: Option<RateLimitPolicy>,
+    identity: Option<RateLimitPolicy>,
+}
+
+impl RateLimiter {
+    pub fn from_env(env: &EnvironmentVariables, pgpool: &Pool<Postgres>) -> anyhow::Result<Self> {
+        let public = RateLimitPolicy::parse(&env.rate_limit_public, "RATE_LIMIT_PUBLIC")?;
+        let private = RateLimitPolicy::parse(&env.rate_limit_private, "RATE_LIMIT_PRIVATE")?;
+        let identity = RateLimitPolicy::parse(&env.rate_limit_identity, "RATE_LIMIT_IDENTITY")?;
+
+        let backend: Box<dyn RateLimitBackend> = match env.rate_limit_backend.as_ref() {
+            "memory" => Box::new(MemoryBackend::default()),
+            "postgres" => Box::new(PostgresBackend {
+                pgpool: pgpool.clone(),
+                retention: public.iter().chain(private.iter()).chain(identity.iter()).map(|policy| policy.period).max().unwrap_or_default(),
+                calls: AtomicU64::new(0),
+            }),
+            other => bail!("Unknown RATE_LIMIT_BACKEND {other}, expected memory or postgres"),
+        };
+
+        Ok(Self { backend, public, private, identity })
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    pub fn policy(&self, group: RouteGroup) -> Option<RateLimitPolicy> {
+        match group {
+            RouteGroup::Public => self.public,
+            RouteGroup::Private => self.private,
+            RouteGroup::Identity => self.identity,
+        }
+    }
+
+    // Take a token from the caller's bucket of the group, None when the group is unlimited
+    pub async fn check(&self, group: RouteGroup, key: &str) -> anyhow::Result<Option<RateLimitDecision>> {
+        let Some(policy) = self.policy(group) else {
+            return Ok(None);
+        };
+        let (allowed, tokens) = self.backend.take(&format!("{}:{key}", group.label()), policy).await?;
+        Ok(Some(RateLimitDecision::new(policy, allowed, tokens)))
+    }
+}
@@ -0,0 +1,47 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Purpose of a key derived from SECRET, every use gets its own key so one can never stand in for another
+pub const API_KEY_DIGESTS: &str = "api-key-digests";
+pub const INVITATION_TOKENS: &str = "invitation-tokens";
+pub const LOCAL_JWT: &str = "local-jwt";
+pub const METRICS_CREDENTIALS: &str = "metrics-credentials";
+
+// Key for one purpose derived from the configured secret as HMAC(secret, purpose)
+pub fn derive_key(secret: &str, purpose: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(secret, purpose.as_bytes()))
+}
+
+// Sign a payload with the configured secret, tokens have the form `payload.signature` in unpadded base64url
+pub fn sign(secret: &str, payload: &[u8]) -> String {
+    let signature = digest(secret, payload);
+    format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), URL_SAFE_NO_PAD.encode(signature))
+}
+
+// Verify a token produced by `sign` and return its payload, the signature is compared in constant time
+pub fn verify(secret: &str, token: &str) -> Option<Vec<u8>> {
+    let (payload, signature) = token.split_once('.')?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+    verify_digest(secret, &payload, &signature).then_some(payload)
+}
+
+// Keyed digest of a secret for storage at rest
+pub fn digest(secret: &str, value: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(value);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Check a value against a digest produced by `digest`, compared in constant time
+pub fn verify_digest(secret: &str, value: &[u8], expected: &[u8]) -> bool {
+    let Ok(mut mac) = HmacSha256::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(value);
+    mac.verify_slice(expected).is_ok()
+}
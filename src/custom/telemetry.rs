@@ -0,0 +1,387 @@
+use std::{
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use async_stream::stream;
+use axum_prometheus::{
+    metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle, PrometheusRecorder},
+    utils::SECONDS_DURATION_BUCKETS,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{Stream, StreamExt};
+use ipnet::IpNet;
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit};
+use reqwest::{header::CONTENT_TYPE, Client, Url};
+use sqlx::{Pool, Postgres};
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    auth::LoginError,
+    config::{ConfigState, EnvironmentVariables},
+    custom::signing::{derive_key, digest, verify_digest, METRICS_CREDENTIALS},
+};
+
+pub const DB_POOL_CONNECTIONS: &str = "db_pool_connections";
+pub const DB_POOL_SIZE: &str = "db_pool_size";
+pub const DB_POOL_MAX_CONNECTIONS: &str = "db_pool_max_connections";
+pub const DB_QUERY_DURATION_SECONDS: &str = "db_query_duration_seconds";
+pub const KEYCLOAK_REQUESTS_TOTAL: &str = "keycloak_requests_total";
+pub const KEYCLOAK_REQUEST_DURATION_SECONDS: &str = "keycloak_request_duration_seconds";
+pub const USERS_CREATED_TOTAL: &str = "users_created_total";
+pub const USERS_DELETED_TOTAL: &str = "users_deleted_total";
+pub const OUTBOX_EVENTS_TOTAL: &str = "outbox_events_total";
+pub const OUTBOX_SINK_DURATION_SECONDS: &str = "outbox_sink_duration_seconds";
+pub const LOGIN_FAILURES_TOTAL: &str = "login_failures_total";
+pub const LOGIN_THROTTLED_TOTAL: &str = "login_throttled_total";
+pub const LOGIN_LOCKOUTS_TOTAL: &str = "login_lockouts_total";
+pub const RATE_LIMITED_TOTAL: &str = "rate_limited_total";
+pub const IDEMPOTENT_REPLAYS_TOTAL: &str = "idempotent_replays_total";
+
+// Global prometheus recorder, it can only be installed once per process
+static METRIC_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+// Groups of metrics that can be switched off with METRICS_SETS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricSet {
+    Http,
+    Pool,
+    Database,
+    Keycloak,
+    Domain,
+}
+
+impl MetricSet {
+    const ALL: [MetricSet; 5] = [MetricSet::Http, MetricSet::Pool, MetricSet::Database, MetricSet::Keycloak, MetricSet::Domain];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MetricSet::Http => "http",
+            MetricSet::Pool => "pool",
+            MetricSet::Database => "database",
+            MetricSet::Keycloak => "keycloak",
+            MetricSet::Domain => "domain",
+        }
+    }
+
+    // Set a metric family belongs to, None for families this service does not own
+    fn of_family(family: &str) -> Option<Self> {
+        const PREFIXES: [(&str, MetricSet); 9] = [
+            ("axum_http_", MetricSet::Http),
+            ("db_pool_", MetricSet::Pool),
+            ("db_query_", MetricSet::Database),
+            ("keycloak_", MetricSet::Keycloak),
+            ("users_", MetricSet::Domain),
+            ("login_", MetricSet::Domain),
+            ("rate_limited_", MetricSet::Domain),
+            ("idempotent_", MetricSet::Domain),
+            ("outbox_", MetricSet::Domain),
+        ];
+        PREFIXES.iter().find(|(prefix, _)| family.starts_with(prefix)).map(|(_, set)| *set)
+    }
+}
+
+// Metric sets exported on /metrics, who may scrape them and where they are pushed
+#[derive(Debug, Clone)]
+pub struct MetricsOptions {
+    pub sets: Vec<MetricSet>,
+    // Keyed digests of the accepted Authorization header values, empty leaves the endpoint open
+    pub credentials: Vec<Vec<u8>>,
+    // Key of the credential digests, derived from SECRET
+    digest_key: String,
+    pub basic_auth: bool,
+    // Empty allows every client address
+    pub allowed_networks: Vec<IpNet>,
+    pub push: Option<PushOptions>,
+}
+
+// Pushgateway compatible endpoint receiving the metrics every `interval`
+#[derive(Debug, Clone)]
+pub struct PushOptions {
+    pub url: Url,
+    pub interval: Duration,
+}
+
+impl MetricsOptions {
+    pub fn from_env(env: &EnvironmentVariables) -> anyhow::Result<Self> {
+        let mut sets = Vec::new();
+        for name in env.metrics_sets.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match MetricSet::ALL.iter().find(|set| set.label() == name) {
+                Some(set) => sets.push(*set),
+                None => bail!("Unknown metric set {name} in METRICS_SETS, expected http, pool, database, keycloak or domain"),
+            }
+        }
+
+        let digest_key = derive_key(&env.secret, METRICS_CREDENTIALS);
+        let mut credentials = Vec::new();
+        let basic_auth = !env.metrics_basic_auth.is_empty();
+        if basic_auth {
+            if !env.metrics_basic_auth.contains(':') {
+                bail!("METRICS_BASIC_AUTH must look like <username>:<password>");
+            }
+            let value = format!("Basic {}", STANDARD.encode(env.metrics_basic_auth.as_bytes()));
+            credentials.push(digest(&digest_key, value.as_bytes()));
+        }
+        if !env.metrics_bearer_token.is_empty() {
+            credentials.push(digest(&digest_key, format!("Bearer {}", env.metrics_bearer_token).as_bytes()));
+        }
+
+        let allowed_networks = env.metrics_allowed_ips
+            .split(',')
+            .map(str::trim)
+            .filter(|network| !network.is_empty())
+            .map(|network| {
+                // Plain addresses are single host networks
+                network.parse::<IpNet>()
+                    .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                    .with_context(|| format!("METRICS_ALLOWED_IPS contains an invalid address or network: {network}"))
+            })
+            .collect::<anyhow::Result<Vec<IpNet>>>()?;
+
+        let push = match env.metrics_push_url.trim() {
+            "" => None,
+            url => {
+                let interval: u64 = env.metrics_push_interval_secs.parse().context("METRICS_PUSH_INTERVAL_SECS must be a number of seconds")?;
+                if interval == 0 {
+                    bail!("METRICS_PUSH_INTERVAL_SECS must be greater than zero");
+                }
+                Some(PushOptions {
+                    url: Url::parse(url).context("METRICS_PUSH_URL must be a URL")?,
+                    interval: Duration::from_secs(interval),
+                })
+            },
+        };
+
+        Ok(Self { sets, credentials, digest_key, basic_auth, allowed_networks, push })
+    }
+
+    pub fn enabled(&self, set: MetricSet) -> bool {
+        self.sets.contains(&set)
+    }
+
+    // Sample the pool gauges and render the enabled sets in the Prometheus text format
+    pub fn render(&self, pgpool: &Pool<Postgres>) -> String {
+        self.render_from(&prometheus_handle(), pgpool)
+    }
+
+    // Same as `render` for any recorder, tests record into their own
+    pub fn render_from(&self, handle: &PrometheusHandle, pgpool: &Pool<Postgres>) -> String {
+        if self.enabled(MetricSet::Pool) {
+            record_pool(pgpool);
+        }
+
+        let mut output = String::new();
+        for line in handle.render().lines() {
+            // `# HELP name ...` and `# TYPE name ...` lines name the family in their third word
+            let family = match line.strip_prefix("# ") {
+                Some(comment) => comment.split_whitespace().nth(1).unwrap_or_default(),
+                None => line.split(['{', ' ']).next().unwrap_or_default(),
+            };
+            if MetricSet::of_family(family).is_none_or(|set| self.enabled(set)) {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        output
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.allowed_networks.is_empty() || self.allowed_networks.iter().any(|network| network.contains(&ip))
+    }
+
+    // Compare the Authorization header against every configured credential in constant time
+    pub fn authorizes(&self, authorization: Option<&[u8]>) -> bool {
+        if self.credentials.is_empty() {
+            return true;
+        }
+        let Some(authorization) = authorization else {
+            return false;
+        };
+        self.credentials.iter().any(|expected| verify_digest(&self.digest_key, authorization, expected))
+    }
+}
+
+// Replace the metrics of the push group with the current values of the recorder
+pub async fn push_metrics(client: &Client, options: &MetricsOptions, handle: &PrometheusHandle, pgpool: &Pool<Postgres>) -> anyhow::Result<()> {
+    let Some(push) = &options.push else {
+        return Ok(());
+    };
+    // Credentials embedded in the URL are sent as basic auth
+    let response = client.put(push.url.clone())
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(options.render_from(handle, pgpool))
+        .send()
+        .await
+        .context("Failed to reach the push gateway")?;
+    if !response.status().is_success() {
+        bail!("Push gateway returned {}", response.status());
+    }
+    Ok(())
+}
+
+// Push the metrics periodically when METRICS_PUSH_URL is set
+pub fn spawn_metrics_push(config: &Arc<ConfigState>) {
+    let Some(push) = config.metrics.push.clone() else {
+        return;
+    };
+    let config = config.clone();
+    tokio::spawn(async move {
+        let client = Client::new();
+        let mut interval = tokio::time::interval(push.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(err) = push_metrics(&client, &config.metrics, &prometheus_handle(), &config.pgpool).await {
+                eprintln!("Failed to push metrics: {err:#}");
+            }
+        }
+    });
+}
+
+// Install the recorder on first use
+pub fn prometheus_handle() -> PrometheusHandle {
+    METRIC_HANDLE
+        .get_or_init(|| {
+            let recorder = build_recorder();
+            let handle = recorder.handle();
+            let upkeep = handle.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    upkeep.run_upkeep();
+                }
+            });
+            metrics::set_global_recorder(recorder).expect("Failed to set global recorder");
+            describe_metrics();
+            handle
+        })
+        .clone()
+}
+
+// Every `_duration_seconds` metric is a histogram
+pub(crate) fn build_recorder() -> PrometheusRecorder {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_duration_seconds".to_string()), SECONDS_DURATION_BUCKETS)
+        .expect("Duration buckets are not empty")
+        .build_recorder()
+}
+
+pub(crate) fn describe_metrics() {
+    describe_gauge!(DB_POOL_CONNECTIONS, Unit::Count, "Connections of the database pool by state (idle, in_use)");
+    describe_gauge!(DB_POOL_SIZE, Unit::Count, "Open connections of the database pool");
+    describe_gauge!(DB_POOL_MAX_CONNECTIONS, Unit::Count, "Configured upper bound of the database pool");
+    describe_histogram!(DB_QUERY_DURATION_SECONDS, Unit::Seconds, "Latency of user queries by function and outcome (ok, error, cancelled)");
+    describe_counter!(KEYCLOAK_REQUESTS_TOTAL, Unit::Count, "Calls to the Keycloak token endpoint by operation and outcome");
+    describe_histogram!(KEYCLOAK_REQUEST_DURATION_SECONDS, Unit::Seconds, "Latency of calls to the Keycloak token endpoint by operation");
+    describe_counter!(USERS_CREATED_TOTAL, Unit::Count, "Committed user creations by source (api, batch, import, invitation)");
+    describe_counter!(USERS_DELETED_TOTAL, Unit::Count, "Committed user deletions by source (api, batch)");
+    describe_counter!(OUTBOX_EVENTS_TOTAL, Unit::Count, "Outbox deliveries by outcome (delivered, failed, dead_lettered)");
+    describe_histogram!(OUTBOX_SINK_DURATION_SECONDS, Unit::Seconds, "Latency of outbox deliveries by sink and outcome (ok, error)");
+    describe_counter!(LOGIN_FAILURES_TOTAL, Unit::Count, "Logins refused for invalid credentials");
+    describe_counter!(LOGIN_THROTTLED_TOTAL, Unit::Count, "Logins refused during a lockout by scope (ip, username)");
+    describe_counter!(LOGIN_LOCKOUTS_TOTAL, Unit::Count, "Lockouts started by scope (ip, username)");
+    describe_counter!(RATE_LIMITED_TOTAL, Unit::Count, "Requests refused by the rate limiter by route group (public, private, identity)");
+    describe_counter!(IDEMPOTENT_REPLAYS_TOTAL, Unit::Count, "Stored responses replayed for retried Idempotency-Keys");
+}
+
+fn record_pool(pgpool: &Pool<Postgres>) {
+    let size = pgpool.size() as f64;
+    let idle = pgpool.num_idle() as f64;
+    gauge!(DB_POOL_SIZE).set(size);
+    gauge!(DB_POOL_MAX_CONNECTIONS).set(pgpool.options().get_max_connections() as f64);
+    gauge!(DB_POOL_CONNECTIONS, "state" => "idle").set(idle);
+    gauge!(DB_POOL_CONNECTIONS, "state" => "in_use").set((size - idle).max(0.0));
+}
+
+// Records the latency of a query when dropped, queries dropped before they finish count as cancelled
+struct QueryTimer {
+    function: &'static str,
+    started: Instant,
+    outcome: &'static str,
+}
+
+impl QueryTimer {
+    fn start(function: &'static str) -> Self {
+        Self { function, started: Instant::now(), outcome: "cancelled" }
+    }
+
+    fn finish<T>(&mut self, result: &Result<T, sqlx::Error>) {
+        self.outcome = match result {
+            Ok(_) => "ok",
+            Err(_) => "error",
+        };
+    }
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        histogram!(DB_QUERY_DURATION_SECONDS, "function" => self.function, "outcome" => self.outcome)
+            .record(self.started.elapsed().as_secs_f64());
+    }
+}
+
+// Await a query and record its latency under the name of the calling function
+pub async fn timed_query<T>(function: &'static str, query: impl Future<Output = Result<T, sqlx::Error>>) -> Result<T, sqlx::Error> {
+    let mut timer = QueryTimer::start(function);
+    let result = query.await;
+    timer.finish(&result);
+    result
+}
+
+// Same as `timed_query` for row streams, timed from the first poll until the last row or the first error
+pub fn timed_stream<T>(function: &'static str, rows: impl Stream<Item = Result<T, sqlx::Error>>) -> impl Stream<Item = Result<T, sqlx::Error>> {
+    stream! {
+        let mut timer = QueryTimer::start(function);
+        futures::pin_mut!(rows);
+        while let Some(row) = rows.next().await {
+            if row.is_err() {
+                timer.finish(&row);
+                yield row;
+                return;
+            }
+            yield row;
+        }
+        timer.finish(&Ok::<(), sqlx::Error>(()));
+    }
+}
+
+// Count and time one call to the Keycloak token endpoint
+pub fn record_keycloak_call<T>(operation: &'static str, started: Instant, result: &Result<T, LoginError>) {
+    let outcome = match result {
+        Ok(_) => "success",
+        Err(LoginError::InvalidCredentials) => "invalid_credentials",
+        Err(LoginError::Unavailable(_)) => "unavailable",
+        Err(LoginError::Unsupported) | Err(LoginError::Internal(_)) => "error",
+    };
+    counter!(KEYCLOAK_REQUESTS_TOTAL, "operation" => operation, "outcome" => outcome).increment(1);
+    histogram!(KEYCLOAK_REQUEST_DURATION_SECONDS, "operation" => operation).record(started.elapsed().as_secs_f64());
+}
+
+// Only called once the change is committed
+pub fn record_users_created(source: &'static str, count: u64) {
+    if count > 0 {
+        counter!(USERS_CREATED_TOTAL, "source" => source).increment(count);
+    }
+}
+
+pub fn record_users_deleted(source: &'static str, count: u64) {
+    if count > 0 {
+        counter!(USERS_DELETED_TOTAL, "source" => source).increment(count);
+    }
+}
+
+pub fn record_outbox_event(outcome: &'static str) {
+    counter!(OUTBOX_EVENTS_TOTAL, "outcome" => outcome).increment(1);
+}
+
+// Time one delivery of an event to a sink
+pub fn record_sink_call<T>(sink: &'static str, started: Instant, result: &anyhow::Result<T>) {
+    let outcome = match result {
+        Ok(_) => "ok",
+        Err(_) => "error",
+    };
+    histogram!(OUTBOX_SINK_DURATION_SECONDS, "sink" => sink, "outcome" => outcome).record(started.elapsed().as_secs_f64());
+}
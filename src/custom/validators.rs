@@ -1,7 +1,91 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+};
+
 use regex::Regex;
+use serde_json::{json, Map, Value};
+
+use crate::definitions::attribute::{AttributeDefinition, AttributeType};
 
 // Helper function to validate email format using regex
 pub fn is_valid_email(email: &str) -> bool {
     let email_regex = Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").unwrap();
     email_regex.is_match(email)
-}
\ No newline at end of file
+}
+// Helper function to validate attribute names, they are also used in index names
+pub fn is_valid_attribute_name(name: &str) -> bool {
+    let name_regex = Regex::new(r"^[a-z][a-z0-9_]{0,39}$").unwrap();
+    name_regex.is_match(name)
+}
+
+// Helper function to validate organization slugs, they double as subdomain labels
+pub fn is_valid_slug(slug: &str) -> bool {
+    let slug_regex = Regex::new(r"^[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?$").unwrap();
+    slug_regex.is_match(slug)
+}
+
+// Helper function to validate custom attributes against their definitions
+// Partial validation is used for updates, where missing required attributes are kept as stored
+pub fn validate_attributes(
+    attributes: &Map<String, Value>,
+    definitions: &[AttributeDefinition],
+    partial: bool,
+) -> Result<(), String> {
+    for (name, value) in attributes {
+        let definition = match definitions.iter().find(|definition| &definition.name == name) {
+            Some(definition) => definition,
+            None => return Err(format!("Unknown attribute: {name}")),
+        };
+
+        if value.is_null() {
+            if definition.required {
+                return Err(format!("Attribute {name} is required"));
+            }
+            continue;
+        }
+
+        let type_matches = match definition.attribute_type {
+            AttributeType::String => value.is_string(),
+            AttributeType::Number => value.is_number(),
+            AttributeType::Boolean => value.is_boolean(),
+        };
+        if !type_matches {
+            return Err(format!("Attribute {name} must be a {}", json!(definition.attribute_type).as_str().unwrap_or_default()));
+        }
+
+        if let (Some(pattern), Some(text)) = (&definition.pattern, value.as_str()) {
+            let matches = attribute_pattern(name, pattern).is_some_and(|regex| regex.is_match(text));
+            if !matches {
+                return Err(format!("Attribute {name} does not match the required format"));
+            }
+        }
+    }
+
+    if !partial {
+        for definition in definitions.iter().filter(|definition| definition.required) {
+            if attributes.get(&definition.name).is_none_or(Value::is_null) {
+                return Err(format!("Attribute {} is required", definition.name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Compiled patterns by attribute name, recompiled when a definition is recreated with another pattern
+static ATTRIBUTE_PATTERNS: LazyLock<RwLock<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+
+fn attribute_pattern(name: &str, pattern: &str) -> Option<Regex> {
+    if let Some(regex) = ATTRIBUTE_PATTERNS.read().unwrap().get(name).filter(|regex| regex.as_str() == pattern) {
+        return Some(regex.clone());
+    }
+    let regex = Regex::new(pattern).ok()?;
+    ATTRIBUTE_PATTERNS.write().unwrap().insert(name.to_string(), regex.clone());
+    Some(regex)
+}
+
+// Drop the compiled pattern of a deleted attribute
+pub fn forget_attribute_pattern(name: &str) {
+    ATTRIBUTE_PATTERNS.write().unwrap().remove(name);
+}
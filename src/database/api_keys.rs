@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use crate::definitions::api_key::{ApiKey, ApiKeyPageRow, LAST_USED_RESOLUTION_SECS};
+use uuid::Uuid;
+
+const API_KEY_COLUMNS: &str = "key_id, org_id, name, roles, secret_hash, previous_hash, previous_expires_at, created_by, expires_at, last_used_at, rotated_at, created_at";
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create_api_key<'e>(
+    key_id: Uuid,
+    org_id: Uuid,
+    name: &str,
+    roles: &[String],
+    secret_hash: &[u8],
+    created_by: &str,
+    expires_at: Option<DateTime<Utc>>,
+    pool: impl PgExecutor<'e>,
+) -> Result<ApiKey, sqlx::Error> {
+    let row = sqlx::query_as::<_, ApiKey>(&format!(
+    r#"
+        INSERT INTO api_keys (key_id, org_id, name, roles, secret_hash, created_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING {API_KEY_COLUMNS}
+    "#,))
+    .bind(key_id)
+    .bind(org_id)
+    .bind(name)
+    .bind(roles)
+    .bind(secret_hash)
+    .bind(created_by)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn list_api_keys<'e>(org_id: Uuid, limit: i64, offset: i64, pool: impl PgExecutor<'e>) -> Result<Vec<ApiKeyPageRow>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, ApiKeyPageRow>(&format!(
+    r#"
+        SELECT {API_KEY_COLUMNS}, COUNT(*) OVER () AS total
+        FROM api_keys
+        WHERE org_id = $1
+        ORDER BY created_at DESC, key_id
+        LIMIT $2 OFFSET $3
+    "#,))
+    .bind(org_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+// Number of keys of an organization, for pages past the last key where no row carries the total
+pub(crate) async fn count_api_keys<'e>(org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM api_keys WHERE org_id = $1")
+        .bind(org_id)
+        .fetch_one(pool)
+        .await
+}
+
+// Load a key regardless of its organization, used to authenticate requests before the tenant is known
+pub(crate) async fn find_api_key<'e>(key_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<ApiKey>, sqlx::Error> {
+    let row = sqlx::query_as::<_, ApiKey>(&format!(
+    r#"
+        SELECT {API_KEY_COLUMNS}
+        FROM api_keys
+        WHERE key_id = $1
+    "#,))
+    .bind(key_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Record a use of the key, skipped when the last recorded use is recent enough
+pub(crate) async fn touch_api_key<'e>(key_id: Uuid, pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+    r#"
+        UPDATE api_keys
+        SET last_used_at = now()
+        WHERE key_id = $1 AND (last_used_at IS NULL OR last_used_at < now() - make_interval(secs => $2))
+    "#,)
+    .bind(key_id)
+    .bind(LAST_USED_RESOLUTION_SECS as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Replace the secret, the old one stays valid until previous_expires_at
+pub(crate) async fn rotate_api_key<'e>(
+    key_id: Uuid,
+    org_id: Uuid,
+    secret_hash: &[u8],
+    previous_expires_at: Option<DateTime<Utc>>,
+    pool: impl PgExecutor<'e>,
+) -> Result<Option<ApiKey>, sqlx::Error> {
+    let row = sqlx::query_as::<_, ApiKey>(&format!(
+    r#"
+        UPDATE api_keys
+        SET previous_hash = CASE WHEN $4::timestamptz IS NULL THEN NULL ELSE secret_hash END,
+            previous_expires_at = $4,
+            secret_hash = $3,
+            rotated_at = now()
+        WHERE key_id = $1 AND org_id = $2
+        RETURNING {API_KEY_COLUMNS}
+    "#,))
+    .bind(key_id)
+    .bind(org_id)
+    .bind(secret_hash)
+    .bind(previous_expires_at)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn remove_api_key<'e>(key_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<ApiKey>, sqlx::Error> {
+    let row = sqlx::query_as::<_, ApiKey>(&format!(
+    r#"
+        DELETE FROM api_keys
+        WHERE key_id = $1 AND org_id = $2
+        RETURNING {API_KEY_COLUMNS}
+    "#,))
+    .bind(key_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
@@ -0,0 +1,107 @@
+use sqlx::{Executor, PgExecutor, Pool, Postgres};
+use crate::definitions::{attribute::AttributeDefinition, outbox::USER_UPDATED};
+
+pub(crate) async fn list_attribute_definitions<'e>(pool: impl PgExecutor<'e>) -> Result<Vec<AttributeDefinition>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, AttributeDefinition>(
+    r#"
+        SELECT name, attribute_type, required, is_unique, pattern, visibility, description
+        FROM attribute_definitions
+        ORDER BY name
+    "#,)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+// Store a definition, unique attributes are backed by an expression index on users
+pub(crate) async fn create_attribute_definition(definition: AttributeDefinition, pool: &Pool<Postgres>) -> Result<AttributeDefinition, sqlx::Error> {
+    let row = sqlx::query_as::<_, AttributeDefinition>(
+    r#"
+        INSERT INTO attribute_definitions (name, attribute_type, required, is_unique, pattern, visibility, description)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING name, attribute_type, required, is_unique, pattern, visibility, description
+    "#,)
+    .bind(&definition.name)
+    .bind(definition.attribute_type)
+    .bind(definition.required)
+    .bind(definition.is_unique)
+    .bind(&definition.pattern)
+    .bind(definition.visibility)
+    .bind(&definition.description)
+    .fetch_one(pool)
+    .await?;
+
+    // Built concurrently so writes to users continue during the build. CONCURRENTLY cannot run in a
+    // transaction, a failed build leaves an invalid index and the definition behind to be removed.
+    // Identifiers cannot be bound, the name is validated against a strict pattern beforehand
+    if row.is_unique {
+        let create = format!(
+            "CREATE UNIQUE INDEX CONCURRENTLY {} ON users (org_id, (attributes->>'{}'))",
+            row.unique_index_name(), row.name,
+        );
+        if let Err(err) = pool.execute(create.as_str()).await {
+            if let Err(cleanup) = pool.execute(format!("DROP INDEX CONCURRENTLY IF EXISTS {}", row.unique_index_name()).as_str()).await {
+                eprintln!("Failed to drop index {}: {cleanup}", row.unique_index_name());
+            }
+            sqlx::query("DELETE FROM attribute_definitions WHERE name = $1")
+                .bind(&row.name)
+                .execute(pool)
+                .await?;
+            return Err(err);
+        }
+    }
+
+    Ok(row)
+}
+
+// Remove a definition together with its index and every stored value
+pub(crate) async fn remove_attribute_definition(name: &str, pool: &Pool<Postgres>) -> Result<Option<AttributeDefinition>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query_as::<_, AttributeDefinition>(
+    r#"
+        DELETE FROM attribute_definitions
+        WHERE name = $1
+        RETURNING name, attribute_type, required, is_unique, pattern, visibility, description
+    "#,)
+    .bind(name)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if row.is_some() {
+        // Definitions are shared by every organization
+        sqlx::query("SELECT set_config('app.all_tenants', 'on', true)")
+            .execute(&mut *tx)
+            .await?;
+        // Every user losing the attribute is changed, so each one gets its UserUpdated event
+        sqlx::query(
+        r#"
+            WITH updated AS (
+                UPDATE users
+                SET attributes = attributes - $1
+                WHERE attributes ? $1
+                RETURNING user_id, username, email, attributes, enabled, org_id
+            )
+            INSERT INTO outbox_events (event_type, aggregate_id, org_id, payload)
+            SELECT $2, user_id, org_id, to_jsonb(updated) - 'org_id' FROM updated
+        "#,)
+        .bind(name)
+        .bind(USER_UPDATED)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    // Dropped like it was built, concurrently and outside the transaction so writes to users are not blocked.
+    // The values are gone once the transaction committed, an index left behind by a failed drop holds none
+    if let Some(definition) = row.as_ref().filter(|definition| definition.is_unique) {
+        let drop = format!("DROP INDEX CONCURRENTLY IF EXISTS {}", definition.unique_index_name());
+        if let Err(err) = pool.execute(drop.as_str()).await {
+            eprintln!("Failed to drop index {}: {err}", definition.unique_index_name());
+        }
+    }
+
+    Ok(row)
+}
@@ -0,0 +1,166 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use rand::Rng;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Connection, PgConnection, Pool, Postgres,
+};
+use tracing::info;
+
+use crate::config::EnvironmentVariables;
+
+// First delay between connection attempts, doubled after every failure
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// Upper bound of the delay between connection attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+// Connection and pool settings shared by the primary and the read replicas
+#[derive(Debug, Clone)]
+pub struct DatabaseOptions {
+    pub host: String,
+    pub port: u16,
+    username: String,
+    password: Option<String>,
+    database: String,
+    application_name: String,
+    statement_timeout: Option<Duration>,
+    max_connections: u32,
+    min_connections: u32,
+    acquire_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    // Startup keeps retrying the primary until this much time has passed, zero tries once
+    connect_deadline: Duration,
+}
+
+impl DatabaseOptions {
+    pub fn from_env(env: &EnvironmentVariables) -> anyhow::Result<Self> {
+        let seconds = |value: &str, name: &str| value.parse::<u64>().map(Duration::from_secs).with_context(|| format!("{name} must be a number of seconds"));
+        // Zero disables a timeout
+        let optional = |duration: Duration| (!duration.is_zero()).then_some(duration);
+
+        // Only the first colon separates the user from the password, the password is taken verbatim
+        let (username, password) = match env.database_creds.split_once(':') {
+            Some((username, password)) => (username.to_string(), Some(password.to_string())),
+            None => (env.database_creds.to_string(), None),
+        };
+        if username.is_empty() {
+            bail!("DATABASE_CREDS must look like <username>:<password>");
+        }
+
+        let statement_timeout: u64 = env.database_statement_timeout_ms.parse().context("DATABASE_STATEMENT_TIMEOUT_MS must be a number of milliseconds")?;
+        let max_connections: u32 = env.max_pool_connections.parse().context("MAX_POOL_CONNECTIONS must be a number")?;
+        let min_connections: u32 = env.database_min_connections.parse().context("DATABASE_MIN_CONNECTIONS must be a number")?;
+        if min_connections > max_connections {
+            bail!("DATABASE_MIN_CONNECTIONS must not exceed MAX_POOL_CONNECTIONS");
+        }
+        let acquire_timeout = seconds(&env.database_acquire_timeout_secs, "DATABASE_ACQUIRE_TIMEOUT_SECS")?;
+        if acquire_timeout.is_zero() {
+            bail!("DATABASE_ACQUIRE_TIMEOUT_SECS must be greater than zero");
+        }
+
+        Ok(Self {
+            host: env.database_host.to_string(),
+            port: env.database_port.parse().context("DATABASE_PORT must be a port number")?,
+            username,
+            password,
+            database: env.database_name.to_string(),
+            application_name: env.database_application_name.to_string(),
+            statement_timeout: optional(Duration::from_millis(statement_timeout)),
+            max_connections,
+            min_connections,
+            acquire_timeout,
+            idle_timeout: optional(seconds(&env.database_idle_timeout_secs, "DATABASE_IDLE_TIMEOUT_SECS")?),
+            max_lifetime: optional(seconds(&env.database_max_lifetime_secs, "DATABASE_MAX_LIFETIME_SECS")?),
+            connect_deadline: seconds(&env.database_connect_deadline_secs, "DATABASE_CONNECT_DEADLINE_SECS")?,
+        })
+    }
+
+    // Options for a server, each part is passed as is so credentials need no URL escaping
+    pub fn connect_options(&self, host: &str, port: u16) -> PgConnectOptions {
+        let mut options = PgConnectOptions::new()
+            .host(host)
+            .port(port)
+            .username(&self.username)
+            .database(&self.database);
+        if let Some(password) = &self.password {
+            options = options.password(password);
+        }
+        if !self.application_name.is_empty() {
+            options = options.application_name(&self.application_name);
+        }
+        if let Some(timeout) = self.statement_timeout {
+            options = options.options([("statement_timeout", format!("{}ms", timeout.as_millis()))]);
+        }
+        options
+    }
+
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .max_lifetime(self.max_lifetime)
+    }
+
+    // Connect to the primary, retrying with exponential backoff while the database is starting
+    pub async fn connect(&self) -> anyhow::Result<Pool<Postgres>> {
+        let fqdn = format!("{}:{}", self.host, self.port);
+        println!("Attempting to connect to PgPool @ {fqdn}");
+        info!("Attempting to connect to PgPool @ {fqdn}");
+
+        let started = Instant::now();
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            // A server that accepts but never answers must not hold startup past the deadline,
+            // a zero deadline gives its single attempt the acquire timeout
+            let limit = match self.connect_deadline.is_zero() {
+                true => self.acquire_timeout,
+                false => self.connect_deadline.saturating_sub(started.elapsed()),
+            };
+            let result = match tokio::time::timeout(limit, self.try_connect()).await {
+                Ok(result) => result,
+                Err(_) => {
+                    info!("Failed To Connect To DB: timed out");
+                    bail!("Failed To Connect To DB after {attempt} attempt(s): timed out after {}ms", started.elapsed().as_millis());
+                },
+            };
+            match result {
+                Ok(pool) => {
+                    println!("Connected to DB: {fqdn}");
+                    info!("Connected to DB: {fqdn}");
+                    return Ok(pool);
+                },
+                // Wrong credentials or a missing database will not fix themselves
+                Err(sqlx::Error::Database(err)) if err.code().is_some_and(|code| code.starts_with("28") || code == "3D000") => {
+                    info!("Failed To Connect To DB: {err}");
+                    bail!("Failed To Connect To DB: {err}");
+                },
+                Err(err) => {
+                    // Jitter keeps replicas of the service from retrying in lockstep
+                    let wait = delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+                    if started.elapsed() + wait > self.connect_deadline {
+                        info!("Failed To Connect To DB: {err}");
+                        bail!("Failed To Connect To DB after {attempt} attempt(s): {err}");
+                    }
+                    println!("Database not ready (attempt {attempt}), retrying in {}ms: {err}", wait.as_millis());
+                    info!("Database not ready (attempt {attempt}): {err}");
+                    tokio::time::sleep(wait).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                },
+            }
+        }
+    }
+
+    // A single connection fails fast where the pool would keep retrying until its acquire timeout, so it probes first
+    async fn try_connect(&self) -> Result<Pool<Postgres>, sqlx::Error> {
+        let options = self.connect_options(&self.host, self.port);
+        PgConnection::connect_with(&options).await?.close().await.ok();
+        self.pool_options().connect_with(options).await
+    }
+}
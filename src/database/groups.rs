@@ -0,0 +1,246 @@
+use sqlx::PgExecutor;
+use crate::definitions::group::{Group, GroupMember, GroupPageRow, MembershipRole, UserGroup};
+use uuid::Uuid;
+
+// List the direct children of a group, or the top-level groups when no parent is given
+pub(crate) async fn list_groups<'e>(
+    org_id: Uuid,
+    parent_id: Option<Uuid>,
+    limit: i64,
+    offset: i64,
+    pool: impl PgExecutor<'e>,
+) -> Result<Vec<GroupPageRow>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, GroupPageRow>(
+    r#"
+        SELECT group_id, parent_id, name, description, keycloak_id, COUNT(*) OVER () AS total
+        FROM groups
+        WHERE org_id = $1 AND parent_id IS NOT DISTINCT FROM $2
+        ORDER BY lower(name), group_id
+        LIMIT $3 OFFSET $4
+    "#,)
+    .bind(org_id)
+    .bind(parent_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub(crate) async fn find_group<'e>(group_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<Group>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Group>(
+    r#"
+        SELECT group_id, parent_id, name, description, keycloak_id
+        FROM groups
+        WHERE group_id = $1 AND org_id = $2
+    "#,)
+    .bind(group_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn create_group<'e>(group: Group, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Group, sqlx::Error> {
+    let row = sqlx::query_as::<_, Group>(
+    r#"
+        INSERT INTO groups (group_id, org_id, parent_id, name, description)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING group_id, parent_id, name, description, keycloak_id
+    "#,)
+    .bind(group.group_id)
+    .bind(org_id)
+    .bind(group.parent_id)
+    .bind(group.name)
+    .bind(group.description)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Partial update, the parent is only replaced when `parent_id` is Some
+pub(crate) async fn update_group<'e>(
+    group_id: Uuid,
+    org_id: Uuid,
+    name: Option<String>,
+    description: Option<String>,
+    parent_id: Option<Option<Uuid>>,
+    pool: impl PgExecutor<'e>,
+) -> Result<Option<Group>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Group>(
+    r#"
+        UPDATE groups
+        SET name = COALESCE($1, name),
+            description = COALESCE($2, description),
+            parent_id = CASE WHEN $3 THEN $4 ELSE parent_id END
+        WHERE group_id = $5 AND org_id = $6
+        RETURNING group_id, parent_id, name, description, keycloak_id
+    "#,)
+    .bind(name)
+    .bind(description)
+    .bind(parent_id.is_some())
+    .bind(parent_id.flatten())
+    .bind(group_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Delete a group, its subgroups and memberships are removed with it
+pub(crate) async fn remove_group<'e>(group_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<Group>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Group>(
+    r#"
+        DELETE FROM groups
+        WHERE group_id = $1 AND org_id = $2
+        RETURNING group_id, parent_id, name, description, keycloak_id
+    "#,)
+    .bind(group_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Whether `group_id` is `ancestor_id` itself or one of its descendants
+pub(crate) async fn is_descendant<'e>(group_id: Uuid, ancestor_id: Uuid, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let (descendant,): (bool,) = sqlx::query_as(
+    r#"
+        WITH RECURSIVE ancestors AS (
+            SELECT group_id, parent_id FROM groups WHERE group_id = $1
+            UNION
+            SELECT groups.group_id, groups.parent_id
+            FROM groups JOIN ancestors ON groups.group_id = ancestors.parent_id
+        )
+        SELECT EXISTS (SELECT 1 FROM ancestors WHERE group_id = $2)
+    "#,)
+    .bind(group_id)
+    .bind(ancestor_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(descendant)
+}
+
+// Remember the Keycloak group a local group is mirrored to
+pub(crate) async fn set_group_keycloak_id<'e>(group_id: Uuid, keycloak_id: &str, pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE groups SET keycloak_id = $1 WHERE group_id = $2")
+        .bind(keycloak_id)
+        .bind(group_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn find_membership_role<'e>(group_id: Uuid, user_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<MembershipRole>, sqlx::Error> {
+    let row: Option<(MembershipRole,)> = sqlx::query_as(
+    r#"
+        SELECT role
+        FROM group_memberships
+        WHERE group_id = $1 AND user_id = $2
+    "#,)
+    .bind(group_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(role,)| role))
+}
+
+// Add a member or change their role, returns whether the membership is new
+pub(crate) async fn upsert_membership<'e>(group_id: Uuid, org_id: Uuid, user_id: Uuid, role: MembershipRole, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let (inserted,): (bool,) = sqlx::query_as(
+    r#"
+        INSERT INTO group_memberships (group_id, org_id, user_id, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (group_id, user_id) DO UPDATE SET role = EXCLUDED.role
+        RETURNING (xmax = 0)
+    "#,)
+    .bind(group_id)
+    .bind(org_id)
+    .bind(user_id)
+    .bind(role)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(inserted)
+}
+
+pub(crate) async fn remove_membership<'e>(group_id: Uuid, user_id: Uuid, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM group_memberships WHERE group_id = $1 AND user_id = $2")
+        .bind(group_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// List members of a group, optionally including the members of every descendant group
+pub(crate) async fn list_members<'e>(
+    group_id: Uuid,
+    recursive: bool,
+    limit: i64,
+    offset: i64,
+    pool: impl PgExecutor<'e>,
+) -> Result<Vec<GroupMember>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, GroupMember>(
+    r#"
+        WITH RECURSIVE tree AS (
+            SELECT group_id FROM groups WHERE group_id = $1
+            UNION
+            SELECT groups.group_id
+            FROM groups JOIN tree ON groups.parent_id = tree.group_id
+            WHERE $2
+        )
+        SELECT group_memberships.group_id, users.user_id, users.username, group_memberships.role,
+            COUNT(*) OVER () AS total
+        FROM group_memberships
+        JOIN tree ON tree.group_id = group_memberships.group_id
+        JOIN users ON users.org_id = group_memberships.org_id AND users.user_id = group_memberships.user_id
+        ORDER BY lower(users.username), users.user_id, group_memberships.group_id
+        LIMIT $3 OFFSET $4
+    "#,)
+    .bind(group_id)
+    .bind(recursive)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+// Groups a user belongs to directly, plus every ancestor of those groups
+pub(crate) async fn list_user_groups<'e>(user_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Vec<UserGroup>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, UserGroup>(
+    r#"
+        WITH RECURSIVE reachable AS (
+            SELECT groups.group_id, groups.parent_id
+            FROM groups JOIN group_memberships ON group_memberships.group_id = groups.group_id
+            WHERE group_memberships.user_id = $1 AND groups.org_id = $2
+            UNION
+            SELECT groups.group_id, groups.parent_id
+            FROM groups JOIN reachable ON groups.group_id = reachable.parent_id
+        )
+        SELECT groups.group_id, groups.parent_id, groups.name, groups.description, groups.keycloak_id,
+            group_memberships.role,
+            group_memberships.role IS NULL AS inherited
+        FROM reachable
+        JOIN groups ON groups.group_id = reachable.group_id
+        LEFT JOIN group_memberships ON group_memberships.group_id = groups.group_id AND group_memberships.user_id = $1
+        ORDER BY lower(groups.name), groups.group_id
+    "#,)
+    .bind(user_id)
+    .bind(org_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::definitions::idempotency::IdempotencyRecord;
+
+// Claim a key for a new request, false when a live record exists. Expired records and abandoned claims are taken over
+pub(crate) async fn claim_idempotency_key<'e>(
+    scope: &str,
+    key: &str,
+    claim_id: Uuid,
+    fingerprint: &[u8],
+    lock: Duration,
+    ttl: Duration,
+    pool: impl PgExecutor<'e>,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query_scalar::<_, bool>(
+    r#"
+        INSERT INTO idempotency_keys (scope, idempotency_key, claim_id, fingerprint, locked_until, expires_at)
+        VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5), now() + make_interval(secs => $6))
+        ON CONFLICT (scope, idempotency_key) DO UPDATE SET
+            claim_id = EXCLUDED.claim_id,
+            fingerprint = EXCLUDED.fingerprint,
+            status_code = NULL,
+            response_headers = NULL,
+            response_body = NULL,
+            locked_until = EXCLUDED.locked_until,
+            expires_at = EXCLUDED.expires_at,
+            created_at = now()
+        WHERE idempotency_keys.expires_at < now()
+            OR (idempotency_keys.status_code IS NULL AND idempotency_keys.locked_until < now())
+        RETURNING true
+    "#,)
+    .bind(scope)
+    .bind(key)
+    .bind(claim_id)
+    .bind(fingerprint)
+    .bind(lock.as_secs_f64())
+    .bind(ttl.as_secs_f64())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.is_some())
+}
+
+pub(crate) async fn find_idempotency_key<'e>(scope: &str, key: &str, pool: impl PgExecutor<'e>) -> Result<Option<IdempotencyRecord>, sqlx::Error> {
+    let row = sqlx::query_as::<_, IdempotencyRecord>(
+    r#"
+        SELECT fingerprint, status_code, response_headers, response_body
+        FROM idempotency_keys
+        WHERE scope = $1 AND idempotency_key = $2 AND expires_at >= now()
+    "#,)
+    .bind(scope)
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Extend the lock of a running request, false when the claim was lost
+pub(crate) async fn renew_idempotency_key<'e>(scope: &str, key: &str, claim_id: Uuid, lock: Duration, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+    r#"
+        UPDATE idempotency_keys
+        SET locked_until = now() + make_interval(secs => $4)
+        WHERE scope = $1 AND idempotency_key = $2 AND claim_id = $3 AND status_code IS NULL
+    "#,)
+    .bind(scope)
+    .bind(key)
+    .bind(claim_id)
+    .bind(lock.as_secs_f64())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Store the response of a claimed key, ignored when the claim was taken over
+pub(crate) async fn complete_idempotency_key<'e>(
+    scope: &str,
+    key: &str,
+    claim_id: Uuid,
+    status_code: i16,
+    headers: Value,
+    body: &[u8],
+    pool: impl PgExecutor<'e>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+    r#"
+        UPDATE idempotency_keys
+        SET status_code = $4, response_headers = $5, response_body = $6
+        WHERE scope = $1 AND idempotency_key = $2 AND claim_id = $3
+    "#,)
+    .bind(scope)
+    .bind(key)
+    .bind(claim_id)
+    .bind(status_code)
+    .bind(headers)
+    .bind(body)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Give up a claim so the request can be retried, used when it failed with a server error
+pub(crate) async fn release_idempotency_key<'e>(scope: &str, key: &str, claim_id: Uuid, pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM idempotency_keys WHERE scope = $1 AND idempotency_key = $2 AND claim_id = $3 AND status_code IS NULL")
+    .bind(scope)
+    .bind(key)
+    .bind(claim_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn prune_idempotency_keys<'e>(pool: impl PgExecutor<'e>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at < now()")
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
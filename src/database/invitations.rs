@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::PgExecutor;
+use crate::definitions::invitation::{Invitation, InvitationPageRow, InvitationStatus};
+use uuid::Uuid;
+
+const INVITATION_COLUMNS: &str = "invitation_id, org_id, email, username, attributes, status, token_version, invited_by, expires_at, created_at, accepted_at, user_id";
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create_invitation<'e>(
+    invitation_id: Uuid,
+    org_id: Uuid,
+    email: &str,
+    username: Option<String>,
+    attributes: Value,
+    invited_by: &str,
+    expires_at: DateTime<Utc>,
+    pool: impl PgExecutor<'e>,
+) -> Result<Invitation, sqlx::Error> {
+    let row = sqlx::query_as::<_, Invitation>(&format!(
+    r#"
+        INSERT INTO invitations (invitation_id, org_id, email, username, attributes, invited_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING {INVITATION_COLUMNS}
+    "#,))
+    .bind(invitation_id)
+    .bind(org_id)
+    .bind(email)
+    .bind(username)
+    .bind(attributes)
+    .bind(invited_by)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn list_invitations<'e>(
+    org_id: Uuid,
+    status: Option<InvitationStatus>,
+    limit: i64,
+    offset: i64,
+    pool: impl PgExecutor<'e>,
+) -> Result<Vec<InvitationPageRow>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, InvitationPageRow>(&format!(
+    r#"
+        SELECT {INVITATION_COLUMNS}, COUNT(*) OVER () AS total
+        FROM invitations
+        WHERE org_id = $1 AND ($2::text IS NULL OR status = $2)
+        ORDER BY created_at DESC, invitation_id
+        LIMIT $3 OFFSET $4
+    "#,))
+    .bind(org_id)
+    .bind(status)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+// Load and lock an invitation regardless of its organization, used when a token is redeemed
+pub(crate) async fn lock_invitation<'e>(invitation_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<Invitation>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Invitation>(&format!(
+    r#"
+        SELECT {INVITATION_COLUMNS}
+        FROM invitations
+        WHERE invitation_id = $1
+        FOR UPDATE
+    "#,))
+    .bind(invitation_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Extend a pending invitation and invalidate every token issued for it so far
+pub(crate) async fn renew_invitation<'e>(
+    invitation_id: Uuid,
+    org_id: Uuid,
+    expires_at: DateTime<Utc>,
+    pool: impl PgExecutor<'e>,
+) -> Result<Option<Invitation>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Invitation>(&format!(
+    r#"
+        UPDATE invitations
+        SET token_version = token_version + 1, expires_at = $1
+        WHERE invitation_id = $2 AND org_id = $3 AND status = 'pending'
+        RETURNING {INVITATION_COLUMNS}
+    "#,))
+    .bind(expires_at)
+    .bind(invitation_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn revoke_invitation<'e>(invitation_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<Invitation>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Invitation>(&format!(
+    r#"
+        UPDATE invitations
+        SET status = 'revoked'
+        WHERE invitation_id = $1 AND org_id = $2 AND status = 'pending'
+        RETURNING {INVITATION_COLUMNS}
+    "#,))
+    .bind(invitation_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Consume the token of a pending invitation before the account is created, a second redemption no longer matches its version
+pub(crate) async fn claim_invitation<'e>(invitation_id: Uuid, token_version: i32, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+    r#"
+        UPDATE invitations
+        SET token_version = token_version + 1
+        WHERE invitation_id = $1 AND token_version = $2 AND status = 'pending'
+    "#,)
+    .bind(invitation_id)
+    .bind(token_version)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Hand a claimed token back when the account could not be created, unless the invitation was resent or revoked meanwhile
+pub(crate) async fn release_invitation<'e>(invitation_id: Uuid, token_version: i32, pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+    r#"
+        UPDATE invitations
+        SET token_version = token_version - 1
+        WHERE invitation_id = $1 AND token_version = $2 + 1 AND status = 'pending'
+    "#,)
+    .bind(invitation_id)
+    .bind(token_version)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Mark a claimed invitation as accepted, false when it was resent or revoked after the claim
+pub(crate) async fn accept_invitation<'e>(invitation_id: Uuid, token_version: i32, user_id: Uuid, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+    r#"
+        UPDATE invitations
+        SET status = 'accepted', accepted_at = now(), user_id = $1
+        WHERE invitation_id = $2 AND token_version = $3 + 1 AND status = 'pending'
+    "#,)
+    .bind(user_id)
+    .bind(invitation_id)
+    .bind(token_version)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
@@ -0,0 +1,53 @@
+use sqlx::PgExecutor;
+use crate::definitions::auth::LocalAccount;
+use uuid::Uuid;
+
+pub(crate) async fn find_local_account<'e>(username: &str, pool: impl PgExecutor<'e>) -> Result<Option<LocalAccount>, sqlx::Error> {
+    let row = sqlx::query_as::<_, LocalAccount>(
+    r#"
+        SELECT subject, username, email, password_hash, roles
+        FROM local_accounts
+        WHERE lower(username) = lower($1)
+    "#,)
+    .bind(username)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Insert an account, None when the username is taken
+pub(crate) async fn create_local_account<'e>(
+    subject: Uuid,
+    username: &str,
+    email: Option<&str>,
+    password_hash: &str,
+    roles: &[String],
+    pool: impl PgExecutor<'e>,
+) -> Result<Option<LocalAccount>, sqlx::Error> {
+    let row = sqlx::query_as::<_, LocalAccount>(
+    r#"
+        INSERT INTO local_accounts (subject, username, email, password_hash, roles)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO NOTHING
+        RETURNING subject, username, email, password_hash, roles
+    "#,)
+    .bind(subject)
+    .bind(username)
+    .bind(email)
+    .bind(password_hash)
+    .bind(roles)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn remove_local_account<'e>(subject: Uuid, pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM local_accounts WHERE subject = $1")
+    .bind(subject)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -1 +1,72 @@
-pub mod users;
\ No newline at end of file
+pub mod api_keys;
+pub mod attributes;
+pub mod connection;
+pub mod groups;
+pub mod idempotency;
+pub mod invitations;
+pub mod local_accounts;
+pub mod organizations;
+pub mod outbox;
+pub mod rate_limits;
+pub mod replicas;
+pub mod unit_of_work;
+pub mod users;
+
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+// Start a transaction bound to a tenant, the row-level security policies read app.tenant_id
+pub async fn begin_tenant(pool: &Pool<Postgres>, org_id: Uuid) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    bind_tenant(pool.begin().await?, org_id).await
+}
+
+// Start a REPEATABLE READ transaction bound to a tenant, updating a row that a concurrent transaction
+// changed after the snapshot was taken fails with a serialization failure instead of overwriting it
+pub async fn begin_tenant_repeatable(pool: &Pool<Postgres>, org_id: Uuid) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    bind_tenant(pool.begin_with(BEGIN_REPEATABLE_READ).await?, org_id).await
+}
+
+// Statement opening a REPEATABLE READ transaction
+pub const BEGIN_REPEATABLE_READ: &str = "BEGIN ISOLATION LEVEL REPEATABLE READ";
+
+async fn bind_tenant(mut tx: Transaction<'static, Postgres>, org_id: Uuid) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    sqlx::query("SELECT set_config('app.tenant_id', $1, true)")
+        .bind(org_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+// Start a maintenance transaction spanning every organization, the policies hide all rows from sessions without a tenant
+pub async fn begin_all_tenants(pool: &Pool<Postgres>) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT set_config('app.all_tenants', 'on', true)")
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+// Tables protected by a tenant row-level security policy
+const TENANT_TABLES: [&str; 2] = ["users", "groups"];
+
+// Check that the migrations force the tenant row-level security policies exactly when TENANT_RLS expects it.
+// Forcing is left to the migrations, it takes an ACCESS EXCLUSIVE lock and requires owning the tables
+pub async fn verify_tenant_rls(pool: &Pool<Postgres>, enforce: bool) -> anyhow::Result<()> {
+    for table in TENANT_TABLES {
+        let forced: Option<bool> = sqlx::query_scalar("SELECT relrowsecurity AND relforcerowsecurity FROM pg_class WHERE oid = to_regclass($1)")
+            .bind(table)
+            .fetch_optional(pool)
+            .await?;
+        match (forced, enforce) {
+            (None, _) => anyhow::bail!("Table {table} does not exist, run the migrations first"),
+            (Some(true), false) => anyhow::bail!(
+                "Row-level security is forced on {table} but TENANT_RLS is false, set TENANT_RLS=true or run ALTER TABLE {table} NO FORCE ROW LEVEL SECURITY"
+            ),
+            (Some(false), true) => anyhow::bail!(
+                "TENANT_RLS is true but row-level security is not forced on {table}, run the migrations or ALTER TABLE {table} FORCE ROW LEVEL SECURITY"
+            ),
+            _ => {},
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use sqlx::PgExecutor;
+use crate::definitions::organization::Organization;
+use uuid::Uuid;
+
+pub(crate) async fn list_organizations<'e>(pool: impl PgExecutor<'e>) -> Result<Vec<Organization>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, Organization>(
+    r#"
+        SELECT org_id, slug, name
+        FROM organizations
+        ORDER BY slug
+    "#,)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+// Look up an organization by its UUID or slug
+pub(crate) async fn find_organization<'e>(key: &str, pool: impl PgExecutor<'e>) -> Result<Option<Organization>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Organization>(
+    r#"
+        SELECT org_id, slug, name
+        FROM organizations
+        WHERE org_id = $1 OR slug = $2
+    "#,)
+    .bind(Uuid::parse_str(key).ok())
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn create_organization<'e>(organization: Organization, pool: impl PgExecutor<'e>) -> Result<Organization, sqlx::Error> {
+    let row = sqlx::query_as::<_, Organization>(
+    r#"
+        INSERT INTO organizations (org_id, slug, name)
+        VALUES ($1, $2, $3)
+        RETURNING org_id, slug, name
+    "#,)
+    .bind(organization.org_id)
+    .bind(organization.slug)
+    .bind(organization.name)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn update_organization<'e>(
+    org_id: Uuid,
+    slug: Option<String>,
+    name: Option<String>,
+    pool: impl PgExecutor<'e>,
+) -> Result<Option<Organization>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Organization>(
+    r#"
+        UPDATE organizations
+        SET slug = COALESCE($1, slug), name = COALESCE($2, name)
+        WHERE org_id = $3
+        RETURNING org_id, slug, name
+    "#,)
+    .bind(slug)
+    .bind(name)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub(crate) async fn remove_organization<'e>(org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<Organization>, sqlx::Error> {
+    let row = sqlx::query_as::<_, Organization>(
+    r#"
+        DELETE FROM organizations
+        WHERE org_id = $1
+        RETURNING org_id, slug, name
+    "#,)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Keycloak group the groups of an organization are mirrored below, None until group sync first runs
+pub(crate) async fn find_organization_keycloak_id<'e>(org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<String>, sqlx::Error> {
+    let keycloak_id = sqlx::query_scalar::<_, Option<String>>("SELECT keycloak_id FROM organizations WHERE org_id = $1")
+        .bind(org_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(keycloak_id.flatten())
+}
+
+pub(crate) async fn set_organization_keycloak_id<'e>(org_id: Uuid, keycloak_id: &str, pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE organizations SET keycloak_id = $1 WHERE org_id = $2")
+        .bind(keycloak_id)
+        .bind(org_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
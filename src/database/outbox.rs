@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::definitions::outbox::{DeadLetterPageRow, OutboxEvent};
+
+// Lease up to `limit` due events to the caller until `lease_until`, other relays skip them until then.
+// An event waits while an earlier event of the same user is undelivered, dead letters included, so consumers
+// see the events of a user in order
+pub(crate) async fn claim_events<'e>(limit: i64, lease_until: DateTime<Utc>, pool: impl PgExecutor<'e>) -> Result<Vec<OutboxEvent>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, OutboxEvent>(
+    r#"
+        WITH claimed AS (
+            UPDATE outbox_events
+            SET next_attempt_at = $2
+            WHERE event_id IN (
+                SELECT event_id
+                FROM outbox_events pending
+                WHERE delivered_at IS NULL
+                    AND dead_lettered_at IS NULL
+                    AND next_attempt_at <= now()
+                    AND NOT EXISTS (
+                        SELECT 1
+                        FROM outbox_events earlier
+                        WHERE earlier.org_id = pending.org_id
+                            AND earlier.aggregate_id = pending.aggregate_id
+                            AND earlier.sequence < pending.sequence
+                            AND earlier.delivered_at IS NULL
+                    )
+                ORDER BY sequence
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING event_id, event_type, aggregate_id, org_id, payload, occurred_at, attempts, sequence
+        )
+        SELECT event_id, event_type, aggregate_id, org_id, payload, occurred_at, attempts
+        FROM claimed
+        ORDER BY sequence
+    "#,)
+    .bind(limit)
+    .bind(lease_until)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+// End the lease of events that were not attempted, they are due again immediately
+pub(crate) async fn release_events<'e>(event_ids: &[Uuid], pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE outbox_events SET next_attempt_at = now() WHERE event_id = ANY($1)")
+        .bind(event_ids)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn mark_delivered<'e>(event_ids: &[Uuid], pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+    r#"
+        UPDATE outbox_events
+        SET delivered_at = now(), last_error = NULL
+        WHERE event_id = ANY($1)
+    "#,)
+    .bind(event_ids)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Count a failed delivery, the event is retried at `retry_at` unless it is dead-lettered
+pub(crate) async fn record_failure<'e>(
+    event_id: Uuid,
+    error: &str,
+    retry_at: DateTime<Utc>,
+    dead_letter: bool,
+    pool: impl PgExecutor<'e>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+    r#"
+        UPDATE outbox_events
+        SET attempts = attempts + 1,
+            last_error = $2,
+            next_attempt_at = $3,
+            dead_lettered_at = CASE WHEN $4 THEN now() END
+        WHERE event_id = $1
+    "#,)
+    .bind(event_id)
+    .bind(error)
+    .bind(retry_at)
+    .bind(dead_letter)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Delete events delivered before `cutoff`, dead letters are kept
+pub(crate) async fn prune_delivered<'e>(cutoff: DateTime<Utc>, pool: impl PgExecutor<'e>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM outbox_events WHERE delivered_at < $1")
+        .bind(cutoff)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Dead letters of an organization, oldest first
+pub(crate) async fn list_dead_letters<'e>(org_id: Uuid, limit: i64, offset: i64, pool: impl PgExecutor<'e>) -> Result<Vec<DeadLetterPageRow>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, DeadLetterPageRow>(
+    r#"
+        SELECT event_id, event_type, aggregate_id, occurred_at, attempts, last_error, dead_lettered_at, COUNT(*) OVER () AS total
+        FROM outbox_events
+        WHERE org_id = $1 AND dead_lettered_at IS NOT NULL
+        ORDER BY sequence
+        LIMIT $2 OFFSET $3
+    "#,)
+    .bind(org_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+// Number of dead letters of an organization, for pages past the last one where no row carries the total
+pub(crate) async fn count_dead_letters<'e>(org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM outbox_events WHERE org_id = $1 AND dead_lettered_at IS NOT NULL")
+        .bind(org_id)
+        .fetch_one(pool)
+        .await
+}
+
+// Deliver a dead letter again with a fresh set of attempts, the events it held back follow once it is delivered
+pub(crate) async fn requeue_dead_letter<'e>(event_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+    r#"
+        UPDATE outbox_events
+        SET dead_lettered_at = NULL, attempts = 0, next_attempt_at = now()
+        WHERE event_id = $1 AND org_id = $2 AND dead_lettered_at IS NOT NULL
+    "#,)
+    .bind(event_id)
+    .bind(org_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Delete a dead letter that will never be deliverable, the events it held back are delivered without it
+pub(crate) async fn discard_dead_letter<'e>(event_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM outbox_events WHERE event_id = $1 AND org_id = $2 AND dead_lettered_at IS NOT NULL")
+        .bind(event_id)
+        .bind(org_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use sqlx::PgExecutor;
+
+// Refill the bucket for the time since its last use and take a token when one is available, atomically
+pub(crate) async fn take_token<'e>(key: &str, capacity: f64, refill_per_sec: f64, pool: impl PgExecutor<'e>) -> Result<(bool, f64), sqlx::Error> {
+    let row = sqlx::query_as::<_, (bool, f64)>(
+    r#"
+        INSERT INTO rate_limit_buckets AS bucket (key, tokens, allowed, updated_at)
+        VALUES ($1, $2::float8 - 1, true, now())
+        ON CONFLICT (key) DO UPDATE SET
+            allowed = LEAST($2::float8, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3::float8) >= 1,
+            tokens = LEAST($2::float8, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3::float8)
+                - CASE WHEN LEAST($2::float8, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3::float8) >= 1 THEN 1 ELSE 0 END,
+            updated_at = now()
+        RETURNING allowed, tokens
+    "#,)
+    .bind(key)
+    .bind(capacity)
+    .bind(refill_per_sec)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row)
+}
+
+// Delete buckets idle for longer than the retention, they would be full again anyway
+pub(crate) async fn prune_buckets<'e>(retention: Duration, pool: impl PgExecutor<'e>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+    r#"
+        DELETE FROM rate_limit_buckets
+        WHERE updated_at < now() - make_interval(secs => $1)
+    "#,)
+    .bind(retention.as_secs_f64())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
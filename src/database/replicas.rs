@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{config::{ConfigState, EnvironmentVariables}, database::{begin_tenant, connection::DatabaseOptions}};
+
+// Callers tracked for read-your-writes are pruned once the table grows beyond this size
+const PRUNE_THRESHOLD: usize = 10_000;
+
+// Health checks slower than this mark the replica unhealthy
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Current WAL position of the primary, replicas that replayed up to it are not behind
+const PRIMARY_LSN_QUERY: &str = "SELECT pg_current_wal_lsn()::text";
+
+// Replay lag in seconds, zero on a primary or on a replica that replayed the WAL position $1 of the primary, or
+// everything it received when that position is unknown. NULL when the WAL receiver is not running, a disconnected
+// replica stops receiving and would otherwise look caught up
+const LAG_QUERY: &str = r#"
+    SELECT CASE
+        WHEN NOT pg_is_in_recovery() THEN 0
+        WHEN NOT EXISTS (SELECT 1 FROM pg_stat_wal_receiver) THEN NULL
+        WHEN pg_last_wal_replay_lsn() >= $1::pg_lsn THEN 0
+        WHEN $1::pg_lsn IS NULL AND pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+        ELSE EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp())
+    END::float8
+"#;
+
+#[derive(Debug, Clone)]
+pub struct ReplicaOptions {
+    // Reads of a caller go to the primary for this long after its last write
+    pub sticky_for: Duration,
+    // Replicas further behind are skipped, None disables the lag check
+    pub max_lag: Option<Duration>,
+    pub check_interval: Duration,
+}
+
+struct Replica {
+    name: String,
+    pool: Pool<Postgres>,
+    healthy: AtomicBool,
+}
+
+// Primary pool for writes and replica pools for reads, unhealthy replicas are skipped until they recover
+pub struct ReplicaSet {
+    primary: Pool<Postgres>,
+    replicas: Vec<Replica>,
+    options: ReplicaOptions,
+    next: AtomicUsize,
+    recent_writes: Mutex<HashMap<String, Instant>>,
+}
+
+impl ReplicaSet {
+    pub fn new(primary: Pool<Postgres>, replicas: Vec<(String, Pool<Postgres>)>, options: ReplicaOptions) -> Self {
+        Self {
+            primary,
+            replicas: replicas
+                .into_iter()
+                .map(|(name, pool)| Replica { name, pool, healthy: AtomicBool::new(false) })
+                .collect(),
+            options,
+            next: AtomicUsize::new(0),
+            recent_writes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Replicas of DATABASE_REPLICA_HOSTS share the credentials, database name and pool settings of the primary
+    pub async fn from_env(env: &EnvironmentVariables, database: &DatabaseOptions, primary: &Pool<Postgres>) -> anyhow::Result<Self> {
+        let seconds = |value: &str, name: &str| value.parse::<u64>().map(Duration::from_secs).with_context(|| format!("{name} must be a number of seconds"));
+        let check_interval = seconds(&env.database_replica_check_interval_secs, "DATABASE_REPLICA_CHECK_INTERVAL_SECS")?;
+        if check_interval.is_zero() {
+            bail!("DATABASE_REPLICA_CHECK_INTERVAL_SECS must be greater than zero");
+        }
+        let max_lag = seconds(&env.database_replica_max_lag_secs, "DATABASE_REPLICA_MAX_LAG_SECS")?;
+        let options = ReplicaOptions {
+            sticky_for: seconds(&env.database_replica_sticky_secs, "DATABASE_REPLICA_STICKY_SECS")?,
+            max_lag: (!max_lag.is_zero()).then_some(max_lag),
+            check_interval,
+        };
+
+        let mut replicas = Vec::new();
+        for host in env.database_replica_hosts.split(',').map(str::trim).filter(|host| !host.is_empty()) {
+            let (host, port) = split_host_port(host, database.port)?;
+            let name = format!("{host}:{port}");
+            // Connected lazily so a replica that is down at startup only delays its first health check,
+            // a short acquire timeout lets reads fail over to the primary quickly
+            let pool = database.pool_options()
+                .acquire_timeout(CHECK_TIMEOUT)
+                .connect_lazy_with(database.connect_options(&host, port));
+            replicas.push((name, pool));
+        }
+
+        let replicas = Self::new(primary.clone(), replicas, options);
+        replicas.check_health().await;
+        Ok(replicas)
+    }
+
+    pub fn replica_count(&self) -> usize {
+        self.replicas.len()
+    }
+
+    pub fn healthy_count(&self) -> usize {
+        self.replicas.iter().filter(|replica| replica.healthy.load(Ordering::Relaxed)).count()
+    }
+
+    // Index of the replica serving the next read of `subject`, None routes it to the primary
+    pub fn read_target(&self, subject: &str) -> Option<usize> {
+        if self.replicas.is_empty() || self.wrote_recently(subject) {
+            return None;
+        }
+        // Round robin over the replicas, skipping unhealthy ones
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.replicas.len())
+            .map(|offset| (start + offset) % self.replicas.len())
+            .find(|index| self.replicas[*index].healthy.load(Ordering::Relaxed))
+    }
+
+    // Pool for reads that cannot fall back per request, such as streamed exports
+    pub fn read_pool(&self, subject: &str) -> Pool<Postgres> {
+        match self.read_target(subject) {
+            Some(index) => self.replicas[index].pool.clone(),
+            None => self.primary.clone(),
+        }
+    }
+
+    // Read transaction bound to a tenant, retried on the primary when the replica cannot be reached
+    pub async fn begin_read(&self, subject: &str, org_id: Uuid) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+        let Some(index) = self.read_target(subject) else {
+            return begin_tenant(&self.primary, org_id).await;
+        };
+        let replica = &self.replicas[index];
+        match begin_tenant(&replica.pool, org_id).await {
+            Ok(tx) => Ok(tx),
+            Err(err) => {
+                eprintln!("Replica {} unavailable, reading from the primary: {err}", replica.name);
+                replica.healthy.store(false, Ordering::Relaxed);
+                begin_tenant(&self.primary, org_id).await
+            },
+        }
+    }
+
+    // Send the following reads of `subject` to the primary so it sees its own writes. Only this process knows about
+    // the write, callers balanced across several application instances need sticky sessions to read their writes
+    pub fn record_write(&self, subject: &str) {
+        if self.replicas.is_empty() || self.options.sticky_for.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut recent_writes = self.recent_writes.lock().unwrap();
+        if recent_writes.len() >= PRUNE_THRESHOLD {
+            recent_writes.retain(|_, written| now.duration_since(*written) < self.options.sticky_for);
+        }
+        recent_writes.insert(subject.to_string(), now);
+    }
+
+    fn wrote_recently(&self, subject: &str) -> bool {
+        self.recent_writes
+            .lock()
+            .unwrap()
+            .get(subject)
+            .is_some_and(|written| written.elapsed() < self.options.sticky_for)
+    }
+
+    // Probe every replica, replicas lagging more than the configured maximum count as unhealthy
+    pub async fn check_health(&self) {
+        let primary_lsn = match tokio::time::timeout(CHECK_TIMEOUT, sqlx::query_scalar::<_, String>(PRIMARY_LSN_QUERY).fetch_one(&self.primary)).await {
+            Ok(Ok(lsn)) => Some(lsn),
+            _ => None,
+        };
+        for replica in &self.replicas {
+            let lag = sqlx::query_scalar::<_, Option<f64>>(LAG_QUERY).bind(&primary_lsn).fetch_one(&replica.pool);
+            let healthy = match tokio::time::timeout(CHECK_TIMEOUT, lag).await {
+                Ok(Ok(None)) => {
+                    eprintln!("Replica {} is not receiving WAL from the primary", replica.name);
+                    false
+                },
+                Ok(Ok(Some(lag))) => match self.options.max_lag {
+                    Some(max_lag) if lag > max_lag.as_secs_f64() => {
+                        eprintln!("Replica {} is {lag:.1}s behind the primary", replica.name);
+                        false
+                    },
+                    _ => true,
+                },
+                Ok(Err(err)) => {
+                    eprintln!("Replica {} health check failed: {err}", replica.name);
+                    false
+                },
+                Err(_) => {
+                    eprintln!("Replica {} health check timed out", replica.name);
+                    false
+                },
+            };
+            if replica.healthy.swap(healthy, Ordering::Relaxed) != healthy && healthy {
+                println!("Replica {} is healthy", replica.name);
+            }
+        }
+    }
+}
+
+// `host`, `host:port`, `[v6]` or `[v6]:port`, the port of the primary is the default
+fn split_host_port(value: &str, default_port: u16) -> anyhow::Result<(String, u16)> {
+    let parse_port = |port: &str| port.parse::<u16>().with_context(|| format!("Invalid port in replica address {value}"));
+    if let Some(rest) = value.strip_prefix('[') {
+        let (host, port) = rest.split_once(']').with_context(|| format!("Unterminated IPv6 address in replica address {value}"))?;
+        return match port.strip_prefix(':') {
+            Some(port) => Ok((host.to_string(), parse_port(port)?)),
+            None if port.is_empty() => Ok((host.to_string(), default_port)),
+            None => bail!("Invalid replica address {value}"),
+        };
+    }
+    match value.split_once(':') {
+        Some((host, port)) => Ok((host.to_string(), parse_port(port)?)),
+        None => Ok((value.to_string(), default_port)),
+    }
+}
+
+// Re-check the replicas every DATABASE_REPLICA_CHECK_INTERVAL_SECS
+pub fn spawn_replica_health_checks(config: &Arc<ConfigState>) {
+    if config.replicas.replica_count() == 0 {
+        return;
+    }
+    let config = config.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.replicas.options.check_interval);
+        loop {
+            interval.tick().await;
+            config.replicas.check_health().await;
+        }
+    });
+}
@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use rand::Rng;
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::database::{begin_tenant_repeatable, BEGIN_REPEATABLE_READ};
+
+// Attempts made before a serialization failure is returned to the caller
+pub const MAX_ATTEMPTS: u32 = 3;
+
+// Base delay before a retry, grows with every attempt and is jittered
+const RETRY_DELAY: Duration = Duration::from_millis(20);
+
+// Result of a unit of work, deciding whether its transaction is committed or rolled back
+pub trait WorkOutcome {
+    fn commits(&self) -> bool;
+}
+
+impl<T, E> WorkOutcome for Result<T, E> {
+    fn commits(&self) -> bool {
+        self.is_ok()
+    }
+}
+
+// Serialization failures and deadlocks abort a transaction that succeeds when run again
+pub fn is_retryable(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(db_err) => matches!(db_err.code().as_deref(), Some("40001" | "40P01")),
+        _ => false,
+    }
+}
+
+// Run `work` in a REPEATABLE READ transaction bound to a tenant, committed when its outcome succeeds and rolled back
+// otherwise. A database error rolls the transaction back. Serialization failures, raised when the work updates
+// a row another transaction committed to after the snapshot was taken, and deadlocks start the work over in a
+// fresh transaction, so calls `work` makes outside of the database run again. REPEATABLE READ does not catch
+// two transactions that each read what the other writes, work relying on such reads has to lock the rows.
+// The transaction is handed out as Transaction<'a> so the work may borrow anything outliving the call
+pub async fn unit_of_work<'a, T, F>(pool: &Pool<Postgres>, org_id: Uuid, work: F) -> Result<T, sqlx::Error>
+where
+    T: WorkOutcome,
+    F: for<'c> FnMut(&'c mut Transaction<'a, Postgres>) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+{
+    run(pool, Some(org_id), work).await
+}
+
+// Unit of work for rows found before their tenant is known, such as an invitation redeemed by its token
+pub async fn unit_of_work_without_tenant<'a, T, F>(pool: &Pool<Postgres>, work: F) -> Result<T, sqlx::Error>
+where
+    T: WorkOutcome,
+    F: for<'c> FnMut(&'c mut Transaction<'a, Postgres>) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+{
+    run(pool, None, work).await
+}
+
+async fn run<'a, T, F>(pool: &Pool<Postgres>, org_id: Option<Uuid>, mut work: F) -> Result<T, sqlx::Error>
+where
+    T: WorkOutcome,
+    F: for<'c> FnMut(&'c mut Transaction<'a, Postgres>) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = async {
+            let mut tx: Transaction<'a, Postgres> = match org_id {
+                Some(org_id) => begin_tenant_repeatable(pool, org_id).await?,
+                None => pool.begin_with(BEGIN_REPEATABLE_READ).await?,
+            };
+            // Dropping the transaction on an error rolls it back
+            let outcome = work(&mut tx).await?;
+            match outcome.commits() {
+                true => tx.commit().await?,
+                false => tx.rollback().await?,
+            }
+            Ok(outcome)
+        }
+        .await;
+
+        match result {
+            Err(err) if is_retryable(&err) && attempt < MAX_ATTEMPTS => {
+                let delay = (RETRY_DELAY * attempt).mul_f64(rand::thread_rng().gen_range(0.5..=1.5));
+                eprintln!("Transaction conflict (attempt {attempt}), retrying in {}ms: {err}", delay.as_millis());
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
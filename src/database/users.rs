@@ -1,95 +1,377 @@
-use sqlx::{Pool, Postgres};
-use crate::definitions::user::{User, NewUser};
+use async_stream::try_stream;
+use futures::{Stream, TryStreamExt};
+use serde_json::Value;
+use sqlx::{PgExecutor, Pool, Postgres, QueryBuilder};
+use crate::{custom::telemetry::{timed_query, timed_stream}, database::{begin_all_tenants, begin_tenant}, definitions::{keycloak::LinkedUser, outbox::{USER_CREATED, USER_DELETED, USER_UPDATED}, user::{User, NewUser, UserFilter, UserSearchRow}}};
 use uuid::Uuid;
 
-pub(crate) async fn find_user(user_id: Uuid, pool: &Pool<Postgres>) -> Result<Option<User>, sqlx::Error> {
-    let row= sqlx::query_as::<_, User>(
+// Columns of a User row, shared by the queries assembled at runtime
+const USER_COLUMNS: &str = "user_id, username, email, attributes, enabled";
+
+// Every change below writes its domain event to the outbox in the same statement, so an event exists
+// exactly when the change is committed. The payload is the user row the statement returns
+
+pub(crate) async fn find_user<'e>(user_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<User>, sqlx::Error> {
+    let row= timed_query("find_user", sqlx::query_as!(User,
     r#"
-        SELECT user_id, username, email
+        SELECT user_id, username, email, attributes, enabled
         FROM users
-        WHERE user_id = $1
-    "#,)
-    .bind(user_id)
-    .fetch_optional(pool)
+        WHERE user_id = $1 AND org_id = $2
+    "#,
+    user_id,
+    org_id,
+    )
+    .fetch_optional(pool))
     .await?;
     
     Ok(row)
 }
 
-pub async fn create_user(
-    user: User, pool: &Pool<Postgres>
+// Keycloak account of a user, None until the account is created or found through the username
+pub(crate) async fn find_user_keycloak_id<'e>(user_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<Uuid>, sqlx::Error> {
+    let keycloak_id = sqlx::query_scalar::<_, Option<Uuid>>("SELECT keycloak_id FROM users WHERE user_id = $1 AND org_id = $2")
+        .bind(user_id)
+        .bind(org_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(keycloak_id.flatten())
+}
+
+pub(crate) async fn set_user_keycloak_id<'e>(user_id: Uuid, org_id: Uuid, keycloak_id: Uuid, pool: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET keycloak_id = $1 WHERE user_id = $2 AND org_id = $3")
+        .bind(keycloak_id)
+        .bind(user_id)
+        .bind(org_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn create_user<'e>(
+    user: User, org_id: Uuid, pool: impl PgExecutor<'e>
 ) -> Result<Option<User>, sqlx::Error> {
     // Insert the user into the database
-    let result = sqlx::query_as::<_, User>(
+    let result = timed_query("create_user", sqlx::query_as!(User,
         r#"
-        INSERT INTO users (user_id, username, email)
-        VALUES ($1, $2, $3)
-        RETURNING user_id, username, email
+        WITH created AS (
+            INSERT INTO users (user_id, username, email, attributes, enabled, org_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING user_id, username, email, attributes, enabled
+        ), event AS (
+            INSERT INTO outbox_events (event_type, aggregate_id, org_id, payload)
+            SELECT $7, user_id, $6, to_jsonb(created) FROM created
+        )
+        SELECT user_id AS "user_id!", username AS "username!", email, attributes AS "attributes!", enabled AS "enabled!"
+        FROM created
         "#,
+        user.user_id, // Bind the user_id
+        user.username, // Bind the username
+        user.email, // Bind the email
+        user.attributes, // Bind the custom attributes
+        user.enabled, // Bind the enabled flag
+        org_id, // Bind the owning organization
+        USER_CREATED, // Bind the event type
     )
-    .bind(user.user_id) // Bind the user_id
-    .bind(user.username) // Bind the username
-    .bind(user.email) // Bind the username
-    .fetch_optional(pool) // Fetch the inserted row
+    .fetch_optional(pool)) // Fetch the inserted row
     .await?;
 
     Ok(result) // Return the inserted user
 }
-pub async fn update_user(
+// Insert a user or overwrite the row with the same user_id in the organization
+pub async fn upsert_user<'e>(
+    user: User, org_id: Uuid, pool: impl PgExecutor<'e>
+) -> Result<User, sqlx::Error> {
+    let result = timed_query("upsert_user", sqlx::query_as!(User,
+        r#"
+        WITH saved AS (
+            INSERT INTO users (user_id, username, email, attributes, enabled, org_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (org_id, user_id) DO UPDATE
+            SET username = EXCLUDED.username, email = EXCLUDED.email, attributes = EXCLUDED.attributes, enabled = EXCLUDED.enabled
+            -- xmax is only zero on freshly inserted rows
+            RETURNING user_id, username, email, attributes, enabled, xmax = 0 AS inserted
+        ), event AS (
+            INSERT INTO outbox_events (event_type, aggregate_id, org_id, payload)
+            SELECT CASE WHEN inserted THEN $7 ELSE $8 END, user_id, $6, to_jsonb(saved) - 'inserted' FROM saved
+        )
+        SELECT user_id AS "user_id!", username AS "username!", email, attributes AS "attributes!", enabled AS "enabled!"
+        FROM saved
+        "#,
+        user.user_id,
+        user.username,
+        user.email,
+        user.attributes,
+        user.enabled,
+        org_id,
+        USER_CREATED,
+        USER_UPDATED,
+    )
+    .fetch_one(pool))
+    .await?;
+
+    Ok(result)
+}
+
+pub async fn update_user<'e>(
     user_id_in: Uuid,
+    org_id: Uuid,
     new_user: NewUser,
-    pool: &Pool<Postgres>,
+    pool: impl PgExecutor<'e>,
 ) -> Result<Option<User>, sqlx::Error> {
-    // Start building the query
-    let mut query = String::from("UPDATE users SET ");
-    let mut params: Vec<String> = Vec::new();
-    let mut bindings: Vec<String> = Vec::new();
-
-    // Dynamically add fields to update based on the NewUser struct
-    if let Some(username) = new_user.username {
-        if !username.trim().is_empty() {
-            params.push("username = $1".to_string());
-            bindings.push(username);
-        }
-    }
-
-    if let Some(email) = new_user.email {
-        if !email.trim().is_empty() {
-            params.push("email = $2".to_string());
-            bindings.push(email);
-        }
-    }
+    // Blank fields are left untouched
+    let username = new_user.username.filter(|username| !username.trim().is_empty());
+    let email = new_user.email.filter(|email| !email.trim().is_empty());
+    let attributes = new_user.attributes.filter(|attributes| !attributes.is_empty());
 
     // If no fields to update, return None
-    if params.is_empty() {
+    if username.is_none() && email.is_none() && attributes.is_none() {
         return Ok(None);
     }
 
-    // Complete the query
-    query.push_str(&params.join(", "));
-    query.push_str(" WHERE user_id = $3 RETURNING user_id, username, email");
+    // Only the provided columns are assigned, every value is sent as a bind parameter
+    let mut query = QueryBuilder::<Postgres>::new("WITH updated AS (UPDATE users SET ");
+    let mut assignments = query.separated(", ");
+    if let Some(username) = username {
+        assignments.push("username = ").push_bind_unseparated(username);
+    }
+    if let Some(email) = email {
+        assignments.push("email = ").push_bind_unseparated(email);
+    }
+    // Attributes are merged into the stored ones, top-level null values remove a key while nulls nested
+    // inside an object value are stored as given
+    if let Some(mut attributes) = attributes {
+        let removed: Vec<String> = attributes.iter().filter(|(_, value)| value.is_null()).map(|(key, _)| key.clone()).collect();
+        attributes.retain(|_, value| !value.is_null());
+        assignments.push("attributes = (attributes || ")
+            .push_bind_unseparated(Value::Object(attributes))
+            .push_unseparated(") - ")
+            .push_bind_unseparated(removed)
+            .push_unseparated("::text[]");
+    }
+    query.push(" WHERE user_id = ").push_bind(user_id_in);
+    query.push(" AND org_id = ").push_bind(org_id);
+    query.push(format!(" RETURNING {USER_COLUMNS}), event AS (INSERT INTO outbox_events (event_type, aggregate_id, org_id, payload) SELECT "));
+    query.push_bind(USER_UPDATED).push(", user_id, ").push_bind(org_id);
+    query.push(format!(", to_jsonb(updated) FROM updated) SELECT {USER_COLUMNS} FROM updated"));
 
-    // Execute the query, binding the appropriate parameters
-    let result = sqlx::query_as::<_, User>(&query)
-        .bind(bindings.get(0).unwrap()) // Bind the first parameter (username)
-        .bind(bindings.get(1).unwrap_or(&"".to_string())) // Bind second parameter (if exists)
-        .bind(user_id_in) // Bind user_id
-        .fetch_optional(pool)
-        .await?;
+    let result = timed_query("update_user", query.build_query_as::<User>().fetch_optional(pool)).await?;
 
     Ok(result)
 }
 
-pub(crate) async fn remove_user(user_id: Uuid, pool: &Pool<Postgres>) -> Result<Option<User>, sqlx::Error> {
-    let row= sqlx::query_as::<_, User>(
+pub(crate) async fn remove_user<'e>(user_id: Uuid, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<User>, sqlx::Error> {
+    let row= timed_query("remove_user", sqlx::query_as!(User,
     r#"
-        DELETE FROM users
-        WHERE user_id = $1
-        RETURNING user_id, username, email
-    "#,)
-    .bind(user_id)
-    .fetch_optional(pool)
+        WITH deleted AS (
+            DELETE FROM users
+            WHERE user_id = $1 AND org_id = $2
+            RETURNING user_id, username, email, attributes, enabled
+        ), event AS (
+            INSERT INTO outbox_events (event_type, aggregate_id, org_id, payload)
+            SELECT $3, user_id, $2, to_jsonb(deleted) FROM deleted
+        )
+        SELECT user_id AS "user_id!", username AS "username!", email, attributes AS "attributes!", enabled AS "enabled!"
+        FROM deleted
+    "#,
+    user_id,
+    org_id,
+    USER_DELETED,
+    )
+    .fetch_optional(pool))
     .await?;
     
     Ok(row)
 }
+
+pub(crate) async fn set_user_enabled<'e>(user_id: Uuid, org_id: Uuid, enabled: bool, pool: impl PgExecutor<'e>) -> Result<Option<User>, sqlx::Error> {
+    let row = timed_query("set_user_enabled", sqlx::query_as!(User,
+    r#"
+        WITH updated AS (
+            UPDATE users
+            SET enabled = $1
+            WHERE user_id = $2 AND org_id = $3
+            RETURNING user_id, username, email, attributes, enabled
+        ), event AS (
+            INSERT INTO outbox_events (event_type, aggregate_id, org_id, payload)
+            SELECT $4, user_id, $3, to_jsonb(updated) FROM updated
+        )
+        SELECT user_id AS "user_id!", username AS "username!", email, attributes AS "attributes!", enabled AS "enabled!"
+        FROM updated
+    "#,
+    enabled,
+    user_id,
+    org_id,
+    USER_UPDATED,
+    )
+    .fetch_optional(pool))
+    .await?;
+
+    Ok(row)
+}
+
+// Put back the previous state of a user, unless the row changed again since `current` was written
+pub(crate) async fn restore_user<'e>(previous: &User, current: &User, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<Option<User>, sqlx::Error> {
+    let row = timed_query("restore_user", sqlx::query_as!(User,
+    r#"
+        WITH restored AS (
+            UPDATE users
+            SET username = $1, email = $2, attributes = $3, enabled = $4
+            WHERE user_id = $5 AND org_id = $6
+                AND username = $7 AND email IS NOT DISTINCT FROM $8 AND attributes = $9 AND enabled = $10
+            RETURNING user_id, username, email, attributes, enabled
+        ), event AS (
+            INSERT INTO outbox_events (event_type, aggregate_id, org_id, payload)
+            SELECT $11, user_id, $6, to_jsonb(restored) FROM restored
+        )
+        SELECT user_id AS "user_id!", username AS "username!", email, attributes AS "attributes!", enabled AS "enabled!"
+        FROM restored
+    "#,
+    previous.username,
+    previous.email,
+    previous.attributes,
+    previous.enabled,
+    current.user_id,
+    org_id,
+    current.username,
+    current.email,
+    current.attributes,
+    current.enabled,
+    USER_UPDATED,
+    )
+    .fetch_optional(pool))
+    .await?;
+
+    Ok(row)
+}
+
+// Stream the users of every organization with their Keycloak accounts, used to compare the table against Keycloak
+pub(crate) fn stream_all_users(pool: Pool<Postgres>) -> impl Stream<Item = Result<LinkedUser, sqlx::Error>> {
+    timed_stream("stream_all_users", try_stream! {
+        let mut tx = begin_all_tenants(&pool).await?;
+        let mut rows = sqlx::query!(
+        r#"
+            SELECT org_id, keycloak_id, user_id, username, email, attributes, enabled
+            FROM users
+            ORDER BY user_id, org_id
+        "#,)
+        .fetch(&mut *tx);
+
+        while let Some(row) = rows.try_next().await? {
+            yield LinkedUser {
+                org_id: row.org_id,
+                keycloak_id: row.keycloak_id,
+                user: User { user_id: row.user_id, username: row.username, email: row.email, attributes: row.attributes, enabled: row.enabled },
+            };
+        }
+        drop(rows);
+        tx.commit().await?;
+    })
+}
+
+// Stream the user rows of an organization matching `filter` without loading the table into memory
+pub(crate) fn stream_users(org_id: Uuid, filter: UserFilter, pool: Pool<Postgres>) -> impl Stream<Item = Result<User, sqlx::Error>> {
+    timed_stream("stream_users", try_stream! {
+        let mut tx = begin_tenant(&pool, org_id).await?;
+        let mut query = QueryBuilder::<Postgres>::new(format!("SELECT {USER_COLUMNS} FROM users WHERE org_id = "));
+        query.push_bind(org_id);
+        push_user_filter(&mut query, filter);
+        query.push(" ORDER BY user_id");
+        let mut rows = query.build_query_as::<User>().fetch(&mut *tx);
+
+        while let Some(user) = rows.try_next().await? {
+            yield user;
+        }
+        drop(rows);
+        tx.commit().await?;
+    })
+}
+
+// Append the set conditions of `filter` to a WHERE clause
+fn push_user_filter(query: &mut QueryBuilder<'_, Postgres>, filter: UserFilter) {
+    if let Some(enabled) = filter.enabled {
+        query.push(" AND enabled = ").push_bind(enabled);
+    }
+    if let Some(domain) = filter.email_domain.map(|domain| domain.trim().trim_start_matches('@').to_lowercase()) {
+        query.push(" AND lower(split_part(email, '@', 2)) = ").push_bind(domain);
+    }
+}
+
+// Rank users by full-text match on whole words and trigram similarity on fragments.
+// Highlights are built from HTML-escaped values so only the <mark> tags are markup
+pub(crate) async fn search_users<'e>(
+    term: &str,
+    org_id: Uuid,
+    limit: i64,
+    offset: i64,
+    pool: impl PgExecutor<'e>,
+) -> Result<Vec<UserSearchRow>, sqlx::Error> {
+    let rows = timed_query("search_users", sqlx::query_as!(UserSearchRow,
+    r#"
+        WITH search AS (
+            SELECT to_tsquery('simple', $2) AS query, $1::text AS term
+        )
+        SELECT
+            user_id,
+            username,
+            email,
+            attributes,
+            enabled,
+            (ts_rank(search_vector, search.query)
+                + greatest(word_similarity(search.term, username), word_similarity(search.term, coalesce(email, ''))))::real AS "rank!",
+            ts_headline('simple', replace(replace(replace(replace(username, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), search.query, 'StartSel=<mark>, StopSel=</mark>, HighlightAll=true') AS "username_highlight!",
+            ts_headline('simple', replace(replace(replace(replace(email, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), search.query, 'StartSel=<mark>, StopSel=</mark>, HighlightAll=true') AS email_highlight,
+            COUNT(*) OVER () AS "total!"
+        FROM users, search
+        WHERE org_id = $5
+            AND (search_vector @@ search.query
+                OR search.term <% username
+                OR search.term <% email)
+        ORDER BY "rank!" DESC, user_id
+        LIMIT $3 OFFSET $4
+    "#,
+    term,
+    prefix_tsquery(term),
+    limit,
+    offset,
+    org_id,
+    )
+    .fetch_all(pool))
+    .await?;
+
+    Ok(rows)
+}
+
+// Number of users matching a search, for pages past the last match where no row carries the total
+pub(crate) async fn count_search_matches<'e>(term: &str, org_id: Uuid, pool: impl PgExecutor<'e>) -> Result<i64, sqlx::Error> {
+    let total = timed_query("count_search_matches", sqlx::query_scalar!(
+    r#"
+        WITH search AS (
+            SELECT to_tsquery('simple', $2) AS query, $1::text AS term
+        )
+        SELECT COUNT(*) AS "total!"
+        FROM users, search
+        WHERE org_id = $3
+            AND (search_vector @@ search.query
+                OR search.term <% username
+                OR search.term <% email)
+    "#,
+    term,
+    prefix_tsquery(term),
+    org_id,
+    )
+    .fetch_one(pool))
+    .await?;
+
+    Ok(total)
+}
+
+// Build a prefix tsquery from free text, dropping characters with tsquery meaning
+fn prefix_tsquery(term: &str) -> String {
+    term.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("{word}:*"))
+        .collect::<Vec<String>>()
+        .join(" & ")
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+// Keys look like `ak_<key_id>_<secret>`, the id locates the row and the secret is checked against its hash
+pub const API_KEY_PREFIX: &str = "ak_";
+// Header carrying an API key, `Authorization: ApiKey <key>` is accepted as well
+pub const API_KEY_HEADER: &str = "x-api-key";
+// Longest lifetime an API key can be created with
+pub const MAX_API_KEY_TTL_DAYS: i64 = 365;
+// Longest time the secret replaced by a rotation keeps working
+pub const MAX_ROTATION_GRACE_SECS: i64 = 7 * 24 * 3600;
+// last_used_at is written at most once per interval to keep authentication read-mostly
+pub const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+// API key metadata, the secret and its hashes are never serialized
+#[derive(Serialize, FromRow, Debug, Clone)]
+pub(crate) struct ApiKey {
+    pub(crate) key_id: Uuid,
+    #[serde(skip)]
+    pub(crate) org_id: Uuid,
+    pub(crate) name: String,
+    pub(crate) roles: Vec<String>,
+    #[serde(skip)]
+    pub(crate) secret_hash: Vec<u8>,
+    #[serde(skip)]
+    pub(crate) previous_hash: Option<Vec<u8>>,
+    pub(crate) previous_expires_at: Option<DateTime<Utc>>,
+    pub(crate) created_by: String,
+    pub(crate) expires_at: Option<DateTime<Utc>>,
+    pub(crate) last_used_at: Option<DateTime<Utc>>,
+    pub(crate) rotated_at: Option<DateTime<Utc>>,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+// API key row carrying the size of the full result set
+#[derive(FromRow, Debug)]
+pub(crate) struct ApiKeyPageRow {
+    #[sqlx(flatten)]
+    pub(crate) api_key: ApiKey,
+    pub(crate) total: i64,
+}
+
+// Payload of POST /api-keys
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub(crate) name: String,
+    // Realm roles granted to the key, limited to the roles of the caller
+    #[serde(default)]
+    pub(crate) roles: Vec<String>,
+    // Keys without an expiry stay valid until they are deleted
+    pub(crate) expires_in_days: Option<i64>,
+}
+
+// Payload of POST /api-keys/{id}/rotate
+#[derive(Debug, Deserialize, Default)]
+pub struct RotateApiKey {
+    // How long the replaced secret keeps working, defaults to no overlap
+    #[serde(default)]
+    pub(crate) grace_period_secs: i64,
+}
+
+// Authenticated API key, bound to the organization it was created in
+#[derive(Debug, Clone, Copy)]
+pub struct ApiKeyIdentity {
+    pub org_id: Uuid,
+}
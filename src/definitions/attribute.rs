@@ -0,0 +1,118 @@
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::FromRow;
+
+// Age after which the cached User schema is rebuilt, so instances that did not handle a definition change catch up
+const USER_SCHEMA_TTL: Duration = Duration::from_secs(60);
+
+// Prefix of the unique indexes backing unique attributes
+pub const UNIQUE_ATTRIBUTE_INDEX_PREFIX: &str = "users_attribute_unique_";
+
+// JSON type an attribute value must have
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum AttributeType {
+    String,
+    Number,
+    Boolean,
+}
+
+// Who can read an attribute besides administrators
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum AttributeVisibility {
+    // Visible to the user the attribute belongs to
+    #[serde(rename = "self")]
+    #[sqlx(rename = "self")]
+    Owner,
+    // Visible to administrators only
+    Admin,
+}
+
+// Admin-defined custom attribute of a user
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct AttributeDefinition {
+    pub name: String,
+    pub attribute_type: AttributeType,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub is_unique: bool,
+    pub pattern: Option<String>,
+    #[serde(default = "default_visibility")]
+    pub visibility: AttributeVisibility,
+    pub description: Option<String>,
+}
+
+fn default_visibility() -> AttributeVisibility {
+    AttributeVisibility::Admin
+}
+
+impl AttributeDefinition {
+    // Name of the unique index enforcing this attribute
+    pub fn unique_index_name(&self) -> String {
+        format!("{UNIQUE_ATTRIBUTE_INDEX_PREFIX}{}", self.name)
+    }
+
+    // JSON schema of the attribute value, used in the OpenAPI document
+    pub fn json_schema(&self) -> Value {
+        let mut schema = json!({ "type": self.attribute_type });
+        if let Some(pattern) = &self.pattern {
+            schema["pattern"] = json!(pattern);
+        }
+        if let Some(description) = &self.description {
+            schema["description"] = json!(description);
+        }
+        schema
+    }
+}
+
+// JSON schema of the `attributes` object for the current set of definitions
+pub fn attributes_schema(definitions: &[AttributeDefinition]) -> Value {
+    let properties: Map<String, Value> = definitions
+        .iter()
+        .map(|definition| (definition.name.clone(), definition.json_schema()))
+        .collect();
+    let required: Vec<&str> = definitions
+        .iter()
+        .filter(|definition| definition.required)
+        .map(|definition| definition.name.as_str())
+        .collect();
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+// User schema of /api.json, built from the definitions and dropped whenever they change
+#[derive(Default)]
+pub struct UserSchemaCache {
+    schema: RwLock<Option<(Instant, Value)>>,
+}
+
+impl UserSchemaCache {
+    pub fn get(&self) -> Option<Value> {
+        match &*self.schema.read().unwrap() {
+            Some((built, schema)) if built.elapsed() < USER_SCHEMA_TTL => Some(schema.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set(&self, schema: Value) {
+        *self.schema.write().unwrap() = Some((Instant::now(), schema));
+    }
+
+    pub fn invalidate(&self) {
+        *self.schema.write().unwrap() = None;
+    }
+}
@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
 
 // Custom User struct for manual UUID validation
 #[derive(Debug, Deserialize)]
@@ -21,6 +23,30 @@ pub struct TokenResponse {
     pub scope: String,
 }
 
+// Token issued to the client's own service account through the client_credentials grant
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ServiceTokenResponse {
+    pub access_token: String,
+    pub expires_in: u64,
+}
+
+// Payload of POST /token, exchanged for a token through the client_credentials grant
+#[derive(Debug, Deserialize)]
+pub struct ClientCredentials {
+    pub(crate) client_id: String,
+    pub(crate) client_secret: String,
+    pub(crate) scope: Option<String>,
+}
+
+// Service account token returned by POST /token, there is no refresh token for this grant
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ClientTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    #[serde(default)]
+    pub scope: String,
+}
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct LoginResponse {
@@ -29,4 +55,33 @@ pub struct LoginResponse {
     pub expires_in: u16,
     pub refresh_token: String, 
 	pub refresh_expires_in: u16,
-}
\ No newline at end of file
+}
+
+// Account of the local authentication provider
+#[derive(FromRow, Debug, Clone)]
+pub(crate) struct LocalAccount {
+    pub(crate) subject: Uuid,
+    pub(crate) username: String,
+    pub(crate) email: Option<String>,
+    pub(crate) password_hash: String,
+    pub(crate) roles: Vec<String>,
+}
+
+// Claims of tokens issued by the local provider, shaped like Keycloak access tokens
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct LocalClaims {
+    pub(crate) iss: String,
+    pub(crate) sub: Uuid,
+    pub(crate) aud: String,
+    pub(crate) exp: i64,
+    pub(crate) iat: i64,
+    pub(crate) jti: Uuid,
+    pub(crate) preferred_username: String,
+    pub(crate) email: Option<String>,
+    pub(crate) realm_access: RealmAccess,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct RealmAccess {
+    pub(crate) roles: Vec<String>,
+}
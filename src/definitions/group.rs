@@ -0,0 +1,96 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+// Role a member holds within a group
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum MembershipRole {
+    // Can rename the group and manage its members
+    Owner,
+    #[default]
+    Member,
+}
+
+// Group Struct
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub(crate) struct Group {
+    pub(crate) group_id: Uuid,
+    pub(crate) parent_id: Option<Uuid>,
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) keycloak_id: Option<String>,
+}
+
+// Group row carrying the size of the full result set
+#[derive(FromRow, Debug)]
+pub(crate) struct GroupPageRow {
+    #[sqlx(flatten)]
+    pub(crate) group: Group,
+    pub(crate) total: i64,
+}
+
+// Payload for creating and updating groups
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewGroup {
+    pub(crate) name: Option<String>,
+    pub(crate) description: Option<String>,
+    // Absent keeps the current parent, null moves the group to the top level
+    #[serde(default, deserialize_with = "present")]
+    pub(crate) parent_id: Option<Option<Uuid>>,
+}
+
+// Distinguish an explicit null from a missing field
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// Query parameters of GET /groups
+#[derive(Debug, Deserialize)]
+pub struct GroupListQuery {
+    // Only list direct children of this group, top-level groups are listed when absent
+    pub parent_id: Option<Uuid>,
+}
+
+// Payload for adding a member or changing their role
+#[derive(Debug, Deserialize, Default)]
+pub struct NewMembership {
+    #[serde(default)]
+    pub(crate) role: MembershipRole,
+}
+
+// Query parameters of GET /groups/{id}/members
+#[derive(Debug, Deserialize)]
+pub struct MemberListQuery {
+    // Include members of every descendant group
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+// Member of a group, as listed under the group
+#[derive(Serialize, FromRow, Debug)]
+pub(crate) struct GroupMember {
+    pub(crate) group_id: Uuid,
+    pub(crate) user_id: Uuid,
+    pub(crate) username: String,
+    pub(crate) role: MembershipRole,
+    #[serde(skip)]
+    pub(crate) total: i64,
+}
+
+// Group the caller belongs to, either directly or through a descendant group
+#[derive(Serialize, FromRow, Debug)]
+pub(crate) struct UserGroup {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub(crate) group: Group,
+    // Role held in the group, None for groups inherited from a subgroup membership
+    pub(crate) role: Option<MembershipRole>,
+    pub(crate) inherited: bool,
+}
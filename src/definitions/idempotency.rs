@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use serde_json::Value;
+use sqlx::FromRow;
+
+use crate::config::EnvironmentVariables;
+
+// Header carrying the client chosen key of an unsafe request
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+// Set on responses replayed from a stored result
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+// Longest key accepted, matches the column width
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+// Stored outcome of a request, the response fields are None while it is running
+#[derive(FromRow, Debug)]
+pub(crate) struct IdempotencyRecord {
+    pub(crate) fingerprint: Vec<u8>,
+    pub(crate) status_code: Option<i16>,
+    pub(crate) response_headers: Option<Value>,
+    pub(crate) response_body: Option<Vec<u8>>,
+}
+
+// How long results are kept, how long a running request holds its key and the largest body that is fingerprinted
+#[derive(Debug, Clone, Copy)]
+pub struct IdempotencyOptions {
+    pub ttl: Duration,
+    pub lock: Duration,
+    pub max_body_bytes: usize,
+}
+
+impl IdempotencyOptions {
+    pub fn from_env(env: &EnvironmentVariables) -> anyhow::Result<Self> {
+        Ok(Self {
+            ttl: Duration::from_secs(env.idempotency_ttl_secs.parse().context("IDEMPOTENCY_TTL_SECS must be a number of seconds")?),
+            lock: Duration::from_secs(env.idempotency_lock_secs.parse().context("IDEMPOTENCY_LOCK_SECS must be a number of seconds")?),
+            max_body_bytes: env.idempotency_max_body_bytes.parse().context("IDEMPOTENCY_MAX_BODY_BYTES must be a number")?,
+        })
+    }
+}
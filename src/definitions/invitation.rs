@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+// Lifetime of an invitation unless the request asks for another one
+pub const DEFAULT_INVITATION_TTL_HOURS: i64 = 72;
+pub const MAX_INVITATION_TTL_HOURS: i64 = 720;
+
+// State of an invitation, only pending invitations can be accepted
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum InvitationStatus {
+    Pending,
+    Accepted,
+    Revoked,
+}
+
+// Invitation Struct
+#[derive(Serialize, FromRow, Debug, Clone)]
+pub(crate) struct Invitation {
+    pub(crate) invitation_id: Uuid,
+    #[serde(skip)]
+    pub(crate) org_id: Uuid,
+    pub(crate) email: String,
+    pub(crate) username: Option<String>,
+    pub(crate) attributes: Value,
+    pub(crate) status: InvitationStatus,
+    #[serde(skip)]
+    pub(crate) token_version: i32,
+    pub(crate) invited_by: String,
+    pub(crate) expires_at: DateTime<Utc>,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) accepted_at: Option<DateTime<Utc>>,
+    pub(crate) user_id: Option<Uuid>,
+}
+
+// Invitation row carrying the size of the full result set
+#[derive(FromRow, Debug)]
+pub(crate) struct InvitationPageRow {
+    #[sqlx(flatten)]
+    pub(crate) invitation: Invitation,
+    pub(crate) total: i64,
+}
+
+// Payload of POST /invitations
+#[derive(Debug, Deserialize)]
+pub struct NewInvitation {
+    pub(crate) email: Option<String>,
+    pub(crate) username: Option<String>,
+    pub(crate) attributes: Option<Map<String, Value>>,
+    pub(crate) expires_in_hours: Option<i64>,
+}
+
+// Payload of POST /invitations/{id}/resend
+#[derive(Debug, Deserialize, Default)]
+pub struct ResendInvitation {
+    pub(crate) expires_in_hours: Option<i64>,
+}
+
+// Query parameters of GET /invitations
+#[derive(Debug, Deserialize)]
+pub struct InvitationListQuery {
+    pub status: Option<InvitationStatus>,
+}
+
+// Payload of the public POST /invitations/accept
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitation {
+    pub(crate) token: String,
+    // Overrides the username chosen by the inviting administrator
+    pub(crate) username: Option<String>,
+    pub(crate) password: String,
+    // Required attributes the inviting administrator left unset, nothing else may be set by the invitee
+    pub(crate) attributes: Option<Map<String, Value>>,
+}
+
+// Signed content of an invitation token
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct InvitationClaims {
+    pub(crate) invitation_id: Uuid,
+    pub(crate) version: i32,
+    // Expiry as a unix timestamp
+    pub(crate) exp: i64,
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::user::User;
+
+// Prefix of the users Keycloak creates for client service accounts, they never have a local row
+pub const SERVICE_ACCOUNT_PREFIX: &str = "service-account-";
+
+// User as returned by the Keycloak admin API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct KeycloakUser {
+    pub(crate) id: Uuid,
+    pub(crate) username: String,
+    pub(crate) email: Option<String>,
+    #[serde(default)]
+    pub(crate) enabled: bool,
+}
+
+// Local user with its organization and linked Keycloak account, None until the account was created or found
+#[derive(Debug)]
+pub(crate) struct LinkedUser {
+    pub(crate) org_id: Uuid,
+    pub(crate) keycloak_id: Option<Uuid>,
+    pub(crate) user: User,
+}
+
+// Query parameters of POST /keycloak/reconcile
+#[derive(Debug, Deserialize)]
+pub struct ReconcileQuery {
+    // Push local users to Keycloak instead of only reporting drift
+    #[serde(default)]
+    pub fix: bool,
+}
+
+// Local user whose Keycloak counterpart has diverged
+#[derive(Serialize, Debug)]
+pub(crate) struct UserDrift {
+    pub(crate) user_id: Uuid,
+    pub(crate) fields: Vec<&'static str>,
+}
+
+// Differences between the users table and the Keycloak realm, the local table is the source of truth
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct DriftReport {
+    pub(crate) checked: usize,
+    pub(crate) missing_in_keycloak: Vec<Uuid>,
+    // Reported only, Keycloak users without a local row are never removed automatically
+    pub(crate) missing_locally: Vec<KeycloakUser>,
+    pub(crate) mismatched: Vec<UserDrift>,
+    pub(crate) fixed: usize,
+    pub(crate) errors: Vec<String>,
+}
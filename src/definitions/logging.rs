@@ -1,5 +1,7 @@
 pub fn get_included_paths() -> Vec<String> {
     vec![
-        "/metrics".to_string()
+        "/metrics".to_string(),
+        "/health/live".to_string(),
+        "/health/ready".to_string(),
     ]
 }
\ No newline at end of file
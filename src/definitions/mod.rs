@@ -1,3 +1,14 @@
 pub mod user;
+pub mod api_key;
 pub mod auth;
-pub mod logging;
\ No newline at end of file
+pub mod group;
+pub mod idempotency;
+pub mod invitation;
+pub mod keycloak;
+pub mod attribute;
+pub mod logging;
+pub mod organization;
+pub mod outbox;
+pub mod pagination;
+pub mod tls;
+pub mod transfer;
\ No newline at end of file
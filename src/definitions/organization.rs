@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+// Organization of callers whose credentials carry no tenant
+pub const DEFAULT_ORGANIZATION_ID: Uuid = Uuid::nil();
+
+// Organization Struct
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone)]
+pub struct Organization {
+    pub org_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+// Payload for creating and updating organizations
+#[derive(Debug, Deserialize)]
+pub struct NewOrganization {
+    pub(crate) slug: Option<String>,
+    pub(crate) name: Option<String>,
+}
+
+// Request input naming a tenant, checked against the tenant of the credentials
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TenantSource {
+    Header,
+    Subdomain,
+}
+
+impl TenantSource {
+    // Parse the comma separated TENANT_SOURCES list, unknown entries (including the former `claim`) are ignored
+    pub fn parse_list(sources: &str) -> Vec<Self> {
+        sources
+            .split(',')
+            .filter_map(|source| match source.trim() {
+                "header" => Some(TenantSource::Header),
+                "subdomain" => Some(TenantSource::Subdomain),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::config::EnvironmentVariables;
+
+// Event types written by the user queries
+pub const USER_CREATED: &str = "UserCreated";
+pub const USER_UPDATED: &str = "UserUpdated";
+pub const USER_DELETED: &str = "UserDeleted";
+
+// Pending event claimed by the relay
+#[derive(FromRow, Debug, Clone)]
+pub(crate) struct OutboxEvent {
+    pub(crate) event_id: Uuid,
+    pub(crate) event_type: String,
+    pub(crate) aggregate_id: Uuid,
+    pub(crate) org_id: Uuid,
+    // The user row after the change, or before it for UserDeleted
+    pub(crate) payload: Value,
+    pub(crate) occurred_at: DateTime<Utc>,
+    // Failed deliveries so far
+    pub(crate) attempts: i32,
+}
+
+impl OutboxEvent {
+    // Message handed to every sink, consumers deduplicate redeliveries by event_id
+    pub(crate) fn envelope(&self) -> Value {
+        json!({
+            "event_id": self.event_id,
+            "type": self.event_type,
+            "aggregate_id": self.aggregate_id,
+            "org_id": self.org_id,
+            "occurred_at": self.occurred_at,
+            "data": self.payload,
+        })
+    }
+}
+
+// Event that exhausted its delivery attempts, it holds back the later events of its user until it is requeued or discarded
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub(crate) struct DeadLetter {
+    pub(crate) event_id: Uuid,
+    pub(crate) event_type: String,
+    pub(crate) aggregate_id: Uuid,
+    pub(crate) occurred_at: DateTime<Utc>,
+    pub(crate) attempts: i32,
+    pub(crate) last_error: Option<String>,
+    pub(crate) dead_lettered_at: DateTime<Utc>,
+}
+
+// Dead letter row carrying the size of the full result set
+#[derive(FromRow, Debug)]
+pub(crate) struct DeadLetterPageRow {
+    #[sqlx(flatten)]
+    pub(crate) dead_letter: DeadLetter,
+    pub(crate) total: i64,
+}
+
+// Polling, retry and retention settings of the outbox relay
+#[derive(Debug, Clone, Copy)]
+pub struct OutboxOptions {
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    // Time a relay has to deliver a claimed batch before other relays may claim its events
+    pub lease: Duration,
+    // Deliveries attempted before an event is dead-lettered
+    pub max_attempts: i32,
+    // Delay after the first failure, doubled for every further failure
+    pub retry_delay: Duration,
+    // Delivered events are deleted after this long, None keeps them
+    pub retention: Option<Duration>,
+}
+
+impl OutboxOptions {
+    pub fn from_env(env: &EnvironmentVariables) -> anyhow::Result<Self> {
+        let poll_interval: u64 = env.outbox_poll_interval_ms.parse().context("OUTBOX_POLL_INTERVAL_MS must be a number of milliseconds")?;
+        let batch_size: i64 = env.outbox_batch_size.parse().context("OUTBOX_BATCH_SIZE must be a number")?;
+        let max_attempts: i32 = env.outbox_max_attempts.parse().context("OUTBOX_MAX_ATTEMPTS must be a number")?;
+        let lease: u64 = env.outbox_lease_secs.parse().context("OUTBOX_LEASE_SECS must be a number of seconds")?;
+        let retention: u64 = env.outbox_retention_hours.parse().context("OUTBOX_RETENTION_HOURS must be a number of hours")?;
+        if poll_interval == 0 || batch_size <= 0 || lease == 0 || max_attempts <= 0 {
+            bail!("OUTBOX_POLL_INTERVAL_MS, OUTBOX_BATCH_SIZE, OUTBOX_LEASE_SECS and OUTBOX_MAX_ATTEMPTS must be greater than zero");
+        }
+
+        Ok(Self {
+            poll_interval: Duration::from_millis(poll_interval),
+            batch_size,
+            lease: Duration::from_secs(lease),
+            max_attempts,
+            retry_delay: Duration::from_secs(env.outbox_retry_delay_secs.parse().context("OUTBOX_RETRY_DELAY_SECS must be a number of seconds")?),
+            retention: (retention > 0).then(|| Duration::from_secs(retention * 3600)),
+        })
+    }
+}
@@ -0,0 +1,35 @@
+use serde::Serialize;
+use x509_parser::parse_x509_certificate;
+
+// Added as a request extension to every request that arrived over a TLS listener
+#[derive(Debug, Clone, Copy)]
+pub struct TlsConnection;
+
+// Identity of a client that presented a certificate during the TLS handshake, added as a request extension
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    // Distinguished name, e.g. `O=Example, CN=reporting-service`
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub issuer: String,
+    // Colon separated hex
+    pub serial: String,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, certificate) = parse_x509_certificate(der).ok()?;
+        let common_name = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|name| name.as_str().ok())
+            .map(str::to_string);
+        Some(Self {
+            subject: certificate.subject().to_string(),
+            common_name,
+            issuer: certificate.issuer().to_string(),
+            serial: certificate.raw_serial_as_string(),
+        })
+    }
+}
@@ -0,0 +1,97 @@
+use axum::http::{header, HeaderMap};
+use serde::{Deserialize, Serialize};
+
+// Maximum number of row errors included in an import report
+pub const MAX_REPORTED_IMPORT_ERRORS: usize = 1000;
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+// Wire formats supported by user import and export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferFormat {
+    Csv,
+    Ndjson,
+}
+
+impl TransferFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TransferFormat::Csv => CSV_CONTENT_TYPE,
+            TransferFormat::Ndjson => NDJSON_CONTENT_TYPE,
+        }
+    }
+
+    // Pick the export format from the Accept header, NDJSON unless CSV is asked for
+    pub fn from_accept(headers: &HeaderMap) -> Option<Self> {
+        let accept = match headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()) {
+            Some(accept) => accept,
+            None => return Some(TransferFormat::Ndjson),
+        };
+
+        if accept.contains(CSV_CONTENT_TYPE) {
+            Some(TransferFormat::Csv)
+        } else if accept.contains(NDJSON_CONTENT_TYPE) || accept.contains("application/json") || accept.contains("*/*") {
+            Some(TransferFormat::Ndjson)
+        } else {
+            None
+        }
+    }
+
+    // Pick the import format from the Content-Type header
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok())?;
+
+        if content_type.starts_with(CSV_CONTENT_TYPE) {
+            Some(TransferFormat::Csv)
+        } else if content_type.starts_with(NDJSON_CONTENT_TYPE) {
+            Some(TransferFormat::Ndjson)
+        } else {
+            None
+        }
+    }
+}
+
+// What to do when a row fails validation or cannot be written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportErrorMode {
+    // Stop at the first bad row and discard the whole import
+    #[default]
+    Abort,
+    // Skip bad rows, import the rest and list the failures
+    Report,
+}
+
+// Query parameters of POST /users/import
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportOptions {
+    // Validate and write inside a transaction that is always rolled back
+    #[serde(default)]
+    pub dry_run: bool,
+    // Update existing users matched by user_id instead of reporting a conflict
+    #[serde(default)]
+    pub upsert: bool,
+    #[serde(default)]
+    pub on_error: ImportErrorMode,
+}
+
+// Failure of a single imported row
+#[derive(Debug, Serialize)]
+pub struct ImportRowError {
+    pub line: u64,
+    pub status: u16,
+    pub error: String,
+}
+
+// Result of an import run
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub dry_run: bool,
+    pub upsert: bool,
+    pub committed: bool,
+    pub processed: u64,
+    pub imported: u64,
+    pub failed: u64,
+    pub errors: Vec<ImportRowError>,
+}
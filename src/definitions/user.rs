@@ -1,22 +1,143 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use sqlx::FromRow;
 use uuid::Uuid;
 use schemars::JsonSchema;
 
 
 // User Struct
-#[derive(Serialize, Deserialize, JsonSchema, FromRow, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, FromRow, Debug, Clone)]
 pub(crate) struct User {
     pub(crate) user_id: Uuid,
     pub(crate) username: String,
     pub email: Option<String>,  // Update User struct to include email
+    pub(crate) attributes: Value, // Custom attributes, validated against attribute_definitions
+    #[serde(default = "enabled_by_default")]
+    pub(crate) enabled: bool, // Disabled users are kept but cannot sign in
 }
 
+fn enabled_by_default() -> bool {
+    true
+}
 
-// Custom User struct for manual UUID validation
+
+// Row returned by the user search query
+#[derive(FromRow, Debug)]
+pub(crate) struct UserSearchRow {
+    pub(crate) user_id: Uuid,
+    pub(crate) username: String,
+    pub(crate) email: Option<String>,
+    pub(crate) attributes: Value,
+    pub(crate) enabled: bool,
+    pub(crate) rank: f32,
+    pub(crate) username_highlight: String,
+    pub(crate) email_highlight: Option<String>,
+    pub(crate) total: i64,
+}
+
+// Query parameters of GET /users/search
 #[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    pub q: String,
+}
+
+// Search hit with its relevance and highlighted fields
+#[derive(Serialize, Debug)]
+pub(crate) struct UserSearchHit {
+    pub(crate) user: User,
+    pub(crate) rank: f32,
+    pub(crate) highlights: UserHighlights,
+}
+
+// Matched fragments wrapped in <mark> tags
+#[derive(Serialize, Debug)]
+pub(crate) struct UserHighlights {
+    pub(crate) username: String,
+    pub(crate) email: Option<String>,
+}
+
+impl From<UserSearchRow> for UserSearchHit {
+    fn from(row: UserSearchRow) -> Self {
+        Self {
+            user: User {
+                user_id: row.user_id,
+                username: row.username,
+                email: row.email,
+                attributes: row.attributes,
+                enabled: row.enabled,
+            },
+            rank: row.rank,
+            highlights: UserHighlights {
+                username: row.username_highlight,
+                email: row.email_highlight,
+            },
+        }
+    }
+}
+
+// Custom User struct for manual UUID validation
+#[derive(Debug, Clone, Deserialize)]
 pub struct NewUser {
     pub(crate) user_id: String,
     pub(crate) username: Option<String>,
     pub(crate) email: Option<String>, // New optional email field
+    pub(crate) attributes: Option<Map<String, Value>>, // Null values remove an attribute on update
+}
+
+// Optional filters of GET /users/export, unset fields match every user
+#[derive(Debug, Default, Deserialize)]
+pub struct UserFilter {
+    pub enabled: Option<bool>,
+    // Matched case-insensitively against the part of the email after the @
+    pub email_domain: Option<String>,
+}
+
+// Maximum number of operations accepted in a single batch request
+pub const MAX_BATCH_OPERATIONS: usize = 100;
+
+// Batch execution mode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    // Every operation succeeds or the whole batch is rolled back
+    #[default]
+    Atomic,
+    // Operations are applied independently, failures do not affect other items
+    BestEffort,
+}
+
+// Single operation in a batch request
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Create(NewUser),
+    Update(NewUser),
+    Delete { user_id: String },
+}
+
+impl BatchOperation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchOperation::Create(_) => "create",
+            BatchOperation::Update(_) => "update",
+            BatchOperation::Delete { .. } => "delete",
+        }
+    }
+}
+
+// Request body of POST /users:batch
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub mode: BatchMode,
+    pub operations: Vec<BatchOperation>,
+}
+
+// Outcome of a single batch operation, `body` mirrors the single-user endpoints
+#[derive(Debug, Serialize)]
+pub struct BatchItemResult {
+    pub index: usize,
+    pub op: &'static str,
+    pub status: u16,
+    pub body: serde_json::Value,
 }
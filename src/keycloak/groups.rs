@@ -0,0 +1,68 @@
+use anyhow::Context;
+use reqwest::{header, Method, StatusCode};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{expect_success, KeycloakAdmin};
+
+impl KeycloakAdmin {
+    // Create a group, below `parent` when given, and return its Keycloak id
+    pub(crate) async fn create_group(&self, name: &str, parent: Option<&str>) -> anyhow::Result<String> {
+        let path = match parent {
+            Some(parent) => format!("/groups/{parent}/children"),
+            None => "/groups".to_string(),
+        };
+        let response = expect_success(self.send(Method::POST, &path, Some(&json!({ "name": name }))).await?).await?;
+
+        // The id of the new group is only returned through the Location header
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .context("Keycloak did not return the location of the created group")?;
+        location
+            .rsplit('/')
+            .next()
+            .map(str::to_string)
+            .context("Malformed group location")
+    }
+
+    pub(crate) async fn rename_group(&self, group: &str, name: &str) -> anyhow::Result<()> {
+        expect_success(self.send(Method::PUT, &format!("/groups/{group}"), Some(&json!({ "name": name }))).await?).await?;
+        Ok(())
+    }
+
+    // Move an existing group below `parent`, or to the top level
+    pub(crate) async fn move_group(&self, group: &str, name: &str, parent: Option<&str>) -> anyhow::Result<()> {
+        let path = match parent {
+            Some(parent) => format!("/groups/{parent}/children"),
+            None => "/groups".to_string(),
+        };
+        expect_success(self.send(Method::POST, &path, Some(&json!({ "id": group, "name": name }))).await?).await?;
+        Ok(())
+    }
+
+    // Delete a group and its subgroups, groups that are already gone are ignored
+    pub(crate) async fn delete_group(&self, group: &str) -> anyhow::Result<()> {
+        let response = self.send(Method::DELETE, &format!("/groups/{group}"), None).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        expect_success(response).await?;
+        Ok(())
+    }
+
+    pub(crate) async fn add_group_member(&self, user_id: Uuid, group: &str) -> anyhow::Result<()> {
+        expect_success(self.send(Method::PUT, &format!("/users/{user_id}/groups/{group}"), None).await?).await?;
+        Ok(())
+    }
+
+    pub(crate) async fn remove_group_member(&self, user_id: Uuid, group: &str) -> anyhow::Result<()> {
+        let response = self.send(Method::DELETE, &format!("/users/{user_id}/groups/{group}"), None).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        expect_success(response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+pub mod groups;
+pub mod reconcile;
+pub mod sync;
+pub mod users;
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use reqwest::{Client, Method, Response};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::{config::EnvironmentVariables, definitions::auth::ServiceTokenResponse};
+
+// Tokens are refreshed this long before they expire
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(10);
+
+// Cached service account access token
+struct ServiceToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+// Client for the Keycloak admin REST API, authenticated as the service account of KC_CLIENT_ID
+pub struct KeycloakAdmin {
+    client: Client,
+    token_url: String,
+    admin_url: String,
+    client_id: String,
+    client_secret: String,
+    token: Mutex<Option<ServiceToken>>,
+    // Serializes group sync so concurrent changes do not create duplicate Keycloak groups
+    pub(crate) group_sync: Mutex<()>,
+}
+
+impl KeycloakAdmin {
+    pub fn new(client: Client, env: &EnvironmentVariables) -> Self {
+        Self {
+            client,
+            token_url: format!("{}{}", env.kc_server_addr, env.kc_login_path),
+            admin_url: format!("{}/admin/realms/{}", env.kc_server_addr, env.kc_realm),
+            client_id: env.kc_client_id.to_string(),
+            client_secret: env.kc_client_secret.to_string(),
+            token: Mutex::new(None),
+            group_sync: Mutex::new(()),
+        }
+    }
+
+    // Access token of the service account, fetched through the client_credentials grant and cached
+    async fn service_token(&self) -> anyhow::Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some(token) = token.as_ref().filter(|token| token.expires_at > Instant::now()) {
+            return Ok(token.access_token.clone());
+        }
+
+        let response = self.client
+            .post(&self.token_url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+            ])
+            .send()
+            .await
+            .context("Failed to reach Keycloak token endpoint")?;
+        if !response.status().is_success() {
+            bail!("Keycloak rejected service account credentials: {}", response.status());
+        }
+
+        let issued: ServiceTokenResponse = response.json().await.context("Failed to parse service account token")?;
+        let lifetime = Duration::from_secs(issued.expires_in).saturating_sub(TOKEN_EXPIRY_MARGIN);
+        *token = Some(ServiceToken {
+            access_token: issued.access_token.clone(),
+            expires_at: Instant::now() + lifetime,
+        });
+
+        Ok(issued.access_token)
+    }
+
+    // Send an authenticated request to a path below /admin/realms/{realm}
+    pub(crate) async fn send(&self, method: Method, path: &str, body: Option<&Value>) -> anyhow::Result<Response> {
+        let token = self.service_token().await?;
+        let mut request = self.client
+            .request(method.clone(), format!("{}{}", self.admin_url, path))
+            .bearer_auth(token);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        request.send().await.with_context(|| format!("Keycloak admin request {method} {path} failed"))
+    }
+}
+
+// Fail with the response status and body unless the request succeeded
+pub(crate) async fn expect_success(response: Response) -> anyhow::Result<Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    bail!("Keycloak admin API returned {status}: {body}")
+}
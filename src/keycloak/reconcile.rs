@@ -0,0 +1,102 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+
+use super::sync::push_user;
+use crate::{
+    config::ConfigState,
+    database::users::stream_all_users,
+    definitions::{
+        keycloak::{DriftReport, KeycloakUser, LinkedUser, UserDrift, SERVICE_ACCOUNT_PREFIX},
+        user::User,
+    },
+};
+
+// Compare every local user with the Keycloak realm, with `fix` local state is pushed over Keycloak
+pub(crate) async fn reconcile(config: &ConfigState, fix: bool) -> anyhow::Result<DriftReport> {
+    let mut remote: HashMap<_, _> = config.kc_admin
+        .list_users()
+        .await?
+        .into_iter()
+        .filter(|user| !user.username.starts_with(SERVICE_ACCOUNT_PREFIX))
+        .map(|user| (user.id, user))
+        .collect();
+    // Users never linked to an account are matched by username, Keycloak stores usernames in lowercase
+    let by_username: HashMap<_, _> = remote.values().map(|user| (user.username.to_lowercase(), user.id)).collect();
+
+    let mut report = DriftReport::default();
+    let mut users = Box::pin(stream_all_users(config.pgpool.clone()));
+    while let Some(LinkedUser { org_id, keycloak_id, user }) = users.try_next().await? {
+        report.checked += 1;
+
+        // An account is matched at most once, a second user claiming it is missing its own
+        let account = keycloak_id.or_else(|| by_username.get(&user.username.to_lowercase()).copied());
+        match account.and_then(|keycloak_id| remote.remove(&keycloak_id)) {
+            None => report.missing_in_keycloak.push(user.user_id),
+            Some(kc_user) => {
+                let fields = drifted_fields(&user, &kc_user);
+                if fields.is_empty() {
+                    continue;
+                }
+                report.mismatched.push(UserDrift { user_id: user.user_id, fields });
+            },
+        }
+
+        if fix {
+            match push_user(config, org_id, &user, &user.username).await {
+                Ok(true) => report.fixed += 1,
+                Ok(false) => report.errors.push(format!("User {} conflicts with another Keycloak user", user.user_id)),
+                Err(err) => report.errors.push(format!("User {}: {err:#}", user.user_id)),
+            }
+        }
+    }
+
+    report.missing_locally = remote.into_values().collect();
+    report.missing_locally.sort_by(|a, b| a.username.cmp(&b.username));
+
+    Ok(report)
+}
+
+// Fields Keycloak disagrees on, it stores usernames and emails in lowercase
+fn drifted_fields(user: &User, kc_user: &KeycloakUser) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if !user.username.eq_ignore_ascii_case(&kc_user.username) {
+        fields.push("username");
+    }
+    if user.email.as_deref().map(str::to_lowercase) != kc_user.email.as_deref().map(str::to_lowercase) {
+        fields.push("email");
+    }
+    if user.enabled != kc_user.enabled {
+        fields.push("enabled");
+    }
+    fields
+}
+
+// Periodic reconciliation, enabled with KC_RECONCILE_INTERVAL_SECS
+pub(crate) fn spawn_reconciliation(config: &Arc<ConfigState>) {
+    let interval = config.env.kc_reconcile_interval_secs.parse::<u64>().unwrap_or(0);
+    if interval == 0 {
+        return;
+    }
+
+    let config = config.clone();
+    let fix = config.env.kc_reconcile_fix == "true";
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            match reconcile(&config, fix).await {
+                Ok(report) => tracing::info!(
+                    checked = report.checked,
+                    missing_in_keycloak = report.missing_in_keycloak.len(),
+                    missing_locally = report.missing_locally.len(),
+                    mismatched = report.mismatched.len(),
+                    fixed = report.fixed,
+                    errors = report.errors.len(),
+                    "Keycloak reconciliation finished"
+                ),
+                Err(err) => eprintln!("Keycloak reconciliation failed: {err:#}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,176 @@
+use std::{future::Future, sync::Arc};
+
+use anyhow::Context;
+use uuid::Uuid;
+
+use super::KeycloakAdmin;
+
+use crate::{
+    config::ConfigState,
+    database::{
+        begin_tenant,
+        groups::{find_group, set_group_keycloak_id},
+        organizations::{find_organization, find_organization_keycloak_id, set_organization_keycloak_id},
+        users::{find_user, find_user_keycloak_id, set_user_keycloak_id},
+    },
+    definitions::group::Group,
+    definitions::user::User,
+};
+
+// Admin client when user changes are propagated to Keycloak, enabled with KC_USER_SYNC
+pub(crate) fn user_sync(config: &ConfigState) -> Option<&KeycloakAdmin> {
+    (config.env.kc_user_sync == "true").then_some(config.kc_admin.as_ref())
+}
+
+// Keycloak account of a user, the stored id or else the account holding `username`, which is then stored.
+// Local user ids are only unique per organization and accounts created in Keycloak have their own ids,
+// an account already linked to a user of another organization is never taken over
+pub(crate) async fn find_account(config: &ConfigState, org_id: Uuid, user_id: Uuid, username: &str) -> anyhow::Result<Option<Uuid>> {
+    let mut tx = begin_tenant(&config.pgpool, org_id).await?;
+    let keycloak_id = find_user_keycloak_id(user_id, org_id, &mut *tx).await?;
+    tx.commit().await?;
+    if keycloak_id.is_some() {
+        return Ok(keycloak_id);
+    }
+
+    let Some(keycloak_id) = config.kc_admin.find_user_id(username).await? else {
+        return Ok(None);
+    };
+    match link_account(config, org_id, user_id, keycloak_id).await {
+        Ok(()) => Ok(Some(keycloak_id)),
+        Err(sqlx::Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Store the Keycloak account of a user
+async fn link_account(config: &ConfigState, org_id: Uuid, user_id: Uuid, keycloak_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = begin_tenant(&config.pgpool, org_id).await?;
+    set_user_keycloak_id(user_id, org_id, keycloak_id, &mut *tx).await?;
+    tx.commit().await
+}
+
+// Create the Keycloak account of a user and store its id
+// Returns false when Keycloak already has another user with the same username or email
+pub(crate) async fn create_account(config: &ConfigState, org_id: Uuid, user: &User) -> anyhow::Result<bool> {
+    let Some(keycloak_id) = config.kc_admin.create_account(user).await? else {
+        return Ok(false);
+    };
+    link_account(config, org_id, user.user_id, keycloak_id).await?;
+    Ok(true)
+}
+
+// Overwrite the Keycloak account of a user, creating it when there is none or it went missing.
+// `username` is the name the account was last given, it differs from the user's when a rename is pushed
+// Returns false when Keycloak already has another user with the same username or email
+pub(crate) async fn push_user(config: &ConfigState, org_id: Uuid, user: &User, username: &str) -> anyhow::Result<bool> {
+    if let Some(keycloak_id) = find_account(config, org_id, user.user_id, username).await? {
+        if config.kc_admin.update_user(keycloak_id, user).await? {
+            return Ok(true);
+        }
+        eprintln!("Keycloak account of user {} was missing, recreating it", user.user_id);
+    }
+    create_account(config, org_id, user).await
+}
+
+// One-way sync of local groups and memberships into Keycloak groups, enabled with KC_GROUP_SYNC
+pub(crate) fn spawn_group_sync<F, Fut>(config: &Arc<ConfigState>, task: F)
+where
+    F: FnOnce(Arc<ConfigState>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    if config.env.kc_group_sync != "true" {
+        return;
+    }
+
+    // Sync runs after the local change is committed and never fails the request
+    let config = config.clone();
+    tokio::spawn(async move {
+        let _guard = config.kc_admin.group_sync.lock().await;
+        if let Err(err) = task(config.clone()).await {
+            eprintln!("Keycloak group sync failed: {err:#}");
+        }
+    });
+}
+
+// Keycloak group named after the organization slug, the groups of each organization live below their own
+// top-level group so tenants sharing the realm cannot collide on group names
+pub(crate) async fn ensure_organization_group(config: &ConfigState, org_id: Uuid) -> anyhow::Result<String> {
+    if let Some(keycloak_id) = find_organization_keycloak_id(org_id, &config.pgpool).await? {
+        return Ok(keycloak_id);
+    }
+
+    let organization = find_organization(&org_id.to_string(), &config.pgpool)
+        .await?
+        .with_context(|| format!("Organization {org_id} no longer exists"))?;
+    let keycloak_id = config.kc_admin.create_group(&organization.slug, None).await?;
+    set_organization_keycloak_id(org_id, &keycloak_id, &config.pgpool).await?;
+
+    Ok(keycloak_id)
+}
+
+// Keycloak id of the group a local group is mirrored below, its parent or the organization group
+async fn ensure_parent(config: &ConfigState, parent_id: Option<Uuid>, org_id: Uuid) -> anyhow::Result<String> {
+    match parent_id {
+        Some(parent_id) => Box::pin(ensure_group(config, parent_id, org_id)).await,
+        None => ensure_organization_group(config, org_id).await,
+    }
+}
+
+// Keycloak id of a group, creating the group and any missing ancestors first
+pub(crate) async fn ensure_group(config: &ConfigState, group_id: Uuid, org_id: Uuid) -> anyhow::Result<String> {
+    let group = load_group(config, group_id, org_id).await?;
+    if let Some(keycloak_id) = group.keycloak_id {
+        return Ok(keycloak_id);
+    }
+
+    let parent = ensure_parent(config, group.parent_id, org_id).await?;
+    let keycloak_id = config.kc_admin.create_group(&group.name, Some(&parent)).await?;
+    let mut tx = begin_tenant(&config.pgpool, org_id).await?;
+    set_group_keycloak_id(group_id, &keycloak_id, &mut *tx).await?;
+    tx.commit().await?;
+
+    Ok(keycloak_id)
+}
+
+// Read a group inside its organization, the row-level security policies hide it from other sessions
+async fn load_group(config: &ConfigState, group_id: Uuid, org_id: Uuid) -> anyhow::Result<Group> {
+    let mut tx = begin_tenant(&config.pgpool, org_id).await?;
+    let group = find_group(group_id, org_id, &mut *tx)
+        .await?
+        .with_context(|| format!("Group {group_id} no longer exists"))?;
+    tx.commit().await?;
+    Ok(group)
+}
+
+// Mirror the name and, when it changed, the position of a group
+pub(crate) async fn push_group(config: &ConfigState, group_id: Uuid, org_id: Uuid, moved: bool) -> anyhow::Result<()> {
+    let keycloak_id = ensure_group(config, group_id, org_id).await?;
+    let group = load_group(config, group_id, org_id).await?;
+
+    config.kc_admin.rename_group(&keycloak_id, &group.name).await?;
+    if moved {
+        let parent = ensure_parent(config, group.parent_id, org_id).await?;
+        config.kc_admin.move_group(&keycloak_id, &group.name, Some(&parent)).await?;
+    }
+
+    Ok(())
+}
+
+// Mirror a membership change, Keycloak has no group roles so owners and members are treated alike
+pub(crate) async fn push_membership(config: &ConfigState, group_id: Uuid, org_id: Uuid, user_id: Uuid, member: bool) -> anyhow::Result<()> {
+    let mut tx = begin_tenant(&config.pgpool, org_id).await?;
+    let user = find_user(user_id, org_id, &mut *tx)
+        .await?
+        .with_context(|| format!("User {user_id} no longer exists"))?;
+    tx.commit().await?;
+    let kc_user_id = find_account(config, org_id, user_id, &user.username)
+        .await?
+        .with_context(|| format!("User {} has no Keycloak account", user.username))?;
+
+    let keycloak_id = ensure_group(config, group_id, org_id).await?;
+    match member {
+        true => config.kc_admin.add_group_member(kc_user_id, &keycloak_id).await,
+        false => config.kc_admin.remove_group_member(kc_user_id, &keycloak_id).await,
+    }
+}
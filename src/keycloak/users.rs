@@ -0,0 +1,114 @@
+use anyhow::Context;
+use reqwest::{header, Method, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::{expect_success, KeycloakAdmin};
+use crate::definitions::{keycloak::KeycloakUser, user::User};
+
+// Users are fetched from Keycloak in pages of this size
+const USER_PAGE_SIZE: usize = 100;
+
+impl KeycloakAdmin {
+    // Create an enabled user with a permanent password, None when the username or email is taken
+    pub(crate) async fn create_user(&self, username: &str, email: Option<&str>, password: &str) -> anyhow::Result<Option<Uuid>> {
+        let body = json!({
+            "username": username,
+            "email": email,
+            "emailVerified": email.is_some(),
+            "enabled": true,
+            "credentials": [{ "type": "password", "value": password, "temporary": false }],
+        });
+        self.post_user(&body).await
+    }
+
+    // Create the Keycloak account of a local user without credentials, None when the username or email is taken
+    pub(crate) async fn create_account(&self, user: &User) -> anyhow::Result<Option<Uuid>> {
+        let body = json!({
+            "username": user.username,
+            "email": user.email,
+            "enabled": user.enabled,
+        });
+        self.post_user(&body).await
+    }
+
+    // Create a user and return its id, None when the username or email is taken
+    async fn post_user(&self, body: &Value) -> anyhow::Result<Option<Uuid>> {
+        let response = self.send(Method::POST, "/users", Some(body)).await?;
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(None);
+        }
+        let response = expect_success(response).await?;
+
+        // The id of the new user is only returned through the Location header
+        let user_id = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| location.rsplit('/').next())
+            .context("Keycloak did not return the location of the created user")?;
+        Uuid::parse_str(user_id).map(Some).context("Keycloak returned a malformed user id")
+    }
+
+    // Delete a user, users that are already gone are ignored
+    pub(crate) async fn delete_user(&self, user_id: Uuid) -> anyhow::Result<()> {
+        let response = self.send(Method::DELETE, &format!("/users/{user_id}"), None).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        expect_success(response).await?;
+        Ok(())
+    }
+
+    // Overwrite username, email and enabled flag of an account, returns false when it does not exist
+    pub(crate) async fn update_user(&self, keycloak_id: Uuid, user: &User) -> anyhow::Result<bool> {
+        let body = json!({
+            "username": user.username,
+            "email": user.email,
+            "enabled": user.enabled,
+        });
+        let response = self.send(Method::PUT, &format!("/users/{keycloak_id}"), Some(&body)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        expect_success(response).await?;
+        Ok(true)
+    }
+
+    // Keycloak id of the user with exactly this username
+    pub(crate) async fn find_user_id(&self, username: &str) -> anyhow::Result<Option<Uuid>> {
+        let response = self.client
+            .get(format!("{}/users", self.admin_url))
+            .bearer_auth(self.service_token().await?)
+            .query(&[("username", username), ("exact", "true"), ("briefRepresentation", "true")])
+            .send()
+            .await
+            .context("Keycloak admin user lookup failed")?;
+        let users: Vec<KeycloakUser> = expect_success(response)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse Keycloak users")?;
+
+        // Keycloak stores usernames in lowercase
+        Ok(users.into_iter().find(|user| user.username.eq_ignore_ascii_case(username)).map(|user| user.id))
+    }
+
+    // Every user of the realm, fetched page by page
+    pub(crate) async fn list_users(&self) -> anyhow::Result<Vec<KeycloakUser>> {
+        let mut users = Vec::new();
+        loop {
+            let path = format!("/users?briefRepresentation=true&first={}&max={USER_PAGE_SIZE}", users.len());
+            let page: Vec<KeycloakUser> = expect_success(self.send(Method::GET, &path, None).await?)
+                .await?
+                .json()
+                .await
+                .context("Failed to parse Keycloak users")?;
+            let last = page.len() < USER_PAGE_SIZE;
+            users.extend(page);
+            if last {
+                return Ok(users);
+            }
+        }
+    }
+}
@@ -1,33 +1,46 @@
 mod routes;
+mod auth;
 mod definitions;
 mod config;
 mod database;
 mod routers;
 mod middleware;
+mod keycloak;
+mod outbox;
+mod server;
 
 #[macro_use]
 mod custom;
 
-use aide::{
-    axum::ApiRouter,
-    openapi::{Info, OpenApi},
-};
+#[cfg(test)]
+mod tests;
+
+use aide::openapi::{Info, OpenApi};
 use definitions::logging::get_included_paths;
 use tracing::{info_span, Span};
 
-use std::{sync::Arc, time::Duration};
+use std::{os::fd::OwnedFd, sync::Arc, time::Duration};
 use anyhow::{Ok, Result};
-use axum::{extract::MatchedPath, http::{Request, Response}, Extension};
+use axum::{extract::MatchedPath, http::{Request, Response}, Extension, Router};
 
-use routers::{private_router, public_router, metrics_router, open_api_router};
+use routers::{admin_router, app_router};
+use server::{listeners::{bind_listeners, require_client_addresses, take_activated_sockets}, tls::{ReloadableTls, TlsSettings}};
 use config::ConfigState;
 use tracing_appender::rolling;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 use tower_http::trace::TraceLayer;
-use middleware::ignore_logs::ignore_logs;
 
-#[tokio::main]
-async fn main() -> Result<()>{
+fn main() -> Result<()> {
+    // Socket activation changes the environment, which is only sound before the runtime starts its threads
+    let mut activated = take_activated_sockets();
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(&mut activated))
+}
+
+async fn run(activated: &mut Vec<OwnedFd>) -> Result<()>{
     // Start tracing subscriber
     // Configure tracing with log rotation
     let file_appender = rolling::daily("logs", "server.log");
@@ -88,13 +101,10 @@ async fn main() -> Result<()>{
         })
         .on_response(move |response: &Response<_>, latency: Duration, span: &Span| {
             // Do not log responses marked as IGNORE_LOG = "true"
-            match response.headers().get("X-Ignore-Log") {
-                Some(value) => {
-                    if value == "true"{
-                        return;
-                    }
+            if let Some(value) = response.headers().get("X-Ignore-Log") {
+                if value == "true" {
+                    return;
                 }
-                None => {}
             }
             tracing::info!(parent: span, "response generated: {:?}, latency: {:?}", response, latency);
         });
@@ -102,7 +112,17 @@ async fn main() -> Result<()>{
     // Load Configuration
     let config = Arc::new(ConfigState::from_env().await?);
     let app_name_string: String = format!("{}:{}", config.appname.as_str(), config.version.as_str());
-    let bind_url = format!("{}:{}", config.env.hostname, config.env.port);
+
+    // Compare local users with Keycloak in the background when configured
+    keycloak::reconcile::spawn_reconciliation(&config);
+    // Delete expired Idempotency-Key records
+    middleware::idempotency::spawn_idempotency_cleanup(&config);
+    // Re-check read replicas so reads return to them once they recover
+    database::replicas::spawn_replica_health_checks(&config);
+    // Push metrics to METRICS_PUSH_URL for runs too short to be scraped
+    custom::telemetry::spawn_metrics_push(&config);
+    // Deliver the user events of the outbox table to OUTBOX_SINKS
+    outbox::spawn_outbox_relay(&config);
 
     // Describe OpenAPI handler
     let mut api = OpenApi {
@@ -113,17 +133,8 @@ async fn main() -> Result<()>{
         ..OpenApi::default()
     };
     
-    // Get Metrics Router
-    let (metrics_router, prometheus_layer) = metrics_router();
-
     // Build App
-    let app = ApiRouter::new()
-    .merge(private_router(config.clone()))
-    .merge(public_router(config.clone()))
-    .merge(metrics_router)
-    .layer(prometheus_layer)
-    .merge(open_api_router(config.clone()))
-    .layer(axum::middleware::from_fn(ignore_logs))
+    let app = app_router(config.clone())
     .layer(tracer)
     // Create API Spec from routes defined before this
     .finish_api(&mut api);
@@ -131,19 +142,60 @@ async fn main() -> Result<()>{
     // Serialize the OpenAPI document to a JSON string for performance, store it in an atomic type for shared use
     let api_json = Arc::new(serde_json::to_string(&api).expect("Failed to serialize OpenAPI document"));
 
-    // Start webserver on bind_url
-    let listener = tokio::net::TcpListener::bind(bind_url).await.unwrap();
-
-    // Serve axum routes as service with OpenAPI JSON as a layer
-    axum::serve(
-        listener,
-        app
-            // Expose the documentation to the handlers.
-            .layer(Extension(api_json))
-            .into_make_service(),
-    )
-    .await
-    .unwrap();
+    // Bind every listener of LISTEN_ADDRS (TCP, Unix sockets or systemd sockets), HOSTNAME:PORT by default
+    let listeners = bind_listeners(&config.env, activated).await?;
+    require_client_addresses(&listeners, config.env.trust_proxy_headers == "true")?;
+
+    // Expose the documentation to the handlers.
+    let app = app.layer(Extension(api_json.clone()));
+
+    // Terminate TLS when certificates are configured, otherwise serve plain HTTP
+    let tls = match TlsSettings::from_env(&config.env)? {
+        Some(settings) => {
+            let tls = Arc::new(ReloadableTls::new(settings)?);
+            server::tls::spawn_tls_reload(tls.clone())?;
+            Some(tls)
+        },
+        None => None,
+    };
+
+    let mut servers = Vec::new();
+    for listener in listeners {
+        println!("Listening on {}{}", listener.describe(), if tls.is_some() { " (HTTPS)" } else { "" });
+        servers.push(tokio::spawn(server::serve(listener, app.clone(), tls.clone())));
+    }
+
+    // Metrics, docs and health checks on their own listener, isolated from client traffic
+    if !config.env.admin_bind_addr.is_empty() {
+        let admin_app: Router = Router::from(admin_router(config.clone())).layer(Extension(api_json));
+        let listeners = server::listeners::bind(&config.env.admin_bind_addr, &config.env.unix_socket_mode, activated).await?;
+        require_client_addresses(&listeners, config.env.trust_proxy_headers == "true")?;
+        for listener in listeners {
+            println!("Serving admin endpoints on {}", listener.describe());
+            servers.push(tokio::spawn(server::serve(listener, admin_app.clone(), None)));
+        }
+    }
+
+    // select_all panics without futures
+    if servers.is_empty() {
+        anyhow::bail!("No listeners configured");
+    }
+
+    // Stop when any listener fails or on SIGINT / SIGTERM
+    tokio::select! {
+        (result, _, _) = futures::future::select_all(servers) => result??,
+        result = server::shutdown_signal() => {
+            result?;
+            println!("Shutting down");
+        },
+    }
+
+    // Deliver the final values to the push gateway before exiting
+    if config.metrics.push.is_some() {
+        if let Err(err) = custom::telemetry::push_metrics(&reqwest::Client::new(), &config.metrics, &custom::telemetry::prometheus_handle(), &config.pgpool).await {
+            eprintln!("Failed to push metrics: {err:#}");
+        }
+    }
 
     // Return empty result on exit
     Ok(())
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_keycloak_auth::{
+    decode::{Email, KeycloakToken, Profile, ProfileAndEmail},
+    role::KeycloakRole,
+    KeycloakAuthStatus,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::ConfigState,
+    custom::signing::{derive_key, verify_digest, API_KEY_DIGESTS},
+    database::api_keys::{find_api_key, touch_api_key},
+    definitions::api_key::{ApiKey, ApiKeyIdentity, API_KEY_HEADER, API_KEY_PREFIX},
+};
+
+// Issuer and authorized party of tokens synthesized for API keys
+pub(crate) const API_KEY_ISSUER: &str = "api-key";
+
+// Authenticate requests carrying an API key, runs before the Keycloak layer
+pub async fn authenticate_api_key(State(config): State<Arc<ConfigState>>, mut req: Request<Body>, next: Next) -> Response {
+    let Some(presented) = presented_key(&req) else {
+        return next.run(req).await;
+    };
+
+    let api_key = match parse_key(&presented) {
+        Some((key_id, secret)) => match find_api_key(key_id, &config.pgpool).await {
+            Ok(Some(api_key)) if accepts(&config, &api_key, secret) => api_key,
+            Ok(_) => return invalid_api_key(),
+            Err(err) => {
+                eprintln!("Internal Server Error: {err}");
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({ "error": "Internal server error" })),
+                ).into_response();
+            }
+        },
+        None => return invalid_api_key(),
+    };
+
+    if let Err(err) = touch_api_key(api_key.key_id, &config.pgpool).await {
+        eprintln!("Failed to record API key use: {err}");
+    }
+
+    req.extensions_mut().insert(ApiKeyIdentity { org_id: api_key.org_id });
+    req.extensions_mut().insert(key_token(&api_key));
+    next.run(req).await
+}
+
+// Turn the Keycloak layer outcome into a token, unless an API key already authenticated the request
+pub async fn require_authentication(mut req: Request<Body>, next: Next) -> Response {
+    let status = req.extensions_mut().remove::<KeycloakAuthStatus<String, ProfileAndEmail>>();
+    if req.extensions().get::<ApiKeyIdentity>().is_some() {
+        return next.run(req).await;
+    }
+
+    match status {
+        Some(KeycloakAuthStatus::Success(token)) => {
+            req.extensions_mut().insert(token);
+            next.run(req).await
+        },
+        Some(KeycloakAuthStatus::Failure(err)) => match Arc::try_unwrap(err) {
+            Ok(err) => err.into_response(),
+            Err(_) => StatusCode::UNAUTHORIZED.into_response(),
+        },
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+// API key from the X-API-Key header or an `Authorization: ApiKey` header
+fn presented_key(req: &Request<Body>) -> Option<String> {
+    if let Some(value) = req.headers().get(API_KEY_HEADER) {
+        return Some(value.to_str().unwrap_or_default().to_string());
+    }
+
+    let authorization = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, key) = authorization.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("apikey").then(|| key.trim().to_string())
+}
+
+// Split `ak_<key_id>_<secret>` into the key id and the decoded secret
+fn parse_key(key: &str) -> Option<(Uuid, Vec<u8>)> {
+    let (key_id, secret) = key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+    Some((Uuid::parse_str(key_id).ok()?, URL_SAFE_NO_PAD.decode(secret).ok()?))
+}
+
+// Check expiry and the current secret, the secret replaced by a rotation is accepted during its grace period
+fn accepts(config: &ConfigState, api_key: &ApiKey, secret: Vec<u8>) -> bool {
+    let now = Utc::now();
+    if api_key.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return false;
+    }
+    let key = derive_key(&config.env.secret, API_KEY_DIGESTS);
+    if verify_digest(&key, &secret, &api_key.secret_hash) {
+        return true;
+    }
+
+    match (&api_key.previous_hash, api_key.previous_expires_at) {
+        (Some(previous_hash), Some(previous_expires_at)) if previous_expires_at > now => {
+            verify_digest(&key, &secret, previous_hash)
+        },
+        _ => false,
+    }
+}
+
+// Token handed to handlers for an API key, the key id is the subject and the key roles are realm roles
+fn key_token(api_key: &ApiKey) -> KeycloakToken<String> {
+    let expires_at = api_key.expires_at.map(|expires_at| expires_at.timestamp());
+    issued_token(&api_key.key_id.to_string(), API_KEY_ISSUER, &api_key.name, None, &api_key.roles, expires_at)
+}
+
+// Token in the shape produced by the Keycloak layer, for credentials this service verifies itself
+pub(crate) fn issued_token(
+    subject: &str,
+    issuer: &str,
+    username: &str,
+    email: Option<&str>,
+    roles: &[String],
+    expires_at: Option<i64>,
+) -> KeycloakToken<String> {
+    let now = time::OffsetDateTime::now_utc();
+    let expires_at = expires_at
+        .and_then(|expires_at| time::OffsetDateTime::from_unix_timestamp(expires_at).ok())
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH.replace_year(9999).unwrap_or(now));
+
+    KeycloakToken {
+        expires_at,
+        issued_at: now,
+        jwt_id: Uuid::new_v4().to_string(),
+        issuer: issuer.to_string(),
+        audience: vec![String::from("account")],
+        subject: subject.to_string(),
+        authorized_party: issuer.to_string(),
+        roles: roles.iter().map(|role| KeycloakRole::Realm { role: role.clone() }).collect(),
+        extra: ProfileAndEmail {
+            profile: Profile {
+                given_name: None,
+                full_name: None,
+                family_name: None,
+                preferred_username: username.to_string(),
+            },
+            email: Email {
+                email: email.unwrap_or_default().to_string(),
+                email_verified: false,
+            },
+        },
+    }
+}
+
+fn invalid_api_key() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({ "error": "Invalid or expired API key" })),
+    ).into_response()
+}
@@ -0,0 +1,249 @@
+use std::{any::Any, time::Duration};
+
+use aide::axum::ApiRouter;
+use anyhow::Context;
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Request, State},
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body_util::Limited;
+use serde_json::json;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    compression::CompressionLayer,
+    cors::{AllowOrigin, Any as AnyValue, CorsLayer},
+    set_header::SetResponseHeaderLayer,
+};
+
+use crate::{config::EnvironmentVariables, definitions::tls::TlsConnection};
+
+// The streaming user import gets the HTTP_IMPORT_* limits instead of the general ones
+const IMPORT_PATH: &str = "/users/import";
+
+// Browsers cache preflight responses for this long
+const CORS_MAX_AGE: Duration = Duration::from_secs(600);
+
+// Response headers scripts on allowed origins can read
+const CORS_EXPOSED_HEADERS: [&str; 7] = [
+    "retry-after",
+    "ratelimit-limit",
+    "ratelimit-remaining",
+    "ratelimit-reset",
+    "ratelimit-policy",
+    "idempotent-replayed",
+    "content-disposition",
+];
+
+// Layers wrapped around every route, None or false disables a layer
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    pub request_timeout: Option<Duration>,
+    pub body_limit: Option<usize>,
+    pub import_timeout: Option<Duration>,
+    pub import_body_limit: Option<usize>,
+    // Empty allows any origin
+    pub cors_origins: Option<Vec<HeaderValue>>,
+    pub compression: bool,
+    pub security_headers: bool,
+    pub hsts_max_age: Option<Duration>,
+    pub catch_panic: bool,
+    // Proxies whose X-Forwarded-For entries are trusted, None ignores the header
+    pub proxy_hops: Option<usize>,
+}
+
+impl HttpOptions {
+    pub fn from_env(env: &EnvironmentVariables) -> anyhow::Result<Self> {
+        let enabled = |value: u64| (value > 0).then_some(value);
+        let timeout: u64 = env.http_request_timeout_secs.parse().context("HTTP_REQUEST_TIMEOUT_SECS must be a number of seconds")?;
+        let body_limit: u64 = env.http_body_limit_bytes.parse().context("HTTP_BODY_LIMIT_BYTES must be a number")?;
+        let import_timeout: u64 = env.http_import_timeout_secs.parse().context("HTTP_IMPORT_TIMEOUT_SECS must be a number of seconds")?;
+        let import_body_limit: u64 = env.http_import_body_limit_bytes.parse().context("HTTP_IMPORT_BODY_LIMIT_BYTES must be a number")?;
+        let hsts_max_age: u64 = env.http_hsts_max_age_secs.parse().context("HTTP_HSTS_MAX_AGE_SECS must be a number of seconds")?;
+        let proxy_hops: usize = env.trusted_proxy_hops.parse().context("TRUSTED_PROXY_HOPS must be a number")?;
+        if proxy_hops == 0 {
+            anyhow::bail!("TRUSTED_PROXY_HOPS must be at least 1");
+        }
+
+        let cors_origins = match env.http_cors_origins.trim() {
+            "" => None,
+            "*" => Some(Vec::new()),
+            origins => Some(
+                origins
+                    .split(',')
+                    .map(|origin| HeaderValue::from_str(origin.trim()).with_context(|| format!("Invalid CORS origin {origin}")))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+        };
+
+        Ok(Self {
+            request_timeout: enabled(timeout).map(Duration::from_secs),
+            body_limit: enabled(body_limit).map(usize::try_from).transpose().context("HTTP_BODY_LIMIT_BYTES is too large")?,
+            import_timeout: enabled(import_timeout).map(Duration::from_secs),
+            import_body_limit: enabled(import_body_limit).map(usize::try_from).transpose().context("HTTP_IMPORT_BODY_LIMIT_BYTES is too large")?,
+            cors_origins,
+            compression: env.http_compression == "true",
+            security_headers: env.http_security_headers == "true",
+            hsts_max_age: enabled(hsts_max_age).map(Duration::from_secs),
+            catch_panic: env.http_catch_panic == "true",
+            proxy_hops: (env.trust_proxy_headers == "true").then_some(proxy_hops),
+        })
+    }
+}
+
+// Wrap the router in the configured layers, innermost first
+pub fn harden(router: ApiRouter, options: &HttpOptions) -> ApiRouter {
+    // The request body limit replaces the 2 MB default of the axum extractors
+    let limits = RequestLimits {
+        general: (options.request_timeout, options.body_limit),
+        import: (options.import_timeout, options.import_body_limit),
+    };
+    let router = router
+        .layer(DefaultBodyLimit::disable())
+        .layer(axum::middleware::from_fn_with_state(limits, request_limits));
+    let router = match options.catch_panic {
+        true => router.layer(CatchPanicLayer::custom(panic_response)),
+        false => router,
+    };
+    let router = router.layer(axum::middleware::from_fn(error_bodies));
+    let router = match options.compression {
+        true => router.layer(CompressionLayer::new()),
+        false => router,
+    };
+    let router = match options.security_headers {
+        true => security_headers(router, options),
+        false => router,
+    };
+    match &options.cors_origins {
+        Some(origins) => router.layer(cors(origins)),
+        None => router,
+    }
+}
+
+fn security_headers(router: ApiRouter, options: &HttpOptions) -> ApiRouter {
+    let headers = [
+        (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+        (header::REFERRER_POLICY, HeaderValue::from_static("no-referrer")),
+        (header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("default-src 'none'; frame-ancestors 'none'")),
+    ];
+    let router = headers
+        .into_iter()
+        .fold(router, |router, (name, value)| router.layer(SetResponseHeaderLayer::if_not_present(name, value)));
+
+    let hsts = options
+        .hsts_max_age
+        .and_then(|max_age| HeaderValue::from_str(&format!("max-age={}; includeSubDomains", max_age.as_secs())).ok());
+    match hsts {
+        Some(value) => router.layer(axum::middleware::from_fn_with_state((value, options.proxy_hops), strict_transport_security)),
+        None => router,
+    }
+}
+
+// Browsers ignore HSTS received over plain HTTP, and a proxy speaking plain HTTP to its clients must not get it either
+async fn strict_transport_security(
+    State((value, proxy_hops)): State<(HeaderValue, Option<usize>)>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let secure = req.extensions().get::<TlsConnection>().is_some() || (proxy_hops.is_some() && forwarded_https(req.headers()));
+    let mut response = next.run(req).await;
+    if secure {
+        response.headers_mut().entry(header::STRICT_TRANSPORT_SECURITY).or_insert(value);
+    }
+    response
+}
+
+// Scheme reported by the nearest proxy, the last X-Forwarded-Proto entry
+fn forwarded_https(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get_all("x-forwarded-proto")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"))
+}
+
+// Timeout and body limit of a request, None disables either
+#[derive(Debug, Clone, Copy)]
+struct RequestLimits {
+    general: (Option<Duration>, Option<usize>),
+    import: (Option<Duration>, Option<usize>),
+}
+
+// Answer 408 for slow handlers and 413 for large bodies, announced or streamed
+async fn request_limits(State(limits): State<RequestLimits>, req: Request<Body>, next: Next) -> Response {
+    let (timeout, body_limit) = match req.uri().path() == IMPORT_PATH {
+        true => limits.import,
+        false => limits.general,
+    };
+
+    let req = match body_limit {
+        Some(limit) => {
+            let announced = req
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+            if announced.is_some_and(|length| length > limit as u64) {
+                return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+            }
+            req.map(|body| Body::new(Limited::new(body, limit)))
+        },
+        None => req,
+    };
+
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, next.run(req))
+            .await
+            .unwrap_or_else(|_| StatusCode::REQUEST_TIMEOUT.into_response()),
+        None => next.run(req).await,
+    }
+}
+
+fn cors(origins: &[HeaderValue]) -> CorsLayer {
+    let allow_origin = match origins.is_empty() {
+        true => AllowOrigin::any(),
+        false => AllowOrigin::list(origins.iter().cloned()),
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(AnyValue)
+        .allow_headers(AnyValue)
+        .expose_headers(CORS_EXPOSED_HEADERS.map(HeaderName::from_static))
+        .max_age(CORS_MAX_AGE)
+}
+
+// JSON bodies for the plain responses of the timeout and body limit layers
+pub async fn error_bodies(req: Request<Body>, next: Next) -> Response {
+    let response = next.run(req).await;
+    let error = match response.status() {
+        StatusCode::REQUEST_TIMEOUT => "Request timed out",
+        StatusCode::PAYLOAD_TOO_LARGE => "Request body too large",
+        _ => return response,
+    };
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if is_json {
+        return response;
+    }
+    (response.status(), Json(json!({ "error": error }))).into_response()
+}
+
+fn panic_response(err: Box<dyn Any + Send + 'static>) -> Response {
+    let message = err
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| err.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    eprintln!("Internal Server Error: handler panicked: {message}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": "Internal server error" })),
+    ).into_response()
+}
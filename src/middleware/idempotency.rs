@@ -0,0 +1,213 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{FromRequestParts, Request, State},
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_keycloak_auth::decode::KeycloakToken;
+use metrics::counter;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    config::ConfigState,
+    custom::{extractors::Tenant, telemetry::IDEMPOTENT_REPLAYS_TOTAL},
+    database::idempotency::{
+        claim_idempotency_key, complete_idempotency_key, find_idempotency_key, prune_idempotency_keys, release_idempotency_key,
+        renew_idempotency_key,
+    },
+    definitions::idempotency::{IdempotencyRecord, IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER, MAX_IDEMPOTENCY_KEY_LENGTH},
+};
+
+// Expired keys are deleted this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Running requests renew their lock at a third of IDEMPOTENCY_LOCK_SECS, but not more often than this
+const MIN_RENEW_INTERVAL: Duration = Duration::from_millis(100);
+
+// Run unsafe requests carrying an Idempotency-Key at most once per caller, later retries get the stored response
+pub async fn idempotency(State(config): State<Arc<ConfigState>>, req: Request<Body>, next: Next) -> Response {
+    if req.method().is_safe() {
+        return next.run(req).await;
+    }
+    let Some(key) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.run(req).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH => key.to_string(),
+        _ => return error(StatusCode::BAD_REQUEST, "Idempotency-Key must be 1 to 255 visible ASCII characters"),
+    };
+    // Keys are scoped to the organization and the caller, the API key id is the subject of API key requests
+    let Some(subject) = req.extensions().get::<KeycloakToken<String>>().map(|token| token.subject.clone()) else {
+        return next.run(req).await;
+    };
+    let (mut parts, body) = req.into_parts();
+    let scope = match Tenant::from_request_parts(&mut parts, &config).await {
+        Ok(tenant) => format!("{}:{subject}", tenant.org_id()),
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    // Buffer the body to fingerprint it, then hand it on unchanged
+    let options = config.idempotency;
+    let body = match to_bytes(body, options.max_body_bytes).await {
+        Ok(body) => body,
+        Err(_) => return error(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large to be used with an Idempotency-Key"),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(parts.method.as_str());
+    hasher.update(b" ");
+    hasher.update(parts.uri.path_and_query().map(|path| path.as_str()).unwrap_or_default());
+    hasher.update(b"\n");
+    hasher.update(&body);
+    let fingerprint = hasher.finalize().to_vec();
+
+    let claim_id = Uuid::new_v4();
+    match claim_idempotency_key(&scope, &key, claim_id, &fingerprint, options.lock, options.ttl, &config.pgpool).await {
+        Ok(true) => {},
+        Ok(false) => return existing(&config, &scope, &key, &fingerprint).await,
+        Err(err) => {
+            eprintln!("Internal Server Error: {err}");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    }
+
+    let heartbeat = Heartbeat::spawn(&config, &scope, &key, claim_id);
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    drop(heartbeat);
+
+    // Server errors are not stored so the client can retry them
+    if response.status().is_server_error() {
+        release(&config, &scope, &key, claim_id).await;
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            eprintln!("Failed to buffer response for Idempotency-Key: {err}");
+            release(&config, &scope, &key, claim_id).await;
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    };
+    let headers: Map<String, Value> = parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), Value::String(value.to_str().ok()?.to_string()))))
+        .collect();
+    if let Err(err) = complete_idempotency_key(&scope, &key, claim_id, parts.status.as_u16() as i16, Value::Object(headers), &body, &config.pgpool).await {
+        // The response was produced, a retry will see the claim time out instead of a replay
+        eprintln!("Failed to store response for Idempotency-Key: {err}");
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+// Answer a request whose key is already claimed
+async fn existing(config: &ConfigState, scope: &str, key: &str, fingerprint: &[u8]) -> Response {
+    let record = match find_idempotency_key(scope, key, &config.pgpool).await {
+        Ok(Some(record)) => record,
+        // The record expired or was released in between, the client can simply retry
+        Ok(None) => return in_progress(),
+        Err(err) => {
+            eprintln!("Internal Server Error: {err}");
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error");
+        }
+    };
+
+    if record.fingerprint != fingerprint {
+        return error(StatusCode::UNPROCESSABLE_ENTITY, "Idempotency-Key was already used for a different request");
+    }
+    match replay(record) {
+        Some(response) => {
+            counter!(IDEMPOTENT_REPLAYS_TOTAL).increment(1);
+            response
+        },
+        None => in_progress(),
+    }
+}
+
+// Rebuild a stored response, None while the original request is still running
+fn replay(record: IdempotencyRecord) -> Option<Response> {
+    let status = StatusCode::from_u16(u16::try_from(record.status_code?).ok()?).ok()?;
+    let mut response = Response::new(Body::from(record.response_body.unwrap_or_default()));
+    *response.status_mut() = status;
+
+    if let Some(Value::Object(headers)) = record.response_headers {
+        for (name, value) in headers {
+            let (Ok(name), Some(Ok(value))) = (HeaderName::try_from(name), value.as_str().map(HeaderValue::from_str)) else {
+                continue;
+            };
+            response.headers_mut().append(name, value);
+        }
+    }
+    response.headers_mut().insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    Some(response)
+}
+
+async fn release(config: &ConfigState, scope: &str, key: &str, claim_id: Uuid) {
+    if let Err(err) = release_idempotency_key(scope, key, claim_id, &config.pgpool).await {
+        eprintln!("Failed to release Idempotency-Key: {err}");
+    }
+}
+
+// Keeps renewing the lock of a running request so it is not taken over while the handler is still working,
+// stops when dropped. A request cancelled by a disconnect leaves its claim to run out
+struct Heartbeat(JoinHandle<()>);
+
+impl Heartbeat {
+    fn spawn(config: &Arc<ConfigState>, scope: &str, key: &str, claim_id: Uuid) -> Self {
+        let (config, scope, key) = (config.clone(), scope.to_string(), key.to_string());
+        Self(tokio::spawn(async move {
+            let lock = config.idempotency.lock;
+            let mut ticker = tokio::time::interval((lock / 3).max(MIN_RENEW_INTERVAL));
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match renew_idempotency_key(&scope, &key, claim_id, lock, &config.pgpool).await {
+                    Ok(true) => {},
+                    Ok(false) => return,
+                    Err(err) => eprintln!("Failed to renew Idempotency-Key: {err}"),
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn in_progress() -> Response {
+    (
+        StatusCode::CONFLICT,
+        [(header::RETRY_AFTER, "1")],
+        Json(json!({ "error": "A request with this Idempotency-Key is still in progress" })),
+    ).into_response()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+// Delete expired keys in the background
+pub(crate) fn spawn_idempotency_cleanup(config: &Arc<ConfigState>) {
+    let config = config.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = prune_idempotency_keys(&config.pgpool).await {
+                eprintln!("Failed to prune idempotency keys: {err}");
+            }
+        }
+    });
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use crate::{config::ConfigState, custom::extractors::client_addr};
+
+// Restrict /metrics to the allowed networks, then to the configured credentials
+pub async fn protect_metrics(State(config): State<Arc<ConfigState>>, req: Request<Body>, next: Next) -> Response {
+    let options = &config.metrics;
+    // Callers without a known address, e.g. on a Unix socket without trusted proxy headers, are refused
+    if !options.allowed_networks.is_empty() {
+        let allowed = client_addr(req.headers(), req.extensions(), config.http.proxy_hops).is_some_and(|ip| options.allows(ip));
+        if !allowed {
+            return (StatusCode::FORBIDDEN, Json(json!({ "error": "Forbidden" }))).into_response();
+        }
+    }
+
+    let authorization = req.headers().get(header::AUTHORIZATION).map(HeaderValue::as_bytes);
+    if !options.authorizes(authorization) {
+        let challenge = match options.basic_auth {
+            true => r#"Basic realm="metrics""#,
+            false => r#"Bearer realm="metrics""#,
+        };
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, challenge)],
+            Json(json!({ "error": "Unauthorized" })),
+        ).into_response();
+    }
+
+    next.run(req).await
+}
@@ -1 +1,7 @@
-pub mod ignore_logs;
\ No newline at end of file
+pub mod ignore_logs;
+pub mod authentication;
+pub mod hardening;
+pub mod idempotency;
+pub mod rate_limit;
+pub mod metrics_access;
+pub mod read_your_writes;
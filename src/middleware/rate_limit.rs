@@ -0,0 +1,85 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_keycloak_auth::decode::KeycloakToken;
+use metrics::counter;
+use serde_json::json;
+
+use crate::{
+    config::ConfigState,
+    custom::{extractors::client_ip, rate_limiter::{RateLimitDecision, RouteGroup}, telemetry::RATE_LIMITED_TOTAL},
+    definitions::api_key::ApiKeyIdentity,
+};
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+// Throttle a route group, the identity group is keyed by API key or token subject and the others by client IP
+pub async fn rate_limit(State((config, group)): State<(Arc<ConfigState>, RouteGroup)>, req: Request<Body>, next: Next) -> Response {
+    let key = caller_key(&config, group, &req);
+    let decision = match config.rate_limiter.check(group, &key).await {
+        Ok(Some(decision)) => decision,
+        Ok(None) => return next.run(req).await,
+        Err(err) => {
+            // Failing open keeps the API reachable when the shared backend is down
+            eprintln!("Rate limiter unavailable: {err}");
+            return next.run(req).await;
+        }
+    };
+
+    let mut response = match decision.allowed {
+        true => next.run(req).await,
+        false => {
+            counter!(RATE_LIMITED_TOTAL, "group" => group.label()).increment(1);
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, whole_seconds(decision.reset).to_string())],
+                Json(json!({ "error": "Too many requests" })),
+            ).into_response()
+        },
+    };
+    insert_headers(response.headers_mut(), &decision);
+    response
+}
+
+fn caller_key(config: &ConfigState, group: RouteGroup, req: &Request<Body>) -> String {
+    let extensions = req.extensions();
+    match (group, extensions.get::<KeycloakToken<String>>()) {
+        (RouteGroup::Identity, Some(token)) if extensions.get::<ApiKeyIdentity>().is_some() => format!("key:{}", token.subject),
+        (RouteGroup::Identity, Some(token)) => format!("sub:{}", token.subject),
+        _ => format!("ip:{}", client_ip(req.headers(), extensions, config.http.proxy_hops)),
+    }
+}
+
+// An inner limit that is closer to running out keeps its headers
+fn insert_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    let remaining = headers.get(RATELIMIT_REMAINING).and_then(|value| value.to_str().ok()?.parse::<u32>().ok());
+    if remaining.is_some_and(|remaining| remaining <= decision.remaining) {
+        return;
+    }
+    let policy = format!("{};w={}", decision.policy.capacity, decision.policy.period.as_secs());
+    for (name, value) in [
+        (RATELIMIT_LIMIT, decision.policy.capacity.to_string()),
+        (RATELIMIT_REMAINING, decision.remaining.to_string()),
+        (RATELIMIT_RESET, whole_seconds(decision.reset).to_string()),
+        (RATELIMIT_POLICY, policy),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+// Rounded up so clients never retry too early
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_keycloak_auth::decode::KeycloakToken;
+
+use crate::config::ConfigState;
+
+// Pin the reads of a caller to the primary for a while after it changed something
+pub async fn track_writes(State(config): State<Arc<ConfigState>>, req: Request<Body>, next: Next) -> Response {
+    if req.method().is_safe() {
+        return next.run(req).await;
+    }
+    let subject = req.extensions().get::<KeycloakToken<String>>().map(|token| token.subject.clone());
+
+    let response = next.run(req).await;
+    if let Some(subject) = subject.filter(|_| response.status().is_success()) {
+        config.replicas.record_write(&subject);
+    }
+    response
+}
@@ -0,0 +1,194 @@
+pub mod sinks;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use reqwest::Client;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::{
+    config::{ConfigState, EnvironmentVariables},
+    custom::telemetry::{record_outbox_event, record_sink_call},
+    database::outbox::{claim_events, mark_delivered, prune_delivered, record_failure, release_events},
+    definitions::outbox::{OutboxEvent, OutboxOptions},
+};
+
+// Longest wait between two deliveries of a failing event
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+// Delivered events are pruned at most this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(300);
+
+// Destination of domain events, selected with OUTBOX_SINKS
+pub trait EventSink: Send + Sync {
+    // Name accepted by OUTBOX_SINKS
+    fn name(&self) -> &'static str;
+
+    // Deliver one event, an error leaves it to be delivered again later
+    fn publish<'a>(&'a self, event: &'a OutboxEvent) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+// Sinks receiving the events of the outbox table and the relay settings
+pub struct Outbox {
+    sinks: Vec<Box<dyn EventSink>>,
+    pub options: OutboxOptions,
+}
+
+impl Outbox {
+    pub fn from_env(env: &EnvironmentVariables, client: &Client) -> anyhow::Result<Self> {
+        let mut sinks: Vec<Box<dyn EventSink>> = Vec::new();
+        for name in env.outbox_sinks.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let sink: Box<dyn EventSink> = match name {
+                "log" => Box::new(sinks::LogSink),
+                "webhook" => Box::new(sinks::WebhookSink::from_env(env, client.clone())?),
+                "nats" => Box::new(sinks::NatsSink::from_env(env)?),
+                "kafka" => Box::new(sinks::KafkaSink::from_env(env, client.clone())?),
+                other => bail!("Unknown outbox sink {other} in OUTBOX_SINKS, expected log, webhook, nats or kafka"),
+            };
+            sinks.push(sink);
+        }
+
+        let options = OutboxOptions::from_env(env)?;
+        let outbox = Self { sinks, options };
+        if outbox.delivery_bound() > options.lease {
+            bail!("OUTBOX_LEASE_SECS must leave time for one delivery to every sink, at least {}s", outbox.delivery_bound().as_secs());
+        }
+        Ok(outbox)
+    }
+
+    pub fn sink_names(&self) -> Vec<&'static str> {
+        self.sinks.iter().map(|sink| sink.name()).collect()
+    }
+
+    // An event is delivered once every sink accepted it, a failure sends it to all of them again
+    pub(crate) async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        for sink in &self.sinks {
+            let started = Instant::now();
+            let result = sink.publish(event).await;
+            record_sink_call(sink.name(), started, &result);
+            if let Err(err) = result {
+                bail!("{} sink: {err:#}", sink.name());
+            }
+        }
+        Ok(())
+    }
+
+    // Longest a single event can take to be published to every sink
+    fn delivery_bound(&self) -> Duration {
+        sinks::SINK_TIMEOUT * self.sinks.len() as u32
+    }
+
+    // Wait after the given number of failed deliveries
+    fn retry_delay(&self, attempts: i32) -> Duration {
+        let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+        (self.options.retry_delay * 2u32.pow(doublings)).min(MAX_RETRY_DELAY)
+    }
+}
+
+// Outcome of one delivery, written back once the batch is done
+enum Settled {
+    Delivered(Uuid),
+    Failed { event_id: Uuid, error: String, retry_at: DateTime<Utc>, dead_letter: bool },
+}
+
+// Deliver one batch of due events and return how many were claimed.
+// The batch is leased in its own transaction, so no lock is held while the sinks are called, and the
+// outcomes are written back in a second one. A relay that dies leaves its events to be claimed again
+// once the lease ran out, events the lease no longer covers are released without being attempted
+pub async fn relay_events(outbox: &Outbox, pgpool: &Pool<Postgres>) -> Result<usize, sqlx::Error> {
+    let started = Instant::now();
+    let lease = outbox.options.lease;
+    let lease_until = Utc::now() + lease;
+    let events = claim_events(outbox.options.batch_size, lease_until, pgpool).await?;
+
+    let mut settled = Vec::with_capacity(events.len());
+    for event in &events {
+        if started.elapsed() + outbox.delivery_bound() > lease {
+            break;
+        }
+        match outbox.publish(event).await {
+            Ok(()) => {
+                settled.push(Settled::Delivered(event.event_id));
+                record_outbox_event("delivered");
+            },
+            Err(err) => {
+                let attempts = event.attempts + 1;
+                let dead_letter = attempts >= outbox.options.max_attempts;
+                match dead_letter {
+                    true => {
+                        eprintln!("Dead-lettered {} event {} after {attempts} attempts: {err:#}", event.event_type, event.event_id);
+                        record_outbox_event("dead_lettered");
+                    },
+                    false => {
+                        eprintln!("Failed to deliver {} event {} (attempt {attempts}): {err:#}", event.event_type, event.event_id);
+                        record_outbox_event("failed");
+                    },
+                }
+                settled.push(Settled::Failed {
+                    event_id: event.event_id,
+                    error: format!("{err:#}"),
+                    retry_at: Utc::now() + outbox.retry_delay(attempts),
+                    dead_letter,
+                });
+            },
+        }
+    }
+
+    let mut tx = pgpool.begin().await?;
+    let mut delivered = Vec::with_capacity(settled.len());
+    for outcome in &settled {
+        match outcome {
+            Settled::Delivered(event_id) => delivered.push(*event_id),
+            Settled::Failed { event_id, error, retry_at, dead_letter } => {
+                record_failure(*event_id, error, *retry_at, *dead_letter, &mut *tx).await?;
+            },
+        }
+    }
+    mark_delivered(&delivered, &mut *tx).await?;
+    let unattempted: Vec<Uuid> = events.iter().skip(settled.len()).map(|event| event.event_id).collect();
+    if !unattempted.is_empty() {
+        release_events(&unattempted, &mut *tx).await?;
+    }
+    tx.commit().await?;
+
+    Ok(events.len())
+}
+
+// Relay events every OUTBOX_POLL_INTERVAL_MS when sinks are configured, full batches are followed up immediately
+pub fn spawn_outbox_relay(config: &Arc<ConfigState>) {
+    if config.outbox.sinks.is_empty() {
+        return;
+    }
+    let config = config.clone();
+    tokio::spawn(async move {
+        let options = config.outbox.options;
+        let mut last_prune: Option<Instant> = None;
+        loop {
+            let claimed = match relay_events(&config.outbox, &config.pgpool).await {
+                Ok(claimed) => claimed,
+                Err(err) => {
+                    eprintln!("Outbox relay failed: {err}");
+                    0
+                },
+            };
+            if claimed as i64 >= options.batch_size {
+                continue;
+            }
+
+            if let Some(retention) = options.retention.filter(|_| last_prune.is_none_or(|pruned| pruned.elapsed() >= PRUNE_INTERVAL)) {
+                last_prune = Some(Instant::now());
+                let cutoff = Utc::now() - retention;
+                if let Err(err) = prune_delivered(cutoff, &config.pgpool).await {
+                    eprintln!("Failed to prune delivered outbox events: {err}");
+                }
+            }
+            tokio::time::sleep(options.poll_interval).await;
+        }
+    });
+}
@@ -1,18 +1,44 @@
 use std::sync::Arc;
 
-use crate::{config::ConfigState, routes::{auth::login_user, root::get_root, users::{delete_user, post_user, put_user}}};
-use crate::routes::users::get_user;
+use crate::{config::ConfigState, custom::{rate_limiter::RouteGroup, telemetry::{prometheus_handle, MetricSet}}, database::attributes::list_attribute_definitions, definitions::{attribute::{attributes_schema, AttributeDefinition}, user::User}, middleware::{authentication::{authenticate_api_key, require_authentication}, hardening::harden, idempotency::idempotency, ignore_logs::ignore_logs, metrics_access::protect_metrics, rate_limit::rate_limit, read_your_writes::track_writes}, routes::{attributes::{delete_attribute, get_attributes, post_attribute}, auth::{client_token, login_user}, health::{get_live, get_ready}, root::get_root, users::{batch_users, delete_user, disable_user, enable_user, post_user, put_user}}};
+use crate::routes::{api_keys::{delete_api_key, get_api_keys, post_api_key, rotate_key}, keycloak::post_reconcile, invitations::{delete_invitation, get_invitations, post_accept_invitation, post_invitation, resend_invitation}, groups::{delete_group, delete_member, get_group, get_groups, get_members, get_my_groups, post_group, put_group, put_member}, organizations::{delete_organization, get_organization, get_organizations, post_organization, put_organization}, outbox::{delete_dead_letter, get_dead_letters, retry_dead_letter}, transfer::{export_users, import_users}, users::{get_user, search_users}};
 use axum_prometheus::PrometheusMetricLayer;
-use axum_keycloak_auth::{instance::{KeycloakAuthInstance, KeycloakConfig}, layer::KeycloakAuthLayer, PassthroughMode, Url};
-use axum::{Extension, Json};
+use axum::{extract::State, Extension, Json};
+use serde_json::Value;
 use aide::axum::{
     routing::get,
     ApiRouter, IntoApiResponse,
 };
 
-// Serve pre-serialzed JSON
-async fn serve_api(Extension(api_json): Extension<Arc<String>>) -> impl IntoApiResponse {
-    Json((*api_json).clone())
+// Serve pre-serialzed JSON, with the User schema reflecting the current attribute definitions
+async fn serve_api(State(config): State<Arc<ConfigState>>, Extension(api_json): Extension<Arc<String>>) -> impl IntoApiResponse {
+    let mut api: Value = serde_json::from_str(&api_json).unwrap_or_default();
+    api["components"]["schemas"]["User"] = match config.user_schema.get() {
+        Some(schema) => schema,
+        None => match list_attribute_definitions(&config.pgpool).await {
+            Ok(definitions) => {
+                let schema = user_schema(&definitions);
+                config.user_schema.set(schema.clone());
+                schema
+            },
+            // Not cached so the next request tries again
+            Err(err) => {
+                eprintln!("Failed to load attribute definitions: {err}");
+                user_schema(&[])
+            },
+        },
+    };
+
+    Json(api)
+}
+
+fn user_schema(definitions: &[AttributeDefinition]) -> Value {
+    let mut user_schema = serde_json::to_value(schemars::schema_for!(User)).unwrap_or_default();
+    if let Value::Object(schema) = &mut user_schema {
+        schema.remove("$schema");
+    }
+    user_schema["properties"]["attributes"] = attributes_schema(definitions);
+    user_schema
 }
 
 // OpenAPI endpoints
@@ -22,47 +48,78 @@ pub fn open_api_router(config: Arc<ConfigState>) -> ApiRouter {
     .with_state(config)
 }
 
+// Render the metric sets enabled in METRICS_SETS
+async fn serve_metrics(State(config): State<Arc<ConfigState>>) -> String {
+    config.metrics.render(&config.pgpool)
+}
+
 // Metrics endpoints
-pub fn metrics_router() -> (ApiRouter, PrometheusMetricLayer<'static>) {
-    let (mut prometheus_layer, metric_handle) = PrometheusMetricLayer::pair();
+pub fn metrics_router(config: Arc<ConfigState>) -> (ApiRouter, PrometheusMetricLayer<'static>) {
+    // Installs the global recorder before the layer records the first request
+    prometheus_handle();
+    let mut prometheus_layer = PrometheusMetricLayer::new();
     prometheus_layer.enable_response_body_size();
 
     let router = ApiRouter::new()
-    .api_route("/metrics", get(|| async move { metric_handle.render() }));
+    .api_route("/metrics", get(serve_metrics))
+    .with_state(config.clone())
+    .layer(axum::middleware::from_fn_with_state(config, protect_metrics));
 
     (router, prometheus_layer)
 }
 
-// Protector router layer
-pub fn protect(router:ApiRouter, instance: Arc<KeycloakAuthInstance>) -> ApiRouter {
-    router.layer(
-        KeycloakAuthLayer::<String>::builder()
-            .instance(instance)
-            .passthrough_mode(PassthroughMode::Block)
-            .persist_raw_claims(false)
-            .expected_audiences(vec![String::from("account")])
-            .required_roles(vec![String::from("user")])
-            .build(),
-    )
+// Protector router layer, requests need a bearer token of the auth provider or an API key
+pub fn protect(router:ApiRouter, config: Arc<ConfigState>) -> ApiRouter {
+    let router = router.layer(axum::middleware::from_fn(require_authentication));
+    config.auth
+    .authenticate(router)
+    .layer(axum::middleware::from_fn_with_state(config, authenticate_api_key))
 }
 
 // Protected endpoints
 pub fn private_router(config: Arc<ConfigState>) -> ApiRouter {
-    // Create keycloak auth integration instance
-    let keycloak_auth_instance = KeycloakAuthInstance::new(
-        KeycloakConfig::builder()
-            .server(Url::parse(&config.env.kc_server_addr).unwrap()) // TODO fix hardcoded KC server address
-            .realm(String::from("api-template"))
-            .build(),
-    );
-
     let unprotected_router = ApiRouter::new()
     .api_route("/users/{id}", get(get_user).delete(delete_user))
     .api_route("/users/{id}", axum::routing::put(put_user).into())
     .api_route("/users", axum::routing::post(post_user).into())
-    .with_state(config.clone());
-    
-    protect(unprotected_router, keycloak_auth_instance.into())
+    .api_route("/users/{id}/disable", axum::routing::post(disable_user).into())
+    .api_route("/users/{id}/enable", axum::routing::post(enable_user).into())
+    .api_route("/users:batch", axum::routing::post(batch_users).into())
+    .api_route("/users/search", axum::routing::get(search_users).into())
+    .api_route("/users/export", axum::routing::get(export_users).into())
+    .api_route("/users/import", axum::routing::post(import_users).into())
+    .api_route("/attributes", get(get_attributes))
+    .api_route("/attributes", axum::routing::post(post_attribute).into())
+    .api_route("/attributes/{name}", axum::routing::delete(delete_attribute).into())
+    .api_route("/groups", axum::routing::get(get_groups).post(post_group).into())
+    .api_route("/groups/{id}", get(get_group).delete(delete_group))
+    .api_route("/groups/{id}", axum::routing::put(put_group).into())
+    .api_route("/groups/{id}/members", axum::routing::get(get_members).into())
+    .api_route("/groups/{id}/members/{user_id}", axum::routing::put(put_member).delete(delete_member).into())
+    .api_route("/me/groups", get(get_my_groups))
+    .api_route("/invitations", axum::routing::get(get_invitations).post(post_invitation).into())
+    .api_route("/invitations/{id}", axum::routing::delete(delete_invitation).into())
+    .api_route("/invitations/{id}/resend", axum::routing::post(resend_invitation).into())
+    .api_route("/api-keys", axum::routing::get(get_api_keys).post(post_api_key).into())
+    .api_route("/api-keys/{id}", axum::routing::delete(delete_api_key).into())
+    .api_route("/api-keys/{id}/rotate", axum::routing::post(rotate_key).into())
+    .api_route("/keycloak/reconcile", axum::routing::post(post_reconcile).into())
+    .api_route("/outbox/dead-letters", axum::routing::get(get_dead_letters).into())
+    .api_route("/outbox/dead-letters/{id}", axum::routing::delete(delete_dead_letter).into())
+    .api_route("/outbox/dead-letters/{id}/requeue", axum::routing::post(retry_dead_letter).into())
+    .api_route("/organizations", get(get_organizations))
+    .api_route("/organizations", axum::routing::post(post_organization).into())
+    .api_route("/organizations/{id}", get(get_organization).delete(delete_organization))
+    .api_route("/organizations/{id}", axum::routing::put(put_organization).into())
+    .with_state(config.clone())
+    .layer(axum::middleware::from_fn_with_state(config.clone(), track_writes))
+    .layer(axum::middleware::from_fn_with_state(config.clone(), idempotency))
+    // Inside the authentication layers so the bucket belongs to the API key or token subject
+    .layer(axum::middleware::from_fn_with_state((config.clone(), RouteGroup::Identity), rate_limit));
+
+    // Outside the authentication layers so rejected tokens and API key lookups are throttled per client IP
+    protect(unprotected_router, config.clone())
+    .layer(axum::middleware::from_fn_with_state((config, RouteGroup::Private), rate_limit))
 }
 
 // Publically available endpoints
@@ -70,5 +127,62 @@ pub fn public_router(config: Arc<ConfigState>) -> ApiRouter {
     ApiRouter::new()
     .api_route("/", get(get_root))
     .api_route("/login", axum::routing::post(login_user).into())
+    .api_route("/token", axum::routing::post(client_token).into())
+    .api_route("/invitations/accept", axum::routing::post(post_accept_invitation).into())
+    .with_state(config.clone())
+    .layer(axum::middleware::from_fn_with_state((config, RouteGroup::Public), rate_limit))
+}
+
+// Liveness and readiness probes
+pub fn health_router(config: Arc<ConfigState>) -> ApiRouter {
+    ApiRouter::new()
+    .api_route("/health/live", get(get_live))
+    .api_route("/health/ready", get(get_ready))
     .with_state(config)
-}
\ No newline at end of file
+}
+
+// Operational endpoints, merged into the application unless ADMIN_BIND_ADDR gives them their own listener
+pub fn admin_router(config: Arc<ConfigState>) -> ApiRouter {
+    let (metrics_router, _) = metrics_router(config.clone());
+
+    let router = ApiRouter::new()
+    .merge(metrics_router)
+    .merge(open_api_router(config.clone()))
+    .merge(health_router(config.clone()))
+    .layer(axum::middleware::from_fn(ignore_logs));
+
+    harden(router, &config.http)
+}
+
+// Complete application, shared by the server binary and the test harness
+pub fn app_router(config: Arc<ConfigState>) -> ApiRouter {
+    // Get Metrics Router
+    let (metrics_router, prometheus_layer) = metrics_router(config.clone());
+    let http_metrics = config.metrics.enabled(MetricSet::Http);
+    let merge_operational = config.env.admin_bind_addr.is_empty();
+
+    let router = ApiRouter::new()
+    .merge(private_router(config.clone()))
+    .merge(public_router(config.clone()));
+
+    // Metrics, docs and health checks stay off the client facing listener when the admin listener is enabled
+    let router = match merge_operational {
+        true => router.merge(metrics_router),
+        false => router,
+    };
+    // Request metrics are only recorded when the http set is exported
+    let router = match http_metrics {
+        true => router.layer(prometheus_layer),
+        false => router,
+    };
+    let router = match merge_operational {
+        true => router
+            .merge(open_api_router(config.clone()))
+            .merge(health_router(config.clone())),
+        false => router,
+    }
+    .layer(axum::middleware::from_fn(ignore_logs));
+
+    // Timeouts, body limits, CORS, compression and security headers around every route
+    harden(router, &config.http)
+}
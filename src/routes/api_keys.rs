@@ -0,0 +1,222 @@
+use std::sync::Arc;
+use axum::{extract::{Path, Query, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::{decode::KeycloakToken, role::ExpectRoles};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use rand::{rngs::OsRng, RngCore};
+use serde_json::{json, Value};
+use tracing::instrument;
+use uuid::Uuid;
+use aide::axum::IntoApiResponse;
+use crate::{
+    config::ConfigState,
+    custom::{extractors::Tenant, signing::{derive_key, digest, API_KEY_DIGESTS}},
+    database::api_keys::{count_api_keys, create_api_key, find_api_key, list_api_keys, remove_api_key, rotate_api_key},
+    definitions::{
+        api_key::{ApiKey, NewApiKey, RotateApiKey, API_KEY_PREFIX, MAX_API_KEY_TTL_DAYS, MAX_ROTATION_GRACE_SECS},
+        pagination::{Page, PageParams},
+    },
+    expect_admin,
+    middleware::authentication::API_KEY_ISSUER,
+    routes::shared::{internal_error, invalid_uuid, tenant_work},
+};
+
+// Every key holds the role required by the protect layer
+const BASE_ROLE: &str = "user";
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn post_api_key(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(new_key): Json<NewApiKey>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let name = new_key.name.trim();
+    if name.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "API key name must not be empty" })),
+        );
+    }
+
+    // A key can never hold a role its creator does not have
+    let mut roles = new_key.roles;
+    if let Err(err) = token.expect_roles(&roles) {
+        eprintln!("Rejected API key roles: {err}");
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "API keys cannot be granted roles the caller does not hold" })),
+        );
+    }
+    if !roles.iter().any(|role| role == BASE_ROLE) {
+        roles.push(BASE_ROLE.to_string());
+    }
+
+    let expires_at = match new_key.expires_in_days {
+        Some(days) if (1..=MAX_API_KEY_TTL_DAYS).contains(&days) => Some(Utc::now() + Duration::days(days)),
+        Some(_) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": format!("expires_in_days must be between 1 and {MAX_API_KEY_TTL_DAYS}") })),
+            );
+        }
+        None => None,
+    };
+
+    let key_id = Uuid::new_v4();
+    let secret = generate_secret();
+    let secret_hash = digest(&derive_key(&config.env.secret, API_KEY_DIGESTS), &secret);
+    tenant_work(&config, &tenant, |tx| {
+        let (token, roles, secret, secret_hash) = (&token, &roles, &secret, &secret_hash);
+        Box::pin(async move {
+            // Keys created with another key never outlive it
+            let mut expires_at = expires_at;
+            if token.issuer == API_KEY_ISSUER {
+                let parent = match Uuid::parse_str(&token.subject) {
+                    Ok(parent_id) => find_api_key(parent_id, &mut **tx).await?,
+                    Err(_) => None,
+                };
+                let Some(parent) = parent else {
+                    return Ok((
+                        StatusCode::UNAUTHORIZED,
+                        Json(json!({ "error": "Invalid or expired API key" })),
+                    ));
+                };
+                if let Some(parent_expires_at) = parent.expires_at {
+                    expires_at = Some(expires_at.map_or(parent_expires_at, |expires_at| expires_at.min(parent_expires_at)));
+                }
+            }
+
+            let api_key = create_api_key(key_id, tenant.org_id(), name, roles, secret_hash, &token.subject, expires_at, &mut **tx).await?;
+            Ok((StatusCode::CREATED, Json(issued(&api_key, secret))))
+        })
+    }).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_api_keys(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(page): Query<PageParams>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    match list_api_keys(tenant.org_id(), page.limit(), page.offset(), &config.pgpool).await {
+        Ok(rows) => {
+            let total = match rows.first() {
+                Some(row) => row.total,
+                None if page.offset() == 0 => 0,
+                None => match count_api_keys(tenant.org_id(), &config.pgpool).await {
+                    Ok(total) => total,
+                    Err(err) => return internal_error(err),
+                },
+            };
+            let items: Vec<ApiKey> = rows.into_iter().map(|row| row.api_key).collect();
+            (
+                StatusCode::OK,
+                Json(json!(Page {
+                    items,
+                    total,
+                    limit: page.limit(),
+                    offset: page.offset(),
+                })),
+            )
+        },
+        Err(err) => internal_error(err),
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn rotate_key(
+    Extension(token): Extension<KeycloakToken<String>>,
+    key_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    rotate: Option<Json<RotateApiKey>>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let Ok(Path(key_id)) = key_id_result else {
+        return invalid_uuid();
+    };
+    let Json(rotate) = rotate.unwrap_or_default();
+    let previous_expires_at = match rotate.grace_period_secs {
+        0 => None,
+        secs if (1..=MAX_ROTATION_GRACE_SECS).contains(&secs) => Some(Utc::now() + Duration::seconds(secs)),
+        _ => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": format!("grace_period_secs must be between 0 and {MAX_ROTATION_GRACE_SECS}") })),
+            );
+        }
+    };
+
+    let secret = generate_secret();
+    let secret_hash = digest(&derive_key(&config.env.secret, API_KEY_DIGESTS), &secret);
+    tenant_work(&config, &tenant, |tx| {
+        let (secret, secret_hash) = (&secret, &secret_hash);
+        Box::pin(async move {
+            let response = match rotate_api_key(key_id, tenant.org_id(), secret_hash, previous_expires_at, &mut **tx).await? {
+                Some(api_key) => (StatusCode::OK, Json(issued(&api_key, secret))),
+                None => not_found(),
+            };
+            Ok(response)
+        })
+    }).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn delete_api_key(
+    Extension(token): Extension<KeycloakToken<String>>,
+    key_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let Ok(Path(key_id)) = key_id_result else {
+        return invalid_uuid();
+    };
+
+    tenant_work(&config, &tenant, |tx| {
+        Box::pin(async move {
+            let response = match remove_api_key(key_id, tenant.org_id(), &mut **tx).await? {
+                Some(_) => (StatusCode::ACCEPTED, Json(json!({ "message": "API key revoked successfully" }))),
+                None => not_found(),
+            };
+            Ok(response)
+        })
+    }).await
+}
+
+// Random secret part of a key
+fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+// Key metadata together with the full key, which is only ever returned here
+fn issued(api_key: &ApiKey, secret: &[u8]) -> Value {
+    let mut body = json!(api_key);
+    body["key"] = json!(format!("{API_KEY_PREFIX}{}_{}", api_key.key_id.simple(), URL_SAFE_NO_PAD.encode(secret)));
+    body
+}
+
+fn not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "API key not found" })),
+    )
+}
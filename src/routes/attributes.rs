@@ -0,0 +1,124 @@
+use std::sync::Arc;
+use axum::{extract::{Path, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::decode::KeycloakToken;
+use regex::Regex;
+use serde_json::json;
+use sqlx::Error;
+use tracing::instrument;
+use aide::axum::IntoApiResponse;
+use crate::{
+    config::ConfigState,
+    custom::{extractors::Tenant, validators::{forget_attribute_pattern, is_valid_attribute_name}},
+    database::attributes::{create_attribute_definition, list_attribute_definitions, remove_attribute_definition},
+    definitions::attribute::{AttributeDefinition, AttributeType},
+    expect_admin, expect_default_tenant,
+};
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_attributes(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+) -> impl IntoApiResponse {
+    match list_attribute_definitions(&config.pgpool).await {
+        Ok(definitions) => (StatusCode::OK, Json(json!(definitions))),
+        Err(err) => {
+            eprintln!("Internal Server Error: {err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "Internal server error" })),
+            )
+        }
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn post_attribute(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(definition): Json<AttributeDefinition>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+    expect_default_tenant!(tenant);
+
+    if !is_valid_attribute_name(&definition.name) {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "Attribute names must be lowercase snake_case and at most 40 characters" })),
+        );
+    }
+
+    // Patterns only apply to strings and must compile
+    if let Some(pattern) = &definition.pattern {
+        if definition.attribute_type != AttributeType::String {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Patterns are only supported for string attributes" })),
+            );
+        }
+        if let Err(err) = Regex::new(pattern) {
+            eprintln!("Invalid attribute pattern: {err}");
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Invalid pattern" })),
+            );
+        }
+    }
+
+    match create_attribute_definition(definition, &config.pgpool).await {
+        Ok(definition) => {
+            config.user_schema.invalidate();
+            (StatusCode::CREATED, Json(json!(definition)))
+        },
+        Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => {
+            // The primary key rejects duplicates, the unique index rejects existing duplicate values
+            let error = match db_err.constraint() {
+                Some("attribute_definitions_pkey") => "Attribute already exists",
+                _ => "Existing users share values for this attribute",
+            };
+            (StatusCode::CONFLICT, Json(json!({ "error": error })))
+        },
+        Err(err) => {
+            eprintln!("Internal Server Error: {err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "Internal server error" })),
+            )
+        }
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn delete_attribute(
+    Extension(token): Extension<KeycloakToken<String>>,
+    Path(name): Path<String>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+    expect_default_tenant!(tenant);
+
+    match remove_attribute_definition(&name, &config.pgpool).await {
+        Ok(Some(definition)) => {
+            config.user_schema.invalidate();
+            forget_attribute_pattern(&definition.name);
+            (StatusCode::ACCEPTED, Json(json!({ "message": "Attribute deleted successfully" })))
+        },
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Attribute not found" })),
+        ),
+        Err(err) => {
+            eprintln!("Internal Server Error: {err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "Internal server error" })),
+            )
+        }
+    }
+}
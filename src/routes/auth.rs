@@ -1,62 +1,109 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{sync::Arc, time::Duration};
 
 use aide::axum::IntoApiResponse;
-use axum::{extract::{Json, State}, http::StatusCode};
+use axum::{extract::{Json, State}, http::{header, StatusCode}, response::{IntoResponse, Response}};
+use metrics::counter;
 use serde_json::json;
 
-use crate::{config::ConfigState, definitions::auth::{LoginResponse, LoginUser, TokenResponse}};
+use crate::{auth::LoginError, config::ConfigState, custom::{extractors::ClientIp, telemetry::{LOGIN_FAILURES_TOTAL, LOGIN_LOCKOUTS_TOTAL, LOGIN_THROTTLED_TOTAL}}, definitions::auth::{ClientCredentials, LoginUser}};
 
+// Exchange a username and password for tokens, repeated failures lock out the IP and the username
 pub async fn login_user(
     State(config): State<Arc<ConfigState>>,
+    ClientIp(ip): ClientIp,
     Json(new_user): Json<LoginUser>
-) -> impl IntoApiResponse {
-    let mut params: HashMap<&str, &str> = HashMap::new();
-    params.insert("grant_type", "password");
-    params.insert("scope", "email openid");
-    params.insert("client_id", &config.env.kc_client_id);
-    params.insert("client_secret", &config.env.kc_client_secret);
-    params.insert("username", &new_user.username);
-    params.insert("password", &new_user.password);
-
-    let resp = config.client.post(format!("{}{}",config.env.kc_server_addr.to_string(), config.env.kc_login_path.to_string()))
-    .form(&params)
-    .send()
-    .await;
-
-    let res = match resp {
-        Ok(body) => {
-            match body.json::<TokenResponse>().await {
-                Ok(json_body) => {
-                    // Create and return login_response
-                    let login_response: LoginResponse = LoginResponse {
-                        access_token: json_body.access_token,
-                        token_type: json_body.token_type,
-                        expires_in: json_body.expires_in,
-                        refresh_token: json_body.refresh_token,
-                        refresh_expires_in: json_body.refresh_expires_in,
-                    };
+) -> Response {
+    let ip = ip.to_string();
+    let attempt = match config.login_guard.reserve(&ip, &new_user.username) {
+        Ok(attempt) => attempt,
+        Err((scope, remaining)) => {
+            counter!(LOGIN_THROTTLED_TOTAL, "scope" => scope.label()).increment(1);
+            return too_many_attempts(remaining);
+        }
+    };
 
-                    (
-                        StatusCode::OK,
-                        Json(json!(login_response)),
-                    )
-                },
-                Err(err) => {
-                    eprintln!("Failed to parse TokenResponse: {err}");
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(json!({ "status": "internal server error" })),
-                    )
-                },
+    match config.auth.login(&new_user.username, &new_user.password).await {
+        Ok(login_response) => {
+            attempt.succeeded();
+            (
+                StatusCode::OK,
+                Json(json!(login_response)),
+            ).into_response()
+        },
+        Err(LoginError::InvalidCredentials) => {
+            counter!(LOGIN_FAILURES_TOTAL).increment(1);
+            let lockouts = attempt.failed();
+            for (scope, _) in &lockouts {
+                counter!(LOGIN_LOCKOUTS_TOTAL, "scope" => scope.label()).increment(1);
+            }
+            match lockouts.iter().map(|(_, lockout)| *lockout).max() {
+                Some(lockout) => too_many_attempts(lockout),
+                None => (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({ "status": "invalid credentials" })),
+                ).into_response(),
             }
         },
-        Err(err) => {
-            eprintln!("Failed to log in: {err}");
+        Err(LoginError::Unsupported) => (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(json!({ "status": "not supported by the authentication provider" })),
+        ).into_response(),
+        // Provider outages are not the caller's fault and never count as failed attempts
+        Err(LoginError::Unavailable(err)) => {
+            eprintln!("{err}");
             (
-                StatusCode::FORBIDDEN,
-                Json(json!({ "status": "invalid credentials" })),
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "status": "identity provider unavailable" })),
+            ).into_response()
+        },
+        Err(LoginError::Internal(err)) => {
+            eprintln!("{err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "status": "internal server error" })),
+            ).into_response()
+        },
+    }
+}
+
+// Pass client credentials through to the provider for machine clients that have no user account
+pub async fn client_token(
+    State(config): State<Arc<ConfigState>>,
+    Json(credentials): Json<ClientCredentials>
+) -> impl IntoApiResponse {
+    match config.auth.client_token(&credentials.client_id, &credentials.client_secret, credentials.scope.as_deref()).await {
+        Ok(token) => (StatusCode::OK, Json(json!(token))),
+        Err(LoginError::InvalidCredentials) => (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "status": "invalid client credentials" })),
+        ),
+        Err(LoginError::Unsupported) => (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(json!({ "status": "not supported by the authentication provider" })),
+        ),
+        Err(LoginError::Unavailable(err)) => {
+            eprintln!("{err}");
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({ "status": "identity provider unavailable" })),
             )
         },
-    };
-    res
-}
\ No newline at end of file
+        Err(LoginError::Internal(err)) => {
+            eprintln!("{err}");
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({ "status": "identity provider error" })),
+            )
+        },
+    }
+}
+
+// 429 telling the client when to retry, rounded up to whole seconds
+fn too_many_attempts(remaining: Duration) -> Response {
+    let retry_after = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(json!({ "status": "too many login attempts" })),
+    ).into_response()
+}
@@ -0,0 +1,451 @@
+use std::sync::Arc;
+use axum::{extract::{Path, Query, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::{decode::KeycloakToken, role::ExpectRoles};
+use serde_json::{json, Value};
+use sqlx::{Error, PgConnection};
+use tracing::instrument;
+use uuid::Uuid;
+use aide::axum::IntoApiResponse;
+use crate::{
+    config::ConfigState,
+    custom::extractors::Tenant,
+    database::{unit_of_work::unit_of_work, groups::{create_group, find_group, find_membership_role, is_descendant, list_groups, list_members, list_user_groups, remove_group, remove_membership, update_group, upsert_membership}, users::find_user},
+    definitions::{group::{Group, GroupListQuery, MemberListQuery, MembershipRole, NewGroup, NewMembership}, pagination::{Page, PageParams}},
+    expect_admin,
+    keycloak::sync::{ensure_group, push_group, push_membership, spawn_group_sync},
+    routes::shared::{finish_transaction, internal_error, invalid_uuid, read_transaction, tenant_work},
+};
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_groups(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(query): Query<GroupListQuery>,
+    Query(page): Query<PageParams>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let mut tx = match read_transaction(&config, &tenant, &token).await {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+
+    let response = match list_groups(tenant.org_id(), query.parent_id, page.limit(), page.offset(), &mut *tx).await {
+        Ok(rows) => {
+            let total = rows.first().map(|row| row.total).unwrap_or(0);
+            let items: Vec<Group> = rows.into_iter().map(|row| row.group).collect();
+            (
+                StatusCode::OK,
+                Json(json!(Page {
+                    items,
+                    total,
+                    limit: page.limit(),
+                    offset: page.offset(),
+                })),
+            )
+        },
+        Err(err) => internal_error(err),
+    };
+    finish_transaction(tx, response).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_group(
+    Extension(token): Extension<KeycloakToken<String>>,
+    group_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    let Ok(Path(group_id)) = group_id_result else {
+        return invalid_uuid();
+    };
+
+    let mut tx = match read_transaction(&config, &tenant, &token).await {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+
+    let response = match load_group(&token, group_id, tenant, &[MembershipRole::Owner, MembershipRole::Member], &mut tx).await {
+        Ok(Ok(group)) => (StatusCode::OK, Json(json!(group))),
+        Ok(Err(err)) => err,
+        Err(err) => internal_error(err),
+    };
+    finish_transaction(tx, response).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn post_group(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(new_group): Json<NewGroup>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let name = match new_group.name {
+        Some(name) if !name.trim().is_empty() => name,
+        _ => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Group name must not be empty" })),
+            );
+        }
+    };
+
+    // The id is chosen once so every attempt of the unit of work creates the same group
+    let group = Group {
+        group_id: Uuid::new_v4(),
+        parent_id: new_group.parent_id.flatten(),
+        name,
+        description: new_group.description,
+        keycloak_id: None,
+    };
+    let response = tenant_work(&config, &tenant, |tx| {
+        let group = group.clone();
+        Box::pin(async move {
+            if let Some(parent_id) = group.parent_id {
+                if let Err(err) = expect_parent(parent_id, tenant, tx).await? {
+                    return Ok(err);
+                }
+            }
+            let response = match create_group(group, tenant.org_id(), &mut **tx).await {
+                Ok(group) => (StatusCode::CREATED, Json(json!(group))),
+                Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => duplicate_group(),
+                Err(err) => return Err(err),
+            };
+            Ok(response)
+        })
+    }).await;
+
+    if response.0.is_success() {
+        let group_id = response.1["group_id"].as_str().and_then(|id| Uuid::parse_str(id).ok()).unwrap_or_default();
+        let org_id = tenant.org_id();
+        spawn_group_sync(&config, move |config| async move {
+            ensure_group(&config, group_id, org_id).await.map(|_| ())
+        });
+    }
+    response
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn put_group(
+    Extension(token): Extension<KeycloakToken<String>>,
+    group_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(new_group): Json<NewGroup>,
+) -> impl IntoApiResponse {
+    let Ok(Path(group_id)) = group_id_result else {
+        return invalid_uuid();
+    };
+
+    if new_group.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "Group name must not be empty" })),
+        );
+    }
+
+    // Owners may rename their group, only administrators can move it
+    let is_admin = token.expect_roles(&[String::from("administrator")]).is_ok();
+    let moved = new_group.parent_id.is_some();
+    let response = tenant_work(&config, &tenant, |tx| {
+        let (token, new_group) = (&token, new_group.clone());
+        Box::pin(async move {
+            if let Err(err) = load_group(token, group_id, tenant, &[MembershipRole::Owner], tx).await? {
+                return Ok(err);
+            }
+            if let Some(parent_id) = new_group.parent_id {
+                if !is_admin {
+                    return Ok(insufficient_privileges());
+                }
+                if let Some(parent_id) = parent_id {
+                    if let Err(err) = expect_parent(parent_id, tenant, tx).await? {
+                        return Ok(err);
+                    }
+                    if is_descendant(parent_id, group_id, &mut **tx).await? {
+                        return Ok((
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            Json(json!({ "error": "A group cannot be moved below itself" })),
+                        ));
+                    }
+                }
+            }
+
+            let response = match update_group(group_id, tenant.org_id(), new_group.name, new_group.description, new_group.parent_id, &mut **tx).await {
+                Ok(Some(group)) => (StatusCode::OK, Json(json!(group))),
+                Ok(None) => group_not_found(),
+                Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => duplicate_group(),
+                Err(err) => return Err(err),
+            };
+            Ok(response)
+        })
+    }).await;
+
+    if response.0.is_success() {
+        let org_id = tenant.org_id();
+        spawn_group_sync(&config, move |config| async move {
+            push_group(&config, group_id, org_id, moved).await
+        });
+    }
+    response
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn delete_group(
+    Extension(token): Extension<KeycloakToken<String>>,
+    group_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let Ok(Path(group_id)) = group_id_result else {
+        return invalid_uuid();
+    };
+
+    let removed = unit_of_work(&config.pgpool, tenant.org_id(), |tx| {
+        Box::pin(async move {
+            let group = remove_group(group_id, tenant.org_id(), &mut **tx).await?;
+            Ok(group.map(|group| group.keycloak_id).ok_or_else(group_not_found))
+        })
+    }).await;
+    let keycloak_id = match removed {
+        Ok(Ok(keycloak_id)) => keycloak_id,
+        Ok(Err(err)) => return err,
+        Err(err) => return internal_error(err),
+    };
+    let response = (StatusCode::ACCEPTED, Json(json!({ "message": "Group deleted successfully" })));
+
+    // Keycloak removes subgroups together with their parent
+    if let Some(keycloak_id) = keycloak_id {
+        spawn_group_sync(&config, move |config| async move {
+            config.kc_admin.delete_group(&keycloak_id).await
+        });
+    }
+    response
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_members(
+    Extension(token): Extension<KeycloakToken<String>>,
+    group_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(query): Query<MemberListQuery>,
+    Query(page): Query<PageParams>,
+) -> impl IntoApiResponse {
+    let Ok(Path(group_id)) = group_id_result else {
+        return invalid_uuid();
+    };
+
+    let mut tx = match read_transaction(&config, &tenant, &token).await {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+
+    match load_group(&token, group_id, tenant, &[MembershipRole::Owner, MembershipRole::Member], &mut tx).await {
+        Ok(Ok(_)) => {},
+        Ok(Err(err)) => return finish_transaction(tx, err).await,
+        Err(err) => return finish_transaction(tx, internal_error(err)).await,
+    }
+
+    let response = match list_members(group_id, query.recursive, page.limit(), page.offset(), &mut *tx).await {
+        Ok(items) => {
+            let total = items.first().map(|member| member.total).unwrap_or(0);
+            (
+                StatusCode::OK,
+                Json(json!(Page {
+                    items,
+                    total,
+                    limit: page.limit(),
+                    offset: page.offset(),
+                })),
+            )
+        },
+        Err(err) => internal_error(err),
+    };
+    finish_transaction(tx, response).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn put_member(
+    Extension(token): Extension<KeycloakToken<String>>,
+    ids_result: Result<Path<(Uuid, Uuid)>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(membership): Json<NewMembership>,
+) -> impl IntoApiResponse {
+    let Ok(Path((group_id, user_id))) = ids_result else {
+        return invalid_uuid();
+    };
+
+    let response = tenant_work(&config, &tenant, |tx| {
+        let token = &token;
+        Box::pin(async move {
+            if let Err(err) = load_group(token, group_id, tenant, &[MembershipRole::Owner], tx).await? {
+                return Ok(err);
+            }
+
+            // Members must belong to the same organization as the group
+            if find_user(user_id, tenant.org_id(), &mut **tx).await?.is_none() {
+                return Ok((
+                    StatusCode::NOT_FOUND,
+                    Json(json!({ "error": "User not found" })),
+                ));
+            }
+
+            let status = match upsert_membership(group_id, tenant.org_id(), user_id, membership.role, &mut **tx).await? {
+                true => StatusCode::CREATED,
+                false => StatusCode::OK,
+            };
+            Ok((status, Json(json!({ "group_id": group_id, "user_id": user_id, "role": membership.role }))))
+        })
+    }).await;
+
+    if response.0 == StatusCode::CREATED {
+        let org_id = tenant.org_id();
+        spawn_group_sync(&config, move |config| async move {
+            push_membership(&config, group_id, org_id, user_id, true).await
+        });
+    }
+    response
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn delete_member(
+    Extension(token): Extension<KeycloakToken<String>>,
+    ids_result: Result<Path<(Uuid, Uuid)>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    let Ok(Path((group_id, user_id))) = ids_result else {
+        return invalid_uuid();
+    };
+
+    // Members may always leave a group, removing others requires ownership
+    let roles: &[MembershipRole] = match token.subject == user_id.to_string() {
+        true => &[MembershipRole::Owner, MembershipRole::Member],
+        false => &[MembershipRole::Owner],
+    };
+    let response = tenant_work(&config, &tenant, |tx| {
+        let token = &token;
+        Box::pin(async move {
+            if let Err(err) = load_group(token, group_id, tenant, roles, tx).await? {
+                return Ok(err);
+            }
+
+            let response = match remove_membership(group_id, user_id, &mut **tx).await? {
+                true => (StatusCode::ACCEPTED, Json(json!({ "message": "Member removed successfully" }))),
+                false => (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({ "error": "Membership not found" })),
+                ),
+            };
+            Ok(response)
+        })
+    }).await;
+
+    if response.0.is_success() {
+        let org_id = tenant.org_id();
+        spawn_group_sync(&config, move |config| async move {
+            push_membership(&config, group_id, org_id, user_id, false).await
+        });
+    }
+    response
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_my_groups(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Subjects that are not UUIDs cannot be local users and belong to no group
+    let Ok(user_id) = Uuid::parse_str(&token.subject) else {
+        return (StatusCode::OK, Json(json!([])));
+    };
+
+    let mut tx = match read_transaction(&config, &tenant, &token).await {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+
+    let response = match list_user_groups(user_id, tenant.org_id(), &mut *tx).await {
+        Ok(groups) => (StatusCode::OK, Json(json!(groups))),
+        Err(err) => internal_error(err),
+    };
+    finish_transaction(tx, response).await
+}
+
+// Load a group of the tenant, administrators may access any group, others need one of the given roles in it.
+// Refusals are returned as the inner error, database errors as the outer one
+async fn load_group(
+    token: &KeycloakToken<String>,
+    group_id: Uuid,
+    tenant: Tenant,
+    roles: &[MembershipRole],
+    conn: &mut PgConnection,
+) -> Result<Result<Group, (StatusCode, Json<Value>)>, Error> {
+    let Some(group) = find_group(group_id, tenant.org_id(), &mut *conn).await? else {
+        return Ok(Err(group_not_found()));
+    };
+
+    if token.expect_roles(&[String::from("administrator")]).is_ok() {
+        return Ok(Ok(group));
+    }
+
+    let role = match Uuid::parse_str(&token.subject) {
+        Ok(user_id) => find_membership_role(group_id, user_id, &mut *conn).await?,
+        Err(_) => None,
+    };
+    match role {
+        Some(role) if roles.contains(&role) => Ok(Ok(group)),
+        _ => Ok(Err(insufficient_privileges())),
+    }
+}
+
+// Parents must exist within the same organization
+async fn expect_parent(parent_id: Uuid, tenant: Tenant, conn: &mut PgConnection) -> Result<Result<(), (StatusCode, Json<Value>)>, Error> {
+    match find_group(parent_id, tenant.org_id(), conn).await? {
+        Some(_) => Ok(Ok(())),
+        None => Ok(Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "Parent group not found" })),
+        ))),
+    }
+}
+
+fn group_not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Group not found" })),
+    )
+}
+
+fn duplicate_group() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::CONFLICT,
+        Json(json!({ "error": "A sibling group with this name already exists" })),
+    )
+}
+
+fn insufficient_privileges() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({ "error": "insufficient privileges" })),
+    )
+}
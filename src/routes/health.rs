@@ -0,0 +1,36 @@
+use std::{sync::Arc, time::Duration};
+
+use aide::axum::IntoApiResponse;
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::json;
+
+use crate::config::ConfigState;
+
+// Readiness fails instead of hanging when the pool cannot hand out a connection quickly
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The process is up and serving requests
+pub(crate) async fn get_live() -> impl IntoApiResponse {
+    (StatusCode::OK, Json(json!({ "status": "live" })))
+}
+
+// The database is reachable, load balancers should only route traffic to ready replicas
+pub(crate) async fn get_ready(State(config): State<Arc<ConfigState>>) -> impl IntoApiResponse {
+    match tokio::time::timeout(READY_TIMEOUT, sqlx::query("SELECT 1").execute(&config.pgpool)).await {
+        Ok(Ok(_)) => (
+            StatusCode::OK,
+            Json(json!({ "status": "ready", "version": config.version })),
+        ),
+        Ok(Err(err)) => {
+            eprintln!("Readiness check failed: {err}");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "error": "Database unavailable" })),
+            )
+        },
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "Database unavailable" })),
+        ),
+    }
+}
@@ -0,0 +1,355 @@
+use std::sync::Arc;
+use axum::{extract::{Path, Query, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::decode::KeycloakToken;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use sqlx::Error;
+use tracing::instrument;
+use uuid::Uuid;
+use aide::axum::IntoApiResponse;
+use crate::{
+    config::ConfigState,
+    custom::{extractors::Tenant, signing, telemetry::record_users_created, validators::{is_valid_email, validate_attributes}},
+    database::{
+        unit_of_work::{unit_of_work, unit_of_work_without_tenant},
+        invitations::{accept_invitation, claim_invitation, create_invitation, list_invitations, lock_invitation, release_invitation, renew_invitation, revoke_invitation},
+    },
+    definitions::{
+        invitation::{AcceptInvitation, Invitation, InvitationClaims, InvitationListQuery, InvitationStatus, NewInvitation, ResendInvitation, DEFAULT_INVITATION_TTL_HOURS, MAX_INVITATION_TTL_HOURS},
+        pagination::{Page, PageParams},
+        user::NewUser,
+    },
+    expect_admin,
+    routes::{shared::{internal_error, invalid_uuid, tenant_work}, users::{insert_user, load_definitions, validate_new_user}},
+};
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn post_invitation(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(new_invitation): Json<NewInvitation>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let email = match new_invitation.email {
+        Some(email) if is_valid_email(&email) => email,
+        _ => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Invalid email format" })),
+            );
+        }
+    };
+    let expires_at = match expiry(new_invitation.expires_in_hours) {
+        Ok(expires_at) => expires_at,
+        Err(err) => return err,
+    };
+
+    // Required attributes may still be supplied when the invitation is accepted
+    let mut attributes = new_invitation.attributes.unwrap_or_default();
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
+    };
+    if let Err(err) = validate_attributes(&attributes, &definitions, true) {
+        eprintln!("Invalid attributes: {err}");
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": err })),
+        );
+    }
+    attributes.retain(|_, value| !value.is_null());
+
+    let username = new_invitation.username.filter(|username| !username.trim().is_empty());
+    let (invitation_id, attributes) = (Uuid::new_v4(), Value::Object(attributes));
+    tenant_work(&config, &tenant, |tx| {
+        let (config, token, email, username, attributes) = (&config, &token, &email, username.clone(), attributes.clone());
+        Box::pin(async move {
+            let response = match create_invitation(invitation_id, tenant.org_id(), email, username, attributes, &token.subject, expires_at, &mut **tx).await {
+                Ok(invitation) => (StatusCode::CREATED, Json(issued(config, &invitation))),
+                Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => (
+                    StatusCode::CONFLICT,
+                    Json(json!({ "error": "A pending invitation for this email already exists" })),
+                ),
+                Err(err) => return Err(err),
+            };
+            Ok(response)
+        })
+    }).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_invitations(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(query): Query<InvitationListQuery>,
+    Query(page): Query<PageParams>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    match list_invitations(tenant.org_id(), query.status, page.limit(), page.offset(), &config.pgpool).await {
+        Ok(rows) => {
+            let total = rows.first().map(|row| row.total).unwrap_or(0);
+            let items: Vec<Invitation> = rows.into_iter().map(|row| row.invitation).collect();
+            (
+                StatusCode::OK,
+                Json(json!(Page {
+                    items,
+                    total,
+                    limit: page.limit(),
+                    offset: page.offset(),
+                })),
+            )
+        },
+        Err(err) => internal_error(err),
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn resend_invitation(
+    Extension(token): Extension<KeycloakToken<String>>,
+    invitation_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    resend: Option<Json<ResendInvitation>>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let Ok(Path(invitation_id)) = invitation_id_result else {
+        return invalid_uuid();
+    };
+    let Json(resend) = resend.unwrap_or_default();
+    let expires_at = match expiry(resend.expires_in_hours) {
+        Ok(expires_at) => expires_at,
+        Err(err) => return err,
+    };
+
+    // Previously issued tokens stop working once the version is bumped
+    tenant_work(&config, &tenant, |tx| {
+        let config = &config;
+        Box::pin(async move {
+            let response = match renew_invitation(invitation_id, tenant.org_id(), expires_at, &mut **tx).await? {
+                Some(invitation) => (StatusCode::OK, Json(issued(config, &invitation))),
+                None => pending_not_found(),
+            };
+            Ok(response)
+        })
+    }).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn delete_invitation(
+    Extension(token): Extension<KeycloakToken<String>>,
+    invitation_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let Ok(Path(invitation_id)) = invitation_id_result else {
+        return invalid_uuid();
+    };
+
+    tenant_work(&config, &tenant, |tx| {
+        Box::pin(async move {
+            let response = match revoke_invitation(invitation_id, tenant.org_id(), &mut **tx).await? {
+                Some(_) => (StatusCode::ACCEPTED, Json(json!({ "message": "Invitation revoked successfully" }))),
+                None => pending_not_found(),
+            };
+            Ok(response)
+        })
+    }).await
+}
+
+#[instrument(skip(config, accept))]
+#[axum::debug_handler]
+pub async fn post_accept_invitation(
+    State(config): State<Arc<ConfigState>>,
+    Json(accept): Json<AcceptInvitation>,
+) -> impl IntoApiResponse {
+    let claims = match signing::verify(&signing::derive_key(&config.env.secret, signing::INVITATION_TOKENS), &accept.token)
+        .and_then(|payload| serde_json::from_slice::<InvitationClaims>(&payload).ok())
+    {
+        Some(claims) => claims,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Invalid invitation token" })),
+            );
+        }
+    };
+    if claims.exp <= Utc::now().timestamp() {
+        return invitation_gone("Invitation has expired");
+    }
+
+    if accept.password.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "Password must not be empty" })),
+        );
+    }
+
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
+    };
+
+    // The tenant of the invitation is only known once its row is read
+    let claimed = unit_of_work_without_tenant(&config.pgpool, |tx| {
+        let (claims, definitions) = (&claims, &definitions);
+        let (username, accepted_attributes) = (accept.username.clone(), accept.attributes.clone());
+        Box::pin(async move {
+            let Some(invitation) = lock_invitation(claims.invitation_id, &mut **tx).await? else {
+                return Ok(Err(invitation_gone("Invitation is no longer valid")));
+            };
+            if invitation.status != InvitationStatus::Pending || invitation.token_version != claims.version {
+                return Ok(Err(invitation_gone("Invitation is no longer valid")));
+            }
+            if invitation.expires_at <= Utc::now() {
+                return Ok(Err(invitation_gone("Invitation has expired")));
+            }
+
+            // Invitees may only fill in required attributes the administrator left unset
+            let mut attributes = match invitation.attributes.clone() {
+                Value::Object(attributes) => attributes,
+                _ => Default::default(),
+            };
+            for (name, value) in accepted_attributes.unwrap_or_default() {
+                let fillable = !attributes.contains_key(&name)
+                    && definitions.iter().any(|definition| definition.name == name && definition.required);
+                if !fillable {
+                    return Ok(Err((
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        Json(json!({ "error": format!("Attribute {name} cannot be set when accepting an invitation") })),
+                    )));
+                }
+                attributes.insert(name, value);
+            }
+
+            // Validate with the same rules as post_user before anything is created in the identity provider
+            let new_user = NewUser {
+                user_id: Uuid::nil().to_string(),
+                username: username.or(invitation.username.clone()),
+                email: Some(invitation.email.clone()),
+                attributes: Some(attributes),
+            };
+            let user = match validate_new_user(&new_user, definitions) {
+                Ok(user) => user,
+                Err(err) => return Ok(Err(err)),
+            };
+
+            // Consume the token and release the row lock before calling the identity provider, a concurrent
+            // redemption of the same token now fails instead of waiting on the lock
+            match claim_invitation(invitation.invitation_id, claims.version, &mut **tx).await? {
+                true => Ok(Ok((invitation, user))),
+                false => Ok(Err(invitation_gone("Invitation is no longer valid"))),
+            }
+        })
+    }).await;
+    let (invitation, mut user) = match claimed {
+        Ok(Ok(claimed)) => claimed,
+        Ok(Err(err)) => return err,
+        Err(err) => return internal_error(err),
+    };
+
+    let user_id = match config.auth.create_account(&user.username, user.email.as_deref(), &accept.password).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            release(&config, invitation.invitation_id, claims.version).await;
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({ "error": "User already exists" })),
+            );
+        },
+        Err(err) => {
+            eprintln!("Failed to create account: {err:#}");
+            release(&config, invitation.invitation_id, claims.version).await;
+            return (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({ "error": "Identity provider unavailable" })),
+            );
+        },
+    };
+    user.user_id = user_id;
+
+    let response = unit_of_work(&config.pgpool, invitation.org_id, |tx| {
+        let (user, invitation) = (user.clone(), &invitation);
+        Box::pin(async move {
+            let response = insert_user(user, invitation.org_id, &mut **tx).await?;
+            if !response.0.is_success() {
+                return Ok(response);
+            }
+            match accept_invitation(invitation.invitation_id, claims.version, user_id, &mut **tx).await? {
+                true => Ok(response),
+                false => Ok(invitation_gone("Invitation is no longer valid")),
+            }
+        })
+    }).await.unwrap_or_else(internal_error);
+    if response.0.is_success() {
+        record_users_created("invitation", 1);
+        return response;
+    }
+
+    // Keep the identity provider consistent with the local store when the local half failed
+    release(&config, invitation.invitation_id, claims.version).await;
+    if let Err(err) = config.auth.delete_account(user_id).await {
+        eprintln!("Failed to remove account {user_id} after a failed invitation: {err:#}");
+    }
+    response
+}
+
+// Make a claimed token redeemable again after the account could not be created
+async fn release(config: &ConfigState, invitation_id: Uuid, token_version: i32) {
+    if let Err(err) = release_invitation(invitation_id, token_version, &config.pgpool).await {
+        eprintln!("Failed to release invitation {invitation_id}: {err}");
+    }
+}
+
+// Expiry of a new or resent invitation, bounded by MAX_INVITATION_TTL_HOURS
+fn expiry(expires_in_hours: Option<i64>) -> Result<chrono::DateTime<Utc>, (StatusCode, Json<Value>)> {
+    match expires_in_hours.unwrap_or(DEFAULT_INVITATION_TTL_HOURS) {
+        hours @ 1..=MAX_INVITATION_TTL_HOURS => Ok(Utc::now() + Duration::hours(hours)),
+        _ => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": format!("Invitations must expire within 1 to {MAX_INVITATION_TTL_HOURS} hours") })),
+        )),
+    }
+}
+
+// Invitation together with a freshly signed token for its current version
+fn issued(config: &ConfigState, invitation: &Invitation) -> Value {
+    let claims = InvitationClaims {
+        invitation_id: invitation.invitation_id,
+        version: invitation.token_version,
+        exp: invitation.expires_at.timestamp(),
+    };
+    let payload = serde_json::to_vec(&claims).unwrap_or_default();
+    json!({
+        "invitation": invitation,
+        "token": signing::sign(&signing::derive_key(&config.env.secret, signing::INVITATION_TOKENS), &payload),
+    })
+}
+
+fn pending_not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Pending invitation not found" })),
+    )
+}
+
+fn invitation_gone(error: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::GONE,
+        Json(json!({ "error": error })),
+    )
+}
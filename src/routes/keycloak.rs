@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use axum::{extract::{Query, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::decode::KeycloakToken;
+use serde_json::json;
+use tracing::instrument;
+use aide::axum::IntoApiResponse;
+use crate::{
+    config::ConfigState,
+    custom::extractors::Tenant,
+    definitions::keycloak::ReconcileQuery,
+    expect_admin, expect_default_tenant,
+    keycloak::reconcile::reconcile,
+};
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn post_reconcile(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(query): Query<ReconcileQuery>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    // The realm is shared by every organization, so only administrators of the default one may compare it
+    expect_default_tenant!(tenant);
+
+    match reconcile(&config, query.fix).await {
+        Ok(report) => (StatusCode::OK, Json(json!(report))),
+        Err(err) => {
+            eprintln!("Keycloak reconciliation failed: {err:#}");
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({ "error": "Identity provider unavailable" })),
+            )
+        }
+    }
+}
@@ -1,4 +1,14 @@
 pub mod root;
+pub mod health;
 pub mod users;
 pub mod auth;
-pub mod public;
\ No newline at end of file
+pub mod public;
+pub mod attributes;
+pub mod groups;
+pub mod invitations;
+pub mod transfer;
+pub mod organizations;
+pub mod keycloak;
+pub mod api_keys;
+pub mod outbox;
+pub mod shared;
@@ -0,0 +1,224 @@
+use std::sync::Arc;
+use axum::{extract::{Path, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::decode::KeycloakToken;
+use serde_json::{json, Value};
+use sqlx::Error;
+use tracing::instrument;
+use uuid::Uuid;
+use aide::axum::IntoApiResponse;
+use crate::{
+    config::ConfigState,
+    custom::{extractors::Tenant, validators::is_valid_slug},
+    database::{unit_of_work::unit_of_work, organizations::{create_organization, find_organization, find_organization_keycloak_id, list_organizations, remove_organization, update_organization}},
+    definitions::organization::{NewOrganization, Organization, DEFAULT_ORGANIZATION_ID},
+    expect_admin, expect_default_tenant,
+    keycloak::sync::spawn_group_sync,
+    routes::shared::{internal_error, tenant_work},
+};
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_organizations(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+    expect_default_tenant!(tenant);
+
+    match list_organizations(&config.replicas.read_pool(&token.subject)).await {
+        Ok(organizations) => (StatusCode::OK, Json(json!(organizations))),
+        Err(err) => internal_error(err),
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_organization(
+    Extension(token): Extension<KeycloakToken<String>>,
+    Path(key): Path<String>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+    expect_default_tenant!(tenant);
+
+    match find_organization(&key, &config.replicas.read_pool(&token.subject)).await {
+        Ok(Some(organization)) => (StatusCode::OK, Json(json!(organization))),
+        Ok(None) => not_found(),
+        Err(err) => internal_error(err),
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn post_organization(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(new_organization): Json<NewOrganization>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+    expect_default_tenant!(tenant);
+
+    let slug = match new_organization.slug {
+        Some(slug) if is_valid_slug(&slug) => slug,
+        _ => return invalid_slug(),
+    };
+    let name = match new_organization.name {
+        Some(name) if !name.trim().is_empty() => name,
+        _ => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Organization name must not be empty" })),
+            );
+        }
+    };
+
+    let organization = Organization {
+        org_id: Uuid::new_v4(),
+        slug,
+        name,
+    };
+    tenant_work(&config, &tenant, |tx| {
+        let organization = organization.clone();
+        Box::pin(async move {
+            let response = match create_organization(organization, &mut **tx).await {
+                Ok(organization) => (StatusCode::CREATED, Json(json!(organization))),
+                Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => duplicate_organization(),
+                Err(err) => return Err(err),
+            };
+            Ok(response)
+        })
+    }).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn put_organization(
+    Extension(token): Extension<KeycloakToken<String>>,
+    Path(key): Path<String>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(new_organization): Json<NewOrganization>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+    expect_default_tenant!(tenant);
+
+    if new_organization.slug.as_deref().is_some_and(|slug| !is_valid_slug(slug)) {
+        return invalid_slug();
+    }
+    let name = new_organization.name.filter(|name| !name.trim().is_empty());
+
+    let updated = unit_of_work(&config.pgpool, tenant.org_id(), |tx| {
+        let (key, slug, name) = (&key, new_organization.slug.clone(), name.clone());
+        Box::pin(async move {
+            let Some(organization) = find_organization(key, &mut **tx).await? else {
+                return Ok(Err(not_found()));
+            };
+            match update_organization(organization.org_id, slug, name, &mut **tx).await {
+                Ok(Some(updated)) => Ok(Ok((organization, updated))),
+                Ok(None) => Ok(Err(not_found())),
+                Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => Ok(Err(duplicate_organization())),
+                Err(err) => Err(err),
+            }
+        })
+    }).await;
+    let (organization, updated) = match updated {
+        Ok(Ok(updated)) => updated,
+        Ok(Err(err)) => return err,
+        Err(err) => return internal_error(err),
+    };
+
+    // The Keycloak group of the organization is named after its slug
+    if updated.slug != organization.slug {
+        let (org_id, slug) = (updated.org_id, updated.slug.clone());
+        spawn_group_sync(&config, move |config| async move {
+            match find_organization_keycloak_id(org_id, &config.pgpool).await? {
+                Some(keycloak_id) => config.kc_admin.rename_group(&keycloak_id, &slug).await,
+                None => Ok(()),
+            }
+        });
+    }
+    (StatusCode::OK, Json(json!(updated)))
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn delete_organization(
+    Extension(token): Extension<KeycloakToken<String>>,
+    Path(key): Path<String>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+    expect_default_tenant!(tenant);
+
+    let removed = unit_of_work(&config.pgpool, tenant.org_id(), |tx| {
+        let key = &key;
+        Box::pin(async move {
+            let Some(organization) = find_organization(key, &mut **tx).await? else {
+                return Ok(Err(not_found()));
+            };
+
+            // Requests without a tenant fall back to the default organization
+            if organization.org_id == DEFAULT_ORGANIZATION_ID {
+                return Ok(Err((
+                    StatusCode::CONFLICT,
+                    Json(json!({ "error": "The default organization cannot be deleted" })),
+                )));
+            }
+
+            let keycloak_id = find_organization_keycloak_id(organization.org_id, &mut **tx).await?;
+            match remove_organization(organization.org_id, &mut **tx).await {
+                Ok(Some(_)) => Ok(Ok(keycloak_id)),
+                Ok(None) => Ok(Err(not_found())),
+                // 23503 is the SQL state for foreign key violations, users or groups still reference the organization
+                Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23503") => Ok(Err((
+                    StatusCode::CONFLICT,
+                    Json(json!({ "error": "Organization still has users or groups" })),
+                ))),
+                Err(err) => Err(err),
+            }
+        })
+    }).await;
+    let keycloak_id = match removed {
+        Ok(Ok(keycloak_id)) => keycloak_id,
+        Ok(Err(err)) => return err,
+        Err(err) => return internal_error(err),
+    };
+
+    // Groups block the deletion, only the empty organization group is left in Keycloak
+    if let Some(keycloak_id) = keycloak_id {
+        spawn_group_sync(&config, move |config| async move {
+            config.kc_admin.delete_group(&keycloak_id).await
+        });
+    }
+    (StatusCode::ACCEPTED, Json(json!({ "message": "Organization deleted successfully" })))
+}
+
+fn invalid_slug() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(json!({ "error": "Slugs must be lowercase alphanumeric with dashes and at most 63 characters" })),
+    )
+}
+
+fn not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Organization not found" })),
+    )
+}
+
+fn duplicate_organization() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::CONFLICT,
+        Json(json!({ "error": "Organization already exists" })),
+    )
+}
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+use axum::{extract::{Path, Query, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::decode::KeycloakToken;
+use serde_json::{json, Value};
+use tracing::instrument;
+use uuid::Uuid;
+use aide::axum::IntoApiResponse;
+use crate::{
+    config::ConfigState,
+    custom::extractors::Tenant,
+    database::outbox::{count_dead_letters, discard_dead_letter, list_dead_letters, requeue_dead_letter},
+    definitions::{outbox::DeadLetter, pagination::{Page, PageParams}},
+    expect_admin,
+    routes::shared::{internal_error, invalid_uuid},
+};
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn get_dead_letters(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(page): Query<PageParams>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    match list_dead_letters(tenant.org_id(), page.limit(), page.offset(), &config.pgpool).await {
+        Ok(rows) => {
+            let total = match rows.first() {
+                Some(row) => row.total,
+                None if page.offset() == 0 => 0,
+                None => match count_dead_letters(tenant.org_id(), &config.pgpool).await {
+                    Ok(total) => total,
+                    Err(err) => return internal_error(err),
+                },
+            };
+            let items: Vec<DeadLetter> = rows.into_iter().map(|row| row.dead_letter).collect();
+            (
+                StatusCode::OK,
+                Json(json!(Page {
+                    items,
+                    total,
+                    limit: page.limit(),
+                    offset: page.offset(),
+                })),
+            )
+        },
+        Err(err) => internal_error(err),
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn retry_dead_letter(
+    Extension(token): Extension<KeycloakToken<String>>,
+    event_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let Ok(Path(event_id)) = event_id_result else {
+        return invalid_uuid();
+    };
+
+    match requeue_dead_letter(event_id, tenant.org_id(), &config.pgpool).await {
+        Ok(true) => (StatusCode::ACCEPTED, Json(json!({ "message": "Event queued for delivery" }))),
+        Ok(false) => dead_letter_not_found(),
+        Err(err) => internal_error(err),
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn delete_dead_letter(
+    Extension(token): Extension<KeycloakToken<String>>,
+    event_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let Ok(Path(event_id)) = event_id_result else {
+        return invalid_uuid();
+    };
+
+    // Later events of the user are no longer held back, the discarded change never reaches the sink
+    match discard_dead_letter(event_id, tenant.org_id(), &config.pgpool).await {
+        Ok(true) => (StatusCode::ACCEPTED, Json(json!({ "message": "Event discarded" }))),
+        Ok(false) => dead_letter_not_found(),
+        Err(err) => internal_error(err),
+    }
+}
+
+fn dead_letter_not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Dead letter not found" })),
+    )
+}
@@ -0,0 +1,68 @@
+use axum::{http::StatusCode, Json};
+use axum_keycloak_auth::decode::KeycloakToken;
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use sqlx::{Error, Postgres, Transaction};
+
+use crate::{
+    config::ConfigState,
+    custom::extractors::Tenant,
+    database::{begin_tenant, unit_of_work::{unit_of_work, WorkOutcome}},
+};
+
+// Responses with a success status commit the unit of work
+impl WorkOutcome for (StatusCode, Json<Value>) {
+    fn commits(&self) -> bool {
+        self.0.is_success()
+    }
+}
+
+// Run a handler's writes as one tenant transaction, retried on serialization failures and deadlocks
+pub(crate) async fn tenant_work<'a, F>(config: &ConfigState, tenant: &Tenant, work: F) -> (StatusCode, Json<Value>)
+where
+    F: for<'c> FnMut(&'c mut Transaction<'a, Postgres>) -> BoxFuture<'c, Result<(StatusCode, Json<Value>), Error>>,
+{
+    unit_of_work(&config.pgpool, tenant.org_id(), work).await.unwrap_or_else(internal_error)
+}
+
+// Open a transaction bound to the request tenant for work that cannot run again, such as an import
+// consuming the request body, mapping failures to a response
+pub(crate) async fn tenant_transaction(config: &ConfigState, tenant: &Tenant) -> Result<Transaction<'static, Postgres>, (StatusCode, Json<Value>)> {
+    begin_tenant(&config.pgpool, tenant.org_id()).await.map_err(internal_error)
+}
+
+// Tenant transaction for reads, served by a healthy replica unless the caller wrote recently
+pub(crate) async fn read_transaction(config: &ConfigState, tenant: &Tenant, token: &KeycloakToken<String>) -> Result<Transaction<'static, Postgres>, (StatusCode, Json<Value>)> {
+    config.replicas.begin_read(&token.subject, tenant.org_id()).await.map_err(internal_error)
+}
+
+// Commit when the response signals success, otherwise roll back
+pub(crate) async fn finish_transaction(tx: Transaction<'static, Postgres>, response: (StatusCode, Json<Value>)) -> (StatusCode, Json<Value>) {
+    if !response.0.is_success() {
+        if let Err(err) = tx.rollback().await {
+            eprintln!("Failed to roll back transaction: {err}");
+        }
+        return response;
+    }
+
+    match tx.commit().await {
+        Ok(()) => response,
+        Err(err) => internal_error(err),
+    }
+}
+
+// Log an unexpected database error and hide it from the client
+pub(crate) fn internal_error(err: Error) -> (StatusCode, Json<Value>) {
+    eprintln!("Internal Server Error: {err}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": "Internal server error" })),
+    )
+}
+
+pub(crate) fn invalid_uuid() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": "Invalid UUID format" })),
+    )
+}
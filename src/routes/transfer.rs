@@ -0,0 +1,406 @@
+use std::{
+    error::Error as StdError,
+    io,
+    pin::Pin,
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use aide::axum::IntoApiResponse;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_keycloak_auth::decode::KeycloakToken;
+use async_stream::try_stream;
+use csv_async::{AsyncDeserializer, AsyncWriterBuilder};
+use futures::{Stream, StreamExt, TryStreamExt};
+use http_body_util::LengthLimitError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::Error;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite};
+use tokio_util::io::StreamReader;
+use tracing::instrument;
+
+use crate::{
+    config::ConfigState,
+    custom::{extractors::Tenant, telemetry::record_users_created},
+    database::users::{find_user, stream_users, upsert_user},
+    definitions::{
+        transfer::{ImportErrorMode, ImportOptions, ImportRowError, ImportSummary, TransferFormat, MAX_REPORTED_IMPORT_ERRORS},
+        user::{NewUser, User, UserFilter},
+    },
+    expect_admin,
+    keycloak::sync::{create_account, push_user, user_sync},
+    routes::{shared::tenant_transaction, users::{insert_user, load_definitions, unique_violation, validate_new_user}},
+};
+use uuid::Uuid;
+
+// Columns of the CSV export
+const CSV_HEADER: [&str; 5] = ["user_id", "username", "email", "attributes", "enabled"];
+
+// Parsed import row together with its line number in the uploaded file
+type ImportRow = (u64, Result<ImportedUser, String>);
+
+// User of an import, rows without the enabled flag import enabled users
+#[derive(Debug, Deserialize)]
+struct ImportedUser {
+    #[serde(flatten)]
+    user: NewUser,
+    enabled: Option<bool>,
+}
+
+// CSV representation of a user, attributes are embedded as a JSON object
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvUserRow {
+    user_id: String,
+    username: Option<String>,
+    email: Option<String>,
+    attributes: Option<String>,
+    enabled: Option<bool>,
+}
+
+impl TryFrom<CsvUserRow> for ImportedUser {
+    type Error = String;
+
+    fn try_from(row: CsvUserRow) -> Result<Self, Self::Error> {
+        let attributes = match row.attributes.filter(|attributes| !attributes.trim().is_empty()) {
+            Some(attributes) => Some(serde_json::from_str(&attributes).map_err(|err| format!("Invalid attributes column: {err}"))?),
+            None => None,
+        };
+
+        Ok(ImportedUser {
+            user: NewUser {
+                user_id: row.user_id,
+                username: row.username,
+                email: row.email,
+                attributes,
+            },
+            enabled: row.enabled,
+        })
+    }
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn export_users(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(filter): Query<UserFilter>,
+    headers: HeaderMap,
+) -> Response {
+    // Ensure user is admin
+    expect_admin!(&token, into_response);
+
+    let format = match TransferFormat::from_accept(&headers) {
+        Some(format) => format,
+        None => {
+            return (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(json!({ "error": "Supported formats are text/csv and application/x-ndjson" })),
+            ).into_response();
+        }
+    };
+
+    // Rows are encoded one at a time as they arrive from the database
+    let users = stream_users(tenant.org_id(), filter, config.replicas.read_pool(&token.subject));
+    let body = match format {
+        TransferFormat::Csv => Body::from_stream(encode_csv(users)),
+        TransferFormat::Ndjson => Body::from_stream(users.map_err(io::Error::other).and_then(|user| async move {
+            let mut line = serde_json::to_vec(&user)?;
+            line.push(b'\n');
+            Ok(Bytes::from(line))
+        })),
+    };
+
+    ([(header::CONTENT_TYPE, format.content_type())], body).into_response()
+}
+
+#[instrument(skip(config, body))]
+#[axum::debug_handler]
+pub async fn import_users(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Query(options): Query<ImportOptions>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let format = match TransferFormat::from_content_type(&headers) {
+        Some(format) => format,
+        None => {
+            return (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(json!({ "error": "Supported formats are text/csv and application/x-ndjson" })),
+            );
+        }
+    };
+
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
+    };
+
+    // The upload is parsed incrementally, only the current row is held in memory
+    let too_large = Arc::new(AtomicBool::new(false));
+    let reader = StreamReader::new(body.into_data_stream().map_err({
+        let too_large = too_large.clone();
+        move |err| {
+            if exceeds_limit(&err) {
+                too_large.store(true, Ordering::Relaxed);
+            }
+            io::Error::other(err)
+        }
+    }));
+    let mut rows = read_rows(format, reader);
+
+    let mut tx = match tenant_transaction(&config, &tenant).await {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+
+    let mut summary = ImportSummary {
+        dry_run: options.dry_run,
+        upsert: options.upsert,
+        ..ImportSummary::default()
+    };
+    let mut aborted = false;
+    // Keycloak cannot be rolled back with the import, written users are pushed once the import committed
+    let sync = user_sync(&config).is_some();
+    let mut written = Vec::new();
+
+    while let Some((line, row)) = rows.next().await {
+        if too_large.load(Ordering::Relaxed) {
+            break;
+        }
+        summary.processed += 1;
+
+        let user = match row {
+            Ok(ImportedUser { user: new_user, enabled }) => validate_new_user(&new_user, &definitions)
+                .map(|user| User { enabled: enabled.unwrap_or(true), ..user })
+                .map_err(|(status, Json(body))| (status, body["error"].as_str().unwrap_or_default().to_string())),
+            Err(err) => Err((StatusCode::UNPROCESSABLE_ENTITY, err)),
+        };
+
+        // Each row gets a savepoint so a failed insert does not poison the transaction
+        let result = match user {
+            Ok(user) => write_row(user, tenant.org_id(), options.upsert, sync, &mut tx).await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(saved) => {
+                summary.imported += 1;
+                if sync {
+                    written.push(saved);
+                }
+            },
+            Err((status, error)) => {
+                summary.failed += 1;
+                if summary.errors.len() < MAX_REPORTED_IMPORT_ERRORS {
+                    summary.errors.push(ImportRowError {
+                        line,
+                        status: status.as_u16(),
+                        error,
+                    });
+                }
+                if options.on_error == ImportErrorMode::Abort {
+                    aborted = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    // Nothing is written once the upload went over HTTP_IMPORT_BODY_LIMIT_BYTES
+    if too_large.load(Ordering::Relaxed) {
+        if let Err(err) = tx.rollback().await {
+            eprintln!("Failed to roll back import: {err}");
+        }
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({ "error": "Request body too large" })),
+        );
+    }
+
+    if options.dry_run || aborted {
+        if let Err(err) = tx.rollback().await {
+            eprintln!("Failed to roll back import: {err}");
+        }
+    } else if let Err(err) = tx.commit().await {
+        eprintln!("Internal Server Error: {err}");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": "Internal server error"})),
+        );
+    } else {
+        summary.committed = true;
+        // Upserted rows may have replaced existing users, only plain inserts count as creations
+        if !options.upsert {
+            record_users_created("import", summary.imported);
+        }
+        push_imported(&config, tenant.org_id(), options.upsert, &written).await;
+    }
+
+    let status = match aborted {
+        true => StatusCode::UNPROCESSABLE_ENTITY,
+        false => StatusCode::OK,
+    };
+
+    (status, Json(json!(summary)))
+}
+
+// Whether reading the upload failed because of the body limit
+fn exceeds_limit(err: &axum::Error) -> bool {
+    let mut source: Option<&(dyn StdError + 'static)> = Some(err);
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+// Encode users as CSV with a single writer, the header is written once even when there are no rows
+fn encode_csv(users: impl Stream<Item = Result<User, Error>> + Send + 'static) -> impl Stream<Item = io::Result<Bytes>> + Send {
+    try_stream! {
+        let buffer = CsvBuffer::default();
+        let mut writer = AsyncWriterBuilder::new().has_headers(false).create_writer(buffer.clone());
+        writer.write_record(CSV_HEADER).await.map_err(io::Error::other)?;
+        writer.flush().await?;
+        yield buffer.take();
+
+        let mut users = std::pin::pin!(users);
+        while let Some(user) = users.try_next().await.map_err(io::Error::other)? {
+            let row = [user.user_id.to_string(), user.username, user.email.unwrap_or_default(), user.attributes.to_string(), user.enabled.to_string()];
+            writer.write_record(row).await.map_err(io::Error::other)?;
+            writer.flush().await?;
+            yield buffer.take();
+        }
+    }
+}
+
+// Output of the CSV writer, drained into the response after every record
+#[derive(Clone, Default)]
+struct CsvBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl CsvBuffer {
+    fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.0.lock().unwrap()))
+    }
+}
+
+impl AsyncWrite for CsvBuffer {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Turn the request body into a stream of rows for the given format
+fn read_rows<R>(format: TransferFormat, reader: R) -> Pin<Box<dyn Stream<Item = ImportRow> + Send>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    match format {
+        TransferFormat::Csv => AsyncDeserializer::from_reader(reader)
+            .into_deserialize_with_pos::<CsvUserRow>()
+            .map(|(row, position)| {
+                let line = position.line();
+                let row = row
+                    .map_err(|err| format!("Invalid CSV row: {err}"))
+                    .and_then(ImportedUser::try_from);
+                (line, row)
+            })
+            .boxed(),
+        TransferFormat::Ndjson => {
+            futures::stream::unfold((tokio::io::BufReader::new(reader).lines(), 0u64), |(mut lines, mut number)| async move {
+                loop {
+                    number += 1;
+                    match lines.next_line().await {
+                        Ok(Some(line)) if line.trim().is_empty() => continue,
+                        Ok(Some(line)) => {
+                            let row = serde_json::from_str::<ImportedUser>(&line).map_err(|err| format!("Invalid JSON row: {err}"));
+                            return Some(((number, row), (lines, number)));
+                        },
+                        Ok(None) => return None,
+                        Err(err) => return Some(((number, Err(format!("Failed to read upload: {err}"))), (lines, number))),
+                    }
+                }
+            })
+            .boxed()
+        },
+    }
+}
+
+// Write a validated row inside its own savepoint. Returns the saved user and, when `sync` is set, the username
+// its Keycloak account is found under, an upsert may rename a user whose account was never linked
+async fn write_row(
+    user: User,
+    org_id: Uuid,
+    upsert: bool,
+    sync: bool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<(User, String), (StatusCode, String)> {
+    let internal_error = |err: Error| {
+        eprintln!("Internal Server Error: {err}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
+    };
+
+    let mut savepoint = sqlx::Acquire::begin(&mut **tx).await.map_err(internal_error)?;
+    let previous = match sync && upsert {
+        true => find_user(user.user_id, org_id, &mut *savepoint).await.map_err(internal_error)?,
+        false => None,
+    };
+    let (status, Json(body)) = match upsert {
+        true => match upsert_user(user, org_id, &mut *savepoint).await {
+            Ok(user) => (StatusCode::OK, Json(json!(user))),
+            // The username, email or a unique attribute belongs to another user
+            Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => unique_violation(db_err.as_ref()),
+            Err(err) => return Err(internal_error(err)),
+        },
+        false => insert_user(user, org_id, &mut *savepoint).await.map_err(internal_error)?,
+    };
+
+    if status.is_success() {
+        savepoint.commit().await.map_err(internal_error)?;
+        let user: User = serde_json::from_value(body).map_err(|err| internal_error(Error::Decode(err.into())))?;
+        let username = previous.map_or_else(|| user.username.clone(), |previous| previous.username);
+        Ok((user, username))
+    } else {
+        savepoint.rollback().await.map_err(internal_error)?;
+        Err((status, body["error"].as_str().unwrap_or_default().to_string()))
+    }
+}
+
+// Mirror the users of a committed import into Keycloak, failures are left to reconciliation
+async fn push_imported(config: &ConfigState, org_id: Uuid, upsert: bool, written: &[(User, String)]) {
+    for (user, username) in written {
+        let pushed = match upsert {
+            true => push_user(config, org_id, user, username).await,
+            false => create_account(config, org_id, user).await,
+        };
+        match pushed {
+            Ok(true) => {},
+            Ok(false) => eprintln!("Keycloak user sync skipped imported user {}, its username or email is taken", user.user_id),
+            Err(err) => eprintln!("Keycloak user sync failed for imported user {}: {err:#}", user.user_id),
+        }
+    }
+}
@@ -1,10 +1,10 @@
 use std::sync::Arc;
-use axum::{extract::{Path, State}, http::StatusCode, Extension, Json};
-use axum_keycloak_auth::decode::KeycloakToken;
-use serde_json::json;
-use sqlx::Error;
+use axum::{extract::{Path, Query, State}, http::StatusCode, Extension, Json};
+use axum_keycloak_auth::{decode::KeycloakToken, role::ExpectRoles};
+use serde_json::{json, Value};
+use sqlx::{error::DatabaseError, Error, PgExecutor};
 use tracing::instrument;
-use crate::{config::ConfigState, custom::validators::is_valid_email, database::{self, users::{remove_user, update_user}}, definitions::user::{NewUser, User}, expect_admin};
+use crate::{config::ConfigState, keycloak::sync::{create_account, find_account, push_user, user_sync}, custom::{extractors::Tenant, telemetry::{record_users_created, record_users_deleted}, validators::{is_valid_email, validate_attributes}}, database::{self, attributes::list_attribute_definitions, begin_tenant, unit_of_work::{unit_of_work, WorkOutcome}, users::{remove_user, restore_user, set_user_enabled, update_user}}, routes::shared::{finish_transaction, internal_error, read_transaction, tenant_work}, definitions::{attribute::{AttributeDefinition, AttributeVisibility, UNIQUE_ATTRIBUTE_INDEX_PREFIX}, pagination::{Page, PageParams}, user::{BatchItemResult, BatchMode, BatchOperation, BatchRequest, NewUser, User, UserSearchHit, UserSearchQuery, MAX_BATCH_OPERATIONS}}, expect_admin};
 use uuid::Uuid;
 use aide::axum::IntoApiResponse;
 use database::users::{find_user, create_user};
@@ -15,6 +15,7 @@ pub async fn get_user(
     Extension(token): Extension<KeycloakToken<String>>,
     user_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
     State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
 ) -> impl IntoApiResponse {
     // Check if UUID is valid
     let user_id = match user_id_result {
@@ -31,9 +32,22 @@ pub async fn get_user(
         }
     };
 
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
+    };
+
+    let mut tx = match read_transaction(&config, &tenant, &token).await {
+        Ok(tx) => tx,
+        Err(err) => return err,
+    };
+
     // Proceed with finding the user if the UUID was valid
-    match find_user(user_id, &config.pgpool).await {
-        Ok(Some(user)) => (StatusCode::OK, Json(json!(user))),
+    let response = match find_user(user_id, tenant.org_id(), &mut *tx).await {
+        Ok(Some(mut user)) => {
+            redact_attributes(&mut user, &definitions, &token);
+            (StatusCode::OK, Json(json!(user)))
+        },
         Ok(None) => (
             StatusCode::NOT_FOUND,
             Json(json!({ "error": "User not found" })),
@@ -45,88 +59,115 @@ pub async fn get_user(
                 Json(json!({ "error": "Internal server error" })),
             )
         }
-    }
+    };
+    finish_transaction(tx, response).await
 }
 
 #[instrument(skip(config))]
 #[axum::debug_handler]
-pub async fn post_user(
+pub async fn search_users(
     Extension(token): Extension<KeycloakToken<String>>,
     State(config): State<Arc<ConfigState>>,
-    Json(new_user): Json<NewUser>
+    tenant: Tenant,
+    Query(search): Query<UserSearchQuery>,
+    Query(page): Query<PageParams>,
 ) -> impl IntoApiResponse {
-    // Ensure user is admin
-    expect_admin!(&token);
-
-    // Check if UUID is valid
-    let user_id = match Uuid::parse_str(&new_user.user_id) {
-        Ok(uuid) => uuid,
-        Err(err) => {
-            eprintln!("Invalid UUID: {err}");
-            return (
-                StatusCode::UNPROCESSABLE_ENTITY,
-                Json(json!({ "error": "Invalid UUID format" })),
-            );
-        }
-    };
-    
-    // Unwrap `username` and validate it
-    let username = match &new_user.username {
-        Some(name) if !name.trim().is_empty() => name.clone(),
-        _ => {
-            eprintln!("Invalid username: must not be empty");
-            return (
-                StatusCode::UNPROCESSABLE_ENTITY,
-                Json(json!({ "error": "Username must not be empty" })),
-            );
-        }
-    };
+    let term = search.q.trim();
+    if term.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "Search query must not be empty" })),
+        );
+    }
+    // Only letters and digits are searched, anything else would match every user
+    if !term.chars().any(char::is_alphanumeric) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Search query must contain a letter or digit" })),
+        );
+    }
 
-    // Validate the email field if provided
-    let email = match &new_user.email {
-        Some(email) if is_valid_email(email) => Some(email.clone()),
-        Some(_) => {
-            eprintln!("Invalid email: does not match valid email format");
-            return (
-                StatusCode::UNPROCESSABLE_ENTITY,
-                Json(json!({ "error": "Invalid email format" })),
-            );
-        }
-        None => None, // No email provided, set to None
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
     };
-    
 
-    // Proceed to create the user
-    let user = User {
-        user_id,
-        username,
-        email,
+    let mut tx = match read_transaction(&config, &tenant, &token).await {
+        Ok(tx) => tx,
+        Err(err) => return err,
     };
 
-    let res = match create_user(user, &config.pgpool).await {
-        Ok(Some(user)) => (StatusCode::CREATED, Json(json!(user))),
-        Ok(None) => (
-            StatusCode::BAD_REQUEST,
-            Json(json!({ "error": "User creation failed" })),
-        ),
-        Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => {
-            // Compare using `as_deref` to convert `Cow<'_, str>` to `Option<&str>`
-            // 23505 is the SQL state for unique violation
+    let response = match database::users::search_users(term, tenant.org_id(), page.limit(), page.offset(), &mut *tx).await {
+        Ok(rows) => {
+            let total = match rows.first() {
+                Some(row) => row.total,
+                None if page.offset() == 0 => 0,
+                None => match database::users::count_search_matches(term, tenant.org_id(), &mut *tx).await {
+                    Ok(total) => total,
+                    Err(err) => return finish_transaction(tx, internal_error(err)).await,
+                },
+            };
+            let items: Vec<UserSearchHit> = rows
+                .into_iter()
+                .map(UserSearchHit::from)
+                .map(|mut hit| {
+                    redact_attributes(&mut hit.user, &definitions, &token);
+                    hit
+                })
+                .collect();
             (
-                StatusCode::CONFLICT,
-                Json(json!({ "error": "User already exists" })),
+                StatusCode::OK,
+                Json(json!(Page {
+                    items,
+                    total,
+                    limit: page.limit(),
+                    offset: page.offset(),
+                })),
             )
         },
         Err(err) => {
             eprintln!("Internal Server Error: {err}");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({ "error": "Internal server error"})),
+                Json(json!({ "error": "Internal server error" })),
             )
-        },
+        }
+    };
+    finish_transaction(tx, response).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn post_user(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(new_user): Json<NewUser>
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
+    };
+
+    // Validate the payload and create the user
+    let user = match validate_new_user(&new_user, &definitions) {
+        Ok(user) => user,
+        Err(err) => return err,
     };
 
-    res
+    let org_id = tenant.org_id();
+    let response = tenant_work(&config, &tenant, |tx| {
+        let user = user.clone();
+        Box::pin(async move { insert_user(user, org_id, &mut **tx).await })
+    }).await;
+    let response = sync_created(&config, &tenant, response).await;
+    if response.0.is_success() {
+        record_users_created("api", 1);
+    }
+    response
 }
 
 #[instrument(skip(config))]
@@ -135,6 +176,7 @@ pub async fn put_user(
     Extension(token): Extension<KeycloakToken<String>>,
     user_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
     State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
     Json(new_user): Json<NewUser>,
 ) -> impl IntoApiResponse {
     // Ensure user is admin
@@ -155,44 +197,27 @@ pub async fn put_user(
         }
     };
 
-    // Validate the email field if provided
-    let _email = match &new_user.email {
-        Some(email) if is_valid_email(email) => Some(email.clone()),
-        Some(_) => {
-            eprintln!("Invalid email: does not match valid email format");
-            return (
-                StatusCode::UNPROCESSABLE_ENTITY,
-                Json(json!({ "error": "Invalid email format" })),
-            );
-        }
-        None => None, // No email provided, set to None
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
     };
 
+    // Validate the email and attribute fields if provided
+    if let Err(err) = validate_update(&new_user, &definitions) {
+        return err;
+    }
+
     // Perform partial update
-    let res = match update_user(
-        user_id,
-        new_user,
-        &config.pgpool,
-    )
-    .await
-    {
-        Ok(Some(user)) => {
-            (StatusCode::OK, Json(json!(user)))
-        },
-        Ok(None) => (
-            StatusCode::NOT_FOUND,
-            Json(json!({ "error": "User not found or no fields to update" })),
-        ),
-        Err(err) => {
-            eprintln!("Internal Server Error: {err}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({ "error": "Internal server error"})),
-            )
-        }
+    let previous = match previous_user(&config, &tenant, user_id).await {
+        Ok(previous) => previous,
+        Err(err) => return err,
     };
-
-    res
+    let org_id = tenant.org_id();
+    let response = tenant_work(&config, &tenant, |tx| {
+        let new_user = new_user.clone();
+        Box::pin(async move { modify_user(user_id, org_id, new_user, &mut **tx).await })
+    }).await;
+    sync_updated(&config, &tenant, previous, response).await
 }
 
 #[instrument(skip(config))]
@@ -200,7 +225,8 @@ pub async fn put_user(
 pub async fn delete_user(
     Extension(token): Extension<KeycloakToken<String>>,
     user_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
-    State(config): State<Arc<ConfigState>>
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
 ) -> impl IntoApiResponse {
     // Ensure user is admin
     expect_admin!(&token);
@@ -220,23 +246,563 @@ pub async fn delete_user(
         }
     };
 
-    let res = match remove_user(user_id, &config.pgpool).await {
-        Ok(Some(user)) => {
-            println!("User {} deleted successfully", user.user_id);
-            (StatusCode::ACCEPTED, Json(json!({"message": "User deleted successfully"})))
+    // Keycloak is changed first, a local failure afterwards leaves a user who can no longer log in
+    // and whom reconciliation recreates, instead of a deleted user who still can
+    if let Some(kc) = user_sync(&config) {
+        let user = match previous_user(&config, &tenant, user_id).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return user_not_found(),
+            Err(err) => return err,
+        };
+        let deleted = match find_account(&config, tenant.org_id(), user_id, &user.username).await {
+            Ok(Some(keycloak_id)) => kc.delete_user(keycloak_id).await,
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = deleted {
+            return identity_provider_error(err);
+        }
+    }
+
+    let org_id = tenant.org_id();
+    let response = tenant_work(&config, &tenant, |tx| {
+        Box::pin(async move { erase_user(user_id, org_id, &mut **tx).await })
+    }).await;
+    if response.0.is_success() {
+        record_users_deleted("api", 1);
+    }
+    response
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn disable_user(
+    Extension(token): Extension<KeycloakToken<String>>,
+    user_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    set_enabled(token, user_id_result, config, tenant, false).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn enable_user(
+    Extension(token): Extension<KeycloakToken<String>>,
+    user_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+) -> impl IntoApiResponse {
+    set_enabled(token, user_id_result, config, tenant, true).await
+}
+
+// Shared body of enable_user and disable_user
+async fn set_enabled(
+    token: KeycloakToken<String>,
+    user_id_result: Result<Path<Uuid>, axum::extract::rejection::PathRejection>,
+    config: Arc<ConfigState>,
+    tenant: Tenant,
+    enabled: bool,
+) -> (StatusCode, Json<Value>) {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    // Check if UUID is valid
+    let user_id = match user_id_result {
+        Ok(Path(id)) => id,
+        Err(err) => {
+            eprintln!("Invalid UUID: {}", err);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "Invalid UUID format" })),
+            );
+        }
+    };
+
+    let previous = match previous_user(&config, &tenant, user_id).await {
+        Ok(previous) => previous,
+        Err(err) => return err,
+    };
+    let org_id = tenant.org_id();
+    let response = tenant_work(&config, &tenant, |tx| {
+        Box::pin(async move { toggle_user(user_id, org_id, enabled, &mut **tx).await })
+    }).await;
+    sync_updated(&config, &tenant, previous, response).await
+}
+
+#[instrument(skip(config))]
+#[axum::debug_handler]
+pub async fn batch_users(
+    Extension(token): Extension<KeycloakToken<String>>,
+    State(config): State<Arc<ConfigState>>,
+    tenant: Tenant,
+    Json(batch): Json<BatchRequest>,
+) -> impl IntoApiResponse {
+    // Ensure user is admin
+    expect_admin!(&token);
+
+    if batch.operations.is_empty() || batch.operations.len() > MAX_BATCH_OPERATIONS {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": format!("A batch must contain between 1 and {MAX_BATCH_OPERATIONS} operations") })),
+        );
+    }
+
+    let definitions = match load_definitions(&config).await {
+        Ok(definitions) => definitions,
+        Err(err) => return err,
+    };
+
+    let (org_id, definitions) = (tenant.org_id(), &definitions);
+    let mut results: Vec<BatchItemResult> = Vec::with_capacity(batch.operations.len());
+    // Deleted users can no longer be resolved to their Keycloak accounts once the batch has run
+    let accounts = match user_sync(&config) {
+        Some(_) => Some(batch_accounts(&config, org_id, &batch.operations).await),
+        None => None,
+    };
+
+    let committed = match batch.mode {
+        BatchMode::BestEffort => {
+            // Every operation runs in its own transaction
+            for (index, operation) in batch.operations.iter().enumerate() {
+                let op = operation.name();
+                let (status, Json(body)) = tenant_work(&config, &tenant, |tx| {
+                    let operation = operation.clone();
+                    Box::pin(async move { run_batch_operation(operation, definitions, org_id, &mut **tx).await })
+                }).await;
+                results.push(BatchItemResult { index, op, status: status.as_u16(), body });
+            }
+            record_batch_changes(&results);
+            if let Some(accounts) = &accounts {
+                push_batch(&config, org_id, accounts, &results).await;
+            }
+            true
+        },
+        BatchMode::Atomic => {
+            // Stop at the first failure, operations after it are never attempted
+            // Keycloak cannot be rolled back with the batch, changes are pushed after the commit
+            let outcome = unit_of_work(&config.pgpool, org_id, |tx| {
+                let operations = batch.operations.clone();
+                Box::pin(async move {
+                    let mut results = Vec::with_capacity(operations.len());
+                    for (index, operation) in operations.into_iter().enumerate() {
+                        let op = operation.name();
+                        let (status, Json(body)) = run_batch_operation(operation, definitions, org_id, &mut **tx).await?;
+                        results.push(BatchItemResult { index, op, status: status.as_u16(), body });
+                        if !status.is_success() {
+                            break;
+                        }
+                    }
+                    Ok(AtomicBatch(results))
+                })
+            }).await;
+            results = match outcome {
+                Ok(AtomicBatch(results)) => results,
+                Err(err) => return internal_error(err),
+            };
+
+            if results.iter().any(|result| result.status >= 300) {
+                // Report every other operation as dependent on the failed one
+                for result in results.iter_mut().filter(|result| result.status < 300) {
+                    result.status = StatusCode::FAILED_DEPENDENCY.as_u16();
+                    result.body = json!({ "error": "Rolled back because another operation failed" });
+                }
+                for (index, operation) in batch.operations.iter().enumerate().skip(results.len()) {
+                    results.push(BatchItemResult {
+                        index,
+                        op: operation.name(),
+                        status: StatusCode::FAILED_DEPENDENCY.as_u16(),
+                        body: json!({ "error": "Not attempted because another operation failed" }),
+                    });
+                }
+                false
+            } else {
+                record_batch_changes(&results);
+                if let Some(accounts) = &accounts {
+                    push_batch(&config, org_id, accounts, &results).await;
+                }
+                true
+            }
         },
+    };
+
+    // 207 signals that at least one item needs to be inspected
+    let status = match results.iter().all(|result| result.status < 300) {
+        true => StatusCode::OK,
+        false => StatusCode::MULTI_STATUS,
+    };
+
+    (
+        status,
+        Json(json!({
+            "mode": batch.mode,
+            "committed": committed,
+            "results": results,
+        })),
+    )
+}
+
+// Count the committed creations and deletions of a batch
+fn record_batch_changes(results: &[BatchItemResult]) {
+    let committed = |op: &str| results.iter().filter(|result| result.op == op && result.status < 300).count() as u64;
+    record_users_created("batch", committed("create"));
+    record_users_deleted("batch", committed("delete"));
+}
+
+// Validate a creation payload, shared by post_user and batch operations
+pub(crate) fn validate_new_user(new_user: &NewUser, definitions: &[AttributeDefinition]) -> Result<User, (StatusCode, Json<Value>)> {
+    // Check if UUID is valid
+    let user_id = match Uuid::parse_str(&new_user.user_id) {
+        Ok(uuid) => uuid,
+        Err(err) => {
+            eprintln!("Invalid UUID: {err}");
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Invalid UUID format" })),
+            ));
+        }
+    };
+    
+    // Unwrap `username` and validate it
+    let username = match &new_user.username {
+        Some(name) if !name.trim().is_empty() => name.clone(),
+        _ => {
+            eprintln!("Invalid username: must not be empty");
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Username must not be empty" })),
+            ));
+        }
+    };
+
+    // Validate the email field if provided
+    let email = validate_email(&new_user.email)?;
+
+    // Validate custom attributes, null values are dropped
+    let mut attributes = new_user.attributes.clone().unwrap_or_default();
+    if let Err(err) = validate_attributes(&attributes, definitions, false) {
+        eprintln!("Invalid attributes: {err}");
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": err })),
+        ));
+    }
+    attributes.retain(|_, value| !value.is_null());
+
+    Ok(User {
+        user_id,
+        username,
+        email,
+        attributes: Value::Object(attributes),
+        enabled: true,
+    })
+}
+
+// Validate the fields of a partial update
+fn validate_update(new_user: &NewUser, definitions: &[AttributeDefinition]) -> Result<(), (StatusCode, Json<Value>)> {
+    validate_email(&new_user.email)?;
+
+    if let Some(attributes) = &new_user.attributes {
+        if let Err(err) = validate_attributes(attributes, definitions, true) {
+            eprintln!("Invalid attributes: {err}");
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": err })),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Load attribute definitions, mapping failures to a response
+pub(crate) async fn load_definitions(config: &ConfigState) -> Result<Vec<AttributeDefinition>, (StatusCode, Json<Value>)> {
+    list_attribute_definitions(&config.pgpool).await.map_err(|err| {
+        eprintln!("Internal Server Error: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": "Internal server error" })),
+        )
+    })
+}
+
+// Results of an atomic batch, committed only when every operation succeeded
+struct AtomicBatch(Vec<BatchItemResult>);
+
+impl WorkOutcome for AtomicBatch {
+    fn commits(&self) -> bool {
+        self.0.iter().all(|result| result.status < 300)
+    }
+}
+
+// Hide attributes the caller may not read, owners see attributes with `self` visibility
+fn redact_attributes(user: &mut User, definitions: &[AttributeDefinition], token: &KeycloakToken<String>) {
+    if token.expect_roles(&[String::from("administrator")]).is_ok() {
+        return;
+    }
+
+    let is_owner = token.subject == user.user_id.to_string();
+    if let Value::Object(attributes) = &mut user.attributes {
+        attributes.retain(|name, _| {
+            is_owner && definitions
+                .iter()
+                .any(|definition| &definition.name == name && definition.visibility == AttributeVisibility::Owner)
+        });
+    }
+}
+
+// Map a unique violation to a conflict naming the offending attribute
+pub(crate) fn unique_violation(db_err: &dyn DatabaseError) -> (StatusCode, Json<Value>) {
+    match db_err.constraint().and_then(|constraint| constraint.strip_prefix(UNIQUE_ATTRIBUTE_INDEX_PREFIX)) {
+        Some(name) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("Attribute {name} must be unique") })),
+        ),
+        None => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "User already exists" })),
+        ),
+    }
+}
+
+// Validate the email field if provided
+fn validate_email(email: &Option<String>) -> Result<Option<String>, (StatusCode, Json<Value>)> {
+    match email {
+        Some(email) if is_valid_email(email) => Ok(Some(email.clone())),
+        Some(_) => {
+            eprintln!("Invalid email: does not match valid email format");
+            Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Invalid email format" })),
+            ))
+        }
+        None => Ok(None), // No email provided, set to None
+    }
+}
+
+// Insert a validated user and map database errors to responses, unexpected errors are left to the caller
+pub(crate) async fn insert_user<'e>(user: User, org_id: Uuid, executor: impl PgExecutor<'e>) -> Result<(StatusCode, Json<Value>), Error> {
+    let response = match create_user(user, org_id, executor).await {
+        Ok(Some(user)) => (StatusCode::CREATED, Json(json!(user))),
+        Ok(None) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "User creation failed" })),
+        ),
+        Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => {
+            // Compare using `as_deref` to convert `Cow<'_, str>` to `Option<&str>`
+            // 23505 is the SQL state for unique violation
+            unique_violation(db_err.as_ref())
+        },
+        Err(err) => return Err(err),
+    };
+    Ok(response)
+}
+
+// Apply a partial update and map the result to a response
+async fn modify_user<'e>(user_id: Uuid, org_id: Uuid, new_user: NewUser, executor: impl PgExecutor<'e>) -> Result<(StatusCode, Json<Value>), Error> {
+    let response = match update_user(user_id, org_id, new_user, executor).await {
+        Ok(Some(user)) => (StatusCode::OK, Json(json!(user))),
         Ok(None) => (
             StatusCode::NOT_FOUND,
-            Json(json!({ "error": "User not found" })),
+            Json(json!({ "error": "User not found or no fields to update" })),
         ),
-        Err(err) => {
-            eprintln!("Internal Server Error: {err}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({ "error": "Internal server error"})),
-            )
+        Err(Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => {
+            unique_violation(db_err.as_ref())
+        },
+        Err(err) => return Err(err),
+    };
+    Ok(response)
+}
+
+// Delete a user and map the result to a response
+async fn erase_user<'e>(user_id: Uuid, org_id: Uuid, executor: impl PgExecutor<'e>) -> Result<(StatusCode, Json<Value>), Error> {
+    let response = match remove_user(user_id, org_id, executor).await? {
+        Some(user) => {
+            println!("User {} deleted successfully", user.user_id);
+            (StatusCode::ACCEPTED, Json(json!({"message": "User deleted successfully"})))
         },
+        None => user_not_found(),
+    };
+    Ok(response)
+}
+
+// Validate and run a single batch operation
+async fn run_batch_operation<'e>(
+    operation: BatchOperation,
+    definitions: &[AttributeDefinition],
+    org_id: Uuid,
+    executor: impl PgExecutor<'e>,
+) -> Result<(StatusCode, Json<Value>), Error> {
+    match operation {
+        BatchOperation::Create(new_user) => match validate_new_user(&new_user, definitions) {
+            Ok(user) => insert_user(user, org_id, executor).await,
+            Err(err) => Ok(err),
+        },
+        BatchOperation::Update(new_user) => {
+            let Ok(user_id) = Uuid::parse_str(&new_user.user_id) else {
+                return Ok((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({ "error": "Invalid UUID format" })),
+                ));
+            };
+            if let Err(err) = validate_update(&new_user, definitions) {
+                return Ok(err);
+            }
+            modify_user(user_id, org_id, new_user, executor).await
+        },
+        BatchOperation::Delete { user_id } => match Uuid::parse_str(&user_id) {
+            Ok(user_id) => erase_user(user_id, org_id, executor).await,
+            Err(_) => Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "Invalid UUID format" })),
+            )),
+        },
+    }
+}
+
+// Enable or disable a user and map the result to a response
+async fn toggle_user<'e>(user_id: Uuid, org_id: Uuid, enabled: bool, executor: impl PgExecutor<'e>) -> Result<(StatusCode, Json<Value>), Error> {
+    let response = match set_user_enabled(user_id, org_id, enabled, executor).await? {
+        Some(user) => (StatusCode::OK, Json(json!(user))),
+        None => user_not_found(),
+    };
+    Ok(response)
+}
+
+fn user_not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "User not found" })),
+    )
+}
+
+// Stored state of a user before a change, kept to undo the change when Keycloak refuses it
+async fn previous_user(config: &ConfigState, tenant: &Tenant, user_id: Uuid) -> Result<Option<User>, (StatusCode, Json<Value>)> {
+    if user_sync(config).is_none() {
+        return Ok(None);
+    }
+    let mut tx = begin_tenant(&config.pgpool, tenant.org_id()).await.map_err(internal_error)?;
+    let user = find_user(user_id, tenant.org_id(), &mut *tx).await.map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(user)
+}
+
+// Push a committed creation to Keycloak, the local row is removed again when Keycloak refuses the user
+async fn sync_created(config: &ConfigState, tenant: &Tenant, response: (StatusCode, Json<Value>)) -> (StatusCode, Json<Value>) {
+    if user_sync(config).is_none() || !response.0.is_success() {
+        return response;
+    }
+    let Ok(user) = serde_json::from_value::<User>(response.1.0.clone()) else {
+        return response;
+    };
+    let failure = match create_account(config, tenant.org_id(), &user).await {
+        Ok(true) => return response,
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "User already exists in the identity provider" })),
+        ),
+        Err(err) => identity_provider_error(err),
+    };
+
+    let (user_id, org_id) = (user.user_id, tenant.org_id());
+    let undone = tenant_work(config, tenant, |tx| {
+        Box::pin(async move { erase_user(user_id, org_id, &mut **tx).await })
+    }).await;
+    if !undone.0.is_success() {
+        eprintln!("Failed to remove user {user_id} after Keycloak refused it");
+    }
+    failure
+}
+
+// Push a committed update to Keycloak, the previous state is restored when Keycloak refuses the change
+async fn sync_updated(config: &ConfigState, tenant: &Tenant, previous: Option<User>, response: (StatusCode, Json<Value>)) -> (StatusCode, Json<Value>) {
+    if user_sync(config).is_none() || !response.0.is_success() {
+        return response;
+    }
+    let Ok(user) = serde_json::from_value::<User>(response.1.0.clone()) else {
+        return response;
+    };
+    // A renamed account is still found under its previous username
+    let username = previous.as_ref().map_or(&user.username, |previous| &previous.username);
+    let failure = match push_user(config, tenant.org_id(), &user, username).await {
+        Ok(true) => return response,
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "User already exists in the identity provider" })),
+        ),
+        Err(err) => identity_provider_error(err),
+    };
+
+    // Changes made since this one are kept, only a row still holding this change is restored
+    let Some(previous) = previous else {
+        return failure;
     };
+    let restored = async {
+        let mut tx = begin_tenant(&config.pgpool, tenant.org_id()).await?;
+        restore_user(&previous, &user, tenant.org_id(), &mut *tx).await?;
+        tx.commit().await
+    }.await;
+    if let Err(err) = restored {
+        eprintln!("Failed to restore user {} after Keycloak refused the change: {err}", user.user_id);
+    }
+    failure
+}
+
+// Keycloak could not apply a change, the caller rolls the local change back
+fn identity_provider_error(err: anyhow::Error) -> (StatusCode, Json<Value>) {
+    eprintln!("Keycloak user sync failed: {err:#}");
+    (
+        StatusCode::BAD_GATEWAY,
+        Json(json!({ "error": "Identity provider unavailable" })),
+    )
+}
 
-    res
-}
\ No newline at end of file
+// Keycloak accounts of the users updated or deleted by a batch, resolved before the batch runs
+async fn batch_accounts(config: &ConfigState, org_id: Uuid, operations: &[BatchOperation]) -> Vec<Option<Uuid>> {
+    let mut accounts = Vec::with_capacity(operations.len());
+    for operation in operations {
+        let user_id = match operation {
+            BatchOperation::Update(new_user) => Uuid::parse_str(&new_user.user_id).ok(),
+            BatchOperation::Delete { user_id } => Uuid::parse_str(user_id).ok(),
+            BatchOperation::Create(_) => None,
+        };
+        let account = match user_id {
+            Some(user_id) => resolve_account(config, org_id, user_id).await.unwrap_or_else(|err| {
+                eprintln!("Failed to resolve the Keycloak account of user {user_id}: {err:#}");
+                None
+            }),
+            None => None,
+        };
+        accounts.push(account);
+    }
+    accounts
+}
+
+// Keycloak account of a stored user, None when the user or its account does not exist
+async fn resolve_account(config: &ConfigState, org_id: Uuid, user_id: Uuid) -> anyhow::Result<Option<Uuid>> {
+    let mut tx = begin_tenant(&config.pgpool, org_id).await?;
+    let user = find_user(user_id, org_id, &mut *tx).await?;
+    tx.commit().await?;
+    match user {
+        Some(user) => find_account(config, org_id, user_id, &user.username).await,
+        None => Ok(None),
+    }
+}
+
+// Mirror the committed operations of a batch into Keycloak, failures are left to reconciliation
+async fn push_batch(config: &ConfigState, org_id: Uuid, accounts: &[Option<Uuid>], results: &[BatchItemResult]) {
+    for result in results.iter().filter(|result| result.status < 300) {
+        let pushed = match (result.op, accounts[result.index]) {
+            ("delete", Some(keycloak_id)) => config.kc_admin.delete_user(keycloak_id).await,
+            ("delete", None) => Ok(()),
+            (op, _) => match serde_json::from_value::<User>(result.body.clone()) {
+                Ok(user) if op == "create" => create_account(config, org_id, &user).await.map(|_| ()),
+                Ok(user) => push_user(config, org_id, &user, &user.username).await.map(|_| ()),
+                Err(err) => Err(err.into()),
+            },
+        };
+        if let Err(err) = pushed {
+            eprintln!("Keycloak user sync failed for batch operation {}: {err:#}", result.index);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::fs::{FileTypeExt, PermissionsExt},
+    },
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use socket2::{Domain, Socket, Type};
+use tokio::net::{TcpListener, UnixListener};
+
+use crate::config::EnvironmentVariables;
+
+// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+// Pending connections queued by the kernel per listener
+const BACKLOG: i32 = 1024;
+
+// A bound socket, TCP or Unix domain
+pub enum BoundListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl BoundListener {
+    // Human readable address for the startup banner
+    pub fn describe(&self) -> String {
+        match self {
+            BoundListener::Tcp(listener) => listener
+                .local_addr()
+                .map(|address| address.to_string())
+                .unwrap_or_else(|_| "tcp".to_string()),
+            BoundListener::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|address| address.as_pathname().map(|path| format!("unix:{}", path.display())))
+                .unwrap_or_else(|| "unix".to_string()),
+        }
+    }
+}
+
+// Every listener of LISTEN_ADDRS, or HOSTNAME:PORT when it is empty
+pub async fn bind_listeners(env: &EnvironmentVariables, activated: &mut Vec<OwnedFd>) -> anyhow::Result<Vec<BoundListener>> {
+    let default = format!("{}:{}", env.hostname, env.port);
+    let specs: Vec<&str> = match env.listen_addrs.trim() {
+        "" => vec![default.as_str()],
+        specs => specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()).collect(),
+    };
+    if specs.is_empty() {
+        bail!("LISTEN_ADDRS does not name any listener");
+    }
+
+    let mut listeners = Vec::new();
+    for spec in specs {
+        listeners.extend(bind(spec, &env.unix_socket_mode, activated).await?);
+    }
+    Ok(listeners)
+}
+
+// Unix sockets carry no client address, the rate limits and the metrics allow-list then rely on the proxy headers
+pub fn require_client_addresses(listeners: &[BoundListener], trust_proxy_headers: bool) -> anyhow::Result<()> {
+    match listeners.iter().find(|listener| matches!(listener, BoundListener::Unix(_))) {
+        Some(listener) if !trust_proxy_headers => bail!(
+            "{} has no client addresses, serve it behind a proxy and set TRUST_PROXY_HEADERS=true",
+            listener.describe()
+        ),
+        _ => Ok(()),
+    }
+}
+
+// Bind one listener spec: `host:port`, `[v6]:port`, `unix:/path` or `systemd` for every socket activated socket
+pub async fn bind(spec: &str, unix_socket_mode: &str, activated: &mut Vec<OwnedFd>) -> anyhow::Result<Vec<BoundListener>> {
+    if spec == "systemd" {
+        if activated.is_empty() {
+            bail!("systemd listener requested but no sockets were passed through LISTEN_FDS for this process");
+        }
+        return activated.drain(..).map(inherited_listener).collect();
+    }
+    if let Some(path) = spec.strip_prefix("unix:") {
+        let mode = u32::from_str_radix(unix_socket_mode, 8).context("UNIX_SOCKET_MODE must be an octal mode like 660")?;
+        return Ok(vec![BoundListener::Unix(bind_unix(Path::new(path), mode)?)]);
+    }
+    Ok(vec![BoundListener::Tcp(bind_tcp(spec).await?)])
+}
+
+// IPv6 sockets only accept IPv6 so `0.0.0.0:port` and `[::]:port` can be bound side by side
+async fn bind_tcp(spec: &str) -> anyhow::Result<TcpListener> {
+    let mut last_error = None;
+    for address in tokio::net::lookup_host(spec).await.with_context(|| format!("Failed to resolve {spec}"))? {
+        match tcp_socket(address) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    match last_error {
+        Some(err) => Err(err).with_context(|| format!("Failed to bind {spec}")),
+        None => bail!("{spec} did not resolve to any address"),
+    }
+}
+
+fn tcp_socket(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+// Stale sockets left behind by a previous run are replaced, other files are never removed
+fn bind_unix(path: &Path, mode: u32) -> anyhow::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path.display());
+        }
+        fs::remove_file(path).with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    Ok(listener)
+}
+
+// Take the sockets passed through LISTEN_FDS when LISTEN_PID names this process. Changes the environment,
+// so it must run before the runtime starts any thread
+pub fn take_activated_sockets() -> Vec<OwnedFd> {
+    let listen_pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let count: RawFd = std::env::var("LISTEN_FDS").ok().and_then(|count| count.parse().ok()).unwrap_or(0);
+
+    // Child processes must not inherit the sockets a second time
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    if listen_pid != Some(std::process::id()) {
+        return Vec::new();
+    }
+    // systemd hands these descriptors to this process only, nothing else owns them
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count.max(0)).map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }).collect()
+}
+
+// Wrap an inherited listening socket, TCP or Unix depending on its address family
+pub fn inherited_listener(fd: OwnedFd) -> anyhow::Result<BoundListener> {
+    let socket = Socket::from(fd);
+    let address = socket.local_addr().context("File descriptor is not a socket")?;
+    socket.set_nonblocking(true)?;
+
+    if address.is_unix() {
+        Ok(BoundListener::Unix(UnixListener::from_std(socket.into())?))
+    } else if address.as_socket().is_some() {
+        Ok(BoundListener::Tcp(TcpListener::from_std(socket.into())?))
+    } else {
+        bail!("File descriptor has an unsupported address family")
+    }
+}
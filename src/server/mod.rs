@@ -0,0 +1,97 @@
+pub mod listeners;
+pub mod tls;
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, serve::Listener, Router};
+use hyper::{body::Incoming, Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tower::ServiceExt;
+
+use crate::definitions::tls::{ClientCertificate, TlsConnection};
+use listeners::BoundListener;
+use tls::ReloadableTls;
+
+// Connections that do not finish the TLS handshake in time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Resolves on the first SIGINT or SIGTERM
+pub async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {},
+    }
+    Ok(())
+}
+
+// Serve one listener, over TLS when certificates are configured
+pub async fn serve(listener: BoundListener, app: Router, tls: Option<Arc<ReloadableTls>>) -> anyhow::Result<()> {
+    match (listener, tls) {
+        (BoundListener::Tcp(listener), Some(tls)) => serve_tls(listener, app, tls).await,
+        (BoundListener::Unix(listener), Some(tls)) => serve_tls(listener, app, tls).await,
+        (BoundListener::Tcp(listener), None) => {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+            Ok(())
+        },
+        // Unix sockets have no peer address, main refuses them unless the proxy headers are trusted
+        (BoundListener::Unix(listener), None) => {
+            axum::serve(listener, app).await?;
+            Ok(())
+        },
+    }
+}
+
+// Serve HTTPS with HTTP/1.1 and HTTP/2, each connection uses the certificates current at its handshake
+pub async fn serve_tls<L>(mut listener: L, app: Router, tls: Arc<ReloadableTls>) -> anyhow::Result<()>
+where
+    L: Listener,
+    L::Addr: Clone + std::fmt::Debug + Sync + 'static,
+{
+    loop {
+        let (stream, peer) = listener.accept().await;
+        let acceptor = tls.acceptor();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => {
+                    tracing::debug!("TLS handshake with {peer:?} failed: {err}");
+                    return;
+                },
+                Err(_) => {
+                    tracing::debug!("TLS handshake with {peer:?} timed out");
+                    return;
+                },
+            };
+            // Verified by rustls when client authentication is configured, the leaf identifies the client
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| ClientCertificate::from_der(cert));
+
+            let connect_info = ConnectInfo(peer.clone());
+            let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+                req.extensions_mut().insert(connect_info.clone());
+                req.extensions_mut().insert(TlsConnection);
+                if let Some(certificate) = &certificate {
+                    req.extensions_mut().insert(certificate.clone());
+                }
+                app.clone().oneshot(req)
+            });
+            if let Err(err) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Connection with {peer:?} closed with an error: {err}");
+            }
+        });
+    }
+}
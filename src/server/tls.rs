@@ -0,0 +1,168 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::{danger::ClientCertVerifier, WebPkiClientVerifier},
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::config::EnvironmentVariables;
+
+// Protocols offered through ALPN, in order of preference
+const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+// Certificate files and client authentication of the HTTPS listener
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    // Clients must present a certificate signed by one of these CAs when set
+    pub client_ca_path: Option<PathBuf>,
+    // Accept clients without a certificate, those that present one are still verified
+    pub client_auth_optional: bool,
+    // How often the files are checked for changes, zero only reloads on SIGHUP
+    pub reload_interval: Duration,
+}
+
+impl TlsSettings {
+    // None when TLS_CERT_PATH and TLS_KEY_PATH are unset
+    pub fn from_env(env: &EnvironmentVariables) -> anyhow::Result<Option<Self>> {
+        let path = |value: &str| (!value.is_empty()).then(|| PathBuf::from(value));
+        let (cert_path, key_path) = match (path(&env.tls_cert_path), path(&env.tls_key_path)) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) => return Ok(None),
+            _ => bail!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+        };
+        let client_auth_optional = match env.tls_client_auth.as_ref() {
+            "required" => false,
+            "optional" => true,
+            other => bail!("Unknown TLS_CLIENT_AUTH {other}, expected required or optional"),
+        };
+
+        Ok(Some(Self {
+            cert_path,
+            key_path,
+            client_ca_path: path(&env.tls_client_ca_path),
+            client_auth_optional,
+            reload_interval: Duration::from_secs(
+                env.tls_reload_interval_secs.parse().context("TLS_RELOAD_INTERVAL_SECS must be a number of seconds")?,
+            ),
+        }))
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.cert_path, &self.key_path].into_iter().chain(self.client_ca_path.iter())
+    }
+
+    // Build a rustls configuration from the current contents of the files
+    fn load(&self) -> anyhow::Result<Arc<ServerConfig>> {
+        let provider = Arc::new(ring::default_provider());
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("Failed to read certificates from {}", self.cert_path.display()))?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path)
+            .with_context(|| format!("Failed to read private key from {}", self.key_path.display()))?;
+
+        let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca_path {
+            Some(path) => builder.with_client_cert_verifier(self.client_verifier(path, provider)?),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(certs, key).context("Certificate does not match the private key")?;
+        config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|protocol| protocol.to_vec()).collect();
+        Ok(Arc::new(config))
+    }
+
+    fn client_verifier(&self, path: &PathBuf, provider: Arc<CryptoProvider>) -> anyhow::Result<Arc<dyn ClientCertVerifier>> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(path).with_context(|| format!("Failed to read client CAs from {}", path.display()))? {
+            roots.add(cert?).context("Invalid client CA certificate")?;
+        }
+        let builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+        let builder = match self.client_auth_optional {
+            true => builder.allow_unauthenticated(),
+            false => builder,
+        };
+        Ok(builder.build()?)
+    }
+
+    // Latest modification time of the files, used to notice renewed certificates
+    fn modified(&self) -> Option<SystemTime> {
+        self.paths().filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok()).max()
+    }
+}
+
+// rustls configuration that can be swapped while the server runs, open connections keep the one they started with
+pub struct ReloadableTls {
+    settings: TlsSettings,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl ReloadableTls {
+    pub fn new(settings: TlsSettings) -> anyhow::Result<Self> {
+        let current = RwLock::new(settings.load()?);
+        Ok(Self { settings, current })
+    }
+
+    // Acceptor for the next handshake
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    // Load the files again, the previous configuration stays active when they are invalid
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let config = self.settings.load()?;
+        *self.current.write().unwrap() = config;
+        Ok(())
+    }
+}
+
+// Reload certificates on SIGHUP and whenever the files change
+pub(crate) fn spawn_tls_reload(tls: Arc<ReloadableTls>) -> anyhow::Result<()> {
+    let mut hangup = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+    let interval = tls.settings.reload_interval;
+
+    tokio::spawn(async move {
+        let mut modified = tls.settings.modified();
+        let mut ticker = (!interval.is_zero()).then(|| tokio::time::interval(interval));
+        loop {
+            let changed = async {
+                match &mut ticker {
+                    Some(ticker) => ticker.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let reason = tokio::select! {
+                _ = hangup.recv() => {
+                    // Files renewed before the signal must not trigger a second reload on the next tick
+                    modified = tls.settings.modified();
+                    "SIGHUP"
+                },
+                _ = changed => {
+                    let latest = tls.settings.modified();
+                    if latest == modified {
+                        continue;
+                    }
+                    modified = latest;
+                    "file change"
+                },
+            };
+            match tls.reload() {
+                Ok(()) => tracing::info!("Reloaded TLS certificates after {reason}"),
+                Err(err) => eprintln!("Failed to reload TLS certificates after {reason}, keeping the current ones: {err:#}"),
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use super::support::TestApp;
+
+async fn get(router: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = router.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn operational_routes_are_merged_by_default() {
+    let app = TestApp::spawn().await;
+
+    assert_eq!(get(&app.router, "/metrics").await.0, StatusCode::OK);
+    let (status, body) = get(&app.router, "/health/live").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "live");
+}
+
+#[tokio::test]
+async fn admin_listener_isolates_operational_routes() {
+    let app = TestApp::spawn_with_database_env(&[("ADMIN_BIND_ADDR", "127.0.0.1:0")]).await;
+
+    for uri in ["/metrics", "/api.json", "/health/live", "/health/ready"] {
+        assert_eq!(get(&app.router, uri).await.0, StatusCode::NOT_FOUND, "{uri} is still public");
+    }
+    // Business routes keep recording HTTP metrics for the admin listener
+    assert_eq!(get(&app.router, "/").await.0, StatusCode::OK);
+
+    let response = app.admin.clone().oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let metrics = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&metrics).contains("axum_http_requests_total"));
+
+    assert_eq!(get(&app.admin, "/api.json").await.0, StatusCode::OK);
+    let (status, body) = get(&app.admin, "/health/ready").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+}
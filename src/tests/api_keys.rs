@@ -0,0 +1,117 @@
+use axum::{body::Body, http::{header, HeaderName, Method, StatusCode}};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::support::TestApp;
+
+// Send a request authenticated with an API key instead of a bearer token
+async fn key_request(app: &TestApp, method: Method, uri: &str, key: &str, body: Option<Value>) -> StatusCode {
+    let api_key = HeaderName::from_static("x-api-key");
+    let (status, _) = match body {
+        Some(body) => {
+            app.request_raw(method, uri, None, &[(api_key, key), (header::CONTENT_TYPE, "application/json")], Body::from(body.to_string()))
+                .await
+        },
+        None => app.request_raw(method, uri, None, &[(api_key, key)], Body::empty()).await,
+    };
+    status
+}
+
+async fn create_key(app: &TestApp, body: Value) -> Value {
+    let (status, body) = app.request(Method::POST, "/api-keys", Some(&app.admin_token()), Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    body
+}
+
+#[tokio::test]
+async fn api_keys_authenticate_with_scoped_roles() {
+    let app = TestApp::spawn_with_database().await;
+
+    let reader = create_key(&app, json!({ "name": "nightly-report" })).await;
+    assert_eq!(reader["roles"], json!(["user"]));
+    assert!(reader.get("secret_hash").is_none());
+    let reader_key = reader["key"].as_str().unwrap();
+
+    let writer = create_key(&app, json!({ "name": "provisioning", "roles": ["administrator"], "expires_in_days": 30 })).await;
+    let writer_key = writer["key"].as_str().unwrap();
+
+    // Keys cannot exceed the roles of their creator
+    let (status, _) = app
+        .request(Method::POST, "/api-keys", Some(&app.admin_token()), Some(json!({ "name": "root", "roles": ["superuser"] })))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let user_id = Uuid::new_v4();
+    let new_user = json!({ "user_id": user_id, "username": "batch-user" });
+    assert_eq!(key_request(&app, Method::POST, "/users", reader_key, Some(new_user.clone())).await, StatusCode::FORBIDDEN);
+    assert_eq!(key_request(&app, Method::POST, "/users", writer_key, Some(new_user)).await, StatusCode::CREATED);
+    assert_eq!(key_request(&app, Method::GET, &format!("/users/{user_id}"), reader_key, None).await, StatusCode::OK);
+
+    // A tampered secret or an unknown key is rejected outright
+    let replacement = if reader_key.ends_with('A') { 'B' } else { 'A' };
+    let tampered = format!("{}{replacement}", &reader_key[..reader_key.len() - 1]);
+    assert_eq!(key_request(&app, Method::GET, &format!("/users/{user_id}"), &tampered, None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(key_request(&app, Method::GET, &format!("/users/{user_id}"), "ak_nope", None).await, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = app.request(Method::GET, "/api-keys", Some(&app.admin_token()), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 2);
+    let used = body["items"].as_array().unwrap().iter().find(|key| key["key_id"] == reader["key_id"]).unwrap();
+    assert!(used["last_used_at"].is_string());
+    let (_, body) = app.request(Method::GET, "/api-keys?offset=10", Some(&app.admin_token()), None).await;
+    assert_eq!((body["total"].as_i64(), body["items"].as_array().map(Vec::len)), (Some(2), Some(0)));
+
+    // Revoked keys stop working immediately
+    let (status, _) = app
+        .request(Method::DELETE, &format!("/api-keys/{}", reader["key_id"].as_str().unwrap()), Some(&app.admin_token()), None)
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(key_request(&app, Method::GET, &format!("/users/{user_id}"), reader_key, None).await, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rotation_honours_grace_period() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.admin_token();
+
+    let original = create_key(&app, json!({ "name": "sync-job" })).await;
+    let key_id = original["key_id"].as_str().unwrap();
+    let original_key = original["key"].as_str().unwrap();
+
+    let (status, rotated) = app
+        .request(Method::POST, &format!("/api-keys/{key_id}/rotate"), Some(&admin), Some(json!({ "grace_period_secs": 3600 })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let rotated_key = rotated["key"].as_str().unwrap();
+    assert_ne!(rotated_key, original_key);
+
+    // Both secrets work during the grace period
+    assert_eq!(key_request(&app, Method::GET, "/attributes", original_key, None).await, StatusCode::OK);
+    assert_eq!(key_request(&app, Method::GET, "/attributes", rotated_key, None).await, StatusCode::OK);
+
+    // Rotating without a grace period retires every older secret
+    let (_, latest) = app.request(Method::POST, &format!("/api-keys/{key_id}/rotate"), Some(&admin), None).await;
+    let latest_key = latest["key"].as_str().unwrap();
+    assert_eq!(key_request(&app, Method::GET, "/attributes", original_key, None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(key_request(&app, Method::GET, "/attributes", rotated_key, None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(key_request(&app, Method::GET, "/attributes", latest_key, None).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn keys_created_with_a_key_never_outlive_it() {
+    let app = TestApp::spawn_with_database().await;
+    let parent = create_key(&app, json!({ "name": "provisioning", "roles": ["administrator"], "expires_in_days": 30 })).await;
+    let parent_key = parent["key"].as_str().unwrap();
+
+    let api_key = HeaderName::from_static("x-api-key");
+    for child in [json!({ "name": "forever" }), json!({ "name": "longer", "expires_in_days": 365 }), json!({ "name": "shorter", "expires_in_days": 1 })] {
+        let headers = [(api_key.clone(), parent_key), (header::CONTENT_TYPE, "application/json")];
+        let (status, body) = app.request_raw(Method::POST, "/api-keys", None, &headers, Body::from(child.to_string())).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        match child["name"].as_str() {
+            Some("shorter") => assert!(body["expires_at"].as_str() < parent["expires_at"].as_str()),
+            _ => assert_eq!(body["expires_at"], parent["expires_at"]),
+        }
+    }
+}
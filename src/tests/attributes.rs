@@ -0,0 +1,131 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::support::TestApp;
+
+#[tokio::test]
+async fn attributes_are_validated_and_redacted() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+
+    let (status, _) = app
+        .request(Method::POST, "/attributes", Some(&token), Some(json!({
+            "name": "employee_id", "attribute_type": "string", "required": true, "is_unique": true, "pattern": "^E[0-9]+$",
+        })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = app
+        .request(Method::POST, "/attributes", Some(&token), Some(json!({ "name": "nickname", "attribute_type": "string", "visibility": "self" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let create = |username: &'static str, attributes: serde_json::Value| {
+        let user_id = Uuid::new_v4();
+        (user_id, json!({ "user_id": user_id, "username": username, "attributes": attributes }))
+    };
+
+    let (_, missing) = create("oscar", json!({ "nickname": "oz" }));
+    let (status, body) = app.request(Method::POST, "/users", Some(&token), Some(missing)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "Attribute employee_id is required");
+
+    let (_, malformed) = create("oscar", json!({ "employee_id": "X1" }));
+    let (status, _) = app.request(Method::POST, "/users", Some(&token), Some(malformed)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (user_id, valid) = create("oscar", json!({ "employee_id": "E1", "nickname": "oz" }));
+    let (status, _) = app.request(Method::POST, "/users", Some(&token), Some(valid)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, duplicate) = create("peggy", json!({ "employee_id": "E1" }));
+    let (status, body) = app.request(Method::POST, "/users", Some(&token), Some(duplicate)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "Attribute employee_id must be unique");
+
+    // Owners only see attributes with self visibility, other users see none
+    let uri = format!("/users/{user_id}");
+    let owner_token = app.oidc.mint_token(user_id, "oscar", &["user"]);
+    let (_, body) = app.request(Method::GET, &uri, Some(&owner_token), None).await;
+    assert_eq!(body["attributes"], json!({ "nickname": "oz" }));
+    let (_, body) = app.request(Method::GET, &uri, Some(&app.user_token()), None).await;
+    assert_eq!(body["attributes"], json!({}));
+
+    // Null removes an optional attribute on update
+    let (status, body) = app
+        .request(Method::PUT, &uri, Some(&token), Some(json!({ "user_id": user_id, "attributes": { "nickname": null } })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["attributes"], json!({ "employee_id": "E1" }));
+
+    let (_, api) = app.request(Method::GET, "/api.json", None, None).await;
+    let schema = &api["components"]["schemas"]["User"]["properties"]["attributes"];
+    assert_eq!(schema["properties"]["employee_id"]["pattern"], "^E[0-9]+$");
+    assert_eq!(schema["required"], json!(["employee_id"]));
+
+    // The cached schema is rebuilt once a definition changes
+    assert_eq!(app.request(Method::DELETE, "/attributes/nickname", Some(&token), None).await.0, StatusCode::ACCEPTED);
+    let (_, api) = app.request(Method::GET, "/api.json", None, None).await;
+    let schema = &api["components"]["schemas"]["User"]["properties"]["attributes"];
+    assert!(schema["properties"].get("nickname").is_none());
+}
+
+#[tokio::test]
+async fn unique_attribute_over_duplicate_values_is_rejected() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    for username in ["rita", "ron"] {
+        app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": Uuid::new_v4(), "username": username }))).await;
+    }
+    // Values stored before the attribute was defined
+    sqlx::query(r#"UPDATE users SET attributes = '{"desk": "D1"}'"#).execute(&app.config.pgpool).await.unwrap();
+
+    let unique_desk = json!({ "name": "desk", "attribute_type": "string", "is_unique": true });
+    let (status, body) = app.request(Method::POST, "/attributes", Some(&token), Some(unique_desk.clone())).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "Existing users share values for this attribute");
+    let (_, definitions) = app.request(Method::GET, "/attributes", Some(&token), None).await;
+    assert_eq!(definitions, json!([]));
+    let leftover: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_indexes WHERE schemaname = current_schema() AND indexname = 'users_attribute_unique_desk')")
+        .fetch_one(&app.config.pgpool)
+        .await
+        .unwrap();
+    assert!(!leftover);
+
+    sqlx::query(r#"UPDATE users SET attributes = jsonb_build_object('desk', username)"#).execute(&app.config.pgpool).await.unwrap();
+    assert_eq!(app.request(Method::POST, "/attributes", Some(&token), Some(unique_desk)).await.0, StatusCode::CREATED);
+
+    // The index is dropped once the values are removed
+    assert_eq!(app.request(Method::DELETE, "/attributes/desk", Some(&token), None).await.0, StatusCode::ACCEPTED);
+    let leftover: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_indexes WHERE schemaname = current_schema() AND indexname = 'users_attribute_unique_desk')")
+        .fetch_one(&app.config.pgpool)
+        .await
+        .unwrap();
+    assert!(!leftover);
+}
+
+#[tokio::test]
+async fn null_attributes_only_remove_top_level_keys() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    for name in ["nickname", "team"] {
+        let (status, _) = app.request(Method::POST, "/attributes", Some(&token), Some(json!({ "name": name, "attribute_type": "string" }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    let user_id = Uuid::new_v4();
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "sam", "attributes": { "nickname": "s", "team": "red" } })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    // Values stored outside the API may hold nested nulls
+    sqlx::query(r#"UPDATE users SET attributes = attributes || '{"legacy": {"manager": null, "floor": 3}}'"#)
+        .execute(&app.config.pgpool)
+        .await
+        .unwrap();
+
+    let (status, body) = app
+        .request(Method::PUT, &format!("/users/{user_id}"), Some(&token), Some(json!({ "user_id": user_id, "attributes": { "nickname": null, "team": "blue" } })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["attributes"], json!({ "team": "blue", "legacy": { "manager": null, "floor": 3 } }));
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use axum::{body::Body, http::{header, Method, Request, StatusCode}};
+use serde_json::json;
+use tower::ServiceExt;
+
+use super::support::TestApp;
+
+#[tokio::test]
+async fn login_returns_tokens_from_identity_provider() {
+    let app = TestApp::spawn().await;
+    app.oidc.register_account("alice", "correct-horse", &["user"]);
+
+    let (status, body) = app
+        .request(Method::POST, "/login", None, Some(json!({ "username": "alice", "password": "correct-horse" })))
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["token_type"], "Bearer");
+    assert!(body["access_token"].as_str().is_some_and(|token| !token.is_empty()));
+}
+
+#[tokio::test]
+async fn protected_routes_reject_missing_token() {
+    let app = TestApp::spawn().await;
+
+    let (status, _) = app.request(Method::GET, "/users/6f1c3c9e-8f1a-4b8e-9d7a-3f4e2b1c0d9a", None, None).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn protected_routes_require_user_role() {
+    let app = TestApp::spawn().await;
+    let token = app.token(&["guest"]);
+
+    let (status, _) = app.request(Method::GET, "/users/6f1c3c9e-8f1a-4b8e-9d7a-3f4e2b1c0d9a", Some(&token), None).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn expect_admin_rejects_regular_users() {
+    let app = TestApp::spawn().await;
+    let token = app.user_token();
+
+    let (status, body) = app
+        .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": "6f1c3c9e-8f1a-4b8e-9d7a-3f4e2b1c0d9a", "username": "bob" })))
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "insufficient privileges");
+}
+
+#[tokio::test]
+async fn client_credentials_are_passed_through() {
+    let app = TestApp::spawn().await;
+
+    let (status, body) = app
+        .request(Method::POST, "/token", None, Some(json!({ "client_id": "api-client", "client_secret": "test-client-secret" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["token_type"], "Bearer");
+    assert!(body["access_token"].as_str().is_some_and(|token| !token.is_empty()));
+
+    let (status, _) = app
+        .request(Method::POST, "/token", None, Some(json!({ "client_id": "api-client", "client_secret": "wrong" })))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn local_provider_issues_and_validates_its_own_tokens() {
+    let app = TestApp::spawn_with_database_env(&[
+        ("AUTH_PROVIDER", "local"),
+        ("LOCAL_ADMIN_USERNAME", "root"),
+        ("LOCAL_ADMIN_PASSWORD", "bootstrap-pass"),
+    ]).await;
+
+    let (status, _) = app
+        .request(Method::POST, "/login", None, Some(json!({ "username": "root", "password": "wrong" })))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app
+        .request(Method::POST, "/login", None, Some(json!({ "username": "nobody", "password": "wrong" })))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = app
+        .request(Method::POST, "/login", None, Some(json!({ "username": "root", "password": "bootstrap-pass" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let admin = body["access_token"].as_str().unwrap().to_string();
+
+    // Keycloak tokens mean nothing to the local provider
+    let (status, _) = app.request(Method::GET, "/attributes", Some(&app.admin_token()), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Invited users get a local account
+    let (status, issued) = app
+        .request(Method::POST, "/invitations", Some(&admin), Some(json!({ "email": "olivia@example.com", "username": "olivia" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, user) = app
+        .request(Method::POST, "/invitations/accept", None, Some(json!({ "token": issued["token"], "password": "olivia-pass" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) = app
+        .request(Method::POST, "/login", None, Some(json!({ "username": "olivia", "password": "olivia-pass" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let olivia = body["access_token"].as_str().unwrap().to_string();
+
+    let (status, _) = app.request(Method::GET, &format!("/users/{}", user["user_id"].as_str().unwrap()), Some(&olivia), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request(Method::DELETE, &format!("/users/{}", user["user_id"].as_str().unwrap()), Some(&olivia), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request(Method::POST, "/token", None, Some(json!({ "client_id": "api-client", "client_secret": "test-client-secret" })))
+        .await;
+    assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+}
+
+async fn login(app: &TestApp, username: &str, password: &str, ip: &str) -> (StatusCode, Option<String>) {
+    let body = json!({ "username": username, "password": password }).to_string();
+    let response = app
+        .router
+        .clone()
+        .oneshot(
+            Request::post("/login")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-forwarded-for", ip)
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let retry_after = response.headers().get(header::RETRY_AFTER).map(|value| value.to_str().unwrap().to_string());
+    (response.status(), retry_after)
+}
+
+#[tokio::test]
+async fn repeated_login_failures_lock_out_the_username() {
+    let app = TestApp::spawn_with_env(&[("LOGIN_USER_MAX_FAILURES", "3"), ("LOGIN_LOCKOUT_SECS", "1")]).await;
+    app.oidc.register_account("alice", "correct-horse", &["user"]);
+    app.oidc.register_account("bob", "battery-staple", &["user"]);
+
+    assert_eq!(login(&app, "alice", "wrong", "10.0.0.1").await, (StatusCode::FORBIDDEN, None));
+    assert_eq!(login(&app, "alice", "wrong", "10.0.0.1").await, (StatusCode::FORBIDDEN, None));
+    assert_eq!(login(&app, "ALICE", "wrong", "10.0.0.1").await, (StatusCode::TOO_MANY_REQUESTS, Some("1".to_string())));
+
+    // The lockout holds even for the right password, other usernames are unaffected
+    assert_eq!(login(&app, "alice", "correct-horse", "10.0.0.1").await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(login(&app, "bob", "battery-staple", "10.0.0.1").await.0, StatusCode::OK);
+
+    // The next lockout lasts twice as long
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    for _ in 0..2 {
+        assert_eq!(login(&app, "alice", "wrong", "10.0.0.1").await.0, StatusCode::FORBIDDEN);
+    }
+    assert_eq!(login(&app, "alice", "wrong", "10.0.0.1").await, (StatusCode::TOO_MANY_REQUESTS, Some("2".to_string())));
+}
+
+#[tokio::test]
+async fn repeated_login_failures_lock_out_the_ip() {
+    let app = TestApp::spawn_with_env(&[("TRUST_PROXY_HEADERS", "true"), ("LOGIN_IP_MAX_FAILURES", "2")]).await;
+    app.oidc.register_account("alice", "correct-horse", &["user"]);
+
+    assert_eq!(login(&app, "mallory", "guess", "10.0.0.1").await.0, StatusCode::FORBIDDEN);
+    assert_eq!(login(&app, "trent", "guess", "10.0.0.1").await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(login(&app, "alice", "correct-horse", "10.0.0.1").await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(login(&app, "alice", "correct-horse", "10.0.0.2").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn the_client_ip_is_read_from_the_trusted_end_of_x_forwarded_for() {
+    let app = TestApp::spawn_with_env(&[("TRUST_PROXY_HEADERS", "true"), ("LOGIN_IP_MAX_FAILURES", "2")]).await;
+    app.oidc.register_account("alice", "correct-horse", &["user"]);
+
+    // Entries left of the one appended by the proxy are made up by the client
+    assert_eq!(login(&app, "mallory", "guess", "1.1.1.1, 10.0.0.1").await.0, StatusCode::FORBIDDEN);
+    assert_eq!(login(&app, "trent", "guess", "2.2.2.2, 10.0.0.1").await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(login(&app, "alice", "correct-horse", "10.0.0.1, 10.0.0.2").await.0, StatusCode::OK);
+
+    // Behind two proxies the client is the second entry from the right
+    let app = TestApp::spawn_with_env(&[("TRUST_PROXY_HEADERS", "true"), ("TRUSTED_PROXY_HOPS", "2"), ("LOGIN_IP_MAX_FAILURES", "2")]).await;
+    app.oidc.register_account("alice", "correct-horse", &["user"]);
+    assert_eq!(login(&app, "mallory", "guess", "1.1.1.1, 10.0.0.1, 192.168.0.1").await.0, StatusCode::FORBIDDEN);
+    assert_eq!(login(&app, "trent", "guess", "2.2.2.2, 10.0.0.1, 192.168.0.1").await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(login(&app, "alice", "correct-horse", "10.0.0.2, 192.168.0.1").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn concurrent_login_attempts_cannot_exceed_the_failure_limit() {
+    let app = TestApp::spawn_with_env(&[("LOGIN_USER_MAX_FAILURES", "3")]).await;
+    app.oidc.register_account("alice", "correct-horse", &["user"]);
+
+    // Attempts are reserved before the provider answers, so only three guesses are checked
+    let attempts = (0..10).map(|_| login(&app, "alice", "wrong", "10.0.0.1"));
+    let statuses: Vec<StatusCode> = futures::future::join_all(attempts).await.into_iter().map(|(status, _)| status).collect();
+    assert_eq!(statuses.iter().filter(|status| **status == StatusCode::FORBIDDEN).count(), 2);
+    assert_eq!(statuses.iter().filter(|status| **status == StatusCode::TOO_MANY_REQUESTS).count(), 8);
+}
+
+#[tokio::test]
+async fn provider_outages_are_not_counted_as_failures() {
+    let app = TestApp::spawn_with_env(&[("KC_SERVER_ADDR", "http://127.0.0.1:1"), ("LOGIN_USER_MAX_FAILURES", "1")]).await;
+
+    for _ in 0..3 {
+        assert_eq!(login(&app, "alice", "correct-horse", "10.0.0.1").await.0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
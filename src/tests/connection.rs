@@ -0,0 +1,84 @@
+use std::{collections::HashMap, time::Instant};
+
+use reqwest::Url;
+
+use super::support::database::test_database_url;
+use crate::{config::EnvironmentVariables, database::connection::DatabaseOptions};
+
+fn options(variables: &[(&str, &str)]) -> anyhow::Result<DatabaseOptions> {
+    let mut values = HashMap::from([
+        ("DATABASE_HOST", "127.0.0.1"),
+        ("DATABASE_PORT", "5432"),
+        ("DATABASE_CREDS", "app_user:app_password"),
+        ("DATABASE_NAME", "app_db"),
+        ("MAX_POOL_CONNECTIONS", "5"),
+    ]);
+    values.extend(variables.iter().copied());
+    let env = EnvironmentVariables::from_lookup(|key| match values.get(key) {
+        Some(value) => Some(value.to_string()),
+        // Remaining required settings are irrelevant to the database
+        None => ["PORT", "SECRET", "HOSTNAME"].contains(&key).then(|| "unused".to_string()),
+    })?;
+    DatabaseOptions::from_env(&env)
+}
+
+#[test]
+fn credentials_are_not_parsed_as_a_url() {
+    let database = options(&[("DATABASE_CREDS", "app_user:p@ss/w:rd#?%"), ("DATABASE_NAME", "app db")]).unwrap();
+    let connect = database.connect_options("db.internal", 6432);
+
+    assert_eq!(connect.get_username(), "app_user");
+    assert_eq!(connect.get_host(), "db.internal");
+    assert_eq!(connect.get_port(), 6432);
+    assert_eq!(connect.get_database(), Some("app db"));
+    assert_eq!(connect.get_application_name(), Some("api-server-template"));
+    assert!(format!("{connect:?}").contains(r#"password: Some("p@ss/w:rd#?%")"#));
+
+    assert!(options(&[("DATABASE_MIN_CONNECTIONS", "6")]).is_err());
+    assert!(options(&[("DATABASE_CREDS", ":secret")]).is_err());
+}
+
+#[tokio::test]
+async fn startup_retries_until_the_deadline() {
+    let database = options(&[("DATABASE_PORT", "1"), ("DATABASE_CONNECT_DEADLINE_SECS", "2")]).unwrap();
+
+    let started = Instant::now();
+    let err = database.connect().await.unwrap_err().to_string();
+    // Backoff starts at 250 to 500ms and doubles, so a two second deadline allows at least two attempts
+    assert!(started.elapsed().as_millis() >= 700, "gave up after {:?}", started.elapsed());
+    assert!(!err.contains("after 1 attempt"), "{err}");
+}
+
+#[tokio::test]
+async fn unresponsive_servers_do_not_outlast_the_deadline() {
+    // Connections are queued by the kernel but never answered
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port().to_string();
+    let database = options(&[("DATABASE_PORT", &port), ("DATABASE_CONNECT_DEADLINE_SECS", "1")]).unwrap();
+
+    let started = Instant::now();
+    let err = database.connect().await.unwrap_err().to_string();
+    assert!(started.elapsed().as_millis() < 1500, "gave up after {:?}", started.elapsed());
+    assert!(err.contains("timed out"), "{err}");
+}
+
+#[tokio::test]
+async fn pool_settings_apply_to_every_session() {
+    let url = Url::parse(&test_database_url()).unwrap();
+    let port = url.port().unwrap_or(5432).to_string();
+    let creds = format!("{}:{}", url.username(), url.password().unwrap_or_default());
+    let database = options(&[
+        ("DATABASE_HOST", url.host_str().unwrap()),
+        ("DATABASE_PORT", &port),
+        ("DATABASE_CREDS", &creds),
+        ("DATABASE_NAME", url.path().trim_start_matches('/')),
+        ("DATABASE_STATEMENT_TIMEOUT_MS", "500"),
+        ("DATABASE_APPLICATION_NAME", "connection-test"),
+    ]).unwrap();
+    let pool = database.connect().await.unwrap();
+
+    let name: String = sqlx::query_scalar("SELECT current_setting('application_name')").fetch_one(&pool).await.unwrap();
+    assert_eq!(name, "connection-test");
+    let err = sqlx::query("SELECT pg_sleep(2)").execute(&pool).await.unwrap_err();
+    assert_eq!(err.as_database_error().and_then(|err| err.code()).as_deref(), Some("57014"));
+}
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::support::TestApp;
+
+async fn create_user(app: &TestApp, username: &str) -> Uuid {
+    let user_id = Uuid::new_v4();
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&app.admin_token()), Some(json!({ "user_id": user_id, "username": username })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    user_id
+}
+
+async fn create_group(app: &TestApp, name: &str, parent_id: Option<&str>) -> String {
+    let (status, body) = app
+        .request(Method::POST, "/groups", Some(&app.admin_token()), Some(json!({ "name": name, "parent_id": parent_id })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    body["group_id"].as_str().unwrap().to_string()
+}
+
+// Keycloak sync runs in the background, wait until the mock reflects it
+async fn eventually(condition: impl Fn() -> bool) {
+    for _ in 0..200 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("Condition was not met in time");
+}
+
+#[tokio::test]
+async fn group_tree_and_memberships() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.admin_token();
+
+    let engineering = create_group(&app, "Engineering", None).await;
+    let platform = create_group(&app, "Platform", Some(&engineering)).await;
+
+    let (status, _) = app
+        .request(Method::POST, "/groups", Some(&admin), Some(json!({ "name": "platform", "parent_id": engineering })))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, body) = app.request(Method::GET, "/groups", Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["items"][0]["name"], "Engineering");
+
+    let (_, body) = app.request(Method::GET, &format!("/groups?parent_id={engineering}"), Some(&admin), None).await;
+    assert_eq!(body["items"][0]["group_id"], platform.as_str());
+
+    // Owners manage members, plain members cannot
+    let alice = create_user(&app, "alice").await;
+    let bob = create_user(&app, "bob").await;
+    let members = format!("/groups/{platform}/members");
+
+    let (status, _) = app.request(Method::PUT, &format!("{members}/{alice}"), Some(&admin), Some(json!({ "role": "owner" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = app.request(Method::PUT, &format!("{members}/{alice}"), Some(&admin), Some(json!({ "role": "owner" }))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app.request(Method::PUT, &format!("{members}/{bob}"), Some(&app.subject_token(alice)), Some(json!({}))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _) = app.request(Method::PUT, &format!("{members}/{alice}"), Some(&app.subject_token(bob)), Some(json!({ "role": "member" }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Membership in a subgroup makes every ancestor visible as inherited
+    let (status, body) = app.request(Method::GET, "/me/groups", Some(&app.subject_token(bob)), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let groups = body.as_array().unwrap();
+    assert_eq!(groups.len(), 2);
+    let engineering_entry = groups.iter().find(|group| group["group_id"] == engineering.as_str()).unwrap();
+    assert_eq!(engineering_entry["inherited"], true);
+    assert_eq!(engineering_entry["role"], Value::Null);
+    let platform_entry = groups.iter().find(|group| group["group_id"] == platform.as_str()).unwrap();
+    assert_eq!(platform_entry["role"], "member");
+
+    let (_, body) = app.request(Method::GET, &format!("/groups/{engineering}/members"), Some(&admin), None).await;
+    assert_eq!(body["total"], 0);
+    let (_, body) = app.request(Method::GET, &format!("/groups/{engineering}/members?recursive=true"), Some(&admin), None).await;
+    assert_eq!(body["total"], 2);
+
+    let (status, _) = app.request(Method::GET, &format!("/groups/{engineering}"), Some(&app.subject_token(bob)), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request(Method::PUT, &format!("/groups/{engineering}"), Some(&admin), Some(json!({ "parent_id": platform })))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = app.request(Method::DELETE, &format!("{members}/{bob}"), Some(&app.subject_token(bob)), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    // Deleting a group removes its subgroups
+    let (status, _) = app.request(Method::DELETE, &format!("/groups/{engineering}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let (status, _) = app.request(Method::GET, &format!("/groups/{platform}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn groups_sync_to_keycloak() {
+    let app = TestApp::spawn_with_database_env(&[("KC_GROUP_SYNC", "true")]).await;
+    let admin = app.admin_token();
+
+    let engineering = create_group(&app, "Engineering", None).await;
+    let platform = create_group(&app, "Platform", Some(&engineering)).await;
+    let alice = create_user(&app, "alice").await;
+    let kc_alice = app.oidc.register_account("alice", "secret", &["user"]);
+
+    let (status, _) = app
+        .request(Method::PUT, &format!("/groups/{platform}/members/{alice}"), Some(&admin), Some(json!({ "role": "member" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Groups are mirrored below the group of their organization and members are added by their Keycloak id
+    eventually(|| app.oidc.memberships().len() == 1).await;
+    let groups = app.oidc.groups();
+    assert_eq!(groups.len(), 3);
+    let (org_id, org) = groups.iter().find(|(_, group)| group.name == "default").unwrap();
+    assert_eq!(org.parent, None);
+    let (parent_id, parent) = groups.iter().find(|(_, group)| group.name == "Engineering").unwrap();
+    assert_eq!(parent.parent.as_ref(), Some(org_id));
+    let (child_id, child) = groups.iter().find(|(_, group)| group.name == "Platform").unwrap();
+    assert_eq!(child.parent.as_ref(), Some(parent_id));
+    assert!(app.oidc.memberships().contains(&(kc_alice.to_string(), child_id.clone())));
+
+    let (status, _) = app
+        .request(Method::PUT, &format!("/groups/{platform}"), Some(&admin), Some(json!({ "name": "Platform Team" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    eventually(|| app.oidc.groups().values().any(|group| group.name == "Platform Team")).await;
+
+    let (status, _) = app.request(Method::DELETE, &format!("/groups/{engineering}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    eventually(|| app.oidc.groups().len() == 1).await;
+}
+
+#[tokio::test]
+async fn synced_groups_are_namespaced_per_organization() {
+    let app = TestApp::spawn_with_database_env(&[("KC_GROUP_SYNC", "true")]).await;
+    let admin = app.admin_token();
+
+    for slug in ["acme", "globex"] {
+        let (status, _) = app
+            .request(Method::POST, "/organizations", Some(&admin), Some(json!({ "slug": slug, "name": slug })))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let token = app.oidc
+            .mint_token_with_claims(Uuid::new_v4(), "owner", &["user", "administrator"], json!({ "organization": slug }));
+        let (status, _) = app.request(Method::POST, "/groups", Some(&token), Some(json!({ "name": "Engineering" }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    // Both organizations get their own Engineering group
+    eventually(|| app.oidc.groups().len() == 4).await;
+    let groups = app.oidc.groups();
+    let organization = |slug: &str| groups.iter().find(|(_, group)| group.name == slug).map(|(id, _)| id.clone());
+    let (acme, globex) = (organization("acme"), organization("globex"));
+    let parents: Vec<_> = groups.values().filter(|group| group.name == "Engineering").map(|group| group.parent.clone()).collect();
+    assert_eq!(parents.len(), 2);
+    assert!(parents.contains(&acme) && parents.contains(&globex));
+
+    // Renaming the organization renames its Keycloak group
+    let (status, _) = app
+        .request(Method::PUT, "/organizations/acme", Some(&admin), Some(json!({ "slug": "acme-corp" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    eventually(|| app.oidc.groups().values().any(|group| group.name == "acme-corp")).await;
+}
@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use aide::axum::{routing::get, ApiRouter};
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use super::support::TestApp;
+use crate::middleware::hardening::{harden, HttpOptions};
+
+// Send a request, returning the status, headers and JSON body
+async fn send(router: &Router, request: Request<Body>) -> (StatusCode, axum::http::HeaderMap, Value) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, headers, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn default_stack_sets_security_headers_and_limits_bodies() {
+    let app = TestApp::spawn_with_env(&[("HTTP_BODY_LIMIT_BYTES", "64")]).await;
+
+    let (status, headers, _) = send(&app.router, Request::get("/").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+    // HSTS is only sent over TLS, forwarded headers are ignored unless the proxy is trusted
+    assert!(headers.get(header::STRICT_TRANSPORT_SECURITY).is_none());
+    let request = Request::get("/").header("x-forwarded-proto", "https").body(Body::empty()).unwrap();
+    let (_, headers, _) = send(&app.router, request).await;
+    assert!(headers.get(header::STRICT_TRANSPORT_SECURITY).is_none());
+    // CORS is disabled unless origins are configured
+    assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+    let body = json!({ "username": "a".repeat(100), "password": "secret" }).to_string();
+    let request = Request::post("/login").header(header::CONTENT_TYPE, "application/json").body(Body::from(body)).unwrap();
+    let (status, _, body) = send(&app.router, request).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body, json!({ "error": "Request body too large" }));
+
+    let request = Request::get("/metrics").header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+    let (status, headers, _) = send(&app.router, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+}
+
+#[tokio::test]
+async fn hsts_follows_the_scheme_reported_by_a_trusted_proxy() {
+    let app = TestApp::spawn_with_env(&[("TRUST_PROXY_HEADERS", "true")]).await;
+    let forwarded = |proto: &str| Request::get("/").header("x-forwarded-proto", proto).body(Body::empty()).unwrap();
+
+    let (_, headers, _) = send(&app.router, forwarded("https")).await;
+    assert_eq!(headers[header::STRICT_TRANSPORT_SECURITY], "max-age=31536000; includeSubDomains");
+    let (_, headers, _) = send(&app.router, forwarded("http")).await;
+    assert!(headers.get(header::STRICT_TRANSPORT_SECURITY).is_none());
+}
+
+#[tokio::test]
+async fn the_user_import_has_its_own_body_limit() {
+    let app = TestApp::spawn_with_database_env(&[("HTTP_BODY_LIMIT_BYTES", "64"), ("HTTP_IMPORT_BODY_LIMIT_BYTES", "4096")]).await;
+    let token = app.admin_token();
+    let import = |csv: String| {
+        app.request_raw(Method::POST, "/users/import", Some(&token), &[(header::CONTENT_TYPE, "text/csv")], Body::from(csv))
+    };
+
+    let rows: String = (0..10).map(|index| format!("{},imported-{index},\n", Uuid::new_v4())).collect();
+    let (status, body) = import(format!("user_id,username,email\n{rows}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["imported"], 10);
+
+    let (status, _) = import("x".repeat(5000)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn cors_allows_configured_origins() {
+    let app = TestApp::spawn_with_env(&[("HTTP_CORS_ORIGINS", "https://app.example.com")]).await;
+    let preflight = |origin: &str| {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/users")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization,idempotency-key")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, headers, _) = send(&app.router, preflight("https://app.example.com")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+    assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+    let (_, headers, _) = send(&app.router, preflight("https://evil.example.com")).await;
+    assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+    let request = Request::get("/").header(header::ORIGIN, "https://app.example.com").body(Body::empty()).unwrap();
+    let (_, headers, _) = send(&app.router, request).await;
+    assert!(headers[header::ACCESS_CONTROL_EXPOSE_HEADERS].to_str().unwrap().contains("ratelimit-remaining"));
+}
+
+async fn panicking() -> &'static str {
+    panic!("boom")
+}
+
+#[tokio::test]
+async fn panics_and_timeouts_return_json_errors() {
+    let routes = ApiRouter::new()
+        .api_route("/panic", get(panicking))
+        .api_route("/slow", get(|| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            "done"
+        }));
+    let options = HttpOptions {
+        request_timeout: Some(Duration::from_millis(50)),
+        catch_panic: true,
+        ..HttpOptions::default()
+    };
+    let router: Router = harden(routes, &options).into();
+
+    let (status, _, body) = send(&router, Request::get("/panic").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, json!({ "error": "Internal server error" }));
+
+    let (status, headers, body) = send(&router, Request::get("/slow").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(body, json!({ "error": "Request timed out" }));
+    // Security headers are off in these options
+    assert!(headers.get(header::X_FRAME_OPTIONS).is_none());
+}
@@ -0,0 +1,133 @@
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use super::support::TestApp;
+use crate::database::idempotency::{claim_idempotency_key, complete_idempotency_key, find_idempotency_key, renew_idempotency_key};
+
+// POST a JSON body with an Idempotency-Key, returns the status, the replay marker and the body
+async fn post_with_key(app: &TestApp, token: &str, key: &str, body: &Value) -> (StatusCode, bool, Value) {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/users")
+        .header(header::AUTHORIZATION, format!("Bearer {token}"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("idempotency-key", key)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let replayed = response.headers().get("idempotent-replayed").is_some();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, replayed, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn retries_replay_the_stored_response() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.oidc.mint_token(Uuid::new_v4(), "tester", &["user", "administrator"]);
+    let user = json!({ "user_id": Uuid::new_v4(), "username": "retried" });
+
+    let (status, replayed, first) = post_with_key(&app, &admin, "create-retried", &user).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(!replayed);
+
+    // The retry gets the original response instead of a duplicate key conflict
+    let (status, replayed, second) = post_with_key(&app, &admin, "create-retried", &user).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(replayed);
+    assert_eq!(first, second);
+
+    // Reusing the key for another payload is rejected
+    let other = json!({ "user_id": Uuid::new_v4(), "username": "other" });
+    let (status, _, _) = post_with_key(&app, &admin, "create-retried", &other).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Keys are scoped to the caller
+    let other_admin = app.oidc.mint_token(Uuid::new_v4(), "tester", &["user", "administrator"]);
+    let (status, replayed, _) = post_with_key(&app, &other_admin, "create-retried", &other).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(!replayed);
+
+    // Requests without a key behave as before
+    let (status, _) = app.request(Method::POST, "/users", Some(&admin), Some(user)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn concurrent_duplicates_run_once() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.oidc.mint_token(Uuid::new_v4(), "tester", &["user", "administrator"]);
+    let user_id = Uuid::new_v4();
+    let user = json!({ "user_id": user_id, "username": "concurrent" });
+
+    let (first, second) = tokio::join!(
+        post_with_key(&app, &admin, "create-concurrent", &user),
+        post_with_key(&app, &admin, "create-concurrent", &user),
+    );
+    let mut statuses = [first.0, second.0];
+    statuses.sort();
+    // The second request either finds the claim held or arrives late enough for a replay
+    assert!(
+        statuses == [StatusCode::CREATED, StatusCode::CONFLICT] || (statuses == [StatusCode::CREATED; 2] && first.1 != second.1),
+        "unexpected statuses {statuses:?}"
+    );
+
+    let (status, body) = app.request(Method::GET, &format!("/users/{user_id}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "concurrent");
+
+    // Oversized keys are refused
+    let (status, _, _) = post_with_key(&app, &admin, &"k".repeat(256), &user).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn keys_are_scoped_to_the_organization() {
+    let app = TestApp::spawn_with_database().await;
+    let (status, _) = app
+        .request(Method::POST, "/organizations", Some(&app.admin_token()), Some(json!({ "slug": "acme", "name": "Acme" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // One subject acting in two organizations gets a separate run in each
+    let subject = Uuid::new_v4();
+    let default = app.oidc.mint_token(subject, "tester", &["user", "administrator"]);
+    let acme = app.oidc.mint_token_with_claims(subject, "tester", &["user", "administrator"], json!({ "organization": "acme" }));
+    let user = json!({ "user_id": Uuid::new_v4(), "username": "scoped" });
+
+    let (status, replayed, _) = post_with_key(&app, &default, "create-scoped", &user).await;
+    assert_eq!((status, replayed), (StatusCode::CREATED, false));
+    let (status, replayed, _) = post_with_key(&app, &acme, "create-scoped", &user).await;
+    assert_eq!((status, replayed), (StatusCode::CREATED, false));
+}
+
+#[tokio::test]
+async fn running_requests_keep_their_claim() {
+    let app = TestApp::spawn_with_database().await;
+    let pool = &app.config.pgpool;
+    let lock = Duration::from_secs(1);
+    let ttl = Duration::from_secs(60);
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+    // A renewed lock is not taken over
+    assert!(claim_idempotency_key("scope", "renewed", first, b"fingerprint", lock, ttl, pool).await.unwrap());
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(renew_idempotency_key("scope", "renewed", first, lock, pool).await.unwrap());
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(!claim_idempotency_key("scope", "renewed", second, b"fingerprint", lock, ttl, pool).await.unwrap());
+
+    // Once taken over, the previous request can neither renew nor complete the key
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(claim_idempotency_key("scope", "renewed", second, b"fingerprint", lock, ttl, pool).await.unwrap());
+    assert!(!renew_idempotency_key("scope", "renewed", first, lock, pool).await.unwrap());
+    complete_idempotency_key("scope", "renewed", first, 201, json!({}), b"stale", pool).await.unwrap();
+    let record = find_idempotency_key("scope", "renewed", pool).await.unwrap().unwrap();
+    assert_eq!(record.status_code, None);
+}
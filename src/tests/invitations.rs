@@ -0,0 +1,150 @@
+use axum::http::{Method, StatusCode};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde_json::{json, Value};
+
+use super::support::TestApp;
+use crate::custom::signing;
+
+async fn invite(app: &TestApp, email: &str, username: &str) -> Value {
+    let (status, body) = app
+        .request(Method::POST, "/invitations", Some(&app.admin_token()), Some(json!({ "email": email, "username": username })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    body
+}
+
+async fn accept(app: &TestApp, token: &str) -> (StatusCode, Value) {
+    app.request(Method::POST, "/invitations/accept", None, Some(json!({ "token": token, "password": "s3cret-pass" }))).await
+}
+
+#[tokio::test]
+async fn invitation_creates_user_once() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.admin_token();
+
+    let (status, _) = app
+        .request(Method::POST, "/invitations", Some(&admin), Some(json!({ "email": "frank@example.com", "expires_in_hours": 0 })))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let issued = invite(&app, "frank@example.com", "frank").await;
+    assert_eq!(issued["invitation"]["status"], "pending");
+    let token = issued["token"].as_str().unwrap();
+
+    let (status, _) = app
+        .request(Method::POST, "/invitations", Some(&admin), Some(json!({ "email": "FRANK@example.com" })))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Any change to the token invalidates the signature
+    let tampered = format!("{}{}", if token.starts_with('e') { 'f' } else { 'e' }, &token[1..]);
+    let (status, _) = accept(&app, &tampered).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Tokens are signed with a key derived for invitations, never with SECRET itself
+    let (payload, _) = token.split_once('.').unwrap();
+    let payload = URL_SAFE_NO_PAD.decode(payload).unwrap();
+    let (status, _) = accept(&app, &signing::sign(&app.config.env.secret, &payload)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, user) = accept(&app, token).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(user["username"], "frank");
+    assert_eq!(user["email"], "frank@example.com");
+
+    let (status, _) = app.request(Method::GET, &format!("/users/{}", user["user_id"].as_str().unwrap()), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The account exists in the identity provider as well
+    let (status, _) = app
+        .request(Method::POST, "/login", None, Some(json!({ "username": "frank", "password": "s3cret-pass" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = accept(&app, token).await;
+    assert_eq!(status, StatusCode::GONE);
+
+    let (status, body) = app.request(Method::GET, "/invitations?status=accepted", Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["items"][0]["user_id"], user["user_id"]);
+}
+
+#[tokio::test]
+async fn resend_and_revoke_invalidate_tokens() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.admin_token();
+
+    let issued = invite(&app, "grace@example.com", "grace").await;
+    let invitation_id = issued["invitation"]["invitation_id"].as_str().unwrap();
+    let first_token = issued["token"].as_str().unwrap();
+
+    let (status, resent) = app.request(Method::POST, &format!("/invitations/{invitation_id}/resend"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let second_token = resent["token"].as_str().unwrap();
+    assert_ne!(first_token, second_token);
+
+    let (status, _) = accept(&app, first_token).await;
+    assert_eq!(status, StatusCode::GONE);
+
+    let (status, _) = app.request(Method::DELETE, &format!("/invitations/{invitation_id}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let (status, _) = accept(&app, second_token).await;
+    assert_eq!(status, StatusCode::GONE);
+
+    let (status, _) = app.request(Method::DELETE, &format!("/invitations/{invitation_id}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn invitees_only_fill_in_missing_required_attributes() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.admin_token();
+
+    for (name, required) in [("department", true), ("team", true), ("nickname", false)] {
+        let definition = json!({ "name": name, "attribute_type": "string", "required": required });
+        let (status, _) = app.request(Method::POST, "/attributes", Some(&admin), Some(definition)).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let (status, issued) = app
+        .request(Method::POST, "/invitations", Some(&admin), Some(json!({ "email": "ivan@example.com", "username": "ivan", "attributes": { "department": "ops" } })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let token = issued["token"].as_str().unwrap();
+    let accept_with = |attributes: Value| {
+        app.request(Method::POST, "/invitations/accept", None, Some(json!({ "token": token, "password": "s3cret-pass", "attributes": attributes })))
+    };
+
+    // Attributes chosen by the administrator and optional attributes cannot be set by the invitee
+    let (status, _) = accept_with(json!({ "department": "sales", "team": "blue" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = accept_with(json!({ "team": "blue", "nickname": "vanya" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, user) = accept_with(json!({ "team": "blue" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(user["attributes"], json!({ "department": "ops", "team": "blue" }));
+}
+
+#[tokio::test]
+async fn failed_account_creation_keeps_the_token_redeemable() {
+    let app = TestApp::spawn_with_database().await;
+    app.oidc.register_account("judy", "taken-pass", &["user"]);
+
+    let issued = invite(&app, "judy@example.com", "judy").await;
+    let token = issued["token"].as_str().unwrap();
+
+    let (status, _) = accept(&app, token).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, user) = app
+        .request(Method::POST, "/invitations/accept", None, Some(json!({ "token": token, "username": "judith", "password": "s3cret-pass" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(user["username"], "judith");
+
+    let (status, _) = accept(&app, token).await;
+    assert_eq!(status, StatusCode::GONE);
+}
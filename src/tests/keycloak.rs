@@ -0,0 +1,153 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::support::TestApp;
+
+#[tokio::test]
+async fn user_changes_propagate_to_keycloak() {
+    let app = TestApp::spawn_with_database_env(&[("KC_USER_SYNC", "true")]).await;
+    let admin = app.admin_token();
+
+    let user_id = Uuid::new_v4();
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&admin), Some(json!({ "user_id": user_id, "username": "grace", "email": "grace@example.com" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let kc_user = app.oidc.account("grace").unwrap();
+    assert_eq!(kc_user["enabled"], true);
+    let keycloak_id = kc_user["id"].clone();
+
+    // A username taken only in Keycloak removes the committed user again
+    app.oidc.register_account("heidi", "s3cret-pass", &["user"]);
+    let other_id = Uuid::new_v4();
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&admin), Some(json!({ "user_id": other_id, "username": "heidi" })))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = app.request(Method::GET, &format!("/users/{other_id}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Renames keep the account
+    let (status, _) = app
+        .request(Method::PUT, &format!("/users/{user_id}"), Some(&admin), Some(json!({ "user_id": user_id, "username": "grace2", "email": "grace@example.org" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let kc_user = app.oidc.account("grace2").unwrap();
+    assert_eq!((&kc_user["id"], &kc_user["email"]), (&keycloak_id, &json!("grace@example.org")));
+    assert!(app.oidc.account("grace").is_none());
+
+    // A change Keycloak refuses is undone locally
+    let (status, _) = app
+        .request(Method::PUT, &format!("/users/{user_id}"), Some(&admin), Some(json!({ "user_id": user_id, "username": "heidi", "email": "heidi@example.org" })))
+        .await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    let (_, body) = app.request(Method::GET, &format!("/users/{user_id}"), Some(&admin), None).await;
+    assert_eq!((body["username"].as_str(), body["email"].as_str()), (Some("grace2"), Some("grace@example.org")));
+
+    let (status, body) = app.request(Method::POST, &format!("/users/{user_id}/disable"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["enabled"], false);
+    assert_eq!(app.oidc.account("grace2").unwrap()["enabled"], false);
+
+    let (status, _) = app.request(Method::POST, &format!("/users/{user_id}/enable"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.oidc.account("grace2").unwrap()["enabled"], true);
+
+    let (status, _) = app.request(Method::DELETE, &format!("/users/{user_id}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(app.oidc.account("grace2").is_none());
+}
+
+#[tokio::test]
+async fn accounts_are_resolved_per_organization() {
+    let app = TestApp::spawn_with_database_env(&[("KC_USER_SYNC", "true")]).await;
+    let admin = app.admin_token();
+    let (status, _) = app
+        .request(Method::POST, "/organizations", Some(&admin), Some(json!({ "slug": "globex", "name": "Globex" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let globex = app.oidc.mint_token_with_claims(Uuid::new_v4(), "owner", &["user", "administrator"], json!({ "organization": "globex" }));
+
+    // Local user ids are only unique per organization, each user gets its own account
+    let user_id = Uuid::new_v4();
+    for (token, username) in [(&admin, "ken"), (&globex, "leo")] {
+        let (status, _) = app
+            .request(Method::POST, "/users", Some(token), Some(json!({ "user_id": user_id, "username": username })))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    let (status, _) = app
+        .request(Method::PUT, &format!("/users/{user_id}"), Some(&globex), Some(json!({ "user_id": user_id, "email": "leo@example.com" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.oidc.account("leo").unwrap()["email"], "leo@example.com");
+    assert_eq!(app.oidc.account("ken").unwrap()["email"], json!(null));
+
+    let (status, _) = app.request(Method::DELETE, &format!("/users/{user_id}"), Some(&globex), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(app.oidc.account("leo").is_none());
+    assert!(app.oidc.account("ken").is_some());
+
+    // Accounts created directly in Keycloak are found through the username of users that predate the sync
+    let keycloak_id = app.oidc.register_account("mia", "s3cret-pass", &["user"]);
+    let local_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO users (user_id, username, org_id) VALUES ($1, 'mia', '00000000-0000-0000-0000-000000000000')")
+        .bind(local_id)
+        .execute(&app.config.pgpool)
+        .await
+        .unwrap();
+    let (status, _) = app
+        .request(Method::PUT, &format!("/users/{local_id}"), Some(&admin), Some(json!({ "user_id": local_id, "username": "mia2" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.oidc.user(keycloak_id).unwrap()["username"], "mia2");
+
+    let (status, _) = app.request(Method::DELETE, &format!("/users/{local_id}"), Some(&admin), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(app.oidc.user(keycloak_id).is_none());
+}
+
+#[tokio::test]
+async fn reconciliation_reports_and_fixes_drift() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.admin_token();
+
+    // Without sync, local users never reach Keycloak
+    let missing = Uuid::new_v4();
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&admin), Some(json!({ "user_id": missing, "username": "ivan" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // A Keycloak user with its own id and a stale email, and one without a local row
+    let judy = app.oidc.register_account("judy", "s3cret-pass", &["user"]);
+    let stale = Uuid::new_v4();
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&admin), Some(json!({ "user_id": stale, "username": "judy", "email": "judy@example.com" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    app.oidc.register_account("mallory", "s3cret-pass", &["user"]);
+
+    let (status, report) = app.request(Method::POST, "/keycloak/reconcile", Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["checked"], 2);
+    assert_eq!(report["missing_in_keycloak"], json!([missing]));
+    assert_eq!(report["mismatched"], json!([{ "user_id": stale, "fields": ["email"] }]));
+    assert_eq!(report["missing_locally"].as_array().unwrap().len(), 1);
+    assert_eq!(report["missing_locally"][0]["username"], "mallory");
+    assert_eq!(report["fixed"], 0);
+    assert!(app.oidc.account("ivan").is_none());
+
+    let (status, report) = app.request(Method::POST, "/keycloak/reconcile?fix=true", Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["fixed"], 2);
+    assert!(app.oidc.account("ivan").is_some());
+    assert_eq!(app.oidc.user(judy).unwrap()["email"], "judy@example.com");
+
+    // Keycloak-only users are reported but never removed
+    let (_, report) = app.request(Method::POST, "/keycloak/reconcile", Some(&admin), None).await;
+    assert_eq!(report["missing_in_keycloak"], json!([]));
+    assert_eq!(report["mismatched"], json!([]));
+    assert_eq!(report["missing_locally"][0]["username"], "mallory");
+}
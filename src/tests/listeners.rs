@@ -0,0 +1,99 @@
+use std::{
+    fs,
+    os::{fd::OwnedFd, unix::fs::PermissionsExt},
+};
+
+use axum::{routing::get, Router};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+};
+use uuid::Uuid;
+
+use crate::server::{
+    listeners::{bind, inherited_listener, require_client_addresses, BoundListener},
+    serve,
+};
+
+fn app() -> Router {
+    Router::new().route("/", get(|| async { "hello" }))
+}
+
+// Plain HTTP/1.1 request over any stream, returns the status line
+async fn status_line<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> String {
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn unix_sockets_are_served_with_configured_permissions() {
+    let directory = std::env::temp_dir().join(format!("uds-{}", Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("api.sock");
+    let spec = format!("unix:{}", path.display());
+
+    let listeners = bind(&spec, "600", &mut Vec::new()).await.unwrap();
+    assert_eq!(listeners[0].describe(), spec);
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    // Without a peer address the client can only be known from a trusted proxy
+    assert!(require_client_addresses(&listeners, false).is_err());
+    assert!(require_client_addresses(&listeners, true).is_ok());
+    drop(listeners);
+
+    // The stale socket of the previous listener is replaced
+    let listener = bind(&spec, "660", &mut Vec::new()).await.unwrap().pop().unwrap();
+    tokio::spawn(serve(listener, app(), None));
+    assert_eq!(status_line(UnixStream::connect(&path).await.unwrap()).await, "HTTP/1.1 200 OK");
+
+    // Regular files are never removed
+    let file = directory.join("data.txt");
+    fs::write(&file, "keep").unwrap();
+    assert!(bind(&format!("unix:{}", file.display()), "660", &mut Vec::new()).await.is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "keep");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn ipv4_and_ipv6_share_a_port() {
+    let ipv4 = bind("127.0.0.1:0", "660", &mut Vec::new()).await.unwrap().pop().unwrap();
+    assert!(require_client_addresses(std::slice::from_ref(&ipv4), false).is_ok());
+    let BoundListener::Tcp(tcp) = &ipv4 else { panic!("expected a TCP listener") };
+    let port = tcp.local_addr().unwrap().port();
+    tokio::spawn(serve(ipv4, app(), None));
+
+    // Hosts without IPv6 can only check the IPv4 listener
+    if std::net::TcpListener::bind("[::1]:0").is_ok() {
+        let ipv6 = bind(&format!("[::1]:{port}"), "660", &mut Vec::new()).await.unwrap().pop().unwrap();
+        assert_eq!(ipv6.describe(), format!("[::1]:{port}"));
+        tokio::spawn(serve(ipv6, app(), None));
+        assert_eq!(status_line(TcpStream::connect(("::1", port)).await.unwrap()).await, "HTTP/1.1 200 OK");
+    }
+    assert_eq!(status_line(TcpStream::connect(("127.0.0.1", port)).await.unwrap()).await, "HTTP/1.1 200 OK");
+}
+
+#[tokio::test]
+async fn inherited_sockets_keep_their_family() {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    let listener = inherited_listener(OwnedFd::from(socket)).unwrap();
+    assert!(matches!(listener, BoundListener::Tcp(_)));
+    assert_eq!(listener.describe(), address.to_string());
+    tokio::spawn(serve(listener, app(), None));
+    assert_eq!(status_line(TcpStream::connect(address).await.unwrap()).await, "HTTP/1.1 200 OK");
+
+    let path = std::env::temp_dir().join(format!("inherited-{}.sock", Uuid::new_v4()));
+    let socket = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let listener = inherited_listener(OwnedFd::from(socket)).unwrap();
+    assert!(matches!(listener, BoundListener::Unix(_)));
+    fs::remove_file(path).unwrap();
+
+    // The systemd spec needs sockets taken from LISTEN_FDS at startup
+    assert!(bind("systemd", "660", &mut Vec::new()).await.is_err());
+
+    // Descriptors that are not sockets are rejected
+    let file = fs::File::open(env!("CARGO_MANIFEST_DIR")).unwrap();
+    assert!(inherited_listener(OwnedFd::from(file)).is_err());
+}
@@ -0,0 +1,22 @@
+mod support;
+
+mod admin;
+mod api_keys;
+mod attributes;
+mod auth;
+mod connection;
+mod groups;
+mod hardening;
+mod idempotency;
+mod invitations;
+mod keycloak;
+mod listeners;
+mod metrics;
+mod organizations;
+mod outbox;
+mod rate_limit;
+mod replicas;
+mod tls;
+mod transfer;
+mod unit_of_work;
+mod users;
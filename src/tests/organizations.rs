@@ -0,0 +1,224 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderName, Method, StatusCode},
+};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use super::support::TestApp;
+use crate::database::{begin_all_tenants, verify_tenant_rls};
+
+const TENANT_HEADER: HeaderName = HeaderName::from_static("x-tenant-id");
+
+// Administrator token bound to an organization through the tenant claim
+fn tenant_admin(app: &TestApp, tenant: &str) -> String {
+    app.oidc
+        .mint_token_with_claims(Uuid::new_v4(), &format!("{tenant}-admin"), &["user", "administrator"], json!({ "organization": tenant }))
+}
+
+// Send a JSON request on behalf of a tenant selected through the tenant header
+async fn tenant_request(app: &TestApp, method: Method, uri: &str, token: &str, tenant: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let (status, bytes) = match body {
+        Some(body) => {
+            app.request_raw(method, uri, Some(token), &[(TENANT_HEADER, tenant), (header::CONTENT_TYPE, "application/json")], Body::from(body.to_string()))
+                .await
+        },
+        None => app.request_raw(method, uri, Some(token), &[(TENANT_HEADER, tenant)], Body::empty()).await,
+    };
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn organization_crud_round_trip() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+
+    let (status, _) = app.request(Method::GET, "/organizations", Some(&app.user_token()), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request(Method::POST, "/organizations", Some(&token), Some(json!({ "slug": "Not A Slug", "name": "Acme" })))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = app
+        .request(Method::POST, "/organizations", Some(&token), Some(json!({ "slug": "acme", "name": "Acme" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let org_id = body["org_id"].as_str().unwrap().to_string();
+
+    let (status, _) = app
+        .request(Method::POST, "/organizations", Some(&token), Some(json!({ "slug": "acme", "name": "Acme again" })))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, body) = app
+        .request(Method::PUT, "/organizations/acme", Some(&token), Some(json!({ "name": "Acme Corporation" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "Acme Corporation");
+
+    let (status, body) = app.request(Method::GET, &format!("/organizations/{org_id}"), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["slug"], "acme");
+
+    let (status, _) = app.request(Method::DELETE, "/organizations/default", Some(&token), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Administrators of other organizations cannot manage organizations
+    let acme = tenant_admin(&app, "acme");
+    let (status, _) = app.request(Method::GET, "/organizations", Some(&acme), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app.request(Method::POST, "/attributes", Some(&acme), Some(json!({ "name": "badge", "attribute_type": "string" }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Organizations that still own users cannot be removed
+    let user_id = Uuid::new_v4();
+    let (status, _) = app.request(Method::POST, "/users", Some(&acme), Some(json!({ "user_id": user_id, "username": "wile" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _) = app.request(Method::DELETE, "/organizations/acme", Some(&token), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = app.request(Method::DELETE, &format!("/users/{user_id}"), Some(&acme), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let (status, _) = app.request(Method::DELETE, "/organizations/acme", Some(&token), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn users_are_isolated_per_tenant() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+
+    for slug in ["acme", "globex"] {
+        let (status, _) = app
+            .request(Method::POST, "/organizations", Some(&token), Some(json!({ "slug": slug, "name": slug })))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    let (acme, globex) = (tenant_admin(&app, "acme"), tenant_admin(&app, "globex"));
+
+    let user_id = Uuid::new_v4();
+    let uri = format!("/users/{user_id}");
+    let (status, _) = app.request(Method::POST, "/users", Some(&acme), Some(json!({ "user_id": user_id, "username": "roadrunner" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _) = tenant_request(&app, Method::GET, &uri, &acme, "acme", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Other tenants and the default organization cannot see or modify the user
+    let (status, _) = app.request(Method::GET, &uri, Some(&globex), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app.request(Method::DELETE, &uri, Some(&globex), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = app.request(Method::GET, "/users/search?q=roadrunner", Some(&globex), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 0);
+
+    // User ids are scoped per tenant, reusing one reveals nothing about other tenants
+    let (status, _) = app.request(Method::POST, "/users", Some(&globex), Some(json!({ "user_id": user_id, "username": "coyote" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, body) = app.request(Method::GET, &uri, Some(&acme), None).await;
+    assert_eq!(body["username"], "roadrunner");
+
+    // Headers cannot move a token into another organization, known or not
+    for tenant in ["acme", "unknown"] {
+        let (status, body) = tenant_request(&app, Method::GET, &uri, &globex, tenant, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "Token does not belong to this organization");
+    }
+}
+
+#[tokio::test]
+async fn tenant_claim_pins_the_token() {
+    let app = TestApp::spawn_with_database().await;
+    let admin = app.admin_token();
+
+    let (status, _) = app
+        .request(Method::POST, "/organizations", Some(&admin), Some(json!({ "slug": "acme", "name": "Acme" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let token = tenant_admin(&app, "acme");
+
+    // The claim selects the tenant without any header
+    let user_id = Uuid::new_v4();
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "tenant-user" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _) = tenant_request(&app, Method::GET, &format!("/users/{user_id}"), &token, "acme", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A header cannot move the token into another organization
+    let (status, _) = tenant_request(&app, Method::GET, &format!("/users/{user_id}"), &token, "default", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Tokens without the claim only reach the default organization
+    let (status, _) = tenant_request(&app, Method::GET, &format!("/users/{user_id}"), &admin, "acme", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = tenant_request(&app, Method::GET, &format!("/users/{user_id}"), &admin, "default", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // A claim naming no organization is refused
+    let stray = tenant_admin(&app, "initech");
+    let (status, _) = app.request(Method::GET, &format!("/users/{user_id}"), Some(&stray), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+// Superusers bypass row-level security, the policies are checked through a role that does not
+async fn grant_tenant_reader(pool: &Pool<Postgres>) {
+    let _ = sqlx::query("CREATE ROLE tenant_reader NOLOGIN").execute(pool).await;
+    let schema: String = sqlx::query_scalar("SELECT current_schema()").fetch_one(pool).await.unwrap();
+    sqlx::query(&format!(r#"GRANT USAGE ON SCHEMA "{schema}" TO tenant_reader"#)).execute(pool).await.unwrap();
+    sqlx::query("GRANT SELECT ON users TO tenant_reader").execute(pool).await.unwrap();
+}
+
+#[tokio::test]
+async fn row_level_security_hides_rows_without_a_tenant() {
+    let app = TestApp::spawn_with_database().await;
+    let pool = &app.config.pgpool;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+
+    let (status, _) = app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "ruth" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    grant_tenant_reader(pool).await;
+    let count = "SELECT COUNT(*) FROM users";
+    let mut tx = pool.begin().await.unwrap();
+    sqlx::query("SET LOCAL ROLE tenant_reader").execute(&mut *tx).await.unwrap();
+    let hidden: i64 = sqlx::query_scalar(count).fetch_one(&mut *tx).await.unwrap();
+    assert_eq!(hidden, 0);
+    tx.rollback().await.unwrap();
+
+    let mut tx = begin_all_tenants(pool).await.unwrap();
+    sqlx::query("SET LOCAL ROLE tenant_reader").execute(&mut *tx).await.unwrap();
+    let visible: i64 = sqlx::query_scalar(count).fetch_one(&mut *tx).await.unwrap();
+    assert_eq!(visible, 1);
+    tx.rollback().await.unwrap();
+}
+
+#[tokio::test]
+async fn startup_refuses_a_tenant_rls_flag_the_schema_disagrees_with() {
+    let app = TestApp::spawn_with_database().await;
+    let pool = &app.config.pgpool;
+
+    // The migrations force the policies
+    verify_tenant_rls(pool, true).await.unwrap();
+    let err = verify_tenant_rls(pool, false).await.unwrap_err();
+    assert!(err.to_string().contains("TENANT_RLS is false"));
+
+    sqlx::query("ALTER TABLE groups NO FORCE ROW LEVEL SECURITY").execute(pool).await.unwrap();
+    let err = verify_tenant_rls(pool, true).await.unwrap_err();
+    assert!(err.to_string().contains("not forced on groups"));
+}
@@ -0,0 +1,344 @@
+use std::{
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, Method, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{mpsc, Notify},
+};
+use uuid::Uuid;
+
+use super::support::TestApp;
+use crate::{
+    config::EnvironmentVariables,
+    custom::signing::digest,
+    definitions::outbox::OutboxEvent,
+    outbox::{relay_events, Outbox},
+};
+
+// Type, user and payload of every event in the outbox, oldest first
+async fn outbox_events(pool: &Pool<Postgres>) -> Vec<(String, Uuid, Value)> {
+    sqlx::query_as("SELECT event_type, aggregate_id, payload FROM outbox_events ORDER BY sequence")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn user_changes_write_events_with_the_change() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+    let uri = format!("/users/{user_id}");
+
+    let create = json!({ "user_id": user_id, "username": "olga", "email": "olga@example.com" });
+    assert_eq!(app.request(Method::POST, "/users", Some(&token), Some(create.clone())).await.0, StatusCode::CREATED);
+    assert_eq!(app.request(Method::PUT, &uri, Some(&token), Some(json!({ "user_id": user_id, "username": "olivia" }))).await.0, StatusCode::OK);
+    assert_eq!(app.request(Method::POST, &format!("{uri}/disable"), Some(&token), None).await.0, StatusCode::OK);
+
+    // Changes that are rolled back leave no event behind
+    assert_eq!(app.request(Method::POST, "/users", Some(&token), Some(create)).await.0, StatusCode::CONFLICT);
+    let batch = json!({ "mode": "atomic", "operations": [
+        { "op": "create", "user_id": Uuid::new_v4(), "username": "pavel" },
+        { "op": "delete", "user_id": Uuid::new_v4() },
+    ]});
+    assert_eq!(app.request(Method::POST, "/users:batch", Some(&token), Some(batch)).await.0, StatusCode::MULTI_STATUS);
+
+    assert_eq!(app.request(Method::DELETE, &uri, Some(&token), None).await.0, StatusCode::ACCEPTED);
+
+    let events = outbox_events(&app.config.pgpool).await;
+    let types: Vec<&str> = events.iter().map(|(event_type, _, _)| event_type.as_str()).collect();
+    assert_eq!(types, ["UserCreated", "UserUpdated", "UserUpdated", "UserDeleted"]);
+    assert!(events.iter().all(|(_, aggregate_id, _)| *aggregate_id == user_id));
+    assert_eq!(events[0].2["username"], "olga");
+    assert_eq!(events[1].2["username"], "olivia");
+    assert_eq!(events[1].2["email"], "olga@example.com");
+    assert_eq!(events[2].2["enabled"], false);
+    assert_eq!(events[3].2["user_id"], user_id.to_string());
+}
+
+#[tokio::test]
+async fn removing_an_attribute_writes_an_event_for_every_changed_user() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let definition = json!({ "name": "desk", "attribute_type": "string" });
+    assert_eq!(app.request(Method::POST, "/attributes", Some(&token), Some(definition)).await.0, StatusCode::CREATED);
+    let (with_desk, without_desk) = (Uuid::new_v4(), Uuid::new_v4());
+    let user = json!({ "user_id": with_desk, "username": "sven", "attributes": { "desk": "B12" } });
+    assert_eq!(app.request(Method::POST, "/users", Some(&token), Some(user)).await.0, StatusCode::CREATED);
+    let user = json!({ "user_id": without_desk, "username": "tove" });
+    assert_eq!(app.request(Method::POST, "/users", Some(&token), Some(user)).await.0, StatusCode::CREATED);
+
+    assert_eq!(app.request(Method::DELETE, "/attributes/desk", Some(&token), None).await.0, StatusCode::ACCEPTED);
+
+    let events = outbox_events(&app.config.pgpool).await;
+    assert_eq!(events.len(), 3);
+    let (event_type, aggregate_id, payload) = &events[2];
+    assert_eq!((event_type.as_str(), *aggregate_id), ("UserUpdated", with_desk));
+    assert_eq!(payload["username"], "sven");
+    assert_eq!(payload["attributes"], json!({}));
+    assert!(payload.get("org_id").is_none());
+}
+
+// Status answered by the webhook receiver and the requests it got
+type WebhookState = (Arc<AtomicU16>, mpsc::UnboundedSender<(HeaderMap, String)>);
+
+async fn receive_event(
+    State((status, received)): State<WebhookState>,
+    headers: HeaderMap,
+    body: String,
+) -> StatusCode {
+    received.send((headers, body)).unwrap();
+    StatusCode::from_u16(status.load(Ordering::SeqCst)).unwrap()
+}
+
+#[tokio::test]
+async fn webhook_deliveries_are_retried_then_dead_lettered() {
+    let status = Arc::new(AtomicU16::new(503));
+    let (received, mut deliveries) = mpsc::unbounded_channel();
+    let webhook = Router::new().route("/events", post(receive_event)).with_state((status.clone(), received));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/events", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, webhook).await });
+
+    let app = TestApp::spawn_with_database_env(&[
+        ("OUTBOX_SINKS", "webhook"),
+        ("OUTBOX_WEBHOOK_URL", &url),
+        ("OUTBOX_WEBHOOK_SECRET", "hook-secret"),
+        ("OUTBOX_MAX_ATTEMPTS", "2"),
+        ("OUTBOX_RETRY_DELAY_SECS", "0"),
+    ]).await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+    app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "quinn" }))).await;
+    app.request(Method::PUT, &format!("/users/{user_id}"), Some(&token), Some(json!({ "user_id": user_id, "username": "quincy" }))).await;
+    let (outbox, pool) = (&app.config.outbox, &app.config.pgpool);
+
+    // The update waits for the creation of the same user
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 1);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 1);
+    let (attempts, dead_lettered, last_error): (i32, bool, Option<String>) = sqlx::query_as(
+        "SELECT attempts, dead_lettered_at IS NOT NULL, last_error FROM outbox_events WHERE event_type = 'UserCreated'",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    assert_eq!((attempts, dead_lettered), (2, true));
+    assert!(last_error.unwrap().contains("503"));
+
+    // The dead letter holds back the following events of its user until it is queued again
+    status.store(200, Ordering::SeqCst);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 0);
+    let (reply, page) = app.request(Method::GET, "/outbox/dead-letters", Some(&token), None).await;
+    assert_eq!(reply, StatusCode::OK);
+    assert_eq!(page["total"], 1);
+    assert_eq!((page["items"][0]["event_type"].as_str(), page["items"][0]["attempts"].as_i64()), (Some("UserCreated"), Some(2)));
+    let event_id = page["items"][0]["event_id"].as_str().unwrap().to_string();
+    let (reply, _) = app.request(Method::POST, &format!("/outbox/dead-letters/{event_id}/requeue"), Some(&token), None).await;
+    assert_eq!(reply, StatusCode::ACCEPTED);
+    let (reply, _) = app.request(Method::POST, &format!("/outbox/dead-letters/{event_id}/requeue"), Some(&token), None).await;
+    assert_eq!(reply, StatusCode::NOT_FOUND);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 1);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 1);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 0);
+
+    let mut bodies = Vec::new();
+    while let Ok((headers, body)) = deliveries.try_recv() {
+        let signature = format!("sha256={}", STANDARD.encode(digest("hook-secret", body.as_bytes())));
+        assert_eq!(headers["x-event-signature"], signature.as_str());
+        bodies.push(serde_json::from_str::<Value>(&body).unwrap());
+    }
+    assert_eq!(bodies.len(), 4);
+    assert_eq!(bodies[0]["event_id"], bodies[1]["event_id"]);
+    assert_eq!(bodies[2]["event_id"], bodies[0]["event_id"]);
+    assert_eq!(bodies[3]["type"], "UserUpdated");
+    assert_eq!(bodies[3]["data"]["username"], "quincy");
+    let delivered: bool = sqlx::query_scalar("SELECT delivered_at IS NOT NULL FROM outbox_events WHERE event_type = 'UserUpdated'")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert!(delivered);
+}
+
+#[tokio::test]
+async fn discarded_dead_letters_release_the_events_they_held_back() {
+    let status = Arc::new(AtomicU16::new(503));
+    let (received, mut deliveries) = mpsc::unbounded_channel();
+    let webhook = Router::new().route("/events", post(receive_event)).with_state((status.clone(), received));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/events", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, webhook).await });
+
+    let app = TestApp::spawn_with_database_env(&[
+        ("OUTBOX_SINKS", "webhook"),
+        ("OUTBOX_WEBHOOK_URL", &url),
+        ("OUTBOX_MAX_ATTEMPTS", "1"),
+    ]).await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+    app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "rita" }))).await;
+    app.request(Method::PUT, &format!("/users/{user_id}"), Some(&token), Some(json!({ "user_id": user_id, "username": "rhea" }))).await;
+    let (outbox, pool) = (&app.config.outbox, &app.config.pgpool);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 1);
+    status.store(200, Ordering::SeqCst);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 0);
+
+    let (_, page) = app.request(Method::GET, "/outbox/dead-letters", Some(&token), None).await;
+    let event_id = page["items"][0]["event_id"].as_str().unwrap().to_string();
+
+    // Dead letters of other organizations can neither be seen nor discarded
+    let (reply, _) = app.request(Method::POST, "/organizations", Some(&token), Some(json!({ "slug": "umbrella", "name": "Umbrella" }))).await;
+    assert_eq!(reply, StatusCode::CREATED);
+    let other = app.oidc.mint_token_with_claims(Uuid::new_v4(), "owner", &["user", "administrator"], json!({ "organization": "umbrella" }));
+    let (_, page) = app.request(Method::GET, "/outbox/dead-letters", Some(&other), None).await;
+    assert_eq!(page["total"], 0);
+    let (reply, _) = app.request(Method::DELETE, &format!("/outbox/dead-letters/{event_id}"), Some(&other), None).await;
+    assert_eq!(reply, StatusCode::NOT_FOUND);
+
+    let (reply, _) = app.request(Method::DELETE, &format!("/outbox/dead-letters/{event_id}"), Some(&token), None).await;
+    assert_eq!(reply, StatusCode::ACCEPTED);
+    assert_eq!(relay_events(outbox, pool).await.unwrap(), 1);
+
+    let mut bodies = Vec::new();
+    while let Ok((_, body)) = deliveries.try_recv() {
+        bodies.push(serde_json::from_str::<Value>(&body).unwrap());
+    }
+    assert_eq!(bodies.len(), 2);
+    assert_eq!((bodies[1]["type"].as_str(), bodies[1]["data"]["username"].as_str()), (Some("UserUpdated"), Some("rhea")));
+}
+
+// Webhook receiver that answers once the test lets it
+async fn hold_event(State((arrived, release)): State<(mpsc::UnboundedSender<()>, Arc<Notify>)>) -> StatusCode {
+    arrived.send(()).unwrap();
+    release.notified().await;
+    StatusCode::OK
+}
+
+#[tokio::test]
+async fn events_are_leased_without_locking_them_during_delivery() {
+    let (arrived, mut deliveries) = mpsc::unbounded_channel();
+    let release = Arc::new(Notify::new());
+    let webhook = Router::new().route("/events", post(hold_event)).with_state((arrived, release.clone()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/events", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, webhook).await });
+
+    let app = TestApp::spawn_with_database_env(&[("OUTBOX_SINKS", "webhook"), ("OUTBOX_WEBHOOK_URL", &url)]).await;
+    let token = app.admin_token();
+    app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": Uuid::new_v4(), "username": "ulla" }))).await;
+
+    let config = app.config.clone();
+    let relay = tokio::spawn(async move { relay_events(&config.outbox, &config.pgpool).await });
+    deliveries.recv().await.unwrap();
+
+    // While the sink is called the row is not locked, and the lease keeps other relays away from it
+    let pool = &app.config.pgpool;
+    let update = sqlx::query("UPDATE outbox_events SET last_error = 'inspected'").execute(pool);
+    assert!(tokio::time::timeout(Duration::from_secs(2), update).await.is_ok_and(|result| result.is_ok()));
+    assert_eq!(relay_events(&app.config.outbox, pool).await.unwrap(), 0);
+
+    release.notify_one();
+    assert_eq!(relay.await.unwrap().unwrap(), 1);
+    let delivered: bool = sqlx::query_scalar("SELECT delivered_at IS NOT NULL FROM outbox_events").fetch_one(pool).await.unwrap();
+    assert!(delivered);
+}
+
+async fn receive_records(State(received): State<mpsc::UnboundedSender<(HeaderMap, Value)>>, headers: HeaderMap, Json(records): Json<Value>) -> Json<Value> {
+    received.send((headers, records)).unwrap();
+    Json(json!({ "offsets": [{ "partition": 0, "offset": 7, "error_code": null, "error": null }] }))
+}
+
+// JetStream server accepting one connection, it acknowledges every publish on the reply subject and sends
+// the CONNECT options and the published message back to the test
+async fn fake_jetstream(listener: TcpListener, published: mpsc::UnboundedSender<(Value, String, String)>) {
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut socket = BufReader::new(socket);
+    socket.get_mut().write_all(b"INFO {\"server_id\":\"test\",\"proto\":1,\"headers\":true,\"max_payload\":1048576}\r\n").await.unwrap();
+
+    let (mut connect, mut inbox_sid) = (Value::Null, String::new());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if socket.read_line(&mut line).await.unwrap() == 0 {
+            return;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["CONNECT", ..] => connect = serde_json::from_str(line.trim_end().strip_prefix("CONNECT ").unwrap()).unwrap(),
+            ["PING"] => socket.get_mut().write_all(b"PONG\r\n").await.unwrap(),
+            ["SUB", .., sid] => inbox_sid = sid.to_string(),
+            ["HPUB", subject, reply, _, total] => {
+                let mut message = vec![0; total.parse::<usize>().unwrap() + 2];
+                socket.read_exact(&mut message).await.unwrap();
+                let ack = "{\"stream\":\"USERS\",\"seq\":1}";
+                let reply = format!("MSG {reply} {inbox_sid} {}\r\n{ack}\r\n", ack.len());
+                socket.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                published.send((connect.clone(), subject.to_string(), String::from_utf8(message).unwrap())).unwrap();
+            },
+            _ => {},
+        }
+    }
+}
+
+#[tokio::test]
+async fn nats_and_kafka_sinks_publish_the_envelope() {
+    let nats = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let nats_url = format!("nats://relay:secret@{}", nats.local_addr().unwrap());
+    let (published, mut messages) = mpsc::unbounded_channel();
+    tokio::spawn(fake_jetstream(nats, published));
+
+    let (received, mut records) = mpsc::unbounded_channel();
+    let proxy = Router::new().route("/topics/{topic}", post(receive_records)).with_state(received);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, proxy).await });
+
+    let env = EnvironmentVariables::from_lookup(|key| match key {
+        "OUTBOX_SINKS" => Some("nats,kafka".to_string()),
+        "OUTBOX_NATS_URL" => Some(nats_url.clone()),
+        "OUTBOX_KAFKA_REST_URL" => Some(proxy_url.clone()),
+        "DATABASE_HOST" | "DATABASE_PORT" | "DATABASE_CREDS" | "DATABASE_NAME" | "PORT" | "SECRET" | "HOSTNAME" | "MAX_POOL_CONNECTIONS" => Some("unused".to_string()),
+        _ => None,
+    }).unwrap();
+    let outbox = Outbox::from_env(&env, &Client::new()).unwrap();
+    assert_eq!(outbox.sink_names(), ["nats", "kafka"]);
+
+    let event = OutboxEvent {
+        event_id: Uuid::new_v4(),
+        event_type: "UserCreated".to_string(),
+        aggregate_id: Uuid::new_v4(),
+        org_id: Uuid::nil(),
+        payload: json!({ "username": "rosa" }),
+        occurred_at: Utc::now(),
+        attempts: 0,
+    };
+    outbox.publish(&event).await.unwrap();
+
+    let (connect, subject, message) = messages.recv().await.unwrap();
+    assert_eq!((connect["user"].as_str(), connect["pass"].as_str()), (Some("relay"), Some("secret")));
+    assert_eq!(subject, "events.users.UserCreated");
+    let (headers, payload) = message.trim_end().split_once("\r\n\r\n").unwrap();
+    assert!(headers.contains(&format!("Nats-Msg-Id: {}", event.event_id)));
+    assert_eq!(serde_json::from_str::<Value>(payload).unwrap()["data"]["username"], "rosa");
+
+    let (headers, body) = records.recv().await.unwrap();
+    assert_eq!(headers["content-type"], "application/vnd.kafka.json.v2+json");
+    assert_eq!(body["records"][0]["key"], event.aggregate_id.to_string());
+    assert_eq!(body["records"][0]["value"]["event_id"], event.event_id.to_string());
+}
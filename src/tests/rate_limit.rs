@@ -0,0 +1,97 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+};
+use tower::ServiceExt;
+
+use super::support::TestApp;
+
+// Send a request and keep the response headers
+async fn send(app: &TestApp, uri: &str, token: Option<&str>) -> (StatusCode, HeaderMap) {
+    let mut builder = Request::builder().method(Method::GET).uri(uri);
+    if let Some(token) = token {
+        builder = builder.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    let response = app.router.clone().oneshot(builder.body(Body::empty()).unwrap()).await.unwrap();
+    (response.status(), response.headers().clone())
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+    headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default()
+}
+
+#[tokio::test]
+async fn public_routes_are_limited_per_client_ip() {
+    let app = TestApp::spawn_with_env(&[("RATE_LIMIT_PUBLIC", "2/60")]).await;
+
+    let (status, headers) = send(&app, "/", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header_value(&headers, "ratelimit-limit"), "2");
+    assert_eq!(header_value(&headers, "ratelimit-remaining"), "1");
+    assert_eq!(header_value(&headers, "ratelimit-policy"), "2;w=60");
+
+    let (status, headers) = send(&app, "/", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header_value(&headers, "ratelimit-remaining"), "0");
+
+    // An empty bucket refills one request every 30 seconds
+    let (status, headers) = send(&app, "/", None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header_value(&headers, "retry-after"), "30");
+    assert_eq!(header_value(&headers, "ratelimit-reset"), "30");
+
+    // Private routes have their own limits
+    let (status, headers) = send(&app, "/users/not-a-uuid", Some(&app.user_token())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(header_value(&headers, "ratelimit-limit"), "300");
+}
+
+#[tokio::test]
+async fn private_routes_are_limited_per_client_ip_before_authentication() {
+    let app = TestApp::spawn_with_env(&[("RATE_LIMIT_PRIVATE", "2/60"), ("RATE_LIMIT_PUBLIC", "off")]).await;
+
+    // Requests without valid credentials take from the same bucket as authenticated ones
+    let (status, headers) = send(&app, "/users/not-a-uuid", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(header_value(&headers, "ratelimit-remaining"), "1");
+    assert_eq!(send(&app, "/users/not-a-uuid", Some("not-a-token")).await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(send(&app, "/users/not-a-uuid", Some(&app.user_token())).await.0, StatusCode::TOO_MANY_REQUESTS);
+
+    let (status, headers) = send(&app, "/", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers.get("ratelimit-limit").is_none());
+}
+
+#[tokio::test]
+async fn private_routes_can_be_limited_per_subject() {
+    let app = TestApp::spawn_with_env(&[("RATE_LIMIT_IDENTITY", "1/60"), ("RATE_LIMIT_PUBLIC", "off")]).await;
+    let first = app.user_token();
+    let second = app.user_token();
+
+    // The tighter identity limit reports its headers instead of the per IP one
+    let (status, headers) = send(&app, "/users/not-a-uuid", Some(&first)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(header_value(&headers, "ratelimit-limit"), "1");
+    assert_eq!(header_value(&headers, "ratelimit-remaining"), "0");
+    assert_eq!(send(&app, "/users/not-a-uuid", Some(&first)).await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(send(&app, "/users/not-a-uuid", Some(&second)).await.0, StatusCode::BAD_REQUEST);
+
+    // Unauthenticated requests are rejected before reaching an identity bucket
+    let (status, headers) = send(&app, "/users/not-a-uuid", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(header_value(&headers, "ratelimit-limit"), "300");
+}
+
+#[tokio::test]
+async fn postgres_backend_shares_buckets() {
+    let app = TestApp::spawn_with_database_env(&[("RATE_LIMIT_BACKEND", "postgres"), ("RATE_LIMIT_PUBLIC", "2/60")]).await;
+
+    let (status, headers) = send(&app, "/", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header_value(&headers, "ratelimit-remaining"), "1");
+    assert_eq!(send(&app, "/", None).await.0, StatusCode::OK);
+
+    let (status, headers) = send(&app, "/", None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header_value(&headers, "ratelimit-remaining"), "0");
+}
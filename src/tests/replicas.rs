@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use sqlx::postgres::PgPoolOptions;
+use uuid::Uuid;
+
+use super::support::{database::TestDatabase, TestApp};
+use crate::database::replicas::{ReplicaOptions, ReplicaSet};
+
+// application_name of the connection serving a read of `subject`
+async fn served_by(replicas: &ReplicaSet, subject: &str) -> String {
+    let mut tx = replicas.begin_read(subject, Uuid::new_v4()).await.unwrap();
+    let name: String = sqlx::query_scalar("SELECT current_setting('application_name')").fetch_one(&mut *tx).await.unwrap();
+    tx.commit().await.unwrap();
+    name
+}
+
+#[tokio::test]
+async fn replicas_serve_reads_until_the_caller_writes() {
+    let database = TestDatabase::create().await;
+    let options = (*database.pool.connect_options()).clone();
+    let replica = PgPoolOptions::new().max_connections(2).connect_lazy_with(options.application_name("replica"));
+    let replicas = ReplicaSet::new(
+        database.pool.clone(),
+        vec![("replica".to_string(), replica)],
+        ReplicaOptions { sticky_for: Duration::from_secs(60), max_lag: Some(Duration::from_secs(10)), check_interval: Duration::from_secs(5) },
+    );
+
+    // Replicas only receive reads once a health check passed
+    assert_eq!(replicas.read_target("alice"), None);
+    replicas.check_health().await;
+    assert_eq!(replicas.healthy_count(), 1);
+
+    assert_eq!(served_by(&replicas, "alice").await, "replica");
+    replicas.record_write("alice");
+    assert_ne!(served_by(&replicas, "alice").await, "replica");
+    assert_eq!(served_by(&replicas, "bob").await, "replica");
+}
+
+#[tokio::test]
+async fn reads_fall_back_to_the_primary_when_replicas_are_down() {
+    let app = TestApp::spawn_with_database_env(&[("DATABASE_REPLICA_HOSTS", "127.0.0.1:1")]).await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "nia", "email": "nia@example.com" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // A different caller is not pinned to the primary by the write above
+    let (status, body) = app.request(Method::GET, &format!("/users/{user_id}"), Some(&app.admin_token()), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "nia");
+    let (status, _) = app.request(Method::GET, "/users/search?q=nia", Some(&app.admin_token()), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,69 @@
+use std::str::FromStr;
+
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Connection, Executor, PgConnection, Pool, Postgres,
+};
+use uuid::Uuid;
+
+// Connection string of the postgres instance used by database backed tests
+pub const TEST_DATABASE_URL: &str = "TEST_DATABASE_URL";
+
+pub fn test_database_url() -> String {
+    std::env::var(TEST_DATABASE_URL).unwrap_or_else(|_| panic!("{TEST_DATABASE_URL} must point at a PostgreSQL instance to run database tests"))
+}
+
+// Isolated schema holding a fresh copy of the migrations for a single test
+pub struct TestDatabase {
+    pub pool: Pool<Postgres>,
+    url: String,
+    schema: String,
+}
+
+impl TestDatabase {
+    // Database tests fail instead of passing silently when no test database has been configured
+    pub async fn create() -> Self {
+        let url = test_database_url();
+        let schema = format!("test_{}", Uuid::new_v4().simple());
+
+        let mut conn = PgConnection::connect(&url).await.expect("Failed to connect to test database");
+        conn.execute(format!(r#"CREATE SCHEMA "{schema}""#).as_str()).await.unwrap();
+        conn.close().await.unwrap();
+
+        // Keep public on the search path so extensions installed there stay visible
+        let options = PgConnectOptions::from_str(&url)
+            .unwrap()
+            .options([("search_path", format!("{schema},public"))]);
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await
+            .expect("Failed to create test pool");
+
+        sqlx::migrate!().run(&pool).await.expect("Failed to run migrations");
+
+        Self { pool, url, schema }
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let url = self.url.clone();
+        let schema = self.schema.clone();
+
+        // Drop runs outside of an async context, clean up on a dedicated runtime
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async move {
+                    if let Ok(mut conn) = PgConnection::connect(&url).await {
+                        let _ = conn.execute(format!(r#"DROP SCHEMA "{schema}" CASCADE"#).as_str()).await;
+                    }
+                });
+        })
+        .join()
+        .ok();
+    }
+}
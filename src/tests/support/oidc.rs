@@ -0,0 +1,485 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Form, Json, Router,
+};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+// Realm served by the mock, passed to the application as KC_REALM
+pub const REALM: &str = "api-template";
+pub const LOGIN_PATH: &str = "/realms/api-template/protocol/openid-connect/token";
+pub const CLIENT_ID: &str = "api-client";
+pub const CLIENT_SECRET: &str = "test-client-secret";
+
+// Test-only RSA key, the public half is published through the JWKS endpoint
+const SIGNING_KEY_PEM: &[u8] = include_bytes!("fixtures/oidc_signing_key.pem");
+const SIGNING_KEY_ID: &str = "test-key";
+const SIGNING_KEY_MODULUS: &str = "k_5sM0AiWCOWZt2O2qKmQjLcICbxyamjggfsO7fjfAMWL_8Dnl2qnzp4NrZyNQtrjStYxZWRUD05rzDzwj33aKu_jmOs8yvzd-lNmBlz5F_6esAqc9pQ2JpdEqpye6rn7n4XhsuNG9oI9c3nrfJ8EkglQXmmqgTLHDhyU3e8dyUqG8hKYOHSXtZ44_FKGf7wHSI9CUg9RwmIIVXmZqzvQRYzD3vqKqYcnzsRGzPExV4tBTE_p8_gzFpncVKr28QP_u2R7TzsPwZHRyxCKtX71M5-qQ0PMwZv-yrcabk7ZPpt_h6RhRyBXzh5M27dRA710M1o7a8rwbQXYM-OmR-9fQ";
+const SIGNING_KEY_EXPONENT: &str = "AQAB";
+
+// Account known to the mock token endpoint
+#[derive(Clone)]
+struct MockAccount {
+    subject: Uuid,
+    password: String,
+    roles: Vec<String>,
+    email: Option<String>,
+    enabled: bool,
+}
+
+impl MockAccount {
+    fn new(subject: Uuid, password: &str, roles: Vec<String>) -> Self {
+        Self { subject, password: password.to_string(), roles, email: None, enabled: true }
+    }
+
+    // Brief user representation of the admin API
+    fn representation(&self, username: &str) -> Value {
+        json!({
+            "id": self.subject,
+            "username": username,
+            "email": self.email,
+            "enabled": self.enabled,
+        })
+    }
+}
+
+// Group created through the mock admin API
+#[derive(Clone, Debug)]
+pub struct MockGroup {
+    pub name: String,
+    pub parent: Option<String>,
+}
+
+#[derive(Clone)]
+struct MockState {
+    issuer: String,
+    server_addr: String,
+    accounts: Arc<Mutex<HashMap<String, MockAccount>>>,
+    groups: Arc<Mutex<HashMap<String, MockGroup>>>,
+    // Pairs of Keycloak user id and group id
+    memberships: Arc<Mutex<HashSet<(String, String)>>>,
+}
+
+// Local OpenID Connect provider imitating a Keycloak realm
+pub struct MockOidc {
+    pub server_addr: String,
+    state: MockState,
+}
+
+impl MockOidc {
+    pub async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = format!("http://{}", listener.local_addr().unwrap());
+        let state = MockState {
+            issuer: format!("{server_addr}/realms/{REALM}"),
+            server_addr: server_addr.clone(),
+            accounts: Arc::new(Mutex::new(HashMap::new())),
+            groups: Arc::new(Mutex::new(HashMap::new())),
+            memberships: Arc::new(Mutex::new(HashSet::new())),
+        };
+
+        let router = Router::new()
+            .route(&format!("/realms/{REALM}/.well-known/openid-configuration"), get(discovery))
+            .route(&format!("/realms/{REALM}/protocol/openid-connect/certs"), get(certs))
+            .route(LOGIN_PATH, post(token))
+            .route(&format!("/admin/realms/{REALM}/groups"), post(admin_create_group))
+            .route(&format!("/admin/realms/{REALM}/groups/{{id}}"), put(admin_update_group).delete(admin_delete_group))
+            .route(&format!("/admin/realms/{REALM}/groups/{{id}}/children"), post(admin_create_child_group))
+            .route(&format!("/admin/realms/{REALM}/users/{{user}}/groups/{{group}}"), put(admin_add_member).delete(admin_remove_member))
+            .route(&format!("/admin/realms/{REALM}/users"), get(admin_list_users).post(admin_create_user))
+            .route(&format!("/admin/realms/{REALM}/users/{{id}}"), put(admin_update_user).delete(admin_delete_user))
+            .with_state(state.clone());
+
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        Self { server_addr, state }
+    }
+
+    // Register an account that can log in through the password grant
+    pub fn register_account(&self, username: &str, password: &str, roles: &[&str]) -> Uuid {
+        let subject = Uuid::new_v4();
+        self.state.accounts.lock().unwrap().insert(
+            username.to_string(),
+            MockAccount::new(subject, password, roles.iter().map(|role| role.to_string()).collect()),
+        );
+        subject
+    }
+
+    // Admin API representation of the user with the given id
+    pub fn user(&self, subject: Uuid) -> Option<Value> {
+        self.state.accounts
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, account)| account.subject == subject)
+            .map(|(username, account)| account.representation(username))
+    }
+
+    // Admin API representation of the user with the given username
+    pub fn account(&self, username: &str) -> Option<Value> {
+        self.state.accounts.lock().unwrap().get(username).map(|account| account.representation(username))
+    }
+
+    // Groups created through the admin API, keyed by their Keycloak id
+    pub fn groups(&self) -> HashMap<String, MockGroup> {
+        self.state.groups.lock().unwrap().clone()
+    }
+
+    // Memberships created through the admin API as pairs of user id and group id
+    pub fn memberships(&self) -> HashSet<(String, String)> {
+        self.state.memberships.lock().unwrap().clone()
+    }
+
+    // Mint a signed access token for an arbitrary subject and set of realm roles
+    pub fn mint_token(&self, subject: Uuid, username: &str, roles: &[&str]) -> String {
+        self.mint_token_with_claims(subject, username, roles, json!({}))
+    }
+
+    // Mint a token carrying additional claims, such as a tenant claim
+    pub fn mint_token_with_claims(&self, subject: Uuid, username: &str, roles: &[&str], extra: Value) -> String {
+        let roles: Vec<String> = roles.iter().map(|role| role.to_string()).collect();
+        sign(&self.state.issuer, subject, username, &roles, 300, extra)
+    }
+}
+
+fn sign(issuer: &str, subject: Uuid, username: &str, roles: &[String], expires_in: i64, extra: Value) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let mut claims = json!({
+        "exp": now + expires_in,
+        "iat": now,
+        "jti": Uuid::new_v4().to_string(),
+        "iss": issuer,
+        "aud": "account",
+        "sub": subject.to_string(),
+        "typ": "Bearer",
+        "azp": CLIENT_ID,
+        "realm_access": { "roles": roles },
+        "preferred_username": username,
+        "email": format!("{username}@example.com"),
+        "email_verified": true,
+    });
+    if let (Value::Object(claims), Value::Object(extra)) = (&mut claims, extra) {
+        claims.extend(extra);
+    }
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(SIGNING_KEY_ID.to_string());
+    let key = EncodingKey::from_rsa_pem(SIGNING_KEY_PEM).unwrap();
+    jsonwebtoken::encode(&header, &claims, &key).unwrap()
+}
+
+async fn discovery(State(state): State<MockState>) -> Json<Value> {
+    let base = format!("{}/protocol/openid-connect", state.issuer);
+    Json(json!({
+        "issuer": state.issuer,
+        "authorization_endpoint": format!("{base}/auth"),
+        "token_endpoint": format!("{base}/token"),
+        "jwks_uri": format!("{base}/certs"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }))
+}
+
+async fn certs() -> Json<Value> {
+    Json(json!({
+        "keys": [{
+            "kid": SIGNING_KEY_ID,
+            "kty": "RSA",
+            "alg": "RS256",
+            "use": "sig",
+            "n": SIGNING_KEY_MODULUS,
+            "e": SIGNING_KEY_EXPONENT,
+        }]
+    }))
+}
+
+async fn token(
+    State(state): State<MockState>,
+    Form(params): Form<HashMap<String, String>>,
+) -> (StatusCode, Json<Value>) {
+    let param = |name: &str| params.get(name).map(String::as_str).unwrap_or_default();
+
+    if param("client_id") != CLIENT_ID || param("client_secret") != CLIENT_SECRET {
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "invalid_client" })));
+    }
+
+    // Service account tokens carry no realm roles
+    if param("grant_type") == "client_credentials" {
+        let access_token = sign(&state.issuer, Uuid::new_v4(), &format!("service-account-{CLIENT_ID}"), &[], 300, json!({}));
+        return (
+            StatusCode::OK,
+            Json(json!({
+                "access_token": access_token,
+                "expires_in": 300,
+                "token_type": "Bearer",
+                "scope": "profile email",
+            })),
+        );
+    }
+
+    if param("grant_type") != "password" {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "unsupported_grant_type" })));
+    }
+
+    let account = state.accounts.lock().unwrap().get(param("username")).cloned();
+    match account {
+        Some(account) if account.password == param("password") && !account.enabled => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "invalid_grant",
+                "error_description": "Account disabled",
+            })),
+        ),
+        Some(account) if account.password == param("password") => {
+            let access_token = sign(&state.issuer, account.subject, param("username"), &account.roles, 300, json!({}));
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "access_token": access_token,
+                    "expires_in": 300,
+                    "refresh_expires_in": 1800,
+                    "refresh_token": Uuid::new_v4().to_string(),
+                    "token_type": "Bearer",
+                    "id_token": access_token,
+                    "not-before-policy": 0,
+                    "session_state": Uuid::new_v4().to_string(),
+                    "scope": param("scope"),
+                })),
+            )
+        }
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "invalid_grant",
+                "error_description": "Invalid user credentials",
+            })),
+        ),
+    }
+}
+
+// Users created through the admin API can log in with the password grant and hold the user role
+async fn admin_create_user(State(state): State<MockState>, headers: HeaderMap, Json(body): Json<Value>) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let username = body["username"].as_str().unwrap_or_default().to_string();
+    let mut accounts = state.accounts.lock().unwrap();
+    if accounts.contains_key(&username) {
+        return (StatusCode::CONFLICT, Json(json!({ "errorMessage": "User exists with same username" }))).into_response();
+    }
+
+    let subject = Uuid::new_v4();
+    accounts.insert(
+        username,
+        MockAccount {
+            email: body["email"].as_str().map(str::to_string),
+            enabled: body["enabled"].as_bool().unwrap_or(false),
+            ..MockAccount::new(subject, body["credentials"][0]["value"].as_str().unwrap_or_default(), vec!["user".to_string()])
+        },
+    );
+    let location = format!("{}/admin/realms/{REALM}/users/{subject}", state.server_addr);
+    (StatusCode::CREATED, [(header::LOCATION, location)]).into_response()
+}
+
+async fn admin_delete_user(State(state): State<MockState>, Path(id): Path<String>, headers: HeaderMap) -> StatusCode {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let mut accounts = state.accounts.lock().unwrap();
+    let before = accounts.len();
+    accounts.retain(|_, account| account.subject.to_string() != id);
+    match accounts.len() < before {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+// Users sorted by username, including the service account of the client like Keycloak does
+async fn admin_list_users(
+    State(state): State<MockState>,
+    headers: HeaderMap,
+    axum::extract::Query(params): axum::extract::Query<HashMap<String, String>>,
+) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    // `username` with `exact` only matches that username
+    let accounts = state.accounts.lock().unwrap();
+    let mut users: Vec<Value> = accounts
+        .iter()
+        .filter(|(username, _)| params.get("username").is_none_or(|exact| exact == *username))
+        .map(|(username, account)| account.representation(username))
+        .collect();
+    if params.contains_key("username") {
+        return Json(users).into_response();
+    }
+    users.push(json!({ "id": Uuid::nil(), "username": format!("service-account-{CLIENT_ID}"), "enabled": true }));
+    users.sort_by_key(|user| user["username"].as_str().unwrap_or_default().to_string());
+
+    let param = |name: &str, default: usize| params.get(name).and_then(|value| value.parse().ok()).unwrap_or(default);
+    let (first, max) = (param("first", 0), param("max", 100));
+    Json(users.into_iter().skip(first).take(max).collect::<Vec<_>>()).into_response()
+}
+
+async fn admin_update_user(
+    State(state): State<MockState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> StatusCode {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let mut accounts = state.accounts.lock().unwrap();
+    let Some(username) = accounts.iter().find(|(_, account)| account.subject.to_string() == id).map(|(username, _)| username.clone()) else {
+        return StatusCode::NOT_FOUND;
+    };
+    // Like Keycloak, usernames of other users cannot be taken over
+    if body["username"].as_str().is_some_and(|renamed| renamed != username && accounts.contains_key(renamed)) {
+        return StatusCode::CONFLICT;
+    }
+    let mut account = accounts.remove(&username).unwrap();
+    if let Some(email) = body.get("email") {
+        account.email = email.as_str().map(str::to_string);
+    }
+    if let Some(enabled) = body["enabled"].as_bool() {
+        account.enabled = enabled;
+    }
+    let username = body["username"].as_str().map(str::to_string).unwrap_or(username);
+    accounts.insert(username, account);
+    StatusCode::NO_CONTENT
+}
+
+// Admin endpoints only check that a bearer token is present
+fn authorized(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("Bearer "))
+}
+
+// Create a group, or move an existing one when the body carries its id
+fn save_group(state: &MockState, parent: Option<String>, body: &Value) -> Response {
+    let name = body["name"].as_str().unwrap_or_default().to_string();
+    let mut groups = state.groups.lock().unwrap();
+
+    if let Some(id) = body["id"].as_str() {
+        return match groups.get_mut(id) {
+            Some(group) => {
+                group.parent = parent;
+                StatusCode::NO_CONTENT.into_response()
+            },
+            None => StatusCode::NOT_FOUND.into_response(),
+        };
+    }
+
+    if groups.values().any(|group| group.parent == parent && group.name == name) {
+        return StatusCode::CONFLICT.into_response();
+    }
+    let id = Uuid::new_v4().to_string();
+    groups.insert(id.clone(), MockGroup { name, parent });
+    let location = format!("{}/admin/realms/{REALM}/groups/{id}", state.server_addr);
+    (StatusCode::CREATED, [(header::LOCATION, location)]).into_response()
+}
+
+async fn admin_create_group(State(state): State<MockState>, headers: HeaderMap, Json(body): Json<Value>) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    save_group(&state, None, &body)
+}
+
+async fn admin_create_child_group(
+    State(state): State<MockState>,
+    Path(parent): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if !state.groups.lock().unwrap().contains_key(&parent) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    save_group(&state, Some(parent), &body)
+}
+
+async fn admin_update_group(
+    State(state): State<MockState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> StatusCode {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    match state.groups.lock().unwrap().get_mut(&id) {
+        Some(group) => {
+            group.name = body["name"].as_str().unwrap_or_default().to_string();
+            StatusCode::NO_CONTENT
+        },
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn admin_delete_group(State(state): State<MockState>, Path(id): Path<String>, headers: HeaderMap) -> StatusCode {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    // Subgroups are removed together with their parent
+    let mut groups = state.groups.lock().unwrap();
+    if groups.remove(&id).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let mut removed = vec![id];
+    while let Some(parent) = removed.pop() {
+        let children: Vec<String> = groups
+            .iter()
+            .filter(|(_, group)| group.parent.as_deref() == Some(parent.as_str()))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for child in children {
+            groups.remove(&child);
+            removed.push(child);
+        }
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn admin_add_member(State(state): State<MockState>, Path((user, group)): Path<(String, String)>, headers: HeaderMap) -> StatusCode {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    if !state.groups.lock().unwrap().contains_key(&group) {
+        return StatusCode::NOT_FOUND;
+    }
+    state.memberships.lock().unwrap().insert((user, group));
+    StatusCode::NO_CONTENT
+}
+
+async fn admin_remove_member(State(state): State<MockState>, Path((user, group)): Path<(String, String)>, headers: HeaderMap) -> StatusCode {
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    match state.memberships.lock().unwrap().remove(&(user, group)) {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
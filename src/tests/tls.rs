@@ -0,0 +1,188 @@
+use std::{fs, net::SocketAddr, path::PathBuf, process::Command, sync::Arc, time::Duration};
+
+use axum::{routing::get, Extension, Router};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+use uuid::Uuid;
+
+use crate::{
+    definitions::tls::ClientCertificate,
+    server::{serve_tls, tls::{spawn_tls_reload, ReloadableTls, TlsSettings}},
+};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/fixtures/tls");
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(FIXTURES).join(name)
+}
+
+// Copy the server certificate into a scratch directory so it can be replaced
+fn scratch_settings(client_ca: bool, client_auth_optional: bool) -> TlsSettings {
+    let directory = std::env::temp_dir().join(format!("tls-{}", Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    for name in ["server.pem", "server.key"] {
+        fs::copy(fixture(name), directory.join(name)).unwrap();
+    }
+    TlsSettings {
+        cert_path: directory.join("server.pem"),
+        key_path: directory.join("server.key"),
+        client_ca_path: client_ca.then(|| fixture("ca.pem")),
+        client_auth_optional,
+        reload_interval: Duration::ZERO,
+    }
+}
+
+// HTTPS server answering /whoami with the common name of the client certificate
+async fn start(tls: Arc<ReloadableTls>) -> SocketAddr {
+    let app = Router::new().route(
+        "/whoami",
+        get(|certificate: Option<Extension<ClientCertificate>>| async move {
+            certificate.and_then(|Extension(certificate)| certificate.common_name).unwrap_or_else(|| "anonymous".to_string())
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve_tls(listener, app, tls));
+    address
+}
+
+async fn connect(address: SocketAddr, alpn: &[&[u8]], client_cert: bool) -> std::io::Result<TlsStream<TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(fixture("ca.pem")).unwrap()).unwrap();
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let mut config = match client_cert {
+        true => builder
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_file(fixture("client.pem")).unwrap()],
+                PrivateKeyDer::from_pem_file(fixture("client.key")).unwrap(),
+            )
+            .unwrap(),
+        false => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+    let stream = TcpStream::connect(address).await?;
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+}
+
+// Send an HTTP/1.1 request, the response body is the last line
+async fn whoami(address: SocketAddr, client_cert: bool) -> std::io::Result<String> {
+    let mut stream = connect(address, &[b"http/1.1"], client_cert).await?;
+    stream.write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response.lines().last().unwrap_or_default().to_string())
+}
+
+fn server_name(stream: &TlsStream<TcpStream>) -> Option<String> {
+    let certificate = stream.get_ref().1.peer_certificates()?.first()?;
+    ClientCertificate::from_der(certificate)?.common_name
+}
+
+#[tokio::test]
+async fn tls_negotiates_http2_and_reloads_certificates() {
+    let settings = scratch_settings(false, false);
+    let tls = Arc::new(ReloadableTls::new(settings.clone()).unwrap());
+    let address = start(tls.clone()).await;
+
+    let stream = connect(address, &[b"h2", b"http/1.1"], false).await.unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(server_name(&stream).as_deref(), Some("server"));
+    assert_eq!(whoami(address, false).await.unwrap(), "anonymous");
+
+    // Invalid files keep the current certificate
+    fs::write(&settings.cert_path, "not a certificate").unwrap();
+    assert!(tls.reload().is_err());
+    let stream = connect(address, &[b"h2"], false).await.unwrap();
+    assert_eq!(server_name(&stream).as_deref(), Some("server"));
+
+    // Renewed files are used for new handshakes, open connections are unaffected
+    fs::copy(fixture("server-renewed.pem"), &settings.cert_path).unwrap();
+    fs::copy(fixture("server-renewed.key"), &settings.key_path).unwrap();
+    tls.reload().unwrap();
+    let renewed = connect(address, &[b"h2"], false).await.unwrap();
+    assert_eq!(server_name(&renewed).as_deref(), Some("server-renewed"));
+    assert_eq!(server_name(&stream).as_deref(), Some("server"));
+
+    fs::remove_dir_all(settings.cert_path.parent().unwrap()).unwrap();
+}
+
+// Replace the scratch certificate with the renewed one
+fn renew(settings: &TlsSettings) {
+    fs::copy(fixture("server-renewed.pem"), &settings.cert_path).unwrap();
+    fs::copy(fixture("server-renewed.key"), &settings.key_path).unwrap();
+}
+
+// Poll new handshakes until the renewed certificate is served
+async fn serves_renewed(address: SocketAddr) -> bool {
+    for _ in 0..50 {
+        let stream = connect(address, &[b"h2"], false).await.unwrap();
+        if server_name(&stream).as_deref() == Some("server-renewed") {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn changed_files_are_reloaded() {
+    let settings = TlsSettings { reload_interval: Duration::from_millis(100), ..scratch_settings(false, false) };
+    let tls = Arc::new(ReloadableTls::new(settings.clone()).unwrap());
+    spawn_tls_reload(tls.clone()).unwrap();
+    let address = start(tls).await;
+
+    // Keeps the modification time apart from the original copy on coarse filesystems
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    renew(&settings);
+    assert!(serves_renewed(address).await);
+
+    fs::remove_dir_all(settings.cert_path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn sighup_reloads_the_files() {
+    let settings = scratch_settings(false, false);
+    let tls = Arc::new(ReloadableTls::new(settings.clone()).unwrap());
+    spawn_tls_reload(tls.clone()).unwrap();
+    let address = start(tls).await;
+
+    // Without a reload interval only the signal picks up the new files
+    renew(&settings);
+    let stream = connect(address, &[b"h2"], false).await.unwrap();
+    assert_eq!(server_name(&stream).as_deref(), Some("server"));
+    let status = Command::new("kill").args(["-HUP", &std::process::id().to_string()]).status().unwrap();
+    assert!(status.success());
+    assert!(serves_renewed(address).await);
+
+    fs::remove_dir_all(settings.cert_path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn mutual_tls_exposes_the_client_identity() {
+    let required = Arc::new(ReloadableTls::new(scratch_settings(true, false)).unwrap());
+    let address = start(required).await;
+    assert_eq!(whoami(address, true).await.unwrap(), "reporting-service");
+    assert!(whoami(address, false).await.is_err());
+
+    let optional = Arc::new(ReloadableTls::new(scratch_settings(true, true)).unwrap());
+    let address = start(optional).await;
+    assert_eq!(whoami(address, true).await.unwrap(), "reporting-service");
+    assert_eq!(whoami(address, false).await.unwrap(), "anonymous");
+}
@@ -0,0 +1,214 @@
+use axum::{
+    body::Body,
+    http::{header, Method, StatusCode},
+};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::support::TestApp;
+
+#[tokio::test]
+async fn csv_import_then_ndjson_export() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    let csv = format!("user_id,username,email\n{first},ivan,ivan@example.com\n{second},judy,\n");
+
+    let (status, body) = app
+        .request_raw(Method::POST, "/users/import", Some(&token), &[(header::CONTENT_TYPE, "text/csv")], Body::from(csv))
+        .await;
+    let summary: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["imported"], 2);
+    assert_eq!(summary["committed"], true);
+
+    let (status, body) = app
+        .request_raw(Method::GET, "/users/export", Some(&token), &[(header::ACCEPT, "application/x-ndjson")], Body::empty())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let users: Vec<Value> = body.split(|byte| *byte == b'\n').filter(|line| !line.is_empty()).map(|line| serde_json::from_slice(line).unwrap()).collect();
+    assert_eq!(users.len(), 2);
+    assert!(users.iter().any(|user| user["username"] == "judy" && user["email"].is_null()));
+
+    let (status, body) = app
+        .request_raw(Method::GET, "/users/export", Some(&token), &[(header::ACCEPT, "text/csv")], Body::empty())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let mut rows = [format!("{first},ivan,ivan@example.com,{{}},true"), format!("{second},judy,,{{}},true")];
+    rows.sort();
+    assert_eq!(String::from_utf8_lossy(&body), format!("user_id,username,email,attributes,enabled\n{}\n{}\n", rows[0], rows[1]));
+}
+
+#[tokio::test]
+async fn upsert_import_reports_unique_violations() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    app.request(Method::POST, "/attributes", Some(&token), Some(json!({ "name": "badge", "attribute_type": "string", "is_unique": true }))).await;
+    app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": Uuid::new_v4(), "username": "nell", "attributes": { "badge": "B1" } }))).await;
+
+    let ndjson = format!("{}\n", json!({ "user_id": Uuid::new_v4(), "username": "ned", "attributes": { "badge": "B1" } }));
+    let (status, body) = app
+        .request_raw(Method::POST, "/users/import?upsert=true&on_error=report", Some(&token), &[(header::CONTENT_TYPE, "application/x-ndjson")], Body::from(ndjson))
+        .await;
+    let summary: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["errors"][0]["status"], 409);
+    assert_eq!(summary["errors"][0]["error"], "Attribute badge must be unique");
+}
+
+#[tokio::test]
+async fn export_applies_filters() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let disabled = Uuid::new_v4();
+    for (user_id, username, email) in [(disabled, "kate", "kate@Example.com"), (Uuid::new_v4(), "liam", "liam@example.com"), (Uuid::new_v4(), "mona", "mona@other.org")] {
+        let (status, _) = app
+            .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": username, "email": email })))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    let (status, _) = app.request(Method::POST, &format!("/users/{disabled}/disable"), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let export = |query: &'static str| {
+        let (app, token) = (&app, &token);
+        async move {
+            let (status, body) = app
+                .request_raw(Method::GET, &format!("/users/export{query}"), Some(token), &[(header::ACCEPT, "application/x-ndjson")], Body::empty())
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            let mut usernames: Vec<String> = body
+                .split(|byte| *byte == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| serde_json::from_slice::<Value>(line).unwrap()["username"].as_str().unwrap().to_string())
+                .collect();
+            usernames.sort();
+            usernames
+        }
+    };
+
+    assert_eq!(export("").await, ["kate", "liam", "mona"]);
+    assert_eq!(export("?enabled=true").await, ["liam", "mona"]);
+    assert_eq!(export("?email_domain=EXAMPLE.com").await, ["kate", "liam"]);
+    assert_eq!(export("?enabled=false&email_domain=example.com").await, ["kate"]);
+    // Values are bound as parameters, SQL in a filter only fails to match
+    assert!(export("?email_domain=x%27%20OR%20%271%27%3D%271").await.is_empty());
+}
+
+#[tokio::test]
+async fn ndjson_import_modes() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let existing = Uuid::new_v4();
+    app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": existing, "username": "kim" }))).await;
+
+    let ndjson = format!(
+        "{}\n{}\n{}\n",
+        json!({ "user_id": existing, "username": "kimberly" }),
+        json!({ "user_id": Uuid::new_v4(), "username": "" }),
+        json!({ "user_id": Uuid::new_v4(), "username": "leo" }),
+    );
+    let import = |query: &'static str, body: String| {
+        let app = &app;
+        let token = token.clone();
+        async move {
+            let (status, body) = app
+                .request_raw(Method::POST, &format!("/users/import?{query}"), Some(&token), &[(header::CONTENT_TYPE, "application/x-ndjson")], Body::from(body))
+                .await;
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        }
+    };
+
+    // Conflicting row aborts the whole import
+    let (status, summary) = import("on_error=abort", ndjson.clone()).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(summary["committed"], false);
+    assert_eq!(summary["errors"][0]["status"], 409);
+
+    // Dry runs report every failure without writing anything
+    let (status, summary) = import("on_error=report&upsert=true&dry_run=true", ndjson.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!((summary["imported"].as_u64(), summary["failed"].as_u64()), (Some(2), Some(1)));
+    assert_eq!(summary["errors"][0]["line"], 2);
+    assert_eq!(summary["errors"][0]["error"], "Username must not be empty");
+    assert_eq!(summary["committed"], false);
+
+    let (status, summary) = import("on_error=report&upsert=true", ndjson).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["committed"], true);
+    let (_, user) = app.request(Method::GET, &format!("/users/{existing}"), Some(&token), None).await;
+    assert_eq!(user["username"], "kimberly");
+}
+
+#[tokio::test]
+async fn committed_imports_reach_keycloak() {
+    let app = TestApp::spawn_with_database_env(&[("KC_USER_SYNC", "true")]).await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+    let import = |query: &'static str, ndjson: String| {
+        let token = token.clone();
+        let app = &app;
+        async move {
+            let (status, body) = app
+                .request_raw(Method::POST, &format!("/users/import{query}"), Some(&token), &[(header::CONTENT_TYPE, "application/x-ndjson")], Body::from(ndjson))
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            serde_json::from_slice::<Value>(&body).unwrap()
+        }
+    };
+
+    // Dry runs never reach Keycloak
+    let summary = import("?dry_run=true", json!({ "user_id": user_id, "username": "nina" }).to_string()).await;
+    assert_eq!(summary["committed"], false);
+    assert!(app.oidc.account("nina").is_none());
+
+    let summary = import("", json!({ "user_id": user_id, "username": "nina", "email": "nina@example.com" }).to_string()).await;
+    assert_eq!(summary["committed"], true);
+    let keycloak_id = app.oidc.account("nina").unwrap()["id"].clone();
+
+    // Upserts rename the existing account
+    let summary = import("?upsert=true", json!({ "user_id": user_id, "username": "nina2" }).to_string()).await;
+    assert_eq!(summary["imported"], 1);
+    assert!(app.oidc.account("nina").is_none());
+    assert_eq!(app.oidc.account("nina2").unwrap()["id"], keycloak_id);
+}
+
+#[tokio::test]
+async fn csv_round_trip_keeps_disabled_users() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+    let (status, _) = app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "oscar" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = app.request(Method::POST, &format!("/users/{user_id}/disable"), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, csv) = app
+        .request_raw(Method::GET, "/users/export", Some(&token), &[(header::ACCEPT, "text/csv")], Body::empty())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(String::from_utf8_lossy(&csv).ends_with(",false\n"));
+
+    // Re-importing the export over a re-enabled user disables it again
+    let (status, _) = app.request(Method::POST, &format!("/users/{user_id}/enable"), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app
+        .request_raw(Method::POST, "/users/import?upsert=true", Some(&token), &[(header::CONTENT_TYPE, "text/csv")], Body::from(csv))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["imported"], 1);
+    let (_, user) = app.request(Method::GET, &format!("/users/{user_id}"), Some(&token), None).await;
+    assert_eq!(user["enabled"], false);
+
+    // Into a fresh organization the export recreates the user disabled
+    let (status, _) = app.request(Method::POST, "/organizations", Some(&token), Some(json!({ "slug": "initech", "name": "Initech" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let initech = app.oidc.mint_token_with_claims(Uuid::new_v4(), "owner", &["user", "administrator"], json!({ "organization": "initech" }));
+    let csv = format!("user_id,username,email,attributes,enabled\n{user_id},oscar,,{{}},false\n");
+    let (status, _) = app
+        .request_raw(Method::POST, "/users/import", Some(&initech), &[(header::CONTENT_TYPE, "text/csv")], Body::from(csv))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, user) = app.request(Method::GET, &format!("/users/{user_id}"), Some(&initech), None).await;
+    assert_eq!(user["enabled"], false);
+}
@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use super::support::database::TestDatabase;
+use crate::{
+    database::{
+        unit_of_work::{is_retryable, unit_of_work, MAX_ATTEMPTS},
+        users::create_user,
+    },
+    definitions::{organization::DEFAULT_ORGANIZATION_ID, user::User},
+};
+
+// Fail the current statement with the given SQL state, as a concurrent transaction would
+const RAISE: &str = "DO $$ BEGIN RAISE EXCEPTION 'conflict' USING ERRCODE = '{code}'; END $$";
+
+fn user(user_id: Uuid) -> User {
+    User { user_id, username: "nina".to_string(), email: None, attributes: json!({}), enabled: true }
+}
+
+async fn count_users(pool: &Pool<Postgres>) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(pool).await.unwrap()
+}
+
+#[tokio::test]
+async fn serialization_failures_are_retried_in_a_fresh_transaction() {
+    let db = TestDatabase::create().await;
+    let attempts = AtomicU32::new(0);
+    let user_id = Uuid::new_v4();
+
+    let outcome = unit_of_work(&db.pool, DEFAULT_ORGANIZATION_ID, |tx| {
+        let attempts = &attempts;
+        Box::pin(async move {
+            // The insert of the failed attempt is rolled back, otherwise the retry would hit a duplicate key
+            create_user(user(user_id), DEFAULT_ORGANIZATION_ID, &mut **tx).await?;
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                sqlx::query(&RAISE.replace("{code}", "40001")).execute(&mut **tx).await?;
+            }
+            Ok::<_, sqlx::Error>(Ok::<_, ()>(()))
+        })
+    })
+    .await;
+
+    assert!(matches!(outcome, Ok(Ok(()))));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(count_users(&db.pool).await, 1);
+}
+
+#[tokio::test]
+async fn updates_racing_a_concurrent_commit_are_retried() {
+    let db = TestDatabase::create().await;
+    let user_id = Uuid::new_v4();
+    create_user(user(user_id), DEFAULT_ORGANIZATION_ID, &db.pool).await.unwrap();
+    let attempts = AtomicU32::new(0);
+
+    let outcome = unit_of_work(&db.pool, DEFAULT_ORGANIZATION_ID, |tx| {
+        let (attempts, pool) = (&attempts, &db.pool);
+        Box::pin(async move {
+            // Reading the row takes the snapshot, the first attempt then loses the row to another transaction
+            let username: String = sqlx::query_scalar("SELECT username FROM users WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(&mut **tx)
+                .await?;
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                sqlx::query("UPDATE users SET email = 'nina@example.com' WHERE user_id = $1").bind(user_id).execute(pool).await?;
+            }
+            sqlx::query("UPDATE users SET username = $1 WHERE user_id = $2")
+                .bind(format!("{username}-renamed"))
+                .bind(user_id)
+                .execute(&mut **tx)
+                .await?;
+            Ok::<_, sqlx::Error>(Ok::<_, ()>(()))
+        })
+    })
+    .await;
+
+    assert!(matches!(outcome, Ok(Ok(()))));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    let (username, email): (String, Option<String>) = sqlx::query_as("SELECT username, email FROM users WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(username, "nina-renamed");
+    assert_eq!(email.as_deref(), Some("nina@example.com"));
+}
+
+#[tokio::test]
+async fn failed_outcomes_and_persistent_conflicts_roll_back() {
+    let db = TestDatabase::create().await;
+
+    // An unsuccessful outcome is returned as is, without its writes
+    let outcome = unit_of_work(&db.pool, DEFAULT_ORGANIZATION_ID, |tx| {
+        Box::pin(async move {
+            create_user(user(Uuid::new_v4()), DEFAULT_ORGANIZATION_ID, &mut **tx).await?;
+            Ok::<_, sqlx::Error>(Err::<(), _>("rejected"))
+        })
+    })
+    .await;
+    assert!(matches!(outcome, Ok(Err("rejected"))));
+    assert_eq!(count_users(&db.pool).await, 0);
+
+    // Deadlocks are retried until the attempts run out, then the error reaches the caller
+    let attempts = AtomicU32::new(0);
+    let outcome = unit_of_work(&db.pool, DEFAULT_ORGANIZATION_ID, |tx| {
+        let attempts = &attempts;
+        Box::pin(async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            create_user(user(Uuid::new_v4()), DEFAULT_ORGANIZATION_ID, &mut **tx).await?;
+            sqlx::query(&RAISE.replace("{code}", "40P01")).execute(&mut **tx).await?;
+            Ok::<_, sqlx::Error>(Ok::<_, ()>(()))
+        })
+    })
+    .await;
+    assert!(outcome.as_ref().is_err_and(is_retryable));
+    assert_eq!(attempts.load(Ordering::SeqCst), MAX_ATTEMPTS);
+    assert_eq!(count_users(&db.pool).await, 0);
+
+    // Other errors are not retried
+    let attempts = AtomicU32::new(0);
+    let outcome = unit_of_work(&db.pool, DEFAULT_ORGANIZATION_ID, |tx| {
+        let attempts = &attempts;
+        Box::pin(async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            sqlx::query("SELECT 1 / 0").execute(&mut **tx).await?;
+            Ok::<_, sqlx::Error>(Ok::<_, ()>(()))
+        })
+    })
+    .await;
+    assert!(outcome.is_err());
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
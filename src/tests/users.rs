@@ -0,0 +1,162 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::support::TestApp;
+
+#[tokio::test]
+async fn user_crud_round_trip() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+    let uri = format!("/users/{user_id}");
+
+    let (status, body) = app
+        .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": user_id, "username": "carol", "email": "carol@example.com" })))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["username"], "carol");
+
+    let (status, body) = app.request(Method::GET, &uri, Some(&app.user_token()), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["email"], "carol@example.com");
+
+    let (status, body) = app
+        .request(Method::PUT, &uri, Some(&token), Some(json!({ "user_id": user_id, "username": "caroline" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "caroline");
+    // Fields missing from the update are left as they were
+    assert_eq!(body["email"], "carol@example.com");
+
+    let (status, _) = app.request(Method::DELETE, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let (status, _) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn duplicate_user_is_a_conflict() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let user = json!({ "user_id": Uuid::new_v4(), "username": "dave" });
+
+    let (status, _) = app.request(Method::POST, "/users", Some(&token), Some(user.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) = app.request(Method::POST, "/users", Some(&token), Some(user)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "User already exists");
+}
+
+#[tokio::test]
+async fn invalid_input_is_rejected() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+
+    let (status, _) = app
+        .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": "not-a-uuid", "username": "erin" })))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = app
+        .request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": Uuid::new_v4(), "username": "erin", "email": "nope" })))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "Invalid email format");
+
+    let (status, _) = app.request(Method::GET, "/users/not-a-uuid", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn atomic_batch_rolls_back_on_failure() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let created = Uuid::new_v4();
+
+    let (status, body) = app
+        .request(Method::POST, "/users:batch", Some(&token), Some(json!({
+            "mode": "atomic",
+            "operations": [
+                { "op": "create", "user_id": created, "username": "frank" },
+                { "op": "create", "user_id": Uuid::new_v4(), "username": "grace", "email": "invalid" },
+                { "op": "delete", "user_id": Uuid::new_v4() },
+            ],
+        })))
+        .await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(body["committed"], false);
+    assert_eq!(body["results"][0]["status"], 424);
+    assert_eq!(body["results"][1]["status"], 422);
+    assert_eq!(body["results"][2]["status"], 424);
+
+    let (status, _) = app.request(Method::GET, &format!("/users/{created}"), Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn best_effort_batch_reports_each_item() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    let user_id = Uuid::new_v4();
+
+    let (status, body) = app
+        .request(Method::POST, "/users:batch", Some(&token), Some(json!({
+            "mode": "best_effort",
+            "operations": [
+                { "op": "create", "user_id": user_id, "username": "heidi" },
+                { "op": "create", "user_id": user_id, "username": "heidi" },
+                { "op": "update", "user_id": user_id, "username": "heidi2" },
+                { "op": "delete", "user_id": Uuid::new_v4() },
+            ],
+        })))
+        .await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(body["committed"], true);
+    let statuses: Vec<u64> = body["results"].as_array().unwrap().iter().map(|result| result["status"].as_u64().unwrap()).collect();
+    assert_eq!(statuses, vec![201, 409, 200, 404]);
+    assert_eq!(body["results"][1]["body"]["error"], "User already exists");
+}
+
+#[tokio::test]
+async fn search_ranks_and_highlights_matches() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    for (username, email) in [("margaret", "maggie@example.com"), ("marvin", "marvin@corp.example"), ("nina", "nina@example.com")] {
+        app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": Uuid::new_v4(), "username": username, "email": email }))).await;
+    }
+
+    let (status, body) = app.request(Method::GET, "/users/search?q=marg", Some(&app.user_token()), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["items"][0]["user"]["username"], "margaret");
+    assert_eq!(body["items"][0]["highlights"]["username"], "<mark>margaret</mark>");
+
+    let (status, body) = app.request(Method::GET, "/users/search?q=mar&limit=1&offset=1", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["limit"], 1);
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+
+    // Pages past the last match still report the total
+    let (_, body) = app.request(Method::GET, "/users/search?q=mar&offset=5", Some(&token), None).await;
+    assert_eq!((body["total"].as_i64(), body["items"].as_array().map(Vec::len)), (Some(2), Some(0)));
+
+    let (status, _) = app.request(Method::GET, "/users/search?q=%20", Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = app.request(Method::GET, "/users/search?q=%25%26%21", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn search_highlights_escape_stored_markup() {
+    let app = TestApp::spawn_with_database().await;
+    let token = app.admin_token();
+    app.request(Method::POST, "/users", Some(&token), Some(json!({ "user_id": Uuid::new_v4(), "username": "<script>oscar</script>" }))).await;
+
+    let (status, body) = app.request(Method::GET, "/users/search?q=oscar", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["items"][0]["highlights"]["username"], "&lt;script&gt;<mark>oscar</mark>&lt;/script&gt;");
+    assert_eq!(body["items"][0]["user"]["username"], "<script>oscar</script>");
+}